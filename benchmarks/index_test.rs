@@ -1,29 +1,56 @@
-use hnsw_rust::{HnswIndex, VectorItem, EuclideanDistance, DistanceCalculator}; // Added DistanceCalculator
-use rand::Rng;
+use hnsw_rust::{eval, HnswIndex, VectorItem, EuclideanDistance};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use indicatif::{ProgressBar, ProgressStyle};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 // Constants from the HNSW paper
 const M: usize = 16;  // Number of connections per layer
 const EF_CONSTRUCTION: usize = 128;  // Size of dynamic candidate list during construction
 const EF_SEARCH: usize = 64;  // Size of dynamic candidate list during search
+const SEED: u64 = 42;  // Seeds both the data and the index, so runs are comparable
 
-fn generate_random_vector(dim: usize) -> Vec<f64> {
-    let mut rng = rand::thread_rng();
+fn generate_random_vector(rng: &mut StdRng, dim: usize) -> Vec<f64> {
     (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect()
 }
 
-fn calculate_ground_truth(query: &VectorItem, vectors: &[VectorItem], k: usize) -> Vec<(usize, f64)> {
-    let distance_calc = EuclideanDistance;
-    let mut distances: Vec<(usize, f64)> = vectors
-        .iter()
-        .map(|v| (v.id, distance_calc.calculate(query, v)))  // Removed unwrap as it returns f64 directly
-        .collect();
-    distances.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-    distances.into_iter().take(k).collect()
+// Writes the run as ann-benchmarks' exported results (one row per parameter
+// set, times in milliseconds and index size in kB), so it can be plotted
+// alongside the standard comparisons.
+fn write_ann_benchmarks_json(
+    path: &str,
+    hnsw: &HnswIndex,
+    dataset: &str,
+    k: usize,
+    build_time: Duration,
+    index_bytes: usize,
+    metrics: &eval::Metrics,
+) -> std::io::Result<()> {
+    let config = hnsw.config();
+    let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+    let rows = serde_json::json!([{
+        "algorithm": "hnsw-rust",
+        "parameters": format!("hnsw-rust(M={}, efConstruction={}, ef={})", config.m, config.ef_construction, config.ef_search),
+        "dataset": dataset,
+        "count": k,
+        "distance": "euclidean",
+        "build": build_time.as_secs_f64(),
+        "indexsize": index_bytes as f64 / 1024.0,
+        "k-nn": metrics.recall,
+        "qps": metrics.qps,
+        "p50": ms(metrics.latency_p50),
+        "p95": ms(metrics.latency_p95),
+        "p99": ms(metrics.latency_p99),
+    }]);
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(file, &rows)?;
+    Ok(())
 }
 
 fn main() {
+    // `--json <path>` also writes the results in the ann-benchmarks layout
+    let args: Vec<String> = std::env::args().collect();
+    let json_path = args.iter().position(|arg| arg == "--json").and_then(|i| args.get(i + 1));
+
     let n_vectors = 100_000;  // Number of vectors to index
     let dim = 128;           // Dimension of vectors
     let n_queries = 100;     // Number of queries to test
@@ -38,9 +65,11 @@ fn main() {
     println!("  M: {}", M);
     println!("  EF Construction: {}", EF_CONSTRUCTION);
     println!("  EF Search: {}", EF_SEARCH);
+    println!("  Seed: {}", SEED);
 
     println!("\nInitializing HNSW index...");
-    let hnsw = HnswIndex::new(Box::new(EuclideanDistance));
+    let hnsw = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(SEED);
+    let mut rng = StdRng::seed_from_u64(SEED);
 
     println!("Generating {} random vectors...", n_vectors);
     let pb = ProgressBar::new(n_vectors as u64);
@@ -53,26 +82,32 @@ fn main() {
     for i in 0..n_vectors {
         vectors.push(VectorItem {
             id: i,
-            vector: generate_random_vector(dim),
+            vector: generate_random_vector(&mut rng, dim),
         });
         pb.inc(1);
     }
     pb.finish_with_message("Vector generation complete");
 
     println!("\nBuilding index...");
+    let pb = ProgressBar::new(n_vectors as u64);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
+        .unwrap()
+        .progress_chars("=>-"));
     let build_start = Instant::now();
-    match hnsw.batch_add(vectors.clone()) {
-        Ok(_) => {
-            let build_time = build_start.elapsed();
-            println!("Index built successfully:");
-            println!("  Build time: {:?}", build_time);
-            println!("  Vectors per second: {:.2}", n_vectors as f64 / build_time.as_secs_f64());
-        }
-        Err(e) => {
-            println!("Error building index: {}", e);
-            return;
-        }
+    let built = hnsw.batch_add_with_progress(vectors.clone(), |done, _| {
+        pb.set_position(done as u64);
+        true
+    });
+    if let Err(e) = built {
+        println!("Error building index: {}", e);
+        return;
     }
+    let build_time = build_start.elapsed();
+    pb.finish_with_message("Index build complete");
+    println!("Index built successfully:");
+    println!("  Build time: {:?}", build_time);
+    println!("  Vectors per second: {:.2}", n_vectors as f64 / build_time.as_secs_f64());
 
     let stats = hnsw.get_stats();
     println!("\nIndex Statistics:");
@@ -82,79 +117,38 @@ fn main() {
              stats.total_connections as f64 / stats.total_nodes as f64);
     println!("  Max level: {}", stats.max_level);
     println!("  Level distribution:");
-    let mut levels: Vec<_> = stats.level_distribution.into_iter().collect();
-    levels.sort_by_key(|&(k, _)| k);
-    for (level, count) in levels {
+    for (level, count) in &stats.level_distribution {
         println!("    Level {}: {} nodes", level, count);
     }
+    println!("  Degree per layer:");
+    for (level, degrees) in stats.layer_degrees.iter().enumerate() {
+        println!("    Level {}: avg {:.2}, max {}", level, degrees.average, degrees.max);
+    }
+    println!("  Isolated nodes: {}", stats.isolated_nodes);
+    println!("  Layer 0 components: {}", stats.components);
 
     println!("\nPerforming {} test queries...", n_queries);
-    let pb = ProgressBar::new(n_queries as u64);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
-        .unwrap()
-        .progress_chars("=>-"));
-
-    let mut total_query_time = std::time::Duration::new(0, 0);
-    let mut recall_sum = 0.0;
-    let mut avg_precision_sum = 0.0;
-
-    for _ in 0..n_queries {
-        let query = VectorItem {
+    let queries: Vec<VectorItem> = (0..n_queries)
+        .map(|_| VectorItem {
             id: n_vectors + 1,
-            vector: generate_random_vector(dim),
-        };
-
-        // Get ground truth
-        let ground_truth = calculate_ground_truth(&query, &vectors, k);
-        
-        // Perform HNSW search
-        let query_start = Instant::now();
-        match hnsw.search(&query, k) {
-            Ok(results) => {
-                total_query_time += query_start.elapsed();
-
-                // Calculate recall (fraction of true nearest neighbors found)
-                let result_ids: Vec<_> = results.iter().map(|r| r.id).collect();
-                let truth_ids: Vec<_> = ground_truth.iter().map(|&(id, _)| id).collect();
-                let correct = result_ids.iter()
-                    .filter(|&&id| truth_ids.contains(&id))
-                    .count();
-                let recall = correct as f64 / k as f64;
-                recall_sum += recall;
-
-                // Calculate average precision
-                let mut running_precision = 0.0;
-                let mut correct_count = 0;
-                for (i, &id) in result_ids.iter().enumerate() {
-                    if truth_ids.contains(&id) {
-                        correct_count += 1;
-                        running_precision += correct_count as f64 / (i + 1) as f64;
-                    }
-                }
-                let avg_precision = if correct_count > 0 {
-                    running_precision / correct_count as f64
-                } else {
-                    0.0
-                };
-                avg_precision_sum += avg_precision;
-            }
-            Err(e) => println!("Search error: {}", e),
+            vector: generate_random_vector(&mut rng, dim),
+        })
+        .collect();
+    let ground_truth = eval::ground_truth(&vectors, &queries, k, &EuclideanDistance);
+    let metrics = match eval::evaluate(&hnsw, &queries, &ground_truth, k) {
+        Ok(metrics) => metrics,
+        Err(e) => {
+            println!("Search error: {}", e);
+            return;
         }
-        pb.inc(1);
-    }
-    pb.finish();
-
-    let avg_query_time = total_query_time / n_queries as u32;
-    let avg_recall = recall_sum / n_queries as f64;
-    let mean_avg_precision = avg_precision_sum / n_queries as f64;
+    };
 
     println!("\nPerformance Metrics:");
-    println!("  Average query time: {:?}", avg_query_time);
-    println!("  Queries per second: {:.2}", 
-             1.0 / avg_query_time.as_secs_f64());
-    println!("  Average recall@{}: {:.4}", k, avg_recall);
-    println!("  Mean average precision: {:.4}", mean_avg_precision);
+    println!("  Average query time: {:?}", metrics.latency_mean);
+    println!("  Query time p50/p95/p99: {:?} / {:?} / {:?}", metrics.latency_p50, metrics.latency_p95, metrics.latency_p99);
+    println!("  Queries per second: {:.2}", metrics.qps);
+    println!("  Average recall@{}: {:.4}", k, metrics.recall);
+    println!("  Mean average precision: {:.4}", metrics.map);
 
     let memory_per_vector = dim * std::mem::size_of::<f64>();
     let memory_per_node = memory_per_vector + 
@@ -165,4 +159,12 @@ fn main() {
     println!("  Per vector: {} bytes", memory_per_vector);
     println!("  Per node avg: {} bytes", memory_per_node);
     println!("  Total: {:.2} MB", total_memory as f64 / 1024.0 / 1024.0);
-}
\ No newline at end of file
+
+    if let Some(path) = json_path {
+        let dataset = format!("random-{}-{}-euclidean", n_vectors, dim);
+        match write_ann_benchmarks_json(path, &hnsw, &dataset, k, build_time, total_memory, &metrics) {
+            Ok(()) => println!("\nWrote ann-benchmarks results to {}", path),
+            Err(e) => println!("\nError writing {}: {}", path, e),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+fn main() {
+    // The C header is generated only for `ffi` builds, so other builds do
+    // not pay for cbindgen. cbindgen reads the whole crate to find the
+    // opaque types `src/ffi.rs` hands out, and `cbindgen.toml` limits what
+    // it exports to those and the functions. It goes to OUT_DIR; set
+    // HNSW_RUST_UPDATE_HEADER to also refresh the checked-in
+    // `include/hnsw_rust.h`, which a test keeps in sync.
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-env-changed=HNSW_RUST_UPDATE_HEADER");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let config = cbindgen::Config::from_root_or_default(&crate_dir);
+        let header = cbindgen::Builder::new()
+            .with_crate(&crate_dir)
+            .with_config(config)
+            .generate()
+            .expect("Unable to generate the C header");
+        header.write_to_file(format!("{}/hnsw_rust.h", std::env::var("OUT_DIR").unwrap()));
+        if std::env::var_os("HNSW_RUST_UPDATE_HEADER").is_some() {
+            header.write_to_file(format!("{}/include/hnsw_rust.h", crate_dir));
+        }
+    }
+    // The gRPC server's messages and service, compiled with a vendored protoc
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/hnsw.proto");
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/hnsw.proto"], &["proto"])
+            .expect("Unable to compile proto/hnsw.proto");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
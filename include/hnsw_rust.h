@@ -0,0 +1,89 @@
+/*
+ * Generated by cbindgen from src/ffi.rs; do not edit.
+ *
+ * Functions returning int32_t return 0 on success and -1 on failure, and
+ * hnsw_rust_last_error() then describes the failure. An index may be shared
+ * between threads, but hnsw_rust_index_free must not race other calls on it.
+ */
+
+#ifndef HNSW_RUST_H
+#define HNSW_RUST_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct HnswIndex HnswIndex;
+
+/**
+ * Creates an empty index. A `dimension` of 0 lets the first insert set it.
+ */
+struct HnswIndex *hnsw_rust_index_new(size_t dimension);
+
+/**
+ * Releases an index; null is ignored.
+ *
+ * # Safety
+ * `index` must be null or a live index pointer, and is invalid afterwards.
+ */
+void hnsw_rust_index_free(struct HnswIndex *index);
+
+/**
+ * Inserts the `len` values at `vector` under `id`.
+ *
+ * # Safety
+ * `index` must be null or a live index pointer, and `vector` must point to
+ * `len` values.
+ */
+int32_t hnsw_rust_index_add(const struct HnswIndex *index,
+                            uint64_t id,
+                            const double *vector,
+                            size_t len);
+
+/**
+ * Writes up to `k` nearest ids, nearest first, to `ids` and their distances
+ * to `distances` (which may be null). Returns the number of results, or -1.
+ *
+ * # Safety
+ * `index` must be null or a live index pointer and `query` must point to
+ * `len` values. `ids` must have room for `k` values, and so must
+ * `distances` unless null.
+ */
+ptrdiff_t hnsw_rust_index_search(const struct HnswIndex *index,
+                                 const double *query,
+                                 size_t len,
+                                 size_t k,
+                                 uint64_t *ids,
+                                 double *distances);
+
+/**
+ * The number of vectors in the index, or 0 for null.
+ *
+ * # Safety
+ * `index` must be null or a live index pointer.
+ */
+size_t hnsw_rust_index_len(const struct HnswIndex *index);
+
+/**
+ * Saves the index with `HnswIndex::save`.
+ *
+ * # Safety
+ * `index` must be null or a live index pointer, and `path` null or a
+ * NUL-terminated string.
+ */
+int32_t hnsw_rust_index_save(const struct HnswIndex *index, const char *path);
+
+/**
+ * Loads an index written by `hnsw_rust_index_save`, or returns null.
+ *
+ * # Safety
+ * `path` must be null or a NUL-terminated string.
+ */
+struct HnswIndex *hnsw_rust_index_load(const char *path);
+
+/**
+ * The last error on this thread, or null if there was none. The string is
+ * valid until the next failing call on the same thread.
+ */
+const char *hnsw_rust_last_error(void);
+
+#endif  /* HNSW_RUST_H */
@@ -0,0 +1,135 @@
+//! Conversions between `VectorItem`s and Apache Arrow arrays, so the index
+//! can take columns from and hand results to DataFusion or polars. Vectors
+//! travel as `FixedSizeList<Float32>` or `FixedSizeList<Float64>` and ids as
+//! any integer column. The index owns its vectors, so each conversion copies
+//! them once; the value buffers are read and built in bulk.
+
+use crate::vector::VectorItem;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, Float64Type, Int32Type, Int64Type, UInt32Type, UInt64Type};
+use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float64Array, RecordBatch, UInt32Array, UInt64Array};
+use arrow_schema::{DataType, Field, Schema};
+use std::sync::Arc;
+
+/// Items from a vector column, keyed by row number.
+pub fn vectors_from_arrow(vectors: &dyn Array) -> Result<Vec<VectorItem>, String> {
+    items_from_arrow(&UInt64Array::from_iter_values(0..vectors.len() as u64), vectors)
+}
+
+/// Items from an integer id column and a vector column of the same length.
+pub fn items_from_arrow(ids: &dyn Array, vectors: &dyn Array) -> Result<Vec<VectorItem>, String> {
+    let ids = ids_from_arrow(ids)?;
+    let vectors = vectors.as_fixed_size_list_opt().ok_or("Vector column is not a fixed-size list")?;
+    if ids.len() != vectors.len() {
+        return Err(format!("{} ids for {} vectors", ids.len(), vectors.len()));
+    }
+    if vectors.null_count() > 0 || vectors.values().null_count() > 0 {
+        return Err("Vector column has nulls".to_string());
+    }
+    let dimension = vectors.value_length() as usize;
+    let values: Vec<f64> = match vectors.value_type() {
+        DataType::Float32 => vectors.values().as_primitive::<Float32Type>().values().iter().map(|&x| x as f64).collect(),
+        DataType::Float64 => vectors.values().as_primitive::<Float64Type>().values().to_vec(),
+        other => return Err(format!("Expected float32 or float64 vectors, got {}", other)),
+    };
+    Ok(ids
+        .into_iter()
+        .enumerate()
+        .map(|(row, id)| {
+            let start = vectors.value_offset(row) as usize;
+            VectorItem { id, vector: values[start..start + dimension].to_vec() }
+        })
+        .collect())
+}
+
+/// Items from the named id and vector columns of `batch`.
+pub fn items_from_record_batch(batch: &RecordBatch, id_column: &str, vector_column: &str) -> Result<Vec<VectorItem>, String> {
+    let column = |name: &str| batch.column_by_name(name).ok_or_else(|| format!("Record batch has no column {}", name));
+    items_from_arrow(column(id_column)?.as_ref(), column(vector_column)?.as_ref())
+}
+
+fn ids_from_arrow(column: &dyn Array) -> Result<Vec<usize>, String> {
+    if column.null_count() > 0 {
+        return Err("Id column has nulls".to_string());
+    }
+    let ids: Vec<i128> = match column.data_type() {
+        DataType::Int32 => column.as_primitive::<Int32Type>().values().iter().map(|&id| id as i128).collect(),
+        DataType::Int64 => column.as_primitive::<Int64Type>().values().iter().map(|&id| id as i128).collect(),
+        DataType::UInt32 => column.as_primitive::<UInt32Type>().values().iter().map(|&id| id as i128).collect(),
+        DataType::UInt64 => column.as_primitive::<UInt64Type>().values().iter().map(|&id| id as i128).collect(),
+        other => return Err(format!("Expected integer ids, got {}", other)),
+    };
+    ids.into_iter().map(|id| usize::try_from(id).map_err(|_| format!("Id {} is not a valid id", id))).collect()
+}
+
+/// A `FixedSizeList<Float64>` of the items' vectors, which must all have the
+/// same dimension.
+pub fn vectors_to_arrow(items: &[VectorItem]) -> Result<FixedSizeListArray, String> {
+    let dimension = items.first().map_or(0, |item| item.vector.len());
+    if let Some(item) = items.iter().find(|item| item.vector.len() != dimension) {
+        return Err(format!("Vector {} has {} dimensions, expected {}", item.id, item.vector.len(), dimension));
+    }
+    let size = i32::try_from(dimension).map_err(|_| "Dimension does not fit in an i32".to_string())?;
+    let values = Float64Array::from_iter_values(items.iter().flat_map(|item| item.vector.iter().copied()));
+    let field = Arc::new(Field::new("item", DataType::Float64, false));
+    FixedSizeListArray::try_new(field, size, Arc::new(values), None).map_err(|e| e.to_string())
+}
+
+/// One row per result with columns `query` (position of the query in the
+/// batch), `rank` (0 for the nearest), `id`, `distance` and `vector`.
+/// `distances` must line up with `results`.
+pub fn results_to_record_batch(results: &[Vec<VectorItem>], distances: &[Vec<f64>]) -> Result<RecordBatch, String> {
+    if results.len() != distances.len() || results.iter().zip(distances).any(|(r, d)| r.len() != d.len()) {
+        return Err("Distances do not line up with the results".to_string());
+    }
+    let rows = || results.iter().enumerate().flat_map(|(query, hits)| hits.iter().enumerate().map(move |(rank, item)| (query, rank, item)));
+    let items: Vec<VectorItem> = rows().map(|(_, _, item)| item.clone()).collect();
+    let vectors = vectors_to_arrow(&items)?;
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(rows().map(|(query, _, _)| query as u64))),
+        Arc::new(UInt32Array::from_iter_values(rows().map(|(_, rank, _)| rank as u32))),
+        Arc::new(UInt64Array::from_iter_values(rows().map(|(_, _, item)| item.id as u64))),
+        Arc::new(Float64Array::from_iter_values(distances.iter().flatten().copied())),
+        Arc::new(vectors),
+    ];
+    let schema = Schema::new(vec![
+        Field::new("query", DataType::UInt64, false),
+        Field::new("rank", DataType::UInt32, false),
+        Field::new("id", DataType::UInt64, false),
+        Field::new("distance", DataType::Float64, false),
+        Field::new("vector", columns[4].data_type().clone(), false),
+    ]);
+    RecordBatch::try_new(Arc::new(schema), columns).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EuclideanDistance, HnswIndex};
+    use arrow_array::{Float32Array, Int64Array};
+
+    #[test]
+    fn test_arrow_roundtrip() {
+        let item = Arc::new(Field::new("item", DataType::Float32, false));
+        let values = Float32Array::from_iter_values((0..40).map(|x| x as f32));
+        let vectors = FixedSizeListArray::try_new(item, 2, Arc::new(values), None).unwrap();
+        let ids = Int64Array::from_iter_values(100..120);
+
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        index.add_arrow(&ids, &vectors).unwrap();
+        assert_eq!(index.get(103).unwrap().vector, vec![6.0, 7.0]);
+        assert!(index.add_arrow(&Int64Array::from_iter_values(0..3), &vectors).is_err());
+        assert_eq!(vectors_from_arrow(&vectors.slice(5, 2)).unwrap()[1].vector, vec![12.0, 13.0]);
+
+        let queries = vec![VectorItem { id: 0, vector: vec![6.0, 7.0] }, VectorItem { id: 1, vector: vec![0.0, 0.0] }];
+        let batch = index.batch_search_arrow(&queries, 3).unwrap();
+        assert_eq!(batch.num_rows(), 6);
+        assert_eq!(batch.column_by_name("id").unwrap().as_primitive::<UInt64Type>().value(0), 103);
+        assert_eq!(batch.column_by_name("distance").unwrap().as_primitive::<Float64Type>().value(0), 0.0);
+        assert_eq!(batch.column_by_name("query").unwrap().as_primitive::<UInt64Type>().value(3), 1);
+
+        let back = items_from_record_batch(&batch, "id", "vector").unwrap();
+        assert_eq!((back[0].id, &back[0].vector), (103, &vec![6.0, 7.0]));
+    }
+}
@@ -0,0 +1,109 @@
+//! Async access to an index for tokio applications.
+//!
+//! Index operations are CPU-bound and take the index's locks, so running them
+//! directly in an async task stalls the runtime's worker thread. `AsyncIndex`
+//! moves each call onto tokio's blocking pool instead. Pair it with
+//! `HnswIndex::with_snapshot_reads` so searches never wait on inserts either.
+
+use crate::hnsw::HnswIndex;
+use crate::vector::VectorItem;
+use std::sync::Arc;
+
+/// A shared index whose methods run on tokio's blocking thread pool. Clones
+/// share the index. Errors are the index's, or a message if the operation
+/// panicked.
+#[derive(Clone)]
+pub struct AsyncIndex {
+    index: Arc<HnswIndex>,
+}
+
+impl AsyncIndex {
+    pub fn new(index: HnswIndex) -> Self {
+        AsyncIndex { index: Arc::new(index) }
+    }
+
+    /// The shared index, for calls that are cheap enough to make inline.
+    pub fn index(&self) -> &Arc<HnswIndex> {
+        &self.index
+    }
+
+    /// Runs `op` on the blocking pool. Fails only if `op` panicked.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&HnswIndex) -> T + Send + 'static,
+    ) -> Result<T, String> {
+        let index = Arc::clone(&self.index);
+        tokio::task::spawn_blocking(move || op(&index))
+            .await
+            .map_err(|e| format!("Index operation failed: {}", e))
+    }
+
+    pub async fn add(&self, item: VectorItem) -> Result<(), String> {
+        self.run(move |index| index.add(item)).await?
+    }
+
+    pub async fn add_with_payload(&self, item: VectorItem, payload: serde_json::Value) -> Result<(), String> {
+        self.run(move |index| index.add_with_payload(item, payload)).await?
+    }
+
+    pub async fn par_batch_add(&self, items: Vec<VectorItem>) -> Result<(), String> {
+        self.run(move |index| index.par_batch_add(items)).await?
+    }
+
+    pub async fn remove(&self, id: usize) -> Result<bool, String> {
+        self.run(move |index| index.remove(id)).await?
+    }
+
+    pub async fn search(&self, query: VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        self.run(move |index| index.search(&query, k)).await?
+    }
+
+    pub async fn search_with_payloads(
+        &self,
+        query: VectorItem,
+        k: usize,
+    ) -> Result<Vec<(VectorItem, Option<serde_json::Value>)>, String> {
+        self.run(move |index| index.search_with_payloads(&query, k)).await?
+    }
+
+    pub async fn batch_search(&self, queries: Vec<VectorItem>, k: usize) -> Result<Vec<Vec<VectorItem>>, String> {
+        self.run(move |index| index.batch_search(&queries, k)).await?
+    }
+}
+
+impl From<HnswIndex> for AsyncIndex {
+    fn from(index: HnswIndex) -> Self {
+        AsyncIndex::new(index)
+    }
+}
+
+impl From<Arc<HnswIndex>> for AsyncIndex {
+    fn from(index: Arc<HnswIndex>) -> Self {
+        AsyncIndex { index }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistance;
+
+    #[tokio::test]
+    async fn test_async_index() {
+        let index = AsyncIndex::new(HnswIndex::new(Box::new(EuclideanDistance)).with_snapshot_reads(100));
+        let items = (0..50).map(|i| VectorItem { id: i, vector: vec![i as f64, 0.0] }).collect();
+        index.par_batch_add(items).await.unwrap();
+        index.add(VectorItem { id: 50, vector: vec![50.0, 0.0] }).await.unwrap();
+        assert!(index.remove(0).await.unwrap());
+
+        let query = VectorItem { id: 0, vector: vec![10.2, 0.0] };
+        let ids: Vec<usize> = index.search(query.clone(), 3).await.unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids, [10, 11, 9]);
+        assert!(index.search(VectorItem { id: 0, vector: vec![1.0] }, 3).await.is_err());
+
+        // Clones share the index
+        let clone = index.clone();
+        assert_eq!(clone.run(|index| index.len()).await, Ok(50));
+        assert!(index.run(|_| panic!("boom")).await.unwrap_err().contains("Index operation failed"));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::Mutex;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A structural change to the graph. Ids are external ids.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum GraphEvent {
+    EdgeAdded { from: usize, to: usize, level: usize },
+    EdgeRemoved { from: usize, to: usize, level: usize },
+    EntryPoint { id: Option<usize> },
+}
+
+impl fmt::Display for GraphEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphEvent::EdgeAdded { from, to, level } => write!(f, "+{}->{}@{}", from, to, level),
+            GraphEvent::EdgeRemoved { from, to, level } => write!(f, "-{}->{}@{}", from, to, level),
+            GraphEvent::EntryPoint { id: Some(id) } => write!(f, "entry={}", id),
+            GraphEvent::EntryPoint { id: None } => write!(f, "entry=none"),
+        }
+    }
+}
+
+/// Logs every graph mutation (insert, overwrite, remove, entry point change)
+/// with the edges it added and removed, one line per sampled operation:
+///
+/// ```text
+/// insert 42 level 1: +42->7@0 +7->42@0 -7->13@0 entry=42
+/// ```
+///
+/// Meant for chasing rare graph corruption in the field; sampling keeps the
+/// log to a manageable size. Write errors are reported on stderr and never
+/// fail the mutation.
+pub struct AuditLog {
+    sink: Mutex<Box<dyn Write + Send>>,
+    sample_every: u64,
+    operations: AtomicU64,
+    // Events of the operation in progress, if it is sampled. Mutations are
+    // serialized by the graph's write lock, so there is at most one.
+    current: Mutex<Option<(String, Vec<GraphEvent>)>>,
+}
+
+impl AuditLog {
+    /// Logs every operation to `sink`.
+    pub fn new(sink: impl Write + Send + 'static) -> Self {
+        AuditLog {
+            sink: Mutex::new(Box::new(sink)),
+            sample_every: 1,
+            operations: AtomicU64::new(0),
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn to_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(AuditLog::new(BufWriter::new(file)))
+    }
+
+    /// Logs only every `n`th operation (all of its events or none).
+    pub fn with_sample_every(mut self, n: u64) -> Self {
+        self.sample_every = n.max(1);
+        self
+    }
+
+    pub(crate) fn begin(&self, operation: impl FnOnce() -> String) {
+        let sampled = self.operations.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.sample_every);
+        *self.current.lock().unwrap() = sampled.then(|| (operation(), Vec::new()));
+    }
+
+    pub(crate) fn record(&self, event: impl FnOnce() -> GraphEvent) {
+        if let Some((_, events)) = self.current.lock().unwrap().as_mut() {
+            events.push(event());
+        }
+    }
+
+    pub(crate) fn finish(&self) {
+        let Some((operation, events)) = self.current.lock().unwrap().take() else {
+            return;
+        };
+        let mut line = format!("{}:", operation);
+        for event in &events {
+            line.push(' ');
+            line.push_str(&event.to_string());
+        }
+        let mut sink = self.sink.lock().unwrap();
+        if let Err(e) = writeln!(sink, "{}", line).and_then(|_| sink.flush()) {
+            eprintln!("Failed to write audit log: {}", e);
+        }
+    }
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog").field("sample_every", &self.sample_every).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sampling() {
+        let buffer = SharedBuffer::default();
+        let log = AuditLog::new(buffer.clone()).with_sample_every(2);
+        for id in 0..4 {
+            log.begin(|| format!("insert {}", id));
+            log.record(|| GraphEvent::EdgeAdded { from: id, to: 9, level: 0 });
+            log.record(|| GraphEvent::EntryPoint { id: Some(id) });
+            log.finish();
+        }
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text, "insert 0: +0->9@0 entry=0\ninsert 2: +2->9@0 entry=2\n");
+    }
+}
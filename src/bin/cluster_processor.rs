@@ -1,21 +1,59 @@
 use std::fs::{self, File};
 use std::io::{BufReader, BufWriter, Write, BufRead};
-use std::path::Path;
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::collections::{btree_map::Entry, BTreeMap, HashMap};
 use indicatif::{ProgressBar, ProgressStyle};
-use serde::{Deserialize, Serialize};
-use hnsw_rust::{HnswIndex, VectorItem, EuclideanDistance};
+use hnsw_rust::cluster::{
+    Agglomerative, ClusterQuality, Dbscan, Dendrogram, KMeans, Linkage, MiniBatchKMeans, OnlineKMeans,
+    QualityScorer,
+};
 
 #[derive(Debug)]
 struct Args {
     input: String,
     output: String,
     clusters: usize,
+    /// Set by `--mini-batch <size>`: stream the input in batches of this
+    /// many vectors instead of loading it all.
+    mini_batch: Option<usize>,
+    /// Set by `--dbscan <eps> <min_points>`: cluster by density instead of
+    /// k-means, ignoring the cluster count.
+    dbscan: Option<(f64, usize)>,
+    /// Set by `--agglomerative <single|complete|average> <cut_height>`:
+    /// cluster hierarchically and cut the dendrogram at that height.
+    agglomerative: Option<(Linkage, f64)>,
+    /// Set by `--online`: assign vectors as they are read, so the input can
+    /// be an unbounded stream (`-` reads standard input).
+    online: bool,
+}
+
+// Removes `flag` and the `values` arguments after it, returning those values
+fn take_flag(args: &mut Vec<String>, flag: &str, values: usize) -> Option<Vec<String>> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    let end = (i + 1 + values).min(args.len());
+    Some(args.drain(i..end).skip(1).collect())
 }
 
 impl Args {
     fn from_env() -> Self {
-        let args: Vec<String> = std::env::args().collect();
+        let mut args: Vec<String> = std::env::args().collect();
+        let mini_batch = take_flag(&mut args, "--mini-batch", 1)
+            .map(|values| values.first().and_then(|s| s.parse().ok()).unwrap_or(10_000));
+        let dbscan = take_flag(&mut args, "--dbscan", 2).map(|values| {
+            let eps = values.first().and_then(|s| s.parse().ok()).unwrap_or(1.0);
+            let min_points = values.get(1).and_then(|s| s.parse().ok()).unwrap_or(5);
+            (eps, min_points)
+        });
+        let online = take_flag(&mut args, "--online", 0).is_some();
+        let agglomerative = take_flag(&mut args, "--agglomerative", 2).map(|values| {
+            let linkage = match values.first().map(String::as_str) {
+                Some("single") => Linkage::Single,
+                Some("complete") => Linkage::Complete,
+                _ => Linkage::Average,
+            };
+            let height = values.get(1).and_then(|s| s.parse().ok()).unwrap_or(1.0);
+            (linkage, height)
+        });
         
         Args {
             input: args.get(1)
@@ -27,20 +65,104 @@ impl Args {
             clusters: args.get(3)
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(10),
+            mini_batch,
+            dbscan,
+            agglomerative,
+            online,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct VectorFile {
-    vectors: Vec<Vec<f64>>,
-    filenames: Vec<String>,
+fn progress_bar(len: u64) -> ProgressBar {
+    let pb = ProgressBar::new(len);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+        .unwrap()
+        .progress_chars("=>-"));
+    pb
+}
+
+// Sorted so ids (and therefore clusters) don't depend on directory order
+fn input_files(dir_path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir_path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+    Ok(paths)
+}
+
+fn file_name(file_path: &Path) -> String {
+    file_path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn parse_vector(line: &str) -> Vec<f64> {
+    line.split_whitespace()
+        .filter_map(|s| s.parse().ok())
+        .collect()
+}
+
+fn write_vector(writer: &mut impl Write, vector: &[f64], filename: &str) -> std::io::Result<()> {
+    for component in vector {
+        write!(writer, "{:.6} ", component)?;
+    }
+    writeln!(writer, "| {}", filename)
+}
+
+fn quality_lines(quality: &ClusterQuality) -> [String; 3] {
+    [
+        format!("Inertia: {:.6}", quality.inertia),
+        format!("Silhouette (higher is better): {:.4}", quality.silhouette),
+        format!("Davies-Bouldin (lower is better): {:.4}", quality.davies_bouldin),
+    ]
+}
+
+fn write_header(writer: &mut impl Write, cluster_id: usize, size: usize) -> std::io::Result<()> {
+    writeln!(writer, "# Cluster {} - {} vectors", cluster_id, size)?;
+    writeln!(writer, "# Format: vector_components | original_filename")
+}
+
+// `summary` lines go between the totals and the cluster sizes
+fn write_stats(
+    output_dir: &Path,
+    total: usize,
+    k_clusters: usize,
+    summary: &[String],
+    mut sizes: Vec<(usize, usize)>,
+) -> std::io::Result<()> {
+    let stats_path = output_dir.join("cluster_stats.txt");
+    let mut stats_writer = BufWriter::new(File::create(stats_path)?);
+    writeln!(stats_writer, "Clustering Statistics")?;
+    writeln!(stats_writer, "--------------------")?;
+    writeln!(stats_writer, "Total vectors: {}", total)?;
+    writeln!(stats_writer, "Number of clusters: {}", k_clusters)?;
+    for line in summary {
+        writeln!(stats_writer, "{}", line)?;
+    }
+    writeln!(stats_writer, "\nCluster sizes:")?;
+
+    // Stable sort, so equal sizes stay in cluster id order
+    sizes.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+
+    for (cluster_id, size) in sizes {
+        writeln!(stats_writer, "Cluster {:5}: {:6} vectors", cluster_id, size)?;
+    }
+
+    Ok(())
 }
 
 struct ClusterProcessor {
-    index: HnswIndex,
     vector_map: HashMap<usize, (Vec<f64>, String)>, 
-    cluster_map: HashMap<usize, Vec<usize>>,        
+    cluster_map: BTreeMap<usize, Vec<usize>>,
+    // Vectors in no cluster, written to noise.txt
+    noise: Vec<usize>,
+    // Written to dendrogram.txt when clustering hierarchically
+    dendrogram: Option<Dendrogram>,
+    // Lines for cluster_stats.txt describing the run
+    summary: Vec<String>,
     processed_count: usize,
     k_clusters: usize,
 }
@@ -48,30 +170,24 @@ struct ClusterProcessor {
 impl ClusterProcessor {
     fn new(k_clusters: usize) -> Self {
         ClusterProcessor {
-            index: HnswIndex::new(Box::new(EuclideanDistance)),
             vector_map: HashMap::new(),
-            cluster_map: HashMap::new(),
+            cluster_map: BTreeMap::new(),
+            noise: Vec::new(),
+            dendrogram: None,
+            summary: Vec::new(),
             processed_count: 0,
             k_clusters,
         }
     }
 
     fn process_directory(&mut self, dir_path: &Path) -> std::io::Result<()> {
-        let total_files = fs::read_dir(dir_path)?.count();
-        let pb = ProgressBar::new(total_files as u64);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-            .unwrap()
-            .progress_chars("=>-"));
-
-        for entry in fs::read_dir(dir_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                self.process_file(&path)?;
-                pb.inc(1);
-                pb.set_message(format!("File: {}", path.display()));
-            }
+        let paths = input_files(dir_path)?;
+        let pb = progress_bar(paths.len() as u64);
+
+        for path in paths {
+            self.process_file(&path)?;
+            pb.inc(1);
+            pb.set_message(format!("File: {}", path.display()));
         }
         pb.finish_with_message("Directory processing complete");
 
@@ -81,27 +197,14 @@ impl ClusterProcessor {
     fn process_file(&mut self, file_path: &Path) -> std::io::Result<()> {
         let file = File::open(file_path)?;
         let reader = BufReader::new(file);
-        let filename = file_path.file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .into_owned();
+        let filename = file_name(file_path);
 
         for line in reader.lines() {
-            let line = line?;
-            let vector: Vec<f64> = line
-                .split_whitespace()
-                .filter_map(|s| s.parse().ok())
-                .collect();
+            let vector = parse_vector(&line?);
 
             if !vector.is_empty() {
                 let id = self.processed_count;
-                self.vector_map.insert(id, (vector.clone(), filename.clone()));
-                
-                self.index.add(VectorItem {
-                    id,
-                    vector: vector.clone(),
-                }).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
+                self.vector_map.insert(id, (vector, filename.clone()));
                 self.processed_count += 1;
             }
         }
@@ -111,40 +214,101 @@ impl ClusterProcessor {
 
     fn cluster_vectors(&mut self) -> std::io::Result<()> {
         println!("\nClustering {} vectors into {} clusters...", self.processed_count, self.k_clusters);
-        let pb = ProgressBar::new(self.processed_count as u64);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-            .unwrap()
-            .progress_chars("=>-"));
-
-        // Initialize cluster centers
-        for i in 0..self.k_clusters {
-            if let Some((_vector, _)) = self.vector_map.get(&i) {
-                self.cluster_map.insert(i, vec![i]);
-                pb.inc(1);
-            }
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap());
+        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+        pb.set_message("Running k-means");
+
+        // Ids are assigned densely from 0, so position i holds vector i
+        let vectors: Vec<Vec<f64>> = (0..self.processed_count)
+            .map(|id| self.vector_map[&id].0.clone())
+            .collect();
+        let clustering = KMeans::new(self.k_clusters)
+            .fit(&vectors)
+            .map_err(std::io::Error::other)?;
+
+        for (id, &cluster_id) in clustering.assignments.iter().enumerate() {
+            self.cluster_map.entry(cluster_id)
+                .or_default()
+                .push(id);
         }
+        pb.set_message("Scoring clusters");
+        let quality = clustering.quality(&vectors).map_err(std::io::Error::other)?;
+        pb.finish_with_message(format!("Clustering complete after {} iterations", clustering.iterations));
 
-        // Assign vectors to clusters
-        for id in self.k_clusters..self.processed_count {
-            if let Some((vector, _)) = self.vector_map.get(&id) {
-                let query = VectorItem {
-                    id,
-                    vector: vector.clone(),
-                };
-
-                if let Ok(nearest) = self.index.search(&query, 1) {
-                    if let Some(nearest) = nearest.first() {
-                        let cluster_id = nearest.id % self.k_clusters;
-                        self.cluster_map.entry(cluster_id)
-                            .or_insert_with(Vec::new)
-                            .push(id);
-                    }
-                }
+        self.summary = vec![
+            format!("Iterations: {}", clustering.iterations),
+            format!("Converged: {}", clustering.converged),
+        ];
+        self.summary.extend(quality_lines(&quality));
+
+        Ok(())
+    }
+
+    fn cluster_dbscan(&mut self, eps: f64, min_points: usize) -> std::io::Result<()> {
+        println!("\nClustering {} vectors by density (eps {}, min points {})...", self.processed_count, eps, min_points);
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap());
+        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+        pb.set_message("Running DBSCAN");
+
+        let vectors: Vec<Vec<f64>> = (0..self.processed_count)
+            .map(|id| self.vector_map[&id].0.clone())
+            .collect();
+        let clustering = Dbscan::new(eps, min_points)
+            .fit(&vectors)
+            .map_err(std::io::Error::other)?;
+
+        for (id, label) in clustering.labels.iter().enumerate() {
+            match label {
+                Some(cluster_id) => self.cluster_map.entry(*cluster_id).or_default().push(id),
+                None => self.noise.push(id),
             }
-            pb.inc(1);
         }
-        pb.finish_with_message("Clustering complete");
+        pb.finish_with_message(format!("Found {} clusters", clustering.clusters));
+
+        self.k_clusters = clustering.clusters;
+        self.summary = vec![
+            format!("Algorithm: DBSCAN (eps {}, min points {})", eps, min_points),
+            format!("Noise vectors: {}", self.noise.len()),
+        ];
+
+        Ok(())
+    }
+
+    fn cluster_agglomerative(&mut self, linkage: Linkage, height: f64) -> std::io::Result<()> {
+        println!("\nClustering {} vectors hierarchically ({:?} linkage, cut at {})...", self.processed_count, linkage, height);
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap());
+        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+        pb.set_message("Merging clusters");
+
+        let vectors: Vec<Vec<f64>> = (0..self.processed_count)
+            .map(|id| self.vector_map[&id].0.clone())
+            .collect();
+        let dendrogram = Agglomerative::new(linkage)
+            .fit(&vectors)
+            .map_err(std::io::Error::other)?;
+
+        for (id, cluster_id) in dendrogram.cut(height).into_iter().enumerate() {
+            self.cluster_map.entry(cluster_id)
+                .or_default()
+                .push(id);
+        }
+        pb.finish_with_message(format!("Found {} clusters", self.cluster_map.len()));
+
+        self.k_clusters = self.cluster_map.len();
+        self.summary = vec![
+            format!("Algorithm: agglomerative ({:?} linkage)", linkage),
+            format!("Cut height: {}", height),
+        ];
+        self.dendrogram = Some(dendrogram);
 
         Ok(())
     }
@@ -153,52 +317,232 @@ impl ClusterProcessor {
         fs::create_dir_all(output_dir)?;
         println!("\nWriting clusters to {}", output_dir.display());
         
-        let pb = ProgressBar::new(self.k_clusters as u64);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-            .unwrap()
-            .progress_chars("=>-"));
+        let pb = progress_bar(self.k_clusters as u64);
 
         for (cluster_id, vector_ids) in &self.cluster_map {
             let output_path = output_dir.join(format!("cluster_{:05}.txt", cluster_id));
             let mut writer = BufWriter::new(File::create(&output_path)?);
 
-            writeln!(writer, "# Cluster {} - {} vectors", cluster_id, vector_ids.len())?;
-            writeln!(writer, "# Format: vector_components | original_filename")?;
+            write_header(&mut writer, *cluster_id, vector_ids.len())?;
 
             for &id in vector_ids {
                 if let Some((vector, filename)) = self.vector_map.get(&id) {
-                    // Write vector components
-                    for component in vector {
-                        write!(writer, "{:.6} ", component)?;
-                    }
-                    writeln!(writer, "| {}", filename)?;
+                    write_vector(&mut writer, vector, filename)?;
                 }
             }
             pb.inc(1);
         }
+        if !self.noise.is_empty() {
+            let mut writer = BufWriter::new(File::create(output_dir.join("noise.txt"))?);
+            writeln!(writer, "# Noise - {} vectors", self.noise.len())?;
+            writeln!(writer, "# Format: vector_components | original_filename")?;
+            for id in &self.noise {
+                let (vector, filename) = &self.vector_map[id];
+                write_vector(&mut writer, vector, filename)?;
+            }
+        }
+        if let Some(dendrogram) = &self.dendrogram {
+            let mut writer = BufWriter::new(File::create(output_dir.join("dendrogram.txt"))?);
+            writeln!(writer, "# {} vectors are clusters 0..{}; merge i creates cluster {} + i", dendrogram.points, dendrogram.points, dendrogram.points)?;
+            writeln!(writer, "# Format: left right distance size")?;
+            for merge in &dendrogram.merges {
+                writeln!(writer, "{} {} {:.6} {}", merge.left, merge.right, merge.distance, merge.size)?;
+            }
+        }
         pb.finish_with_message("Cluster files written");
 
-        let stats_path = output_dir.join("cluster_stats.txt");
-        let mut stats_writer = BufWriter::new(File::create(stats_path)?);
-        writeln!(stats_writer, "Clustering Statistics")?;
-        writeln!(stats_writer, "--------------------")?;
-        writeln!(stats_writer, "Total vectors: {}", self.processed_count)?;
-        writeln!(stats_writer, "Number of clusters: {}", self.k_clusters)?;
-        writeln!(stats_writer, "\nCluster sizes:")?;
-        
-        let mut sizes: Vec<_> = self.cluster_map
+        let sizes = self.cluster_map
             .iter()
             .map(|(id, vectors)| (*id, vectors.len()))
             .collect();
-        sizes.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+        write_stats(output_dir, self.processed_count, self.k_clusters, &self.summary, sizes)
+    }
+}
 
-        for (cluster_id, size) in sizes {
-            writeln!(stats_writer, "Cluster {:5}: {:6} vectors", cluster_id, size)?;
+// Calls `f` with each batch of up to `batch_size` vectors read from `paths`,
+// and the files they came from.
+fn for_each_batch(
+    paths: &[PathBuf],
+    batch_size: usize,
+    message: &str,
+    mut f: impl FnMut(&[Vec<f64>], &[String]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let pb = progress_bar(paths.len() as u64);
+    pb.set_message(message.to_string());
+    let mut vectors = Vec::with_capacity(batch_size);
+    let mut filenames = Vec::with_capacity(batch_size);
+    for path in paths {
+        let filename = file_name(path);
+        for line in BufReader::new(File::open(path)?).lines() {
+            let vector = parse_vector(&line?);
+            if vector.is_empty() {
+                continue;
+            }
+            vectors.push(vector);
+            filenames.push(filename.clone());
+            if vectors.len() == batch_size {
+                f(&vectors, &filenames)?;
+                vectors.clear();
+                filenames.clear();
+            }
         }
+        pb.inc(1);
+    }
+    if !vectors.is_empty() {
+        f(&vectors, &filenames)?;
+    }
+    pb.finish_with_message(format!("{} complete", message));
+    Ok(())
+}
+
+/// Clusters with mini-batch k-means, holding only the centroids and one
+/// batch in memory. The input is read three times: to fit the centroids,
+/// to count each cluster's vectors, and to write them out.
+struct StreamingProcessor {
+    kmeans: MiniBatchKMeans,
+    batch_size: usize,
+    k_clusters: usize,
+}
+
+impl StreamingProcessor {
+    fn new(k_clusters: usize, batch_size: usize) -> Self {
+        StreamingProcessor {
+            kmeans: MiniBatchKMeans::new(k_clusters),
+            batch_size: batch_size.max(1),
+            k_clusters,
+        }
+    }
+
+    fn run(&mut self, input_dir: &Path, output_dir: &Path) -> std::io::Result<()> {
+        let paths = input_files(input_dir)?;
+        println!("\nClustering into {} clusters in batches of {}...", self.k_clusters, self.batch_size);
+
+        let kmeans = &mut self.kmeans;
+        for_each_batch(&paths, self.batch_size, "Fitting centroids", |vectors, _| {
+            kmeans.partial_fit(vectors).map_err(std::io::Error::other)
+        })?;
+
+        let mut sizes = vec![0usize; self.kmeans.centroids().len()];
+        let mut scorer = QualityScorer::new(self.kmeans.centroids()).map_err(std::io::Error::other)?;
+        for_each_batch(&paths, self.batch_size, "Assigning vectors", |vectors, _| {
+            let assignments = self.kmeans.predict(vectors).map_err(std::io::Error::other)?;
+            for &cluster_id in &assignments {
+                sizes[cluster_id] += 1;
+            }
+            scorer.add(vectors, &assignments).map_err(std::io::Error::other)
+        })?;
+
+        fs::create_dir_all(output_dir)?;
+        println!("\nWriting clusters to {}", output_dir.display());
+        let mut writers = BTreeMap::new();
+        for (cluster_id, &size) in sizes.iter().enumerate().filter(|(_, &size)| size > 0) {
+            let output_path = output_dir.join(format!("cluster_{:05}.txt", cluster_id));
+            let mut writer = BufWriter::new(File::create(&output_path)?);
+            write_header(&mut writer, cluster_id, size)?;
+            writers.insert(cluster_id, writer);
+        }
+        for_each_batch(&paths, self.batch_size, "Writing clusters", |vectors, filenames| {
+            let assignments = self.kmeans.predict(vectors).map_err(std::io::Error::other)?;
+            for ((vector, filename), cluster_id) in vectors.iter().zip(filenames).zip(assignments) {
+                write_vector(writers.get_mut(&cluster_id).unwrap(), vector, filename)?;
+            }
+            Ok(())
+        })?;
+        for writer in writers.values_mut() {
+            writer.flush()?;
+        }
+
+        let mut summary = vec![format!("Mini-batch size: {}", self.batch_size)];
+        summary.extend(quality_lines(&scorer.finish()));
+        let total = sizes.iter().sum();
+        let sizes = sizes.into_iter().enumerate().filter(|&(_, size)| size > 0).collect();
+        write_stats(output_dir, total, self.k_clusters, &summary, sizes)
+    }
+}
+
+// The online processor rewrites cluster_stats.txt every this many vectors
+const STATS_EVERY: usize = 100_000;
+
+/// Clusters with sequential k-means, writing each vector to its cluster file
+/// as soon as it is read. Nothing but the centroids is kept, so the input
+/// may be endless; cluster_stats.txt is kept up to date as it goes.
+struct OnlineProcessor {
+    kmeans: OnlineKMeans,
+    writers: BTreeMap<usize, BufWriter<File>>,
+    output_dir: PathBuf,
+    k_clusters: usize,
+    processed_count: usize,
+    pb: ProgressBar,
+}
 
+impl OnlineProcessor {
+    fn new(k_clusters: usize, output_dir: &Path) -> Self {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {pos} vectors ({per_sec}) {msg}")
+            .unwrap());
+        OnlineProcessor {
+            kmeans: OnlineKMeans::new(k_clusters),
+            writers: BTreeMap::new(),
+            output_dir: output_dir.to_path_buf(),
+            k_clusters,
+            processed_count: 0,
+            pb,
+        }
+    }
+
+    fn run(&mut self, input: &str) -> std::io::Result<()> {
+        fs::create_dir_all(&self.output_dir)?;
+        println!("\nClustering into {} clusters as vectors arrive...", self.k_clusters);
+        if input == "-" {
+            self.process_lines(std::io::stdin().lock(), "stdin")?;
+        } else {
+            for path in input_files(Path::new(input))? {
+                self.process_lines(BufReader::new(File::open(&path)?), &file_name(&path))?;
+            }
+        }
+        self.write_stats()?;
+        self.pb.finish_with_message("Clustering complete");
         Ok(())
     }
+
+    fn process_lines(&mut self, reader: impl BufRead, filename: &str) -> std::io::Result<()> {
+        for line in reader.lines() {
+            let vector = parse_vector(&line?);
+            if vector.is_empty() {
+                continue;
+            }
+            let cluster_id = self.kmeans.update(&vector).map_err(std::io::Error::other)?;
+            let writer = match self.writers.entry(cluster_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let output_path = self.output_dir.join(format!("cluster_{:05}.txt", cluster_id));
+                    let mut writer = BufWriter::new(File::create(&output_path)?);
+                    writeln!(writer, "# Cluster {} - streamed, see cluster_stats.txt for its size", cluster_id)?;
+                    writeln!(writer, "# Format: vector_components | original_filename")?;
+                    entry.insert(writer)
+                }
+            };
+            write_vector(writer, &vector, filename)?;
+
+            self.processed_count += 1;
+            self.pb.inc(1);
+            if self.processed_count.is_multiple_of(STATS_EVERY) {
+                self.write_stats()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes the cluster files, then rewrites the stats to match them
+    fn write_stats(&mut self) -> std::io::Result<()> {
+        for writer in self.writers.values_mut() {
+            writer.flush()?;
+        }
+        let summary = ["Algorithm: online k-means".to_string()];
+        let sizes = self.kmeans.counts().iter().copied().enumerate().collect();
+        write_stats(&self.output_dir, self.processed_count, self.k_clusters, &summary, sizes)
+    }
 }
 
 fn main() {
@@ -209,7 +553,28 @@ fn main() {
     println!("Input directory:  {}", args.input);
     println!("Output directory: {}", args.output);
     println!("Number of clusters: {}", args.clusters);
-    println!("\nUse: cargo run --bin cluster-processor <input_dir> <output_dir> <num_clusters>");
+    println!("\nUse: cargo run --bin cluster-processor <input_dir> <output_dir> <num_clusters> [--mini-batch <size>]");
+    println!("     cargo run --bin cluster-processor <input_dir> <output_dir> --dbscan <eps> <min_points>");
+    println!("     cargo run --bin cluster-processor <input_dir> <output_dir> --agglomerative <single|complete|average> <cut_height>");
+    println!("     cargo run --bin cluster-processor <input_dir|-> <output_dir> <num_clusters> --online");
+
+    if args.online {
+        let mut processor = OnlineProcessor::new(args.clusters, Path::new(&args.output));
+        match processor.run(&args.input) {
+            Ok(()) => println!("\nProcessing complete! Check {} for results", args.output),
+            Err(e) => eprintln!("Error during clustering: {}", e),
+        }
+        return;
+    }
+
+    if let Some(batch_size) = args.mini_batch {
+        let mut processor = StreamingProcessor::new(args.clusters, batch_size);
+        match processor.run(Path::new(&args.input), Path::new(&args.output)) {
+            Ok(()) => println!("\nProcessing complete! Check {} for results", args.output),
+            Err(e) => eprintln!("Error during clustering: {}", e),
+        }
+        return;
+    }
 
     let mut processor = ClusterProcessor::new(args.clusters);
 
@@ -218,7 +583,12 @@ fn main() {
         return;
     }
 
-    if let Err(e) = processor.cluster_vectors() {
+    let clustered = match (args.dbscan, args.agglomerative) {
+        (Some((eps, min_points)), _) => processor.cluster_dbscan(eps, min_points),
+        (None, Some((linkage, height))) => processor.cluster_agglomerative(linkage, height),
+        (None, None) => processor.cluster_vectors(),
+    };
+    if let Err(e) = clustered {
         eprintln!("Error during clustering: {}", e);
         return;
     }
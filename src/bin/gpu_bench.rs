@@ -0,0 +1,94 @@
+// src/bin/gpu_bench.rs
+//
+// Builds the same index with `EuclideanDistance` and with `GpuDistance`,
+// then runs the same batch of queries against both, and reports the build
+// time, query throughput and how many GPU results match the CPU ones.
+// Whether the GPU wins depends on the hardware, the dimension and how many
+// threads feed it:
+//
+//     cargo run --release --features gpu --bin gpu-bench <items> <dimensions> <queries>
+use hnsw_rust::gpu::GpuDistance;
+use hnsw_rust::{DistanceCalculator, EuclideanDistance, HnswIndex, VectorItem};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::process;
+use std::time::{Duration, Instant};
+
+const K: usize = 10;
+const SEED: u64 = 42;
+
+struct Args {
+    items: usize,
+    dimensions: usize,
+    queries: usize,
+}
+
+impl Args {
+    fn from_env() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        Args {
+            items: args.get(1).and_then(|s| s.parse().ok()).unwrap_or(20_000),
+            dimensions: args.get(2).and_then(|s| s.parse().ok()).unwrap_or(1536),
+            queries: args.get(3).and_then(|s| s.parse().ok()).unwrap_or(1_000),
+        }
+    }
+}
+
+fn random_items(rng: &mut StdRng, count: usize, dim: usize) -> Vec<VectorItem> {
+    (0..count).map(|id| VectorItem { id, vector: (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect() }).collect()
+}
+
+// Build time, search time and the results of `queries`
+fn run(
+    calculator: Box<dyn DistanceCalculator + Send + Sync>,
+    items: &[VectorItem],
+    queries: &[VectorItem],
+) -> Result<(Duration, Duration, Vec<Vec<usize>>), String> {
+    let index = HnswIndex::new(calculator).with_seed(SEED);
+    let start = Instant::now();
+    index.par_batch_add(items.to_vec())?;
+    let build = start.elapsed();
+    let start = Instant::now();
+    let results = index.batch_search(queries, K)?;
+    let search = start.elapsed();
+    Ok((build, search, results.iter().map(|results| results.iter().map(|item| item.id).collect()).collect()))
+}
+
+fn main() {
+    let args = Args::from_env();
+    let gpu = match GpuDistance::new() {
+        Ok(gpu) => gpu,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    println!("adapter: {}", gpu.adapter());
+    println!("{} items, {} dimensions, {} queries, {} threads", args.items, args.dimensions, args.queries, rayon::current_num_threads());
+
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let items = random_items(&mut rng, args.items, args.dimensions);
+    let queries = random_items(&mut rng, args.queries, args.dimensions);
+
+    let mut runs = Vec::new();
+    for (name, calculator) in [
+        ("cpu", Box::new(EuclideanDistance) as Box<dyn DistanceCalculator + Send + Sync>),
+        ("gpu", Box::new(gpu)),
+    ] {
+        let (build, search, results) = run(calculator, &items, &queries).unwrap_or_else(|e| {
+            eprintln!("{}: {}", name, e);
+            process::exit(1);
+        });
+        println!(
+            "{}: build {:.2}s, search {:.0} queries/s",
+            name,
+            build.as_secs_f64(),
+            args.queries as f64 / search.as_secs_f64()
+        );
+        runs.push(results);
+    }
+
+    let (cpu, gpu) = (&runs[0], &runs[1]);
+    let shared: usize = cpu.iter().zip(gpu).map(|(a, b)| a.iter().filter(|id| b.contains(id)).count()).sum();
+    let total: usize = cpu.iter().map(Vec::len).sum();
+    println!("gpu results matching cpu: {:.1}%", 100.0 * shared as f64 / total.max(1) as f64);
+}
@@ -0,0 +1,151 @@
+// src/bin/hnsw.rs
+//
+// Command-line front end for building, querying and inspecting saved
+// indexes. Vector files are recognized by extension: .fvecs, .bvecs, .npy,
+// .csv (`id,v0,v1,...`) and .jsonl (`{"id": .., "vector": [..]}`).
+//
+//     hnsw build <vectors> <index> [--seed N]
+//     hnsw search <index> <queries> [k]
+//     hnsw stats <index>
+//     hnsw convert <input> <output>
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use indicatif::{ProgressBar, ProgressStyle};
+use hnsw_rust::{io as vecio, DistanceCalculator, EuclideanDistance, HnswIndex, VectorItem};
+
+const USAGE: &str = "Use:
+    hnsw build <vectors> <index> [--seed N]
+    hnsw search <index> <queries> [k]
+    hnsw stats <index>
+    hnsw convert <input> <output>
+
+Vector files: .fvecs, .bvecs, .npy, .csv, .jsonl";
+
+// Vectors are read and inserted this many at a time
+const BATCH: usize = 10_000;
+
+type Items = Box<dyn Iterator<Item = io::Result<VectorItem>>>;
+
+fn extension(path: &str) -> &str {
+    Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or("")
+}
+
+fn unsupported(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported vector file {:?}", path))
+}
+
+/// Streams the vectors of `path`, picking the reader by extension.
+fn read_items(path: &str) -> io::Result<Items> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(match extension(path) {
+        "fvecs" => Box::new(vecio::fvecs(reader)),
+        "bvecs" => Box::new(vecio::bvecs(reader)),
+        "csv" => Box::new(vecio::csv(reader)),
+        "jsonl" => Box::new(vecio::jsonl(reader)),
+        "npy" => Box::new(vecio::read_npy(reader)?.into_iter().map(Ok)),
+        _ => return Err(unsupported(path)),
+    })
+}
+
+fn write_items(path: &str, items: &[VectorItem]) -> io::Result<()> {
+    let write = match extension(path) {
+        "fvecs" => vecio::write_fvecs,
+        "bvecs" => vecio::write_bvecs,
+        "csv" => vecio::write_csv,
+        "jsonl" => vecio::write_jsonl,
+        "npy" => vecio::write_npy,
+        _ => return Err(unsupported(path)),
+    };
+    write(&mut BufWriter::new(File::create(path)?), items)
+}
+
+fn build(input: &str, output: &str, seed: Option<u64>) -> io::Result<()> {
+    let mut index = HnswIndex::new(Box::new(EuclideanDistance));
+    if let Some(seed) = seed {
+        index = index.with_seed(seed);
+    }
+    let progress = ProgressBar::new_spinner();
+    progress.set_style(ProgressStyle::default_spinner()
+        .template("{spinner:.green} [{elapsed_precise}] {pos} vectors ({per_sec})")
+        .unwrap());
+
+    let mut items = read_items(input)?.peekable();
+    while items.peek().is_some() {
+        let batch = items.by_ref().take(BATCH).collect::<io::Result<Vec<_>>>()?;
+        let len = batch.len() as u64;
+        index.par_batch_add(batch).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        progress.inc(len);
+    }
+    progress.finish();
+    index.save(output)?;
+    println!("Saved {} vectors to {}", index.len(), output);
+    Ok(())
+}
+
+fn search(index: &str, queries: &str, k: usize) -> io::Result<()> {
+    let index = HnswIndex::load(index)?;
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for query in read_items(queries)? {
+        let query = query?;
+        let results = index.search(&query, k).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        write!(out, "{}", query.id)?;
+        for result in &results {
+            write!(out, "\t{}:{:.6}", result.id, EuclideanDistance.distance(&query.vector, &result.vector))?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+fn stats(index: &str) -> io::Result<()> {
+    let index = HnswIndex::load(index)?;
+    let stats = index.get_stats();
+    println!("{}", index.config());
+    println!("nodes: {}", stats.total_nodes);
+    println!("connections: {}", stats.total_connections);
+    println!("isolated nodes: {}", stats.isolated_nodes);
+    println!("layer 0 components: {}", stats.components);
+    for (level, degrees) in stats.layer_degrees.iter().enumerate() {
+        println!("layer {}: {} nodes, degree avg {:.2} max {}", level, degrees.nodes, degrees.average, degrees.max);
+    }
+    Ok(())
+}
+
+fn convert(input: &str, output: &str) -> io::Result<()> {
+    let items = read_items(input)?.collect::<io::Result<Vec<_>>>()?;
+    write_items(output, &items)?;
+    println!("Wrote {} vectors to {}", items.len(), output);
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["build", input, output] => build(input, output, None),
+        ["build", input, output, "--seed", seed] => match seed.parse() {
+            Ok(seed) => build(input, output, Some(seed)),
+            Err(_) => usage(),
+        },
+        ["search", index, queries] => search(index, queries, 10),
+        ["search", index, queries, k] => match k.parse() {
+            Ok(k) => search(index, queries, k),
+            Err(_) => usage(),
+        },
+        ["stats", index] => stats(index),
+        ["convert", input, output] => convert(input, output),
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,138 @@
+// src/bin/hnsw_server.rs
+//
+// Serves one Euclidean index over gRPC (see proto/hnsw.proto). The index is
+// loaded from <index-path> if the file exists and written back there by the
+// Save RPC:
+//
+//     cargo run --release --features grpc --bin hnsw_server <index-path> [addr]
+//
+// The address defaults to 127.0.0.1:50051.
+use std::path::PathBuf;
+use std::process;
+use hnsw_rust::{AsyncIndex, DistanceCalculator, EuclideanDistance, HnswIndex, VectorItem};
+use tonic::{Request, Response, Status, Streaming};
+
+mod proto {
+    tonic::include_proto!("hnsw");
+}
+
+use proto::hnsw_server::{Hnsw, HnswServer};
+use proto::{
+    BulkInsertResponse, DeleteRequest, DeleteResponse, Hit, InsertRequest, InsertResponse, SaveRequest,
+    SaveResponse, SearchRequest, SearchResponse,
+};
+
+// Streamed inserts are added with `par_batch_add` this many at a time
+const BULK_BATCH: usize = 10_000;
+
+struct Server {
+    index: AsyncIndex,
+    path: PathBuf,
+}
+
+impl Server {
+    async fn run<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&HnswIndex) -> Result<T, Status> + Send + 'static,
+    ) -> Result<T, Status> {
+        self.index.run(op).await.map_err(Status::internal)?
+    }
+}
+
+fn item(request: InsertRequest) -> VectorItem {
+    VectorItem { id: request.id as usize, vector: request.vector }
+}
+
+#[tonic::async_trait]
+impl Hnsw for Server {
+    async fn insert(&self, request: Request<InsertRequest>) -> Result<Response<InsertResponse>, Status> {
+        let item = item(request.into_inner());
+        self.run(move |index| index.add(item).map_err(Status::invalid_argument)).await?;
+        Ok(Response::new(InsertResponse {}))
+    }
+
+    async fn bulk_insert(
+        &self,
+        request: Request<Streaming<InsertRequest>>,
+    ) -> Result<Response<BulkInsertResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut inserted = 0;
+        let mut batch = Vec::with_capacity(BULK_BATCH);
+        loop {
+            let message = stream.message().await?;
+            let done = message.is_none();
+            batch.extend(message.map(item));
+            if batch.len() >= BULK_BATCH || (done && !batch.is_empty()) {
+                let items = std::mem::replace(&mut batch, Vec::with_capacity(BULK_BATCH));
+                let count = items.len() as u64;
+                self.run(move |index| index.par_batch_add(items).map_err(Status::invalid_argument)).await?;
+                inserted += count;
+            }
+            if done {
+                return Ok(Response::new(BulkInsertResponse { inserted }));
+            }
+        }
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<DeleteResponse>, Status> {
+        let id = request.into_inner().id as usize;
+        let removed = self.run(move |index| index.remove(id).map_err(Status::internal)).await?;
+        Ok(Response::new(DeleteResponse { removed }))
+    }
+
+    async fn search(&self, request: Request<SearchRequest>) -> Result<Response<SearchResponse>, Status> {
+        let request = request.into_inner();
+        let query = VectorItem { id: 0, vector: request.vector };
+        let k = request.k as usize;
+        let hits = self
+            .run(move |index| {
+                let results = index.search(&query, k).map_err(Status::invalid_argument)?;
+                Ok(results
+                    .iter()
+                    .map(|item| Hit { id: item.id as u64, distance: EuclideanDistance.distance(&query.vector, &item.vector) })
+                    .collect())
+            })
+            .await?;
+        Ok(Response::new(SearchResponse { hits }))
+    }
+
+    async fn save(&self, _request: Request<SaveRequest>) -> Result<Response<SaveResponse>, Status> {
+        let path = self.path.clone();
+        self.run(move |index| index.save(&path).map_err(|e| Status::internal(e.to_string()))).await?;
+        Ok(Response::new(SaveResponse {}))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(path) = args.get(1).map(PathBuf::from) else {
+        eprintln!("Use: hnsw_server <index-path> [addr]");
+        process::exit(2);
+    };
+    let addr = match args.get(2).map_or("127.0.0.1:50051", String::as_str).parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            eprintln!("Invalid address: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let index = match path.exists() {
+        true => match HnswIndex::load(&path) {
+            Ok(index) => index,
+            Err(e) => {
+                eprintln!("Error loading {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        false => HnswIndex::new(Box::new(EuclideanDistance)),
+    };
+    println!("Serving {} vectors from {} on {}", index.len(), path.display(), addr);
+
+    let server = Server { index: AsyncIndex::new(index), path };
+    if let Err(e) = tonic::transport::Server::builder().add_service(HnswServer::new(server)).serve(addr).await {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
+    }
+}
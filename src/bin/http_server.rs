@@ -0,0 +1,164 @@
+// src/bin/http_server.rs
+//
+// Serves one Euclidean index over HTTP with JSON bodies, loading it from
+// [index-path] if given:
+//
+//     cargo run --release --features http --bin http_server [addr] [index-path]
+//
+//     POST /vectors  [{"id": 1, "vector": [0.1, 0.2], "payload": {...}}, ...]
+//                    -> {"inserted": 1}   ("payload" is optional)
+//     POST /search   {"vector": [0.1, 0.2], "k": 10}
+//                    -> {"hits": [{"id": 1, "distance": 0.0, "payload": {...}}]}
+//     GET  /stats    -> vector count, layer distribution and counters
+//
+// The address defaults to 127.0.0.1:8080. Errors come back as
+// {"error": "..."} with status 400.
+use std::collections::BTreeMap;
+use std::process;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use hnsw_rust::{AsyncIndex, DistanceCalculator, EuclideanDistance, HnswIndex, VectorItem};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+type Index = AsyncIndex;
+type ApiError = (StatusCode, Json<Value>);
+
+#[derive(Deserialize)]
+struct NewVector {
+    id: usize,
+    vector: Vec<f64>,
+    #[serde(default)]
+    payload: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct SearchBody {
+    vector: Vec<f64>,
+    #[serde(default = "default_k")]
+    k: usize,
+}
+
+fn default_k() -> usize {
+    10
+}
+
+#[derive(Serialize)]
+struct Hit {
+    id: usize,
+    distance: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    vectors: usize,
+    dimension: Option<usize>,
+    metric: String,
+    level_distribution: BTreeMap<usize, usize>,
+    total_connections: usize,
+    inserts: u64,
+    updates: u64,
+    searches: u64,
+    search_seconds: f64,
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message.into() })))
+}
+
+async fn run<T: Send + 'static>(
+    index: Index,
+    op: impl FnOnce(&HnswIndex) -> Result<T, String> + Send + 'static,
+) -> Result<T, ApiError> {
+    index
+        .run(op)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e }))))?
+        .map_err(bad_request)
+}
+
+async fn add_vectors(State(index): State<Index>, Json(vectors): Json<Vec<NewVector>>) -> Result<Json<Value>, ApiError> {
+    let inserted = vectors.len();
+    run(index, move |index| {
+        // Payloads need one insert each; the rest go in one parallel batch
+        let (with_payload, plain): (Vec<_>, Vec<_>) = vectors.into_iter().partition(|v| v.payload.is_some());
+        index.par_batch_add(plain.into_iter().map(|v| VectorItem { id: v.id, vector: v.vector }).collect())?;
+        for v in with_payload {
+            index.add_with_payload(VectorItem { id: v.id, vector: v.vector }, v.payload.unwrap())?;
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(Json(json!({ "inserted": inserted })))
+}
+
+async fn search(State(index): State<Index>, Json(body): Json<SearchBody>) -> Result<Json<Value>, ApiError> {
+    let hits = run(index, move |index| {
+        let query = VectorItem { id: 0, vector: body.vector };
+        let results = index.search_with_payloads(&query, body.k)?;
+        Ok(results
+            .into_iter()
+            .map(|(item, payload)| Hit { id: item.id, distance: EuclideanDistance.distance(&query.vector, &item.vector), payload })
+            .collect::<Vec<_>>())
+    })
+    .await?;
+    Ok(Json(json!({ "hits": hits })))
+}
+
+async fn stats(State(index): State<Index>) -> Result<Json<Stats>, ApiError> {
+    let stats = run(index, |index| {
+        let graph = index.get_stats();
+        let metrics = index.metrics().snapshot();
+        Ok(Stats {
+            vectors: graph.total_nodes,
+            dimension: index.dimension(),
+            metric: index.config().metric,
+            level_distribution: graph.level_distribution,
+            total_connections: graph.total_connections,
+            inserts: metrics.inserts,
+            updates: metrics.updates,
+            searches: metrics.searches,
+            search_seconds: metrics.search_time.as_secs_f64(),
+        })
+    })
+    .await?;
+    Ok(Json(stats))
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let addr = args.get(1).map_or("127.0.0.1:8080", String::as_str);
+    let index = match args.get(2) {
+        Some(path) => match HnswIndex::load(path) {
+            Ok(index) => index,
+            Err(e) => {
+                eprintln!("Error loading {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => HnswIndex::new(Box::new(EuclideanDistance)),
+    };
+
+    let app = Router::new()
+        .route("/vectors", post(add_vectors))
+        .route("/search", post(search))
+        .route("/stats", get(stats))
+        .with_state(AsyncIndex::new(index));
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Cannot listen on {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+    println!("Listening on {}", addr);
+    if let Err(e) = axum::serve(listener, app).await {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,147 @@
+// src/bin/param_sweep.rs
+//
+// Builds one index per M/ef_construction pair over the same seeded random
+// data, queries each across a range of ef_search and writes one CSV row per
+// combination, for plotting recall against QPS:
+//
+//     cargo run --release --bin param-sweep [output.csv]
+//         [--vectors N] [--dim D] [--queries Q] [--k K]
+//         [--m 8,16,32] [--ef-construction 100,200] [--ef-search 10,20,40,80,160]
+//
+// Without an output path the CSV goes to stdout.
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::time::Instant;
+use hnsw_rust::{eval, EuclideanDistance, HnswIndex, VectorItem};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Seeds both the data and every index, so rows differ only by parameters
+const SEED: u64 = 42;
+
+const USAGE: &str = "Use: param-sweep [output.csv] [--vectors N] [--dim D] [--queries Q] [--k K]
+    [--m 8,16,32] [--ef-construction 100,200] [--ef-search 10,20,40,80,160]";
+
+#[derive(Debug)]
+struct Args {
+    output: Option<String>,
+    vectors: usize,
+    dim: usize,
+    queries: usize,
+    k: usize,
+    m: Vec<usize>,
+    ef_construction: Vec<usize>,
+    ef_search: Vec<usize>,
+}
+
+fn parse_list(value: &str) -> Result<Vec<usize>, String> {
+    value
+        .split(',')
+        .map(|v| v.trim().parse().map_err(|_| format!("Invalid number {:?}", v)))
+        .collect()
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut parsed = Args {
+        output: None,
+        vectors: 20_000,
+        dim: 64,
+        queries: 200,
+        k: 10,
+        m: vec![8, 16, 32],
+        ef_construction: vec![100, 200],
+        ef_search: vec![10, 20, 40, 80, 160],
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if parsed.output.replace(arg.clone()).is_some() {
+                return Err(format!("Unexpected argument {:?}", arg));
+            }
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = || value.parse::<usize>().map_err(|_| format!("Invalid value {:?} for {}", value, arg));
+        match arg.as_str() {
+            "--vectors" => parsed.vectors = number()?,
+            "--dim" => parsed.dim = number()?,
+            "--queries" => parsed.queries = number()?,
+            "--k" => parsed.k = number()?,
+            "--m" => parsed.m = parse_list(value)?,
+            "--ef-construction" => parsed.ef_construction = parse_list(value)?,
+            "--ef-search" => parsed.ef_search = parse_list(value)?,
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    if parsed.vectors == 0 || parsed.dim == 0 || parsed.queries == 0 || parsed.k == 0 {
+        return Err("--vectors, --dim, --queries and --k must be positive".to_string());
+    }
+    Ok(parsed)
+}
+
+fn random_items(rng: &mut StdRng, count: usize, dim: usize) -> Vec<VectorItem> {
+    (0..count)
+        .map(|id| VectorItem { id, vector: (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect() })
+        .collect()
+}
+
+fn sweep(args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let vectors = random_items(&mut rng, args.vectors, args.dim);
+    let queries = random_items(&mut rng, args.queries, args.dim);
+    eprintln!("Computing ground truth for {} queries...", queries.len());
+    let truth = eval::ground_truth(&vectors, &queries, args.k, &EuclideanDistance);
+
+    writeln!(out, "m,ef_construction,ef_search,build_seconds,recall,map,qps,p50_ms,p99_ms")?;
+    for &m in &args.m {
+        for &ef_construction in &args.ef_construction {
+            eprintln!("Building M={} ef_construction={}...", m, ef_construction);
+            let mut index = HnswIndex::new(Box::new(EuclideanDistance))
+                .with_seed(SEED)
+                .with_m(m)
+                .with_ef_construction(ef_construction);
+            let start = Instant::now();
+            index.par_batch_add(vectors.clone()).map_err(io::Error::other)?;
+            let build_seconds = start.elapsed().as_secs_f64();
+
+            for &ef_search in &args.ef_search {
+                index = index.with_ef_search(ef_search);
+                let metrics = eval::evaluate(&index, &queries, &truth, args.k).map_err(io::Error::other)?;
+                writeln!(
+                    out,
+                    "{},{},{},{:.3},{:.4},{:.4},{:.1},{:.4},{:.4}",
+                    m,
+                    ef_construction,
+                    ef_search,
+                    build_seconds,
+                    metrics.recall,
+                    metrics.map,
+                    metrics.qps,
+                    metrics.latency_p50.as_secs_f64() * 1000.0,
+                    metrics.latency_p99.as_secs_f64() * 1000.0,
+                )?;
+            }
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let result = match &args.output {
+        Some(path) => File::create(path).and_then(|file| sweep(&args, &mut BufWriter::new(file))),
+        None => sweep(&args, &mut BufWriter::new(io::stdout().lock())),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
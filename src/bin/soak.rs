@@ -0,0 +1,244 @@
+// src/bin/soak.rs
+//
+// Runs mixed insert/update/search traffic against one index for a long time,
+// pausing it periodically to check invariants and report memory use. Meant
+// to be run before releases to catch leaks, drift and deadlocks:
+//
+//     cargo run --release --bin soak <seconds> <threads> <dimensions>
+use std::collections::HashMap;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use hnsw_rust::{EuclideanDistance, DistanceCalculator, HnswIndex, VectorItem};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+const CHECK_EVERY: Duration = Duration::from_secs(10);
+// No operation finishing for this long is treated as a deadlock
+const STALL_LIMIT: Duration = Duration::from_secs(60);
+const IDS_PER_THREAD: usize = 5_000;
+const K: usize = 10;
+const SAMPLE: usize = 200;
+
+#[derive(Debug)]
+struct Args {
+    seconds: u64,
+    threads: usize,
+    dimensions: usize,
+}
+
+impl Args {
+    fn from_env() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        Args {
+            seconds: args.get(1).and_then(|s| s.parse().ok()).unwrap_or(60),
+            threads: args.get(2).and_then(|s| s.parse().ok()).unwrap_or(4),
+            dimensions: args.get(3).and_then(|s| s.parse().ok()).unwrap_or(16),
+        }
+    }
+}
+
+/// What the index should contain. Worker `t` owns the ids congruent to `t`
+/// modulo the thread count, so each model is only touched by one worker.
+#[derive(Default)]
+struct Model {
+    // id -> (vector, version written as the payload)
+    items: HashMap<usize, (Vec<f64>, u64)>,
+}
+
+struct Soak {
+    index: HnswIndex,
+    models: Vec<Mutex<Model>>,
+    // Workers hold it shared per operation; the checker takes it exclusively
+    // to pause traffic.
+    gate: RwLock<()>,
+    ops: AtomicU64,
+    stop: AtomicBool,
+    dimensions: usize,
+}
+
+fn random_vector(rng: &mut impl Rng, dim: usize) -> Vec<f64> {
+    (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect()
+}
+
+fn worker(soak: &Soak, thread_id: usize, threads: usize) -> Result<(), String> {
+    let mut rng = StdRng::seed_from_u64(thread_id as u64);
+    let mut next_version = 0u64;
+    while !soak.stop.load(Ordering::Relaxed) {
+        let _traffic = soak.gate.read().unwrap();
+        let mut model = soak.models[thread_id].lock().unwrap();
+        let roll = rng.gen_range(0..100);
+
+        if roll < 60 && !model.items.is_empty() {
+            let query = VectorItem { id: usize::MAX, vector: random_vector(&mut rng, soak.dimensions) };
+            let results = soak.index.search(&query, K)?;
+            if results.len() > K {
+                return Err(format!("search returned {} results for k = {}", results.len(), K));
+            }
+        } else if roll < 70 && !model.items.is_empty() {
+            let id = *model.items.keys().nth(rng.gen_range(0..model.items.len())).unwrap();
+            if !soak.index.remove(id)? {
+                return Err(format!("remove of stored id {} found nothing", id));
+            }
+            model.items.remove(&id);
+        } else {
+            // Insert a new id or overwrite an existing one
+            let slot = rng.gen_range(0..IDS_PER_THREAD);
+            let id = slot * threads + thread_id;
+            let vector = random_vector(&mut rng, soak.dimensions);
+            next_version += 1;
+            let payload = serde_json::json!({ "version": next_version });
+            soak.index.add_with_payload(VectorItem { id, vector: vector.clone() }, payload)?;
+            model.items.insert(id, (vector, next_version));
+        }
+        soak.ops.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Checks the paused index against the models and returns a recall estimate.
+fn check(soak: &Soak, rng: &mut StdRng) -> Result<f64, String> {
+    let models: Vec<_> = soak.models.iter().map(|m| m.lock().unwrap()).collect();
+    let live: usize = models.iter().map(|m| m.items.len()).sum();
+    let stats = soak.index.get_stats();
+    if stats.total_nodes != live {
+        return Err(format!("index holds {} nodes, expected {}", stats.total_nodes, live));
+    }
+    if stats.level_distribution.values().sum::<usize>() != live {
+        return Err("level distribution does not add up to the node count".to_string());
+    }
+
+    let all: Vec<_> = models.iter().flat_map(|m| m.items.iter()).collect();
+    let sample: Vec<_> = all.choose_multiple(rng, SAMPLE).collect();
+    let mut found = 0;
+    for &&(&id, (vector, version)) in &sample {
+        match soak.index.payload(id) {
+            Some(payload) if payload["version"] == *version => {}
+            other => return Err(format!("id {} has payload {:?}, expected version {}", id, other, version)),
+        }
+
+        let query = VectorItem { id, vector: vector.clone() };
+        let results = soak.index.search(&query, K)?;
+        if results.len() != K.min(live) {
+            return Err(format!("search for id {} returned {} of {} results", id, results.len(), K.min(live)));
+        }
+        let distances: Vec<_> = results.iter().map(|r| EuclideanDistance.distance(&query.vector, &r.vector)).collect();
+        if distances.windows(2).any(|w| w[0] > w[1]) {
+            return Err(format!("results for id {} are not sorted by distance", id));
+        }
+        for result in &results {
+            let owner = &models[result.id % models.len()];
+            match owner.items.get(&result.id) {
+                Some((stored, _)) if *stored == result.vector => {}
+                _ => return Err(format!("search returned stale or unknown vector for id {}", result.id)),
+            }
+        }
+        if results.first().map(|r| r.id) == Some(id) {
+            found += 1;
+        }
+    }
+
+    Ok(if sample.is_empty() { 1.0 } else { found as f64 / sample.len() as f64 })
+}
+
+/// Resident set size in bytes, where the platform exposes it.
+fn resident_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
+
+fn main() {
+    let args = Args::from_env();
+    println!("Soak test");
+    println!("---------");
+    println!("Duration:   {}s", args.seconds);
+    println!("Threads:    {}", args.threads);
+    println!("Dimensions: {}", args.dimensions);
+    println!("\nUse: cargo run --release --bin soak <seconds> <threads> <dimensions>\n");
+
+    let soak = Arc::new(Soak {
+        index: HnswIndex::new(Box::new(EuclideanDistance)).with_seed(0),
+        models: (0..args.threads).map(|_| Mutex::new(Model::default())).collect(),
+        gate: RwLock::new(()),
+        ops: AtomicU64::new(0),
+        stop: AtomicBool::new(false),
+        dimensions: args.dimensions,
+    });
+
+    let workers: Vec<_> = (0..args.threads)
+        .map(|t| {
+            let soak = Arc::clone(&soak);
+            let threads = args.threads;
+            thread::spawn(move || worker(&soak, t, threads))
+        })
+        .collect();
+
+    // Watchdog: abort if no operation completes for STALL_LIMIT
+    {
+        let soak = Arc::clone(&soak);
+        thread::spawn(move || {
+            let mut last = (soak.ops.load(Ordering::Relaxed), Instant::now());
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                let ops = soak.ops.load(Ordering::Relaxed);
+                if ops != last.0 {
+                    last = (ops, Instant::now());
+                } else if last.1.elapsed() > STALL_LIMIT && !soak.stop.load(Ordering::Relaxed) {
+                    eprintln!("No progress for {:?} after {} operations; likely deadlock", STALL_LIMIT, ops);
+                    process::exit(2);
+                }
+            }
+        });
+    }
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(args.seconds);
+    let mut rng = StdRng::seed_from_u64(u64::MAX);
+    let mut failure = None;
+    let baseline = resident_bytes();
+    while Instant::now() < deadline && failure.is_none() {
+        thread::sleep(CHECK_EVERY.min(deadline.saturating_duration_since(Instant::now())));
+        if workers.iter().any(|w| w.is_finished()) {
+            break;
+        }
+
+        let _paused = soak.gate.write().unwrap();
+        match check(&soak, &mut rng) {
+            Ok(recall) => {
+                let nodes = soak.index.get_stats().total_nodes;
+                let rss = resident_bytes();
+                println!(
+                    "[{:>6.0}s] ops {:>10}  nodes {:>7}  self-recall {:.3}  rss {}",
+                    start.elapsed().as_secs_f64(),
+                    soak.ops.load(Ordering::Relaxed),
+                    nodes,
+                    recall,
+                    match (rss, baseline) {
+                        (Some(rss), Some(base)) => format!("{} MiB (+{} MiB)", rss >> 20, rss.saturating_sub(base) >> 20),
+                        _ => "n/a".to_string(),
+                    }
+                );
+            }
+            Err(e) => failure = Some(e),
+        }
+    }
+
+    soak.stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        match worker.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => failure = failure.or(Some(e)),
+            Err(_) => failure = failure.or(Some("worker panicked".to_string())),
+        }
+    }
+
+    match failure {
+        Some(e) => {
+            eprintln!("\nInvariant violated: {}", e);
+            process::exit(1);
+        }
+        None => println!("\nSoak test passed: {} operations", soak.ops.load(Ordering::Relaxed)),
+    }
+}
@@ -39,9 +39,9 @@ fn create_test_vectors(
             let mut vector = Vec::with_capacity(dimensions);
             
             // Generate vector components with some noise around the center
-            for i in 0..dimensions {
+            for &component in &center {
                 let noise = rng.gen_range(-0.2..0.2);
-                vector.push(center[i] + noise);
+                vector.push(component + noise);
             }
 
             // Write vector to file with high precision
@@ -0,0 +1,913 @@
+//! Clustering of vectors under Euclidean distance. Nearest-centroid lookups
+//! go through an `HnswIndex` over the centroids, so assignment stays cheap
+//! for large `k`, DBSCAN finds neighborhoods with `search_radius`, and
+//! agglomerative clustering only considers merges along nearest-neighbor
+//! edges.
+//!
+//! Assignment, centroid updates and scoring run on the rayon thread pool.
+//! Floating-point sums are always taken in the same order, so results do
+//! not depend on the number of threads.
+
+use crate::hnsw::HnswIndex;
+use crate::vector::{EuclideanDistance, VectorItem};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rayon::prelude::*;
+
+/// Lloyd's k-means: assign every vector to its nearest centroid, move each
+/// centroid to the mean of its vectors, and repeat until no centroid moves
+/// more than the tolerance or the iteration limit is reached.
+#[derive(Clone, Debug)]
+pub struct KMeans {
+    k: usize,
+    max_iterations: usize,
+    tolerance: f64,
+    seed: u64,
+}
+
+/// The outcome of `KMeans::fit`.
+#[derive(Clone, Debug)]
+pub struct Clustering {
+    pub centroids: Vec<Vec<f64>>,
+    /// The cluster of each input vector, by position.
+    pub assignments: Vec<usize>,
+    /// Sum of squared distances from each vector to its centroid.
+    pub inertia: f64,
+    pub iterations: usize,
+    /// Whether the centroids settled before the iteration limit.
+    pub converged: bool,
+}
+
+impl KMeans {
+    pub fn new(k: usize) -> Self {
+        KMeans { k, max_iterations: 100, tolerance: 1e-6, seed: 0 }
+    }
+
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Converged once no centroid moves further than `tolerance`.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Seeds the k-means++ initialization; the same seed and input give the
+    /// same clustering.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Clusters `vectors`, choosing the initial centroids by k-means++.
+    /// Fewer than `k` distinct vectors give fewer clusters. A cluster that
+    /// loses all its vectors keeps its centroid.
+    pub fn fit(&self, vectors: &[Vec<f64>]) -> Result<Clustering, String> {
+        if self.k == 0 {
+            return Err("k must be at least 1".to_string());
+        }
+        check_dimensions(vectors, None)?;
+
+        let mut centroids = Vec::with_capacity(self.k);
+        kmeans_plus_plus(vectors, &mut centroids, self.k, &mut StdRng::seed_from_u64(self.seed));
+
+        let mut assignments = vec![0; vectors.len()];
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < self.max_iterations && !centroids.is_empty() {
+            iterations += 1;
+            assignments = assign(&centroids, vectors)?;
+            let updated = means(&centroids, vectors, &assignments);
+            let shift = centroids
+                .iter()
+                .zip(&updated)
+                .map(|(old, new)| squared_distance(old, new))
+                .fold(0.0, f64::max)
+                .sqrt();
+            centroids = updated;
+            if shift <= self.tolerance {
+                converged = true;
+                break;
+            }
+        }
+
+        // The last update may have moved centroids; match them up again
+        if !centroids.is_empty() {
+            assignments = assign(&centroids, vectors)?;
+        }
+        let inertia = vectors
+            .par_chunks(SUM_CHUNK)
+            .zip(assignments.par_chunks(SUM_CHUNK))
+            .map(|(vectors, assignments)| {
+                vectors.iter().zip(assignments).map(|(vector, &cluster)| squared_distance(vector, &centroids[cluster])).sum()
+            })
+            .collect::<Vec<f64>>()
+            .into_iter()
+            .sum();
+        Ok(Clustering { centroids, assignments, inertia, iterations, converged })
+    }
+}
+
+impl Clustering {
+    /// Scores this clustering of `vectors`, which must be the vectors it was
+    /// fitted on.
+    pub fn quality(&self, vectors: &[Vec<f64>]) -> Result<ClusterQuality, String> {
+        let mut scorer = QualityScorer::new(&self.centroids)?;
+        scorer.add(vectors, &self.assignments)?;
+        Ok(scorer.finish())
+    }
+}
+
+/// Quality scores for comparing clusterings of the same data, for instance
+/// with different `k`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClusterQuality {
+    /// Sum of squared distances from each vector to its centroid; lower is
+    /// tighter, but always falls as `k` grows.
+    pub inertia: f64,
+    /// Mean simplified silhouette, in [-1, 1]; higher is better. A vector's
+    /// own and nearest other cluster are measured by their centroids rather
+    /// than by all of their members.
+    pub silhouette: f64,
+    /// Davies–Bouldin index; lower is better. Each cluster is compared with
+    /// its nearest centroids only.
+    pub davies_bouldin: f64,
+}
+
+// Parallel sums over vectors are split into chunks of this size, then the
+// chunk totals are added in order
+const SUM_CHUNK: usize = 4096;
+
+// Davies–Bouldin compares each cluster with this many of its nearest
+// centroids rather than all of them
+const DB_NEIGHBORS: usize = 10;
+
+/// Accumulates `ClusterQuality` a batch at a time, for data that is
+/// streamed rather than held in memory. Nearest other centroids are found
+/// through an index over the centroids.
+pub struct QualityScorer {
+    centroids: Vec<Vec<f64>>,
+    index: HnswIndex,
+    counts: Vec<usize>,
+    // Per cluster: summed distance to the centroid, and summed silhouette
+    spread: Vec<f64>,
+    silhouette: Vec<f64>,
+    inertia: f64,
+}
+
+impl QualityScorer {
+    pub fn new(centroids: &[Vec<f64>]) -> Result<Self, String> {
+        Ok(QualityScorer {
+            centroids: centroids.to_vec(),
+            index: centroid_index(centroids)?,
+            counts: vec![0; centroids.len()],
+            spread: vec![0.0; centroids.len()],
+            silhouette: vec![0.0; centroids.len()],
+            inertia: 0.0,
+        })
+    }
+
+    /// Adds `vectors`, each in the cluster given by `assignments`.
+    pub fn add(&mut self, vectors: &[Vec<f64>], assignments: &[usize]) -> Result<(), String> {
+        if vectors.len() != assignments.len() {
+            return Err(format!("{} vectors but {} assignments", vectors.len(), assignments.len()));
+        }
+        check_dimensions(vectors, self.centroids.first().map(Vec::len))?;
+        if let Some(&cluster) = assignments.iter().find(|&&cluster| cluster >= self.centroids.len()) {
+            return Err(format!("No cluster {}", cluster));
+        }
+        // Each vector's squared distance to its centroid and silhouette
+        let scores = vectors
+            .par_iter()
+            .zip(assignments)
+            .map(|(vector, &cluster)| {
+                let own = squared_distance(vector, &self.centroids[cluster]);
+                let query = VectorItem { id: 0, vector: vector.clone() };
+                let other = self.index.search(&query, 2)?.into_iter().find(|item| item.id != cluster);
+                let silhouette = other.map_or(0.0, |other| {
+                    let (a, b) = (own.sqrt(), squared_distance(vector, &other.vector).sqrt());
+                    if a.max(b) > 0.0 { (b - a) / a.max(b) } else { 0.0 }
+                });
+                Ok((own, silhouette))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for (&cluster, (own, silhouette)) in assignments.iter().zip(scores) {
+            self.inertia += own;
+            self.spread[cluster] += own.sqrt();
+            self.counts[cluster] += 1;
+            self.silhouette[cluster] += silhouette;
+        }
+        Ok(())
+    }
+
+    /// The scores of everything added so far. With fewer than two clusters
+    /// the silhouette and Davies–Bouldin index are 0.
+    pub fn finish(&self) -> ClusterQuality {
+        let total: usize = self.counts.iter().sum();
+        // Members of single-vector clusters score 0, as in the usual silhouette
+        let silhouette: f64 = self
+            .silhouette
+            .iter()
+            .zip(&self.counts)
+            .filter(|&(_, &count)| count > 1)
+            .map(|(sum, _)| sum)
+            .sum();
+
+        let scatter: Vec<f64> =
+            self.spread.iter().zip(&self.counts).map(|(sum, &count)| sum / count.max(1) as f64).collect();
+        let mut worst = Vec::new();
+        for (i, centroid) in self.centroids.iter().enumerate().filter(|&(i, _)| self.counts[i] > 0) {
+            let query = VectorItem { id: i, vector: centroid.clone() };
+            let neighbors = self.index.search(&query, DB_NEIGHBORS + 1).unwrap_or_default();
+            let ratio = neighbors
+                .iter()
+                .filter(|item| item.id != i && self.counts[item.id] > 0)
+                .map(|item| (item.id, squared_distance(centroid, &item.vector).sqrt()))
+                .filter(|&(_, separation)| separation > 0.0)
+                .map(|(j, separation)| (scatter[i] + scatter[j]) / separation)
+                .reduce(f64::max);
+            worst.extend(ratio);
+        }
+
+        ClusterQuality {
+            inertia: self.inertia,
+            silhouette: if total > 0 { silhouette / total as f64 } else { 0.0 },
+            davies_bouldin: if worst.is_empty() { 0.0 } else { worst.iter().sum::<f64>() / worst.len() as f64 },
+        }
+    }
+}
+
+/// Mini-batch k-means (Sculley, 2010) for data streamed in chunks: each call
+/// to `partial_fit` assigns a batch to the current centroids and moves each
+/// centroid towards its vectors with a step of one over the number of
+/// vectors it has seen. Only the centroids are kept in memory.
+#[derive(Clone, Debug)]
+pub struct MiniBatchKMeans {
+    k: usize,
+    centroids: Vec<Vec<f64>>,
+    counts: Vec<usize>,
+    rng: StdRng,
+}
+
+impl MiniBatchKMeans {
+    pub fn new(k: usize) -> Self {
+        MiniBatchKMeans { k, centroids: Vec::new(), counts: Vec::new(), rng: StdRng::seed_from_u64(0) }
+    }
+
+    /// Seeds the k-means++ initialization.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Updates the centroids with one batch. The first batches also seed the
+    /// centroids by k-means++, until there are `k` of them.
+    pub fn partial_fit(&mut self, batch: &[Vec<f64>]) -> Result<(), String> {
+        if self.k == 0 {
+            return Err("k must be at least 1".to_string());
+        }
+        check_dimensions(batch, self.centroids.first().map(Vec::len))?;
+        if self.centroids.len() < self.k {
+            kmeans_plus_plus(batch, &mut self.centroids, self.k, &mut self.rng);
+            self.counts.resize(self.centroids.len(), 0);
+        }
+        if self.centroids.is_empty() {
+            return Ok(());
+        }
+
+        for (vector, cluster) in batch.iter().zip(assign(&self.centroids, batch)?) {
+            self.counts[cluster] += 1;
+            let step = 1.0 / self.counts[cluster] as f64;
+            for (c, x) in self.centroids[cluster].iter_mut().zip(vector) {
+                *c += step * (x - *c);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn centroids(&self) -> &[Vec<f64>] {
+        &self.centroids
+    }
+
+    /// The nearest centroid of each vector.
+    pub fn predict(&self, vectors: &[Vec<f64>]) -> Result<Vec<usize>, String> {
+        if self.centroids.is_empty() {
+            return Err("No centroids; call partial_fit first".to_string());
+        }
+        check_dimensions(vectors, self.centroids.first().map(Vec::len))?;
+        assign(&self.centroids, vectors)
+    }
+}
+
+// `OnlineKMeans` checks this many centroids from its (stale) index against
+// the current centroid positions
+const ONLINE_CANDIDATES: usize = 4;
+
+/// Sequential k-means for unbounded streams: each vector is assigned as it
+/// arrives and its centroid moves towards it by one over the cluster's
+/// size. The first `k` distinct vectors seed the centroids, so unlike
+/// `KMeans` the result depends on arrival order.
+///
+/// The centroid index is rebuilt once the centroids have taken `k` updates
+/// since the last build; in between, the nearest few indexed centroids are
+/// compared at their current positions.
+pub struct OnlineKMeans {
+    k: usize,
+    centroids: Vec<Vec<f64>>,
+    counts: Vec<usize>,
+    index: Option<HnswIndex>,
+    // Updates since `index` was built
+    stale: usize,
+}
+
+impl OnlineKMeans {
+    pub fn new(k: usize) -> Self {
+        OnlineKMeans { k, centroids: Vec::new(), counts: Vec::new(), index: None, stale: 0 }
+    }
+
+    /// Assigns `vector` to a cluster, updates that cluster's centroid and
+    /// returns the cluster.
+    pub fn update(&mut self, vector: &[f64]) -> Result<usize, String> {
+        if self.k == 0 {
+            return Err("k must be at least 1".to_string());
+        }
+        if let Some(first) = self.centroids.first().filter(|first| first.len() != vector.len()) {
+            return Err(format!("Vector has {} dimensions, expected {}", vector.len(), first.len()));
+        }
+        if self.centroids.len() < self.k && !self.centroids.iter().any(|c| c == vector) {
+            self.centroids.push(vector.to_vec());
+            self.counts.push(1);
+            self.index = None;
+            return Ok(self.centroids.len() - 1);
+        }
+
+        let cluster = self.nearest(vector)?;
+        self.counts[cluster] += 1;
+        let step = 1.0 / self.counts[cluster] as f64;
+        for (c, x) in self.centroids[cluster].iter_mut().zip(vector) {
+            *c += step * (x - *c);
+        }
+        self.stale += 1;
+        Ok(cluster)
+    }
+
+    fn nearest(&mut self, vector: &[f64]) -> Result<usize, String> {
+        if self.index.is_none() || self.stale >= self.centroids.len() {
+            self.index = Some(centroid_index(&self.centroids)?);
+            self.stale = 0;
+        }
+        let query = VectorItem { id: 0, vector: vector.to_vec() };
+        let candidates = self.index.as_ref().unwrap().search(&query, ONLINE_CANDIDATES)?;
+        candidates
+            .iter()
+            .map(|item| item.id)
+            .min_by(|&a, &b| {
+                squared_distance(&self.centroids[a], vector).total_cmp(&squared_distance(&self.centroids[b], vector))
+            })
+            .ok_or_else(|| "No centroid found".to_string())
+    }
+
+    pub fn centroids(&self) -> &[Vec<f64>] {
+        &self.centroids
+    }
+
+    /// How many vectors each cluster has been assigned.
+    pub fn counts(&self) -> &[usize] {
+        &self.counts
+    }
+}
+
+/// DBSCAN: vectors with at least `min_points` vectors (themselves included)
+/// within `eps` are core points, and clusters are the groups of core points
+/// reachable from each other, plus the vectors within `eps` of them. Other
+/// vectors are noise. The number of clusters is found, not given, and
+/// clusters may take any shape.
+#[derive(Clone, Debug)]
+pub struct Dbscan {
+    eps: f64,
+    min_points: usize,
+}
+
+/// The outcome of `Dbscan::fit`.
+#[derive(Clone, Debug)]
+pub struct DensityClustering {
+    /// The cluster of each input vector, by position, or None for noise.
+    pub labels: Vec<Option<usize>>,
+    pub clusters: usize,
+}
+
+impl Dbscan {
+    pub fn new(eps: f64, min_points: usize) -> Self {
+        Dbscan { eps, min_points }
+    }
+
+    /// Clusters `vectors`, indexing them to find each one's `eps`
+    /// neighborhood. Neighborhoods are approximate, as `search_radius` is.
+    pub fn fit(&self, vectors: &[Vec<f64>]) -> Result<DensityClustering, String> {
+        if !(self.eps >= 0.0 && self.eps.is_finite()) {
+            return Err(format!("eps must be a non-negative number, got {}", self.eps));
+        }
+        if self.min_points == 0 {
+            return Err("min_points must be at least 1".to_string());
+        }
+        check_dimensions(vectors, None)?;
+
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_deterministic_build(0);
+        index.batch_add(vectors.iter().cloned().enumerate().map(|(id, vector)| VectorItem { id, vector }).collect())?;
+        let neighborhood = |i: usize| -> Result<Vec<usize>, String> {
+            let query = VectorItem { id: i, vector: vectors[i].clone() };
+            Ok(index.search_radius(&query, self.eps)?.into_iter().map(|item| item.id).collect())
+        };
+
+        let mut labels = vec![None; vectors.len()];
+        let mut visited = vec![false; vectors.len()];
+        let mut clusters = 0;
+        for start in 0..vectors.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut frontier = neighborhood(start)?;
+            if frontier.len() < self.min_points {
+                continue;
+            }
+            labels[start] = Some(clusters);
+            // Border points keep the first cluster that reaches them
+            while let Some(i) = frontier.pop() {
+                labels[i].get_or_insert(clusters);
+                if !visited[i] {
+                    visited[i] = true;
+                    let neighbors = neighborhood(i)?;
+                    if neighbors.len() >= self.min_points {
+                        frontier.extend(neighbors);
+                    }
+                }
+            }
+            clusters += 1;
+        }
+        Ok(DensityClustering { labels, clusters })
+    }
+}
+
+/// How `Agglomerative` measures the distance between two clusters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Linkage {
+    /// The closest pair of members.
+    Single,
+    /// The furthest pair of members.
+    Complete,
+    /// The mean distance over all pairs of members.
+    Average,
+}
+
+/// Bottom-up hierarchical clustering: every vector starts as its own
+/// cluster, and the two closest clusters are merged until one is left.
+///
+/// Only clusters joined by an edge of the `neighbors`-nearest-neighbor
+/// graph are candidates for merging, which keeps the work near linear
+/// rather than quadratic. Linkage distances are updated by the
+/// Lance–Williams formulas; where only one of the merged clusters has an
+/// edge to a third, the other's distance to it is estimated from their
+/// centroids. Parts of the graph left unconnected are joined last, nearest
+/// centroids first, so the dendrogram always ends in a single root.
+#[derive(Clone, Debug)]
+pub struct Agglomerative {
+    linkage: Linkage,
+    neighbors: usize,
+}
+
+/// The merges made by `Agglomerative::fit`, in order. Clusters are numbered
+/// like SciPy's linkage matrix: the input vectors are `0..points`, and merge
+/// `i` creates cluster `points + i`.
+#[derive(Clone, Debug)]
+pub struct Dendrogram {
+    pub points: usize,
+    pub merges: Vec<Merge>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Merge {
+    pub left: usize,
+    pub right: usize,
+    /// The linkage distance between `left` and `right`.
+    pub distance: f64,
+    /// The number of vectors in the merged cluster.
+    pub size: usize,
+}
+
+// A pending merge, popped closest first (ties by cluster ids)
+#[derive(PartialEq)]
+struct Candidate {
+    distance: f64,
+    pair: (usize, usize),
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance).then_with(|| other.pair.cmp(&self.pair))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Agglomerative {
+    pub fn new(linkage: Linkage) -> Self {
+        Agglomerative { linkage, neighbors: 15 }
+    }
+
+    /// How many nearest neighbors of each vector may be merged with it
+    /// directly. More give a closer approximation, at more cost.
+    pub fn with_neighbors(mut self, neighbors: usize) -> Self {
+        self.neighbors = neighbors;
+        self
+    }
+
+    pub fn fit(&self, vectors: &[Vec<f64>]) -> Result<Dendrogram, String> {
+        check_dimensions(vectors, None)?;
+        let n = vectors.len();
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_deterministic_build(0);
+        index.batch_add(vectors.iter().cloned().enumerate().map(|(id, vector)| VectorItem { id, vector }).collect())?;
+
+        // Cluster id -> (neighboring cluster id -> linkage distance)
+        let mut edges: Vec<HashMap<usize, f64>> = vec![HashMap::new(); n];
+        let neighbors = (0..n)
+            .into_par_iter()
+            .map(|i| index.neighbors_of(i, self.neighbors))
+            .collect::<Result<Vec<_>, String>>()?;
+        for (i, neighbors) in neighbors.into_iter().enumerate() {
+            for neighbor in neighbors {
+                let distance = squared_distance(&vectors[i], &neighbor.vector).sqrt();
+                edges[i].insert(neighbor.id, distance);
+                edges[neighbor.id].insert(i, distance);
+            }
+        }
+        let mut heap: BinaryHeap<Candidate> = edges
+            .iter()
+            .enumerate()
+            .flat_map(|(a, edges)| {
+                edges.iter().filter(move |&(&b, _)| a < b).map(move |(&b, &distance)| Candidate { distance, pair: (a, b) })
+            })
+            .collect();
+
+        let mut centroids = vectors.to_vec();
+        let mut sizes = vec![1usize; n];
+        let mut active = vec![true; n];
+        let mut merges = Vec::new();
+        loop {
+            let Some(Candidate { distance, pair: (a, b) }) = heap.pop() else {
+                // The graph is disconnected: link what is left to its
+                // nearest centroids and carry on
+                let roots: Vec<usize> = (0..active.len()).filter(|&c| active[c]).collect();
+                if roots.len() < 2 {
+                    break;
+                }
+                let root_index = HnswIndex::new(Box::new(EuclideanDistance)).with_deterministic_build(0);
+                root_index.batch_add(roots.iter().map(|&c| VectorItem { id: c, vector: centroids[c].clone() }).collect())?;
+                for &c in &roots {
+                    for other in root_index.neighbors_of(c, self.neighbors.max(1))? {
+                        let distance = squared_distance(&centroids[c], &other.vector).sqrt();
+                        edges[c].insert(other.id, distance);
+                        edges[other.id].insert(c, distance);
+                        heap.push(Candidate { distance, pair: (c.min(other.id), c.max(other.id)) });
+                    }
+                }
+                continue;
+            };
+            // Skip candidates whose clusters were merged away since
+            if !active[a] || !active[b] {
+                continue;
+            }
+            let c = n + merges.len();
+            let size = sizes[a] + sizes[b];
+            merges.push(Merge { left: a, right: b, distance, size });
+            active[a] = false;
+            active[b] = false;
+
+            let centroid: Vec<f64> = centroids[a]
+                .iter()
+                .zip(&centroids[b])
+                .map(|(x, y)| (x * sizes[a] as f64 + y * sizes[b] as f64) / size as f64)
+                .collect();
+            let (from_a, from_b) = (std::mem::take(&mut edges[a]), std::mem::take(&mut edges[b]));
+            let mut merged = HashMap::new();
+            for &x in from_a.keys().chain(from_b.keys()) {
+                if x == a || x == b || merged.contains_key(&x) {
+                    continue;
+                }
+                let estimate = |y: usize| squared_distance(&centroids[y], &centroids[x]).sqrt();
+                let to_a = from_a.get(&x).copied().unwrap_or_else(|| estimate(a));
+                let to_b = from_b.get(&x).copied().unwrap_or_else(|| estimate(b));
+                let distance = match self.linkage {
+                    Linkage::Single => to_a.min(to_b),
+                    Linkage::Complete => to_a.max(to_b),
+                    Linkage::Average => (to_a * sizes[a] as f64 + to_b * sizes[b] as f64) / size as f64,
+                };
+                merged.insert(x, distance);
+            }
+            for (&x, &distance) in &merged {
+                edges[x].remove(&a);
+                edges[x].remove(&b);
+                edges[x].insert(c, distance);
+                heap.push(Candidate { distance, pair: (x, c) });
+            }
+            edges.push(merged);
+            centroids.push(centroid);
+            sizes.push(size);
+            active.push(true);
+        }
+        Ok(Dendrogram { points: n, merges })
+    }
+}
+
+impl Dendrogram {
+    /// Flat clusters from cutting the tree at `height`: vectors end up
+    /// together when joined by merges at or below that distance. Clusters
+    /// are numbered from 0 in order of their first vector.
+    pub fn cut(&self, height: f64) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.points + self.merges.len()).collect();
+        for (i, merge) in self.merges.iter().enumerate() {
+            if merge.distance <= height {
+                parent[merge.left] = self.points + i;
+                parent[merge.right] = self.points + i;
+            }
+        }
+        let mut labels = HashMap::new();
+        (0..self.points)
+            .map(|point| {
+                let mut root = point;
+                while parent[root] != root {
+                    root = parent[root];
+                }
+                let next = labels.len();
+                *labels.entry(root).or_insert(next)
+            })
+            .collect()
+    }
+}
+
+// k-means++ seeding, topping `centroids` up to `k`: the first centroid is a
+// random vector, and each further one is drawn with probability proportional
+// to its squared distance from the nearest centroid so far.
+fn kmeans_plus_plus(vectors: &[Vec<f64>], centroids: &mut Vec<Vec<f64>>, k: usize, rng: &mut StdRng) {
+    if centroids.is_empty() {
+        match vectors.choose(rng) {
+            Some(first) => centroids.push(first.clone()),
+            None => return,
+        }
+    }
+    let mut weights: Vec<f64> = vectors
+        .par_iter()
+        .map(|v| centroids.iter().map(|c| squared_distance(v, c)).fold(f64::INFINITY, f64::min))
+        .collect();
+    while centroids.len() < k {
+        let next = match WeightedIndex::new(&weights) {
+            Ok(weights) => &vectors[weights.sample(rng)],
+            // Every vector coincides with a centroid already
+            Err(_) => break,
+        };
+        weights.par_iter_mut().zip(vectors).for_each(|(weight, vector)| {
+            *weight = weight.min(squared_distance(vector, next));
+        });
+        centroids.push(next.clone());
+    }
+}
+
+// Every vector must have `dimension` components, or the first vector's
+// count if `dimension` is None.
+fn check_dimensions(vectors: &[Vec<f64>], dimension: Option<usize>) -> Result<(), String> {
+    let Some(expected) = dimension.or(vectors.first().map(Vec::len)) else {
+        return Ok(());
+    };
+    match vectors.iter().find(|v| v.len() != expected) {
+        Some(v) => Err(format!("Vector has {} dimensions, expected {}", v.len(), expected)),
+        None => Ok(()),
+    }
+}
+
+/// The nearest centroid of each vector, looked up in an index over the
+/// centroids.
+fn assign(centroids: &[Vec<f64>], vectors: &[Vec<f64>]) -> Result<Vec<usize>, String> {
+    let index = centroid_index(centroids)?;
+    vectors
+        .par_iter()
+        .map(|vector| {
+            let query = VectorItem { id: 0, vector: vector.clone() };
+            let nearest = index.search(&query, 1)?;
+            nearest.first().map(|item| item.id).ok_or_else(|| "No centroid found".to_string())
+        })
+        .collect()
+}
+
+pub(crate) fn centroid_index(centroids: &[Vec<f64>]) -> Result<HnswIndex, String> {
+    let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(0);
+    for (id, centroid) in centroids.iter().enumerate() {
+        index.add(VectorItem { id, vector: centroid.clone() })?;
+    }
+    Ok(index)
+}
+
+// The mean of each cluster's vectors, or the old centroid for an empty one.
+// Clusters are averaged in parallel, each over its vectors in input order.
+fn means(centroids: &[Vec<f64>], vectors: &[Vec<f64>], assignments: &[usize]) -> Vec<Vec<f64>> {
+    let mut members = vec![Vec::new(); centroids.len()];
+    for (i, &cluster) in assignments.iter().enumerate() {
+        members[cluster].push(i);
+    }
+    members
+        .par_iter()
+        .zip(centroids)
+        .map(|(members, old)| {
+            if members.is_empty() {
+                return old.clone();
+            }
+            let mut sum = vec![0.0; old.len()];
+            for &i in members {
+                for (sum, x) in sum.iter_mut().zip(&vectors[i]) {
+                    *sum += x;
+                }
+            }
+            sum.into_iter().map(|x| x / members.len() as f64).collect()
+        })
+        .collect()
+}
+
+pub(crate) fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // `per_blob` points scattered around each of `centers`, interleaved
+    fn blobs(centers: &[[f64; 2]], per_blob: usize) -> Vec<Vec<f64>> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..per_blob)
+            .flat_map(|_| centers.iter().map(|c| vec![c[0] + rng.gen_range(-1.0..1.0), c[1] + rng.gen_range(-1.0..1.0)]).collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    fn test_kmeans_finds_blobs() {
+        let centers = [[0.0, 0.0], [20.0, 0.0], [0.0, 20.0], [20.0, 20.0]];
+        let vectors = blobs(&centers, 50);
+        let clustering = KMeans::new(4).fit(&vectors).unwrap();
+        assert!(clustering.converged);
+        assert_eq!(clustering.assignments.len(), 200);
+
+        // Every blob ends up in one cluster of its own, centred near the blob
+        for (b, center) in centers.iter().enumerate() {
+            let cluster = clustering.assignments[b];
+            assert!((b..200).step_by(4).all(|i| clustering.assignments[i] == cluster));
+            assert!(squared_distance(&clustering.centroids[cluster], center) < 0.25);
+        }
+        assert!(clustering.inertia / 200.0 < 1.0);
+
+        // Input sorted by blob, where the first k vectors share a blob
+        let mut sorted = vectors.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let clustering = KMeans::new(4).with_seed(3).fit(&sorted).unwrap();
+        for center in &centers {
+            assert!(clustering.centroids.iter().any(|c| squared_distance(c, center) < 0.25));
+        }
+
+        assert_eq!(KMeans::new(5).fit(&[vec![1.0], vec![1.0]]).unwrap().centroids, vec![vec![1.0]]);
+        assert!(KMeans::new(0).fit(&vectors).is_err());
+        assert!(KMeans::new(2).fit(&[vec![1.0], vec![1.0, 2.0]]).is_err());
+    }
+
+    #[test]
+    fn test_mini_batch_kmeans() {
+        let centers = [[0.0, 0.0], [20.0, 0.0], [0.0, 20.0]];
+        let vectors = blobs(&centers, 200);
+        let mut kmeans = MiniBatchKMeans::new(3).with_seed(1);
+        assert!(kmeans.predict(&vectors).is_err());
+        for batch in vectors.chunks(50) {
+            kmeans.partial_fit(batch).unwrap();
+        }
+        assert_eq!(kmeans.centroids().len(), 3);
+        for center in &centers {
+            assert!(kmeans.centroids().iter().any(|c| squared_distance(c, center) < 0.25));
+        }
+
+        let assignments = kmeans.predict(&vectors).unwrap();
+        for b in 0..3 {
+            assert!((b..600).step_by(3).all(|i| assignments[i] == assignments[b]));
+        }
+        assert!(kmeans.partial_fit(&[vec![1.0]]).is_err());
+    }
+
+    #[test]
+    fn test_cluster_quality() {
+        let centers = [[0.0, 0.0], [20.0, 0.0], [0.0, 20.0]];
+        let vectors = blobs(&centers, 50);
+        let good = KMeans::new(3).fit(&vectors).unwrap();
+        let quality = good.quality(&vectors).unwrap();
+        assert!((quality.inertia - good.inertia).abs() < 1e-9);
+        assert!(quality.silhouette > 0.9, "{:?}", quality);
+        assert!(quality.davies_bouldin < 0.1, "{:?}", quality);
+
+        // Splitting the data in two merges two blobs, which scores worse
+        let poor = KMeans::new(2).fit(&vectors).unwrap().quality(&vectors).unwrap();
+        assert!(poor.silhouette < quality.silhouette);
+        assert!(poor.davies_bouldin > quality.davies_bouldin);
+
+        // Batches add up to the same scores
+        let mut scorer = QualityScorer::new(&good.centroids).unwrap();
+        for (batch, assignments) in vectors.chunks(40).zip(good.assignments.chunks(40)) {
+            scorer.add(batch, assignments).unwrap();
+        }
+        let batched = scorer.finish();
+        assert!((batched.silhouette - quality.silhouette).abs() < 1e-9);
+        assert!(scorer.add(&vectors[..1], &[7]).is_err());
+
+        let single = KMeans::new(1).fit(&vectors).unwrap().quality(&vectors).unwrap();
+        assert_eq!((single.silhouette, single.davies_bouldin), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_dbscan_finds_rings() {
+        // Two concentric rings, which k-means cannot separate, and one outlier
+        let ring = |radius: f64, n: usize| {
+            (0..n).map(move |i| {
+                let angle = i as f64 * std::f64::consts::TAU / n as f64;
+                vec![radius * angle.cos(), radius * angle.sin()]
+            })
+        };
+        let mut vectors: Vec<Vec<f64>> = ring(5.0, 100).chain(ring(20.0, 300)).collect();
+        vectors.push(vec![50.0, 50.0]);
+
+        let clustering = Dbscan::new(1.0, 3).fit(&vectors).unwrap();
+        assert_eq!(clustering.clusters, 2);
+        assert!(clustering.labels[..100].iter().all(|&label| label == clustering.labels[0]));
+        assert!(clustering.labels[100..400].iter().all(|&label| label == clustering.labels[100]));
+        assert_ne!(clustering.labels[0], clustering.labels[100]);
+        assert_eq!(clustering.labels[400], None);
+
+        // Too sparse for any core point
+        assert_eq!(Dbscan::new(0.1, 2).fit(&vectors).unwrap().clusters, 0);
+        assert!(Dbscan::new(-1.0, 3).fit(&vectors).is_err());
+        assert!(Dbscan::new(1.0, 0).fit(&vectors).is_err());
+    }
+
+    #[test]
+    fn test_agglomerative() {
+        let centers = [[0.0, 0.0], [20.0, 0.0], [0.0, 20.0]];
+        let vectors = blobs(&centers, 30);
+        for linkage in [Linkage::Single, Linkage::Complete, Linkage::Average] {
+            let dendrogram = Agglomerative::new(linkage).fit(&vectors).unwrap();
+            assert_eq!(dendrogram.merges.len(), 89, "{:?}", linkage);
+            assert_eq!(dendrogram.merges.last().unwrap().size, 90);
+
+            // Any cut between the blob spread and the blob distance finds them
+            let labels = dendrogram.cut(8.0);
+            assert_eq!(labels[..3], [0, 1, 2], "{:?}", linkage);
+            assert!((0..90).all(|i| labels[i] == i % 3), "{:?}", linkage);
+            assert!(dendrogram.cut(1e9).iter().all(|&label| label == 0));
+            assert_eq!(dendrogram.cut(-1.0), (0..90).collect::<Vec<_>>());
+        }
+        assert!(Agglomerative::new(Linkage::Single).fit(&[]).unwrap().merges.is_empty());
+    }
+
+    #[test]
+    fn test_online_kmeans() {
+        let centers = [[0.0, 0.0], [20.0, 0.0], [0.0, 20.0], [20.0, 20.0]];
+        let vectors = blobs(&centers, 100);
+        let mut kmeans = OnlineKMeans::new(4);
+        let assignments: Vec<usize> = vectors.iter().map(|v| kmeans.update(v).unwrap()).collect();
+        assert_eq!(assignments[..4], [0, 1, 2, 3]);
+        assert!((0..400).all(|i| assignments[i] == i % 4));
+        assert_eq!(kmeans.counts(), [100; 4]);
+        for (centroid, center) in kmeans.centroids().iter().zip(&centers) {
+            assert!(squared_distance(centroid, center) < 0.25);
+        }
+        assert!(kmeans.update(&[1.0]).is_err());
+        assert!(OnlineKMeans::new(0).update(&[1.0]).is_err());
+    }
+
+    #[test]
+    fn test_clustering_ignores_thread_count() {
+        let centers: Vec<[f64; 2]> = (0..8).map(|i| [(i % 4) as f64 * 3.0, (i / 4) as f64 * 3.0]).collect();
+        let vectors = blobs(&centers, 1000);
+        let run = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let clustering = KMeans::new(8).with_max_iterations(5).fit(&vectors).unwrap();
+                let quality = clustering.quality(&vectors).unwrap();
+                (clustering.centroids, clustering.assignments, clustering.inertia, quality)
+            })
+        };
+        assert_eq!(run(1), run(4));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::hnsw::HnswIndex;
+use crate::vector::VectorItem;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+// File extension of saved collections; see `CollectionManager::save`.
+const EXTENSION: &str = "hnsw";
+
+/// Independent indexes kept under names, e.g. one per embedding space. Each
+/// collection is an `HnswIndex` built by the caller, so metric, dimension
+/// and parameters are set per collection. Collections are shared as `Arc`s,
+/// so a handle stays usable after its collection is dropped from the
+/// manager.
+#[derive(Default)]
+pub struct CollectionManager {
+    collections: RwLock<BTreeMap<String, Arc<HnswIndex>>>,
+}
+
+// Names become file names, so they are kept to a safe alphabet.
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    match valid {
+        true => Ok(()),
+        false => Err(format!("Invalid collection name {:?}: use letters, digits, '_' and '-'", name)),
+    }
+}
+
+impl CollectionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `index` as collection `name`, which must not exist yet.
+    pub fn create(&self, name: &str, index: HnswIndex) -> Result<Arc<HnswIndex>, String> {
+        check_name(name)?;
+        let mut collections = self.collections.write().unwrap();
+        if collections.contains_key(name) {
+            return Err(format!("Collection {:?} already exists", name));
+        }
+        let index = Arc::new(index);
+        collections.insert(name.to_string(), Arc::clone(&index));
+        Ok(index)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<HnswIndex>> {
+        self.collections.read().unwrap().get(name).cloned()
+    }
+
+    fn collection(&self, name: &str) -> Result<Arc<HnswIndex>, String> {
+        self.get(name).ok_or_else(|| format!("No collection named {:?}", name))
+    }
+
+    /// Removes collection `name`, returning it if it existed.
+    pub fn remove(&self, name: &str) -> Option<Arc<HnswIndex>> {
+        self.collections.write().unwrap().remove(name)
+    }
+
+    /// Collection names in sorted order.
+    pub fn names(&self) -> Vec<String> {
+        self.collections.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.collections.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.collections.read().unwrap().is_empty()
+    }
+
+    /// `HnswIndex::add` on collection `name`.
+    pub fn add(&self, name: &str, item: VectorItem) -> Result<(), String> {
+        self.collection(name)?.add(item)
+    }
+
+    /// `HnswIndex::search` on collection `name`.
+    pub fn search(&self, name: &str, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        self.collection(name)?.search(query, k)
+    }
+
+    /// Saves every collection to `<dir>/<name>.hnsw` with `HnswIndex::save`,
+    /// creating `dir` if needed.
+    pub fn save(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let collections: Vec<_> = self.collections.read().unwrap().clone().into_iter().collect();
+        for (name, index) in collections {
+            index.save(dir.join(format!("{}.{}", name, EXTENSION)))?;
+        }
+        Ok(())
+    }
+
+    /// Loads every `.hnsw` file in `dir` as a collection named after the
+    /// file. Loaded collections have the defaults `HnswIndex::load` gives.
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let manager = CollectionManager::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if check_name(name).is_err() {
+                continue;
+            }
+            manager.create(name, HnswIndex::load(&path)?).map_err(io::Error::other)?;
+        }
+        Ok(manager)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistance;
+
+    #[test]
+    fn test_collections() {
+        let manager = CollectionManager::new();
+        manager.create("products", HnswIndex::new(Box::new(EuclideanDistance)).with_dimension(2)).unwrap();
+        manager.create("docs", HnswIndex::new(Box::new(EuclideanDistance)).with_dimension(3)).unwrap();
+        assert!(manager.create("docs", HnswIndex::new(Box::new(EuclideanDistance))).is_err());
+        assert!(manager.create("../escape", HnswIndex::new(Box::new(EuclideanDistance))).is_err());
+        assert_eq!(manager.names(), ["docs", "products"]);
+
+        manager.add("products", VectorItem { id: 1, vector: vec![1.0, 0.0] }).unwrap();
+        manager.add("docs", VectorItem { id: 1, vector: vec![0.0, 1.0, 0.0] }).unwrap();
+        assert!(manager.add("docs", VectorItem { id: 2, vector: vec![0.0, 1.0] }).is_err());
+        assert!(manager.add("images", VectorItem { id: 2, vector: vec![0.0, 1.0] }).is_err());
+        let query = VectorItem { id: 0, vector: vec![1.0, 0.1] };
+        assert_eq!(manager.search("products", &query, 5).unwrap()[0].id, 1);
+
+        let dir = std::env::temp_dir().join(format!("hnsw_collections_{}", std::process::id()));
+        manager.save(&dir).unwrap();
+        let loaded = CollectionManager::load(&dir).unwrap();
+        assert_eq!(loaded.names(), ["docs", "products"]);
+        assert_eq!(loaded.get("docs").unwrap().get(1).unwrap().vector, [0.0, 1.0, 0.0]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let docs = manager.remove("docs").unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(manager.len(), 1);
+        assert!(manager.remove("docs").is_none());
+    }
+}
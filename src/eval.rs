@@ -0,0 +1,149 @@
+//! Recall and latency evaluation of an index against exact ground truth, the
+//! loop every benchmark of this crate needs.
+
+use crate::hnsw::HnswIndex;
+use crate::metrics::Stopwatch;
+use crate::vector::{DistanceCalculator, VectorItem};
+use rayon::prelude::*;
+use std::time::Duration;
+
+/// Search quality and speed over a query set; see `evaluate`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Metrics {
+    pub queries: usize,
+    /// Mean recall@k: the share of each query's true neighbors found.
+    pub recall: f64,
+    /// Mean average precision of the result lists.
+    pub map: f64,
+    /// Queries per second, run one at a time on one thread.
+    pub qps: f64,
+    pub latency_mean: Duration,
+    pub latency_p50: Duration,
+    pub latency_p95: Duration,
+    pub latency_p99: Duration,
+}
+
+/// The ids of the `k` items of `vectors` nearest each query under
+/// `calculator`, nearest first, found by exhaustive comparison.
+pub fn ground_truth(
+    vectors: &[VectorItem],
+    queries: &[VectorItem],
+    k: usize,
+    calculator: &(dyn DistanceCalculator + Sync),
+) -> Vec<Vec<usize>> {
+    queries
+        .par_iter()
+        .map(|query| {
+            let mut distances: Vec<(f64, usize)> =
+                vectors.iter().map(|v| (calculator.distance(&query.vector, &v.vector), v.id)).collect();
+            distances.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+            distances.into_iter().take(k).map(|(_, id)| id).collect()
+        })
+        .collect()
+}
+
+/// The share of the first `k` ids of `truth` that appear in `results`, or 1
+/// when there are none to find.
+pub fn recall(results: &[usize], truth: &[usize], k: usize) -> f64 {
+    let truth = &truth[..k.min(truth.len())];
+    if truth.is_empty() {
+        return 1.0;
+    }
+    results.iter().filter(|id| truth.contains(id)).count() as f64 / truth.len() as f64
+}
+
+/// The precision at each rank of `results` holding a true neighbor,
+/// averaged over those ranks; 0 if there are none.
+pub fn average_precision(results: &[usize], truth: &[usize]) -> f64 {
+    let mut found = 0;
+    let mut precision_sum = 0.0;
+    for (rank, id) in results.iter().enumerate() {
+        if truth.contains(id) {
+            found += 1;
+            precision_sum += found as f64 / (rank + 1) as f64;
+        }
+    }
+    if found > 0 { precision_sum / found as f64 } else { 0.0 }
+}
+
+// The nearest-rank percentile of sorted `latencies`
+fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    match latencies.len() {
+        0 => Duration::ZERO,
+        n => latencies[((p * n as f64).ceil() as usize).clamp(1, n) - 1],
+    }
+}
+
+/// Runs each query against `index` for `k` results, timing them one at a
+/// time, and scores the results against `ground_truth[i]`, the true nearest
+/// ids of query `i` (see `ground_truth`).
+pub fn evaluate(
+    index: &HnswIndex,
+    queries: &[VectorItem],
+    ground_truth: &[Vec<usize>],
+    k: usize,
+) -> Result<Metrics, String> {
+    if queries.len() != ground_truth.len() {
+        return Err(format!("{} queries but {} ground truth lists", queries.len(), ground_truth.len()));
+    }
+    let mut latencies = Vec::with_capacity(queries.len());
+    let (mut recall_sum, mut precision_sum) = (0.0, 0.0);
+    for (query, truth) in queries.iter().zip(ground_truth) {
+        let stopwatch = Stopwatch::start();
+        let results = index.search(query, k)?;
+        latencies.push(stopwatch.elapsed());
+
+        let ids: Vec<usize> = results.iter().map(|item| item.id).collect();
+        recall_sum += recall(&ids, truth, k);
+        precision_sum += average_precision(&ids, &truth[..k.min(truth.len())]);
+    }
+
+    let total: Duration = latencies.iter().sum();
+    latencies.sort();
+    let n = queries.len().max(1);
+    Ok(Metrics {
+        queries: queries.len(),
+        recall: recall_sum / n as f64,
+        map: precision_sum / n as f64,
+        qps: if total.is_zero() { 0.0 } else { queries.len() as f64 / total.as_secs_f64() },
+        latency_mean: total / n as u32,
+        latency_p50: percentile(&latencies, 0.50),
+        latency_p95: percentile(&latencies, 0.95),
+        latency_p99: percentile(&latencies, 0.99),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistance;
+
+    #[test]
+    fn test_scores() {
+        assert_eq!(recall(&[1, 2, 9], &[1, 2, 3, 4], 3), 2.0 / 3.0);
+        assert_eq!(recall(&[], &[], 10), 1.0);
+        // Hits at ranks 1 and 3: (1/1 + 2/3) / 2
+        assert!((average_precision(&[1, 9, 2], &[1, 2]) - 5.0 / 6.0).abs() < 1e-12);
+        assert_eq!(average_precision(&[9], &[1]), 0.0);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let vectors: Vec<VectorItem> =
+            (0..200).map(|i| VectorItem { id: i, vector: vec![(i % 20) as f64, (i / 20) as f64] }).collect();
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(3);
+        index.batch_add(vectors.clone()).unwrap();
+        let queries: Vec<VectorItem> =
+            (0..20).map(|i| VectorItem { id: 0, vector: vec![i as f64 * 0.9 + 0.1, 4.3] }).collect();
+
+        let truth = ground_truth(&vectors, &queries, 5, &EuclideanDistance);
+        assert_eq!(truth[0][..2], [80, 100]);
+        let metrics = evaluate(&index, &queries, &truth, 5).unwrap();
+        assert_eq!(metrics.queries, 20);
+        assert!(metrics.recall > 0.95 && metrics.map > 0.95, "{:?}", metrics);
+        assert!(metrics.latency_p50 <= metrics.latency_p99);
+        assert!(metrics.qps > 0.0);
+
+        assert!(evaluate(&index, &queries, &truth[1..], 5).is_err());
+    }
+}
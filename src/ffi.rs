@@ -0,0 +1,228 @@
+//! C API, built with `--features ffi`, declared in `include/hnsw_rust.h`
+//! (refreshed by building with `HNSW_RUST_UPDATE_HEADER` set; see
+//! `build.rs`). An index is an opaque `HnswIndex *` owned by the
+//! caller until `hnsw_rust_index_free`. Indexes use Euclidean distance.
+//! Functions returning `int32_t` return 0 on success and -1 on failure; on
+//! failure `hnsw_rust_last_error` describes what went wrong. Panics are
+//! caught and reported the same way, never unwound into C.
+//!
+//! An index may be shared between threads, except that
+//! `hnsw_rust_index_free` must not race other calls on it.
+
+#![allow(unsafe_code)]
+
+use crate::{DistanceCalculator, EuclideanDistance, HnswIndex, VectorItem};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// Runs `f`, recording its error or panic for `hnsw_rust_last_error`.
+fn guard<T>(f: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(format!("Panic: {}", message))
+    });
+    result.map_err(set_last_error).ok()
+}
+
+fn status(result: Option<()>) -> i32 {
+    if result.is_some() { 0 } else { -1 }
+}
+
+unsafe fn index_ref<'a>(index: *const HnswIndex) -> Result<&'a HnswIndex, String> {
+    // SAFETY: the caller passes null or a pointer from `hnsw_rust_index_new`
+    // or `hnsw_rust_index_load` that has not been freed.
+    unsafe { index.as_ref() }.ok_or_else(|| "Index pointer is null".to_string())
+}
+
+unsafe fn vector_arg(vector: *const f64, len: usize) -> Result<Vec<f64>, String> {
+    match vector.is_null() {
+        true if len > 0 => Err("Vector pointer is null".to_string()),
+        true => Ok(Vec::new()),
+        // SAFETY: the caller guarantees `len` readable values at `vector`.
+        false => Ok(unsafe { std::slice::from_raw_parts(vector, len) }.to_vec()),
+    }
+}
+
+unsafe fn path_arg<'a>(path: *const c_char) -> Result<&'a str, String> {
+    if path.is_null() {
+        return Err("Path pointer is null".to_string());
+    }
+    // SAFETY: the caller guarantees a NUL-terminated string at `path`.
+    unsafe { CStr::from_ptr(path) }.to_str().map_err(|_| "Path is not valid UTF-8".to_string())
+}
+
+/// Creates an empty index. A `dimension` of 0 lets the first insert set it.
+#[no_mangle]
+pub extern "C" fn hnsw_rust_index_new(dimension: usize) -> *mut HnswIndex {
+    let index = HnswIndex::new(Box::new(EuclideanDistance));
+    let index = match dimension {
+        0 => index,
+        dimension => index.with_dimension(dimension),
+    };
+    Box::into_raw(Box::new(index))
+}
+
+/// Releases an index; null is ignored.
+///
+/// # Safety
+/// `index` must be null or a live index pointer, and is invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn hnsw_rust_index_free(index: *mut HnswIndex) {
+    if !index.is_null() {
+        // SAFETY: see the function's safety section.
+        drop(unsafe { Box::from_raw(index) });
+    }
+}
+
+/// Inserts the `len` values at `vector` under `id`.
+///
+/// # Safety
+/// `index` must be null or a live index pointer, and `vector` must point to
+/// `len` values.
+#[no_mangle]
+pub unsafe extern "C" fn hnsw_rust_index_add(index: *const HnswIndex, id: u64, vector: *const f64, len: usize) -> i32 {
+    status(guard(|| {
+        let index = unsafe { index_ref(index) }?;
+        let vector = unsafe { vector_arg(vector, len) }?;
+        index.add(VectorItem { id: id as usize, vector })
+    }))
+}
+
+/// Writes up to `k` nearest ids, nearest first, to `ids` and their distances
+/// to `distances` (which may be null). Returns the number of results, or -1.
+///
+/// # Safety
+/// `index` must be null or a live index pointer and `query` must point to
+/// `len` values. `ids` must have room for `k` values, and so must
+/// `distances` unless null.
+#[no_mangle]
+pub unsafe extern "C" fn hnsw_rust_index_search(
+    index: *const HnswIndex,
+    query: *const f64,
+    len: usize,
+    k: usize,
+    ids: *mut u64,
+    distances: *mut f64,
+) -> isize {
+    let found = guard(|| {
+        let index = unsafe { index_ref(index) }?;
+        if ids.is_null() && k > 0 {
+            return Err("Result pointer is null".to_string());
+        }
+        let query = VectorItem { id: 0, vector: unsafe { vector_arg(query, len) }? };
+        let hits = index.search(&query, k)?;
+        for (i, item) in hits.iter().enumerate() {
+            // SAFETY: `hits` has at most `k` items, and the caller guarantees
+            // room for `k` values.
+            unsafe {
+                *ids.add(i) = item.id as u64;
+                if !distances.is_null() {
+                    *distances.add(i) = EuclideanDistance.distance(&query.vector, &item.vector);
+                }
+            }
+        }
+        Ok(hits.len() as isize)
+    });
+    found.unwrap_or(-1)
+}
+
+/// The number of vectors in the index, or 0 for null.
+///
+/// # Safety
+/// `index` must be null or a live index pointer.
+#[no_mangle]
+pub unsafe extern "C" fn hnsw_rust_index_len(index: *const HnswIndex) -> usize {
+    unsafe { index.as_ref() }.map_or(0, HnswIndex::len)
+}
+
+/// Saves the index with `HnswIndex::save`.
+///
+/// # Safety
+/// `index` must be null or a live index pointer, and `path` null or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn hnsw_rust_index_save(index: *const HnswIndex, path: *const c_char) -> i32 {
+    status(guard(|| {
+        let index = unsafe { index_ref(index) }?;
+        index.save(unsafe { path_arg(path) }?).map_err(|e| e.to_string())
+    }))
+}
+
+/// Loads an index written by `hnsw_rust_index_save`, or returns null.
+///
+/// # Safety
+/// `path` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn hnsw_rust_index_load(path: *const c_char) -> *mut HnswIndex {
+    guard(|| HnswIndex::load(unsafe { path_arg(path) }?).map_err(|e| e.to_string()))
+        .map_or(ptr::null_mut(), |index| Box::into_raw(Box::new(index)))
+}
+
+/// The last error on this thread, or null if there was none. The string is
+/// valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn hnsw_rust_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_c_api_roundtrip() {
+        let index = hnsw_rust_index_new(2);
+        unsafe {
+            for i in 0..20u64 {
+                let vector = [i as f64, 0.0];
+                assert_eq!(hnsw_rust_index_add(index, i, vector.as_ptr(), 2), 0);
+            }
+            assert_eq!(hnsw_rust_index_add(index, 99, [1.0].as_ptr(), 1), -1);
+            let error = CStr::from_ptr(hnsw_rust_last_error()).to_str().unwrap();
+            assert!(error.contains("dimensions"), "{}", error);
+
+            let (mut ids, mut distances) = ([0u64; 3], [0f64; 3]);
+            let query = [4.2, 0.0];
+            assert_eq!(hnsw_rust_index_search(index, query.as_ptr(), 2, 3, ids.as_mut_ptr(), distances.as_mut_ptr()), 3);
+            assert_eq!(ids, [4, 5, 3]);
+            assert!((distances[0] - 0.2).abs() < 1e-9);
+
+            let path = std::env::temp_dir().join(format!("hnsw_ffi_{}.bin", std::process::id()));
+            let c_path = CString::new(path.to_str().unwrap()).unwrap();
+            assert_eq!(hnsw_rust_index_save(index, c_path.as_ptr()), 0);
+            hnsw_rust_index_free(index);
+            let loaded = hnsw_rust_index_load(c_path.as_ptr());
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(hnsw_rust_index_len(loaded), 20);
+            hnsw_rust_index_free(loaded);
+
+            assert!(hnsw_rust_index_load(c_path.as_ptr()).is_null());
+            assert_eq!(hnsw_rust_index_search(ptr::null(), query.as_ptr(), 2, 3, ids.as_mut_ptr(), ptr::null_mut()), -1);
+        }
+    }
+
+    #[test]
+    fn test_header_is_current() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/hnsw_rust.h"));
+        let checked_in = include_str!("../include/hnsw_rust.h");
+        assert!(
+            generated == checked_in,
+            "include/hnsw_rust.h is stale; rebuild with HNSW_RUST_UPDATE_HEADER=1 cargo build --features ffi"
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::{BTreeSet, HashSet};
+
+/// Decides which ids a filtered search may return. Closures work directly;
+/// for multi-tenant filtering, build an id set (or, with the `roaring`
+/// feature, a `RoaringBitmap`) once and share it across queries.
+pub trait IdFilter: Sync {
+    fn allows(&self, id: usize) -> bool;
+}
+
+impl<F> IdFilter for F
+where
+    F: Fn(usize) -> bool + Sync,
+{
+    fn allows(&self, id: usize) -> bool {
+        self(id)
+    }
+}
+
+impl IdFilter for HashSet<usize> {
+    fn allows(&self, id: usize) -> bool {
+        self.contains(&id)
+    }
+}
+
+impl IdFilter for BTreeSet<usize> {
+    fn allows(&self, id: usize) -> bool {
+        self.contains(&id)
+    }
+}
+
+/// Ids above `u32::MAX` cannot be in the bitmap and are never allowed.
+#[cfg(feature = "roaring")]
+impl IdFilter for roaring::RoaringBitmap {
+    fn allows(&self, id: usize) -> bool {
+        u32::try_from(id).is_ok_and(|id| self.contains(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let set: HashSet<usize> = [1, 5].into_iter().collect();
+        assert!(set.allows(5) && !set.allows(2));
+        assert!((|id: usize| id > 3).allows(4));
+
+        #[cfg(feature = "roaring")]
+        {
+            let bitmap: roaring::RoaringBitmap = [1u32, 5].into_iter().collect();
+            assert!(bitmap.allows(5) && !bitmap.allows(2));
+            assert!(!bitmap.allows(u32::MAX as usize + 6));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+/// Merges several ranked result lists (e.g. keyword and vector results for
+/// the same query) into one ranking. Each input list holds `(id, score)`
+/// pairs, best first, where a higher score is better; negate distances
+/// before fusing them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fusion {
+    /// Reciprocal rank fusion: an id scores `sum(1 / (k + rank))` over the
+    /// lists it appears in, with ranks starting at 1. Ignores the scores, so
+    /// lists on different scales need no calibration; `k = 60` is usual.
+    ReciprocalRank { k: f64 },
+    /// Min-max normalizes each list's scores to [0, 1] and sums them with
+    /// one weight per list. An id missing from a list gets 0 for it.
+    Weighted(Vec<f64>),
+}
+
+impl Fusion {
+    /// The fused ranking, best first, with ties broken by id.
+    pub fn fuse(&self, rankings: &[Vec<(usize, f64)>]) -> Result<Vec<(usize, f64)>, String> {
+        let mut fused: HashMap<usize, f64> = HashMap::new();
+        match self {
+            Fusion::ReciprocalRank { k } => {
+                for ranking in rankings {
+                    for (rank, &(id, _)) in ranking.iter().enumerate() {
+                        *fused.entry(id).or_default() += 1.0 / (k + rank as f64 + 1.0);
+                    }
+                }
+            }
+            Fusion::Weighted(weights) => {
+                if weights.len() != rankings.len() {
+                    return Err(format!("{} weights for {} rankings", weights.len(), rankings.len()));
+                }
+                for (ranking, weight) in rankings.iter().zip(weights) {
+                    let (min, max) = ranking
+                        .iter()
+                        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &(_, s)| (min.min(s), max.max(s)));
+                    for &(id, score) in ranking {
+                        // A list whose scores are all equal counts fully
+                        let normalized = if max > min { (score - min) / (max - min) } else { 1.0 };
+                        *fused.entry(id).or_default() += weight * normalized;
+                    }
+                }
+            }
+        }
+
+        let mut fused: Vec<_> = fused.into_iter().collect();
+        fused.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(fused)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fusion() {
+        let keyword = vec![(1, 12.0), (2, 7.5), (3, 1.0)];
+        let vector = vec![(3, -0.1), (1, -0.4), (4, -0.9)];
+
+        let rrf = Fusion::ReciprocalRank { k: 60.0 }.fuse(&[keyword.clone(), vector.clone()]).unwrap();
+        assert_eq!(rrf.iter().map(|&(id, _)| id).collect::<Vec<_>>(), vec![1, 3, 2, 4]);
+
+        let weighted = Fusion::Weighted(vec![0.2, 0.8]).fuse(&[keyword.clone(), vector.clone()]).unwrap();
+        assert_eq!(weighted.iter().map(|&(id, _)| id).collect::<Vec<_>>(), vec![3, 1, 2, 4]);
+        assert!((weighted[0].1 - 0.8).abs() < 1e-12);
+
+        assert!(Fusion::Weighted(vec![1.0]).fuse(&[keyword, vector]).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! Euclidean distances computed on the GPU through wgpu (Vulkan, Metal,
+//! DX12 or GL, whichever the machine has).
+//!
+//! `GpuDistance` keeps a copy of the index's stored vectors in GPU memory,
+//! indexed by slot and rewritten only when the item at a slot changes, so a
+//! batch of candidates costs an upload of just the query and the slots.
+//! Batches from searches and inserts running at the same time, such as the
+//! queries of `batch_search` or the items of `par_batch_add`, are combined:
+//! while one round is on the GPU the next queues up, and it is dispatched
+//! as a whole. Buffers are kept and grown between rounds.
+//!
+//! The GPU works in `f32`, so distances differ from `EuclideanDistance` in
+//! the last few bits. Single comparisons, vectors that are not stored in an
+//! index, and rounds of fewer than `min_batch` distances stay on the CPU in
+//! `f64`. The `gpu-bench` binary compares the two on a machine's hardware.
+
+use crate::vector::{DistanceCalculator, EuclideanDistance, VectorItem};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Rounds below this many distances are computed on the CPU by default.
+pub const DEFAULT_MIN_BATCH: usize = 16;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+const SHADER: &str = r#"
+struct Params {
+    dim: u32,
+    count: u32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> queries: array<f32>;
+@group(0) @binding(2) var<storage, read> vectors: array<f32>;
+// (query, slot) for each distance
+@group(0) @binding(3) var<storage, read> pairs: array<vec2<u32>>;
+@group(0) @binding(4) var<storage, read_write> distances: array<f32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.count) {
+        return;
+    }
+    let query = pairs[i].x * params.dim;
+    let vector = pairs[i].y * params.dim;
+    var sum = 0.0;
+    for (var d = 0u; d < params.dim; d++) {
+        let diff = vectors[vector + d] - queries[query + d];
+        sum += diff * diff;
+    }
+    distances[i] = sqrt(sum);
+}
+"#;
+
+pub struct GpuDistance {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    adapter: wgpu::AdapterInfo,
+    min_batch: usize,
+    // Largest buffer one binding may cover, in bytes
+    max_binding: u64,
+    // Largest number of distances one dispatch can compute
+    max_count: usize,
+    rounds: Mutex<Rounds>,
+    finished: Condvar,
+    resident: Mutex<Resident>,
+}
+
+// Batches waiting for the next round, and finished ones not yet collected
+#[derive(Default)]
+struct Rounds {
+    waiting: Vec<Batch>,
+    // Whether a thread is running rounds; it runs them until none wait
+    running: bool,
+    done: HashMap<u64, Vec<f64>>,
+    next_ticket: u64,
+}
+
+struct Batch {
+    ticket: u64,
+    query: Vec<f64>,
+    stored: Vec<(usize, Arc<VectorItem>)>,
+}
+
+// Where one distance of a round comes from
+enum Source {
+    Gpu(usize),
+    Cpu(f64),
+}
+
+// GPU memory, used only by the thread running rounds
+#[derive(Default)]
+struct Resident {
+    dim: usize,
+    // The item whose vector each slot of `vectors` holds. Holding it keeps
+    // its allocation alive, so a pointer match means the same item.
+    items: Vec<Option<Arc<VectorItem>>>,
+    // The round that last wrote each slot
+    written: Vec<u64>,
+    round: u64,
+    vectors: Option<wgpu::Buffer>,
+    params: Option<wgpu::Buffer>,
+    queries: Option<wgpu::Buffer>,
+    pairs: Option<wgpu::Buffer>,
+    distances: Option<wgpu::Buffer>,
+    readback: Option<wgpu::Buffer>,
+    // Over the buffers above; dropped whenever one is replaced
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl GpuDistance {
+    /// Opens the default GPU adapter and compiles the distance shader.
+    /// Fails if the machine has no usable adapter.
+    pub fn new() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))
+        .ok_or_else(|| "No GPU adapter found".to_string())?;
+        // The adapter's own limits, so the stored vectors can take as much
+        // memory as it allows
+        let limits = adapter.limits();
+        let descriptor = wgpu::DeviceDescriptor { required_limits: limits.clone(), ..Default::default() };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None))
+            .map_err(|e| format!("Failed to open the GPU: {}", e))?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("hnsw distances"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("hnsw distances"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Ok(GpuDistance {
+            device,
+            queue,
+            pipeline,
+            adapter: adapter.get_info(),
+            min_batch: DEFAULT_MIN_BATCH,
+            max_binding: u64::from(limits.max_storage_buffer_binding_size).min(limits.max_buffer_size),
+            max_count: limits.max_compute_workgroups_per_dimension as usize * WORKGROUP_SIZE as usize,
+            rounds: Mutex::default(),
+            finished: Condvar::new(),
+            resident: Mutex::default(),
+        })
+    }
+
+    /// Rounds with fewer than `min_batch` distances are computed on the CPU.
+    pub fn with_min_batch(mut self, min_batch: usize) -> Self {
+        self.min_batch = min_batch;
+        self
+    }
+
+    pub fn min_batch(&self) -> usize {
+        self.min_batch
+    }
+
+    /// The name and backend of the adapter in use.
+    pub fn adapter(&self) -> String {
+        format!("{} ({:?})", self.adapter.name, self.adapter.backend)
+    }
+
+    // The distances for each of `batches`, on the GPU if the round is large
+    // enough and the GPU does not fail.
+    fn run_round(&self, batches: &[Batch]) -> Vec<Vec<f64>> {
+        let total: usize = batches.iter().map(|batch| batch.stored.len()).sum();
+        if total >= self.min_batch.max(1) {
+            if let Ok(distances) = self.resident.lock().unwrap().run(self, batches) {
+                return distances;
+            }
+        }
+        batches
+            .iter()
+            .map(|batch| batch.stored.iter().map(|(_, item)| EuclideanDistance.distance(&batch.query, &item.vector)).collect())
+            .collect()
+    }
+}
+
+impl Resident {
+    fn run(&mut self, gpu: &GpuDistance, batches: &[Batch]) -> Result<Vec<Vec<f64>>, String> {
+        self.round += 1;
+        let dim = batches[0].query.len();
+        if dim == 0 {
+            return Err("Empty query".to_string());
+        }
+        if dim != self.dim {
+            *self = Resident { dim, round: self.round, ..Resident::default() };
+        }
+
+        // Room for every slot in the round that fits a binding
+        let max_slots = (gpu.max_binding / (dim as u64 * 4)) as usize;
+        let fits = |batch: &Batch, slot: usize, item: &VectorItem| {
+            batch.query.len() == dim && item.vector.len() == dim && slot < max_slots
+        };
+        let needed = batches
+            .iter()
+            .flat_map(|batch| batch.stored.iter().filter(|(slot, item)| fits(batch, *slot, item)).map(|(slot, _)| slot + 1))
+            .max()
+            .unwrap_or(0);
+        self.reserve_slots(gpu, needed, max_slots);
+
+        // Pair each distance with a slot holding its vector, writing slots
+        // whose item changed. A slot wanted with two items in one round (by
+        // searches of different snapshots) keeps the first; the other is
+        // computed here.
+        let (mut queries, mut pairs, mut uploads) = (Vec::new(), Vec::new(), Vec::new());
+        let mut sources = Vec::with_capacity(batches.len());
+        for batch in batches {
+            let query = (queries.len() / dim) as u32;
+            if batch.query.len() == dim {
+                queries.extend(batch.query.iter().map(|&x| x as f32));
+            }
+            let sourced = batch.stored.iter().map(|(slot, item)| {
+                let slot = *slot;
+                let current = fits(batch, slot, item) && slot < self.items.len();
+                let current = current && match &self.items[slot] {
+                    Some(held) if Arc::ptr_eq(held, item) => true,
+                    _ if self.written[slot] != self.round => {
+                        self.items[slot] = Some(item.clone());
+                        self.written[slot] = self.round;
+                        uploads.push(slot);
+                        true
+                    }
+                    _ => false,
+                };
+                match current {
+                    true => {
+                        pairs.push([query, slot as u32]);
+                        Source::Gpu(pairs.len() - 1)
+                    }
+                    false => Source::Cpu(EuclideanDistance.distance(&batch.query, &item.vector)),
+                }
+            });
+            sources.push(sourced.collect::<Vec<_>>());
+        }
+        if pairs.len() > gpu.max_count {
+            return Err(format!("{} distances do not fit one dispatch", pairs.len()));
+        }
+        self.upload(gpu, uploads);
+        let distances = match pairs.is_empty() {
+            true => Vec::new(),
+            false => self.dispatch(gpu, &queries, &pairs)?,
+        };
+
+        Ok(sources
+            .into_iter()
+            .map(|sources| {
+                sources
+                    .into_iter()
+                    .map(|source| match source {
+                        Source::Gpu(pair) => distances[pair] as f64,
+                        Source::Cpu(distance) => distance,
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    // Grows the vector buffer to hold `needed` slots, keeping its contents.
+    fn reserve_slots(&mut self, gpu: &GpuDistance, needed: usize, max_slots: usize) {
+        if needed <= self.items.len() {
+            return;
+        }
+        let slots = needed.max(self.items.len() * 2).min(max_slots);
+        let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("vectors"),
+            size: (slots * self.dim * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        if let Some(old) = &self.vectors {
+            let mut encoder = gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            encoder.copy_buffer_to_buffer(old, 0, &buffer, 0, old.size());
+            gpu.queue.submit(Some(encoder.finish()));
+        }
+        self.vectors = Some(buffer);
+        self.items.resize(slots, None);
+        self.written.resize(slots, 0);
+        self.bind_group = None;
+    }
+
+    // Writes the vectors of `slots`, one write per run of adjacent slots.
+    fn upload(&self, gpu: &GpuDistance, mut slots: Vec<usize>) {
+        let Some(vectors) = &self.vectors else {
+            return;
+        };
+        slots.sort_unstable();
+        for run in slots.chunk_by(|a, b| a + 1 == *b) {
+            let data: Vec<f32> = run
+                .iter()
+                .flat_map(|&slot| self.items[slot].as_ref().unwrap().vector.iter().map(|&x| x as f32))
+                .collect();
+            gpu.queue.write_buffer(vectors, (run[0] * self.dim * 4) as wgpu::BufferAddress, bytemuck::cast_slice(&data));
+        }
+    }
+
+    fn dispatch(&mut self, gpu: &GpuDistance, queries: &[f32], pairs: &[[u32; 2]]) -> Result<Vec<f32>, String> {
+        use wgpu::BufferUsages as Usage;
+        let count = pairs.len() as u64;
+        let mut replaced = false;
+        replaced |= reserve(&gpu.device, &mut self.params, 16, Usage::UNIFORM | Usage::COPY_DST, "params");
+        replaced |= reserve(&gpu.device, &mut self.queries, queries.len() as u64 * 4, Usage::STORAGE | Usage::COPY_DST, "queries");
+        replaced |= reserve(&gpu.device, &mut self.pairs, count * 8, Usage::STORAGE | Usage::COPY_DST, "pairs");
+        replaced |= reserve(&gpu.device, &mut self.distances, count * 4, Usage::STORAGE | Usage::COPY_SRC, "distances");
+        reserve(&gpu.device, &mut self.readback, count * 4, Usage::MAP_READ | Usage::COPY_DST, "readback");
+        let (Some(vectors), Some(params), Some(query_buffer), Some(pair_buffer), Some(distances), Some(readback)) =
+            (&self.vectors, &self.params, &self.queries, &self.pairs, &self.distances, &self.readback)
+        else {
+            return Err("GPU buffers are missing".to_string());
+        };
+        if replaced {
+            self.bind_group = None;
+        }
+        let bind_group = self.bind_group.get_or_insert_with(|| {
+            gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &gpu.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: query_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: vectors.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: pair_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 4, resource: distances.as_entire_binding() },
+                ],
+            })
+        });
+
+        gpu.queue.write_buffer(params, 0, bytemuck::cast_slice(&[self.dim as u32, count as u32, 0, 0]));
+        gpu.queue.write_buffer(query_buffer, 0, bytemuck::cast_slice(queries));
+        gpu.queue.write_buffer(pair_buffer, 0, bytemuck::cast_slice(pairs));
+        let mut encoder = gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&gpu.pipeline);
+            pass.set_bind_group(0, &*bind_group, &[]);
+            pass.dispatch_workgroups((count as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(distances, 0, readback, 0, count * 4);
+        gpu.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..count * 4);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        gpu.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("Failed to read distances from the GPU: {}", e))?;
+        let distances = bytemuck::cast_slice::<u8, f32>(&slice.get_mapped_range()).to_vec();
+        readback.unmap();
+        Ok(distances)
+    }
+}
+
+// Makes `buffer` hold at least `size` bytes, replacing it with one of the
+// next power of two if it is smaller. Returns whether it was replaced.
+fn reserve(device: &wgpu::Device, buffer: &mut Option<wgpu::Buffer>, size: u64, usage: wgpu::BufferUsages, label: &str) -> bool {
+    if buffer.as_ref().is_some_and(|buffer| buffer.size() >= size) {
+        return false;
+    }
+    *buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: size.next_power_of_two().max(16),
+        usage,
+        mapped_at_creation: false,
+    }));
+    true
+}
+
+impl DistanceCalculator for GpuDistance {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        EuclideanDistance.distance(a, b)
+    }
+
+    /// Joins the next round, or runs rounds until none are waiting if no
+    /// other thread is, and returns once this batch's round is done.
+    fn distance_batch_stored(&self, query: &[f64], stored: &[(usize, &Arc<VectorItem>)], out: &mut Vec<f64>) {
+        if stored.is_empty() {
+            return;
+        }
+        let mut rounds = self.rounds.lock().unwrap();
+        let ticket = rounds.next_ticket;
+        rounds.next_ticket += 1;
+        let stored = stored.iter().map(|&(slot, item)| (slot, item.clone())).collect();
+        rounds.waiting.push(Batch { ticket, query: query.to_vec(), stored });
+        if !rounds.running {
+            rounds.running = true;
+            while !rounds.waiting.is_empty() {
+                let batches = std::mem::take(&mut rounds.waiting);
+                drop(rounds);
+                let distances = self.run_round(&batches);
+                rounds = self.rounds.lock().unwrap();
+                rounds.done.extend(batches.iter().map(|batch| batch.ticket).zip(distances));
+                self.finished.notify_all();
+            }
+            rounds.running = false;
+        }
+        loop {
+            if let Some(distances) = rounds.done.remove(&ticket) {
+                out.extend(distances);
+                return;
+            }
+            rounds = self.finished.wait(rounds).unwrap();
+        }
+    }
+
+    fn is_euclidean(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        "euclidean"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HnswIndex;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_gpu_distances() {
+        // CI machines often have no GPU, not even a software one
+        let Ok(gpu) = GpuDistance::new() else {
+            eprintln!("skipping: no GPU adapter");
+            return;
+        };
+        let gpu = gpu.with_min_batch(1);
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut vector = |dim: usize| (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect::<Vec<f64>>();
+        let query = vector(1536);
+        let mut items: Vec<Arc<VectorItem>> = (0..100).map(|id| Arc::new(VectorItem { id, vector: vector(1536) })).collect();
+        let check = |gpu: &GpuDistance, items: &[Arc<VectorItem>]| {
+            let stored: Vec<_> = items.iter().enumerate().collect();
+            let mut out = Vec::new();
+            gpu.distance_batch_stored(&query, &stored, &mut out);
+            assert_eq!(out.len(), items.len());
+            for (distance, item) in out.iter().zip(items) {
+                let expected = EuclideanDistance.distance(&query, &item.vector);
+                assert!((distance - expected).abs() <= 1e-4 * expected, "{} vs {}", distance, expected);
+            }
+        };
+        check(&gpu, &items);
+        // A slot whose item changed is written again
+        items[7] = Arc::new(VectorItem { id: 7, vector: vector(1536) });
+        check(&gpu, &items);
+
+        // Concurrent batches share rounds and each get their own distances
+        (0..64).into_par_iter().for_each(|i| check(&gpu, &items[i % 10..]));
+
+        let index = HnswIndex::new(Box::new(gpu));
+        let items: Vec<_> = (0..500).map(|id| VectorItem { id, vector: vector(32) }).collect();
+        index.par_batch_add(items.clone()).unwrap();
+        let results = index.batch_search(&items[..50], 1).unwrap();
+        let found = results.iter().zip(&items).filter(|(results, item)| results[0].id == item.id).count();
+        assert!(found >= 49, "{} of 50 found themselves", found);
+    }
+}
@@ -1,14 +1,64 @@
-use crate::vector::{DistanceCalculator, VectorItem};
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap, HashSet};
-use std::sync::{Arc, Mutex};
-use rand::Rng;
+use crate::audit::{AuditLog, GraphEvent};
+use crate::filter::IdFilter;
+use crate::hnswlib;
+use crate::hooks::{InsertHook, SearchHook};
+use crate::metrics::{self, IndexMetrics, MetricsSink, Stopwatch};
+use crate::node::{self, Node};
+use crate::persist::{self, GraphParams};
+use crate::plan::{PlanInput, QueryPlan, SearchStrategy};
+use crate::pool::VectorPool;
+use crate::quantize::{PreparedQuery, Quantization};
+use crate::quota::Quota;
+use crate::rerank::{Candidate, Reranker};
+use crate::slab::NodeSlab;
+use crate::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use crate::sync::{Arc, Mutex, RwLock};
+use crate::trace::SearchTrace;
+use crate::transform::VectorTransform;
+use crate::validate::{self, ValidationReport};
+use crate::vector::{self, DistanceCalculator, MaskedDistance, VectorItem};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
 
 
+// Defaults for `with_m`, `with_ef_construction` and `with_ef_search`
 const M: usize = 16;
-const M_MAX0: usize = 32;
 const EF_CONSTRUCTION: usize = 100;
-const EF_SEARCH: usize = 64;
+pub(crate) const EF_SEARCH: usize = 64;
+const DEFAULT_PARAMS: GraphParams = GraphParams { m: M, ef_construction: EF_CONSTRUCTION, ef_search: EF_SEARCH };
+// `par_batch_add` links chunks of at most len / PAR_CHUNK_DIVISOR items
+// (capped at PAR_CHUNK_MAX) between parallel search phases.
+const PAR_CHUNK_DIVISOR: usize = 8;
+const PAR_CHUNK_MAX: usize = 1024;
+// Nodes sampled to estimate a filter's selectivity
+const PLAN_SAMPLE: usize = 1000;
+// Filter evaluations between checks of whether the rest of the plan sample
+// could still change a search's strategy
+const PLAN_CHUNK: usize = 64;
+// `search_radius` starts at this k and doubles it until the radius is covered
+const RADIUS_START_K: usize = 16;
+
+/// The highest level worth giving a node that joins an index of `size`
+/// nodes, about log_m(size). Higher layers would hold a node or two and
+/// only lengthen the descent.
+fn level_cap(size: usize, m: usize) -> usize {
+    ((size.max(1) as f64).ln() / (m as f64).ln()).ceil() as usize
+}
+
+// The query's norm, computed once per search for metrics that use norms.
+fn query_norm(calculator: &dyn DistanceCalculator, query: &VectorItem) -> Option<f64> {
+    calculator.uses_norms().then(|| vector::norm(&query.vector))
+}
 
 #[derive(Clone, Debug)]
 struct Neighbor {
@@ -16,14 +66,6 @@ struct Neighbor {
     distance: f64,
 }
 
-#[derive(Clone, Debug)]
-pub struct Node {
-    pub id: usize,
-    pub connections: Vec<Vec<usize>>,
-    pub item: VectorItem,
-    pub layer: usize,
-}
-
 impl Ord for Neighbor {
     fn cmp(&self, other: &Self) -> Ordering {
         self.distance
@@ -47,12 +89,191 @@ impl PartialEq for Neighbor {
 
 impl Eq for Neighbor {}
 
+// Distance from a query to a node's vector, or to the first `prefix`
+// dimensions of both (see `with_resolutions`), under the
+// index metric or a per-query one (see `search_masked`). With `quantized`,
+// full vectors are compared in their quantized form, using what
+// `Quantization::prepare_query` made of the query. With `query_norm`, full vectors
+// are compared through `distance_with_norms` using the nodes' cached norms.
+// With `trace`, every distance is counted there.
+#[derive(Clone, Copy)]
+struct NodeDistance<'a> {
+    calculator: &'a dyn DistanceCalculator,
+    prefix: Option<usize>,
+    quantized: Option<(&'a Quantization, &'a PreparedQuery)>,
+    query_norm: Option<f64>,
+    trace: Option<&'a RefCell<SearchTrace>>,
+}
+
+impl NodeDistance<'_> {
+    fn to(&self, query: &VectorItem, node: &Node) -> f64 {
+        if let Some(trace) = self.trace {
+            trace.borrow_mut().count_distance();
+        }
+        match (self.prefix, self.quantized) {
+            (None, Some((quantization, prepared))) => {
+                quantization.distance(self.calculator, &query.vector, prepared, &node.codes)
+            }
+            (None, None) if self.query_norm.is_some() => {
+                self.calculator.distance_with_norms(&query.vector, self.query_norm.unwrap(), &node.item.vector, node.norm())
+            }
+            _ => self.calculator.distance(node::prefix(&query.vector, self.prefix), node.vector_at(self.prefix)),
+        }
+    }
+
+    // `to` for each of `slots`, appended to `out`. Full vectors go to the
+    // calculator in one `distance_batch_stored` call, prefixes in one
+    // `distance_batch` call.
+    fn batch(&self, query: &VectorItem, nodes: &NodeSlab, slots: &[usize], out: &mut Vec<f64>) {
+        if let Some(trace) = self.trace {
+            for _ in slots {
+                trace.borrow_mut().count_distance();
+            }
+        }
+        match (self.prefix, self.quantized) {
+            (None, Some((quantization, prepared))) => out.extend(slots.iter().map(|&slot| {
+                quantization.distance(self.calculator, &query.vector, prepared, &nodes[slot].codes)
+            })),
+            (None, None) if self.query_norm.is_some() => out.extend(slots.iter().map(|&slot| {
+                let node = &nodes[slot];
+                self.calculator.distance_with_norms(&query.vector, self.query_norm.unwrap(), &node.item.vector, node.norm())
+            })),
+            (None, None) => {
+                let stored: Vec<_> = slots.iter().map(|&slot| (slot, &nodes[slot].item)).collect();
+                self.calculator.distance_batch_stored(&query.vector, &stored, out);
+            }
+            _ => {
+                let vectors: Vec<&[f64]> = slots.iter().map(|&slot| nodes[slot].vector_at(self.prefix)).collect();
+                self.calculator.distance_batch(node::prefix(&query.vector, self.prefix), &vectors, out);
+            }
+        }
+    }
+}
+
+// What a node carries besides its vector and links, for re-inserting it
+// elsewhere.
+struct NodeExtras {
+    payload: Option<serde_json::Value>,
+    expires_at: Option<SystemTime>,
+    deleted: bool,
+}
+
+impl NodeExtras {
+    // `None` when the node has nothing to carry.
+    fn of(node: &Node) -> Option<Self> {
+        let extras = NodeExtras { payload: node.payload.clone(), expires_at: node.expires_at, deleted: node.deleted };
+        (extras.payload.is_some() || extras.expires_at.is_some() || extras.deleted).then_some(extras)
+    }
+
+    fn apply(self, node: &mut Node) {
+        node.payload = self.payload;
+        node.expires_at = self.expires_at;
+        node.deleted = self.deleted;
+    }
+}
+
+// Per-query settings threaded through `search_nodes`.
+struct SearchRequest<'a> {
+    k: usize,
+    calculator: &'a dyn DistanceCalculator,
+    resolutions: &'a [usize],
+    // External ids left out of the results; they do not count toward `k`
+    exclude: &'a [usize],
+    // Only external ids passing this can be returned
+    filter: Option<&'a dyn IdFilter>,
+    // Filled in as the search runs; see `search_traced`
+    trace: Option<&'a RefCell<SearchTrace>>,
+    // Shared by a batch so that its filter is planned once
+    strategy: Option<&'a OnceLock<SearchStrategy>>,
+}
+
 pub struct HnswIndex {
-    nodes: Arc<Mutex<HashMap<usize, Node>>>,
-    entry_point: Arc<Mutex<Option<usize>>>,
+    nodes: Arc<RwLock<NodeSlab>>,
+    entry_point: Arc<RwLock<Option<usize>>>,
     level_lambda: f64,
     max_level: usize,
+    // Links per node above layer 0 (twice that on layer 0), and beam widths
+    m: usize,
+    ef_construction: usize,
+    ef_search: usize,
     distance_calculator: Box<dyn DistanceCalculator + Send + Sync>,
+    transform: Option<VectorTransform>,
+    resolutions: Vec<usize>,
+    // One past the largest id ever inserted; `add_auto` allocates from here.
+    next_id: AtomicUsize,
+    insert_hooks: Vec<Box<dyn InsertHook>>,
+    search_hooks: Vec<Box<dyn SearchHook>>,
+    rerankers: Vec<Box<dyn Reranker>>,
+    quota: Option<Quota>,
+    snapshot_reads: Option<SnapshotReads>,
+    // Fixed by `with_dimension`; otherwise the dimension of the stored vectors.
+    dimension: Option<usize>,
+    duplicate_policy: DuplicatePolicy,
+    neighbor_selection: NeighborSelection,
+    // Level assignment draws from this when seeded, else from `thread_rng`.
+    rng: Option<Mutex<StdRng>>,
+    // Set by `with_deterministic_build`.
+    deterministic: bool,
+    vector_pool: Option<std::sync::Arc<VectorPool>>,
+    audit: Option<AuditLog>,
+    metrics: IndexMetrics,
+    metrics_sinks: Vec<Box<dyn MetricsSink>>,
+    quantization: Option<Quantization>,
+    // Bumped whenever existing nodes change slots or go away (remove,
+    // truncate, clear), so an insert can tell its candidates went stale.
+    slot_epoch: AtomicUsize,
+    // Reused until the graph grows or its slots change; see `plan_sample`.
+    plan_sample: Mutex<Option<Arc<PlanSample>>>,
+}
+
+// Immutable copy of the graph that searches read in snapshot mode.
+struct Graph {
+    nodes: NodeSlab,
+    entry_point: Option<usize>,
+}
+
+// Slots sampled to cost filtered searches, for a graph of `len` nodes at
+// slot epoch `epoch`, and their mean layer-0 degree.
+struct PlanSample {
+    len: usize,
+    epoch: usize,
+    slots: Vec<usize>,
+    average_degree: f64,
+}
+
+/// How an inserted node's neighbors are chosen from the search candidates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NeighborSelection {
+    /// The closest candidates (Algorithm 3 of the HNSW paper).
+    Simple,
+    /// Algorithm 4: skip candidates closer to an already chosen neighbor than
+    /// to the new node. `extend_candidates` also considers the candidates'
+    /// own neighbors; `keep_pruned` fills any free slots with the skipped ones.
+    Heuristic { extend_candidates: bool, keep_pruned: bool },
+}
+
+impl Default for NeighborSelection {
+    fn default() -> Self {
+        NeighborSelection::Heuristic { extend_candidates: false, keep_pruned: true }
+    }
+}
+
+/// What `add` does with an id that is already in the index.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail the insert.
+    Reject,
+    /// Replace the vector and rebuild the node's links from scratch.
+    #[default]
+    Overwrite,
+    /// Keep the stored vector and report success.
+    Ignore,
+}
+
+struct SnapshotReads {
+    published: RwLock<Arc<Graph>>,
+    refresh_every: usize,
+    pending: AtomicUsize,
 }
 
 impl HnswIndex {
@@ -60,434 +281,4088 @@ impl HnswIndex {
         distance_calculator: Box<dyn DistanceCalculator + Send + Sync>,
     ) -> Self {
         HnswIndex {
-            nodes: Arc::new(Mutex::new(HashMap::new())),
-            entry_point: Arc::new(Mutex::new(None)),
+            nodes: Arc::new(RwLock::new(NodeSlab::new())),
+            entry_point: Arc::new(RwLock::new(None)),
             level_lambda: 1.0 / (M as f64).ln(),
             max_level: 16,  // Default max level
+            m: M,
+            ef_construction: EF_CONSTRUCTION,
+            ef_search: EF_SEARCH,
             distance_calculator,
+            transform: None,
+            resolutions: Vec::new(),
+            next_id: AtomicUsize::new(0),
+            insert_hooks: Vec::new(),
+            search_hooks: Vec::new(),
+            rerankers: Vec::new(),
+            quota: None,
+            snapshot_reads: None,
+            dimension: None,
+            duplicate_policy: DuplicatePolicy::default(),
+            neighbor_selection: NeighborSelection::default(),
+            rng: None,
+            deterministic: false,
+            vector_pool: None,
+            audit: None,
+            metrics: IndexMetrics::default(),
+            metrics_sinks: Vec::new(),
+            slot_epoch: AtomicUsize::new(0),
+            plan_sample: Mutex::new(None),
+            quantization: None,
         }
     }
 
-    pub fn add(&self, item: VectorItem) -> Result<(), String> {
-        let node_id = item.id;
-        let node_level = self.random_level();
-        let mut connections = vec![Vec::with_capacity(if node_level == 0 { M_MAX0 } else { M }); node_level + 1];
-    
-        let mut nodes = self.nodes.lock().unwrap();
-        let mut entry_point = self.entry_point.lock().unwrap();
+    /// Registers a hook run on every inserted vector, in registration order.
+    pub fn with_insert_hook(mut self, hook: impl InsertHook + 'static) -> Self {
+        self.insert_hooks.push(Box::new(hook));
+        self
+    }
 
-        // Handle first node case
-        if nodes.is_empty() {
-            let new_node = Node {
-                id: node_id,
-                connections: vec![Vec::with_capacity(M_MAX0); node_level + 1],
-                item: item.clone(),
-                layer: node_level,
-            };
-            nodes.insert(node_id, new_node);
-            *entry_point = Some(node_id);
-            return Ok(());
-        }
+    /// Registers a hook run on every search's results, in registration order.
+    pub fn with_search_hook(mut self, hook: impl SearchHook + 'static) -> Self {
+        self.search_hooks.push(Box::new(hook));
+        self
+    }
 
-        // Find entry point for insertion
-        let curr_ep = entry_point.unwrap();
-        let mut curr_dist = self.calculate_distances(&item, &nodes[&curr_ep].item);
+    /// Adds a final ranking stage to every search, run in registration order
+    /// on a pool of at least 64 candidates (`k` if larger); see `Reranker`.
+    pub fn with_reranker(mut self, reranker: impl Reranker + 'static) -> Self {
+        self.rerankers.push(Box::new(reranker));
+        self
+    }
 
-        // Insert at each layer
-        for level in (0..=node_level).rev() {
-            let neighbors = self.search_at_layer(&nodes, curr_ep, &item, level, 
-                if level == 0 { EF_CONSTRUCTION } else { M })?;
-            
-            let selected = self.select_neighbors(&nodes, &item, &neighbors, level)?;
-            
-            // Create new node's connections at this level
-            if level < connections.len() {
-                connections[level] = selected.clone();
-            }
+    /// Requires every stored vector and query to have `dimension` components
+    /// (after any transform). Without it, the first insert sets the dimension.
+    pub fn with_dimension(mut self, dimension: usize) -> Self {
+        self.dimension = Some(dimension);
+        self
+    }
 
-            // Update reverse connections
-            for &neighbor_id in &selected {
-                // Clone the nodes we need to avoid borrow conflicts
-                let neighbor_item = nodes[&neighbor_id].item.clone();
-                let neighbor_dist = self.calculate_distances(&item, &neighbor_item);
-                let neighbor_level = nodes[&neighbor_id].layer;
-                
-                let reverse_selected = self.select_neighbors(
-                    &nodes,
-                    &neighbor_item,
-                    &[Neighbor { id: node_id, distance: neighbor_dist }],
-                    level
-                )?;
-                
-                // Now do the mutable update
-                if let Some(neighbor_node) = nodes.get_mut(&neighbor_id) {
-                    if level < neighbor_node.connections.len() {
-                        neighbor_node.connections[level] = reverse_selected;
-                    }
-                }
-            }            
+    /// The dimension vectors are stored and searched at, once known.
+    pub fn dimension(&self) -> Option<usize> {
+        self.expected_dimension(&self.nodes.read().unwrap())
+    }
+
+    fn expected_dimension(&self, nodes: &NodeSlab) -> Option<usize> {
+        self.dimension.or_else(|| {
+            nodes.get(0).map(|node| match &self.quantization {
+                Some(quantization) if !quantization.keeps_full_vectors() => quantization.dimension(&node.codes),
+                _ => node.item.vector.len(),
+            })
+        })
+    }
+
+    fn check_dimension(&self, nodes: &NodeSlab, item: &VectorItem, what: &str) -> Result<(), String> {
+        match self.expected_dimension(nodes) {
+            Some(dimension) if item.vector.len() != dimension => Err(format!(
+                "{} {} has {} dimensions, expected {}",
+                what,
+                item.id,
+                item.vector.len(),
+                dimension
+            )),
+            _ => Ok(()),
         }
+    }
 
-        // Insert the new node
-        let new_node = Node {
-            id: node_id,
-            connections,
-            item,
-            layer: node_level,
-        };
-        nodes.insert(node_id, new_node);
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
 
-        // Update entry point if necessary
-        if node_level > nodes[&entry_point.unwrap()].layer {
-            *entry_point = Some(node_id);
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    pub fn with_neighbor_selection(mut self, selection: NeighborSelection) -> Self {
+        self.neighbor_selection = selection;
+        self
+    }
+
+    pub fn neighbor_selection(&self) -> NeighborSelection {
+        self.neighbor_selection
+    }
+
+    /// Sets M, the number of links per node above layer 0 (layer 0 gets
+    /// twice as many), and the level multiplier 1/ln(M) to match. Values
+    /// below 2 are raised to 2.
+    pub fn with_m(mut self, m: usize) -> Self {
+        self.set_m(m);
+        self
+    }
+
+    fn set_m(&mut self, m: usize) {
+        self.m = m.max(2);
+        self.level_lambda = 1.0 / (self.m as f64).ln();
+    }
+
+    /// The beam width used to find an inserted node's neighbors on layer 0.
+    pub fn with_ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction.max(1);
+        self
+    }
+
+    /// The beam width of a search, raised to `k` when `k` is larger.
+    pub fn with_ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = ef_search.max(1);
+        self
+    }
+
+    /// Seeds the RNG used for level assignment, so building the same items in
+    /// the same order gives the same graph. Concurrent inserts still draw
+    /// levels in whatever order they arrive.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Some(Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Makes the graph a pure function of the seed, the configuration and the
+    /// sequence of inserts, so rebuilding gives a bit-identical `save` file.
+    /// Each insert runs start to finish under the write lock, and
+    /// `par_batch_add` links and searches one item at a time. Inserts from
+    /// several threads are still linked in whatever order they arrive.
+    pub fn with_deterministic_build(mut self, seed: u64) -> Self {
+        self.deterministic = true;
+        self.with_seed(seed)
+    }
+
+    /// Stores vectors in `pool`, which other indexes can share. Vectors
+    /// already in the index (e.g. after `load`) move into the pool too.
+    pub fn with_vector_pool(mut self, pool: std::sync::Arc<VectorPool>) -> Self {
+        for node in self.nodes.write().unwrap().iter_mut() {
+            node.item = pool.intern(VectorItem::clone(&node.item));
         }
+        self.vector_pool = Some(pool);
+        self
+    }
 
-        Ok(())
+    /// Preallocates room for `capacity` vectors; see `reserve`.
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.reserve(capacity);
+        self
     }
 
-    fn select_neighbors(
-        &self,
-        nodes: &HashMap<usize, Node>,
-        query: &VectorItem,
-        candidates: &[Neighbor],
-        level: usize,
-    ) -> Result<Vec<usize>, String> {
-        let max_connections = if level == 0 { M_MAX0 } else { M };
-        let mut selected = Vec::with_capacity(max_connections);
-        let mut remaining: Vec<_> = candidates.to_vec();
-        
-        remaining.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
-
-        for candidate in remaining.iter().take(max_connections) {
-            let mut should_add = true;
-            for &existing in &selected {
-                let dist_between = self.calculate_distances(
-                    &nodes[&candidate.id].item,
-                    &nodes[&existing].item
-                );
-                
-                if dist_between < candidate.distance {
-                    should_add = false;
-                    break;
+    /// Makes room for `additional` more vectors without regrowing the node
+    /// storage or the id map. `batch_add` and `par_batch_add` reserve for
+    /// their items automatically.
+    pub fn reserve(&self, additional: usize) {
+        self.nodes.write().unwrap().reserve(additional);
+    }
+
+    /// Stores vectors compressed by `quantization` and traverses the graph on
+    /// the compressed form. Vectors already in the index are converted. A
+    /// quantizer trained on a fixed dimension also fixes the index dimension.
+    pub fn with_quantization(mut self, quantization: Quantization) -> Result<Self, String> {
+        if let Some(trained) = quantization.trained_dimension() {
+            match self.dimension() {
+                Some(dimension) if dimension != trained => {
+                    return Err(format!("Quantizer was trained on {} dimensions, index has {}", trained, dimension));
                 }
-            }
-            
-            if should_add {
-                selected.push(candidate.id);
+                _ => self.dimension = Some(trained),
             }
         }
-        
-        Ok(selected)
+        for node in self.nodes.write().unwrap().iter_mut() {
+            let vector = self.vector_of(node).into_owned().vector;
+            node.codes = quantization.encode(&vector);
+            node.norm = OnceLock::new();
+            node.item = match quantization.keeps_full_vectors() {
+                true => std::sync::Arc::new(VectorItem { id: node.id, vector }),
+                false => std::sync::Arc::new(VectorItem { id: node.id, vector: Vec::new() }),
+            };
+        }
+        self.quantization = Some(quantization);
+        self.publish();
+        Ok(self)
     }
 
+    pub fn quantization(&self) -> Option<&Quantization> {
+        self.quantization.as_ref()
+    }
 
-    fn _insert_at_layer(
-        &self,
-        nodes: &mut HashMap<usize, Node>,
-        current_id: usize,
-        query: &VectorItem,
-        level: usize,
-        ef: usize,
-    ) -> Result<Vec<usize>, String> {
-        let neighbors = self.search_at_layer(nodes, current_id, query, level, ef)?;
-        let selected = self.select_neighbors(nodes, query, &neighbors, level)?;
-        
-        // Update reverse connections
-        for &neighbor_id in &selected {
-            let neighbor_dist = self.calculate_distances(query, &nodes[&neighbor_id].item);
-            let candidate = Neighbor {
-                id: current_id,
-                distance: neighbor_dist,
-            };
-            
-            let reverse_conns = self.select_neighbors(
-                nodes,
-                &nodes[&neighbor_id].item,
-                &[candidate],
-                level
-            )?;
-            
-            if let Some(node) = nodes.get_mut(&neighbor_id) {
-                if level < node.connections.len() {
-                    node.connections[level] = reverse_conns;
-                }
+    // A node's vector: the stored one, or the decoded codes if the index
+    // keeps only those.
+    fn vector_of<'n>(&self, node: &'n Node) -> Cow<'n, VectorItem> {
+        match &self.quantization {
+            Some(quantization) if !quantization.keeps_full_vectors() => {
+                Cow::Owned(VectorItem { id: node.id, vector: quantization.decode(&node.codes) })
             }
+            _ => Cow::Borrowed(&node.item),
         }
-        
-        Ok(selected)
     }
 
-    fn _select_neighbors_heuristic(
-        &self,
-        nodes: &HashMap<usize, Node>,
-        _query: &VectorItem,
-        candidates: &[Neighbor],
-        level: usize,
-    ) -> Result<Vec<usize>, String> {
-        let max_connections = if level == 0 { M_MAX0 } else { M };
-        let mut selected = Vec::with_capacity(max_connections);
-        
-        for candidate in candidates.iter().take(max_connections) {
-            let mut should_add = true;
-            for &existing in &selected {
-                let dist_between = self.calculate_distances(
-                    &nodes[&candidate.id].item,
-                    &nodes[&existing].item
-                );
-                
-                if dist_between < candidate.distance {
-                    should_add = false;
-                    break;
+    // The nodes with full-precision vectors, for writing out an index that
+    // keeps only codes.
+    fn full_precision<'n>(&self, nodes: &'n NodeSlab) -> Cow<'n, NodeSlab> {
+        match &self.quantization {
+            Some(quantization) if !quantization.keeps_full_vectors() => {
+                let mut nodes = nodes.clone();
+                for node in nodes.iter_mut() {
+                    node.item = std::sync::Arc::new(VectorItem { id: node.id, vector: quantization.decode(&node.codes) });
+                    node.codes = Vec::new();
+                    node.norm = OnceLock::new();
                 }
+                Cow::Owned(nodes)
             }
-            
-            if should_add {
-                selected.push(candidate.id);
-            }
+            _ => Cow::Borrowed(nodes),
         }
-        
-        Ok(selected)
     }
 
-    fn random_level(&self) -> usize {
-        let mut rng = rand::thread_rng();
-        let mut level = 0;
-        while rng.gen::<f64>() < self.level_lambda && level < self.max_level {
-            level += 1;
-        }
-        level
+    /// Logs every graph mutation to `audit`; see `AuditLog`.
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
     }
 
-    fn calculate_distances(&self, item1: &VectorItem, item2: &VectorItem) -> f64 {
-        self.distance_calculator.calculate(item1, item2)
+    /// Insert and search counters since creation or the last `reset`.
+    pub fn metrics(&self) -> &IndexMetrics {
+        &self.metrics
     }
 
-    fn search_at_layer(
-        &self,
-        nodes: &HashMap<usize, Node>,
-        entry_point: usize,
-        query: &VectorItem,
-        level: usize,
-        ef: usize,
-    ) -> Result<Vec<Neighbor>, String> {
-        let entry_node = nodes.get(&entry_point)
-            .ok_or_else(|| format!("Entry point {} not found", entry_point))?;
-    
-        if level >= entry_node.connections.len() {
-            return Ok(Vec::new());
+    /// Reports every completed insert and search to `sink` as well as to the
+    /// built-in counters of `metrics`.
+    pub fn with_metrics_sink(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics_sinks.push(Box::new(sink));
+        self
+    }
+
+    fn record_inserts(&self, inserted: usize, updated: usize, elapsed: Duration) {
+        self.metrics.record_inserts(inserted, updated);
+        for sink in &self.metrics_sinks {
+            sink.on_insert(inserted, updated, elapsed);
         }
-    
-        let mut visited = HashSet::new();
-        let mut candidates = BinaryHeap::new();
-        let mut results = BinaryHeap::new();
-    
-        let initial_dist = self.calculate_distances(query, &entry_node.item);
-        let initial = Neighbor {
-            id: entry_point,
-            distance: initial_dist,
-        };
-    
-        candidates.push(initial.clone());
-        results.push(initial);
-        visited.insert(entry_point);
-    
-        while let Some(current) = candidates.pop() {
-            // Get worst distance in results
-            let furthest_dist = results.peek().map_or(f64::INFINITY, |n| n.distance);
-    
-            if current.distance > furthest_dist {
-                break;
-            }
-    
-            if let Some(node) = nodes.get(&current.id) {
-                if level < node.connections.len() {
-                    for &neighbor_id in &node.connections[level] {
-                        if visited.insert(neighbor_id) {
-                            if let Some(neighbor_node) = nodes.get(&neighbor_id) {
-                                let distance = self.calculate_distances(query, &neighbor_node.item);
-                                let neighbor = Neighbor {
-                                    id: neighbor_id,
-                                    distance,
-                                };
-    
-                                if results.len() < ef || distance < furthest_dist {
-                                    candidates.push(neighbor.clone());
-                                    results.push(neighbor);
-                                    
-                                    if results.len() > ef {
-                                        results.pop();
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    }
+
+    /// Enforces `quota` on inserts and searches; violations are reported as
+    /// the `QuotaError` message.
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    pub fn quota(&self) -> Option<&Quota> {
+        self.quota.as_ref()
+    }
+
+    /// Serves searches from a published copy of the graph instead of the live
+    /// one, so queries never wait on inserts. The copy is refreshed after
+    /// every `refresh_every` inserts, at the end of each batch, and on
+    /// `publish()`; until then searches do not see newer vectors.
+    pub fn with_snapshot_reads(mut self, refresh_every: usize) -> Self {
+        self.snapshot_reads = Some(SnapshotReads {
+            published: RwLock::new(Arc::new(self.copy_graph())),
+            refresh_every: refresh_every.max(1),
+            pending: AtomicUsize::new(0),
+        });
+        self
+    }
+
+    /// Makes every insert so far visible to searches in snapshot mode. Copies
+    /// the whole graph; does nothing without `with_snapshot_reads`.
+    pub fn publish(&self) {
+        if let Some(snapshots) = &self.snapshot_reads {
+            let graph = Arc::new(self.copy_graph());
+            snapshots.pending.store(0, AtomicOrdering::Relaxed);
+            *snapshots.published.write().unwrap() = graph;
         }
-    
-        Ok(results.into_sorted_vec())
     }
 
-    fn _select_connections_for_level(
-        &self,
-        nodes: &HashMap<usize, Node>,
-        _item: &VectorItem,
-        candidates: &[Neighbor],
-        level: usize,
-    ) -> Vec<usize> {
-        let max_connections = if level == 0 { M_MAX0 } else { M };
-        let mut selected = Vec::with_capacity(max_connections);
-        let mut remaining: Vec<_> = candidates.to_vec();
-        remaining.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
-
-        'outer: while selected.len() < max_connections && !remaining.is_empty() {
-            let current = remaining.remove(0);
-
-            // Check if this connection would be closer to any already selected neighbor
-            for &selected_id in &selected {
-                let selected_node = &nodes[&selected_id];
-                let dist_between =
-                    self.calculate_distances(&nodes[&current.id].item, &selected_node.item);
-                if dist_between < current.distance {
-                    continue 'outer;
-                }
-            }
+    /// A frozen copy of the graph as it is now. Searches and reads on it
+    /// ignore later writes to the index, which carries on accepting them, so
+    /// it suits consistent exports and handing a replica to other threads.
+    /// Taking one copies the links of every node (vectors are shared, not
+    /// copied); cloning one is cheap.
+    pub fn snapshot(&self) -> IndexSnapshot<'_> {
+        IndexSnapshot { index: self, graph: Arc::new(self.copy_graph()) }
+    }
 
-            selected.push(current.id);
+    /// Makes `id` the node searches and inserts start from. Searches only
+    /// descend from its layer, so anything but a top-layer node costs recall;
+    /// this is meant for tooling and repair. The next `remove` or `truncate`
+    /// replaces a node below the top layer.
+    pub fn set_entry_point(&self, id: usize) -> Result<(), String> {
+        {
+            let nodes = self.nodes.read().unwrap();
+            let mut entry_point = self.entry_point.write().unwrap();
+            let slot = nodes.slot_of(id).ok_or_else(|| format!("Id {} is not in the index", id))?;
+            self.audited(|| "set_entry_point".to_string(), || {
+                *entry_point = Some(slot);
+                self.audit(|| GraphEvent::EntryPoint { id: Some(id) });
+            });
         }
+        self.publish();
+        Ok(())
+    }
 
-        selected
+    fn copy_graph(&self) -> Graph {
+        let nodes = self.nodes.read().unwrap();
+        let entry_point = self.entry_point.read().unwrap();
+        Graph {
+            nodes: nodes.clone(),
+            entry_point: *entry_point,
+        }
     }
 
-    pub fn search(&self, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
-        let nodes = self.nodes.lock().unwrap();
-        let entry_point = self.entry_point.lock().unwrap();
-    
-        if nodes.is_empty() {
-            return Ok(Vec::new());
+    /// Removes every vector and resets the entry point and the `add_auto`
+    /// counter, keeping the configuration (metric, transform, hooks, quota,
+    /// ...) so the same index object can be rebuilt in place.
+    pub fn clear(&self) {
+        {
+            let mut nodes = self.nodes.write().unwrap();
+            let mut entry_point = self.entry_point.write().unwrap();
+            self.audited(|| "clear".to_string(), || {
+                nodes.clear();
+                self.slot_epoch.fetch_add(1, AtomicOrdering::Relaxed);
+                *entry_point = None;
+            });
+            self.next_id.store(0, AtomicOrdering::Relaxed);
         }
-    
-        let ep = entry_point.unwrap();
-        let mut curr_ep = ep;
-        let mut curr_dist = self.calculate_distances(query, &nodes[&curr_ep].item);
-        let ep_level = nodes[&ep].layer;
-    
-        // First traverse down to find a good entering point
-        for level in (1..=ep_level).rev() {
-            loop {
-                let mut best_dist = curr_dist;
-                let mut best_ep = curr_ep;
-                
-                // Check all neighbors at this level
-                if let Some(node) = nodes.get(&curr_ep) {
-                    if level < node.connections.len() {
-                        for &neighbor_id in &node.connections[level] {
-                            let dist = self.calculate_distances(query, &nodes[&neighbor_id].item);
-                            if dist < best_dist {
-                                best_dist = dist;
-                                best_ep = neighbor_id;
-                            }
+        self.publish();
+    }
+
+    /// Keeps only the first `len` vectors inserted (counting overwritten ids
+    /// at their original position) and unlinks the rest. Ids are not reused
+    /// by `add_auto`.
+    pub fn truncate(&self, len: usize) {
+        {
+            let mut nodes = self.nodes.write().unwrap();
+            let mut entry_point = self.entry_point.write().unwrap();
+            if len >= nodes.len() {
+                return;
+            }
+            self.audited(|| format!("truncate {}", len), || {
+                for (slot, node) in nodes.iter().enumerate() {
+                    for (level, links) in nodes.all_links(slot).enumerate() {
+                        for &neighbor in links.iter().filter(|&&neighbor| slot >= len || neighbor >= len) {
+                            self.audit(|| GraphEvent::EdgeRemoved { from: node.id, to: nodes[neighbor].id, level });
                         }
                     }
                 }
-                
-                if best_ep == curr_ep {
-                    break;  // No better neighbor found
+                nodes.truncate(len);
+                self.slot_epoch.fetch_add(1, AtomicOrdering::Relaxed);
+                for slot in 0..len {
+                    for level in 0..nodes.layers(slot) {
+                        nodes.retain_links(slot, level, |neighbor| neighbor < len);
+                    }
                 }
-                curr_ep = best_ep;
-                curr_dist = best_dist;
-            }
+                self.maintain_entry_point(&nodes, &mut entry_point);
+            });
         }
-    
-        // Perform final search at layer 0 with larger ef
-        let mut neighbors = self.search_at_layer(&nodes, curr_ep, query, 0, EF_SEARCH)?;
-        
-        // Sort by distance before returning
-        neighbors.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
-        
-        Ok(neighbors
-            .into_iter()
-            .take(k)
-            .map(|n| nodes[&n.id].item.clone())
-            .collect())
+        self.publish();
     }
-    
-    pub fn batch_add(&self, items: Vec<VectorItem>) -> Result<(), String> {
-        for item in items {
-            self.add(item)?;
+
+    /// Removes `id` and returns whether it was in the index. Nodes that
+    /// linked to it are relinked among its neighbors so the graph stays
+    /// navigable. The most recently inserted vector takes the removed one's
+    /// place in insertion order (`iter`, `truncate`).
+    pub fn remove(&self, id: usize) -> Result<bool, String> {
+        {
+            let mut nodes = self.nodes.write().unwrap();
+            let mut entry_point = self.entry_point.write().unwrap();
+            let Some(slot) = nodes.slot_of(id) else {
+                return Ok(false);
+            };
+            self.audited(|| format!("remove {}", id), || self.unlink_node(&mut nodes, &mut entry_point, slot))?;
         }
-        Ok(())
+        self.publish();
+        Ok(true)
     }
 
-    pub fn get_stats(&self) -> IndexStats {
-        let nodes = self.nodes.lock().unwrap();
-        let mut level_counts = HashMap::new();
-        let mut total_connections = 0;
+    // Detaches the node in `slot`, repairs the lists that pointed at it and
+    // drops it from the slab.
+    fn unlink_node(&self, nodes: &mut NodeSlab, entry_point: &mut Option<usize>, slot: usize) -> Result<(), String> {
+        let id = nodes[slot].id;
+        let orphaned: Vec<Vec<usize>> = nodes.all_links(slot).map(<[usize]>::to_vec).collect();
+        for (level, links) in orphaned.iter().enumerate() {
+            for &neighbor in links {
+                self.audit(|| GraphEvent::EdgeRemoved { from: id, to: nodes[neighbor].id, level });
+            }
+        }
 
-        for node in nodes.values() {
-            *level_counts.entry(node.layer).or_insert(0) += 1;
-            total_connections += node
-                .connections
-                .iter()
-                .map(|conns| conns.len())
-                .sum::<usize>();
+        // Drop every link to the node first, so relinking never picks it
+        let mut damaged = Vec::new();
+        for other in (0..nodes.len()).filter(|&other| other != slot) {
+            for level in 0..nodes.layers(other) {
+                if nodes.links(other, level).contains(&slot) {
+                    nodes.retain_links(other, level, |neighbor| neighbor != slot);
+                    self.audit(|| GraphEvent::EdgeRemoved { from: nodes[other].id, to: id, level });
+                    damaged.push((other, level));
+                }
+            }
         }
 
-        IndexStats {
-            total_nodes: nodes.len(),
-            level_distribution: level_counts,
-            total_connections,
-            max_level: self.max_level,
+        // Offer each damaged list the removed node's neighbors on that layer
+        for (other, level) in damaged {
+            let node = &nodes[other];
+            let query = self.vector_of(node);
+            let current = nodes.links(other, level);
+            let mut candidates: Vec<usize> = current.to_vec();
+            for &neighbor in orphaned.get(level).into_iter().flatten() {
+                if neighbor != other && !candidates.contains(&neighbor) {
+                    candidates.push(neighbor);
+                }
+            }
+            let candidates: Vec<_> = candidates
+                .into_iter()
+                .map(|id| Neighbor { id, distance: self.calculate_distances(&query, &self.vector_of(&nodes[id])) })
+                .collect();
+            let kept = self.select_neighbors(nodes, &query, &candidates, level)?;
+            for &neighbor in current.iter().filter(|neighbor| !kept.contains(neighbor)) {
+                self.audit(|| GraphEvent::EdgeRemoved { from: node.id, to: nodes[neighbor].id, level });
+            }
+            for &neighbor in kept.iter().filter(|neighbor| !current.contains(neighbor)) {
+                self.audit(|| GraphEvent::EdgeAdded { from: node.id, to: nodes[neighbor].id, level });
+            }
+            nodes.set_links(other, level, &kept);
         }
-    }
-}
 
-#[derive(Debug)]
-pub struct IndexStats {
-    pub total_nodes: usize,
-    pub level_distribution: HashMap<usize, usize>,
-    pub total_connections: usize,
-    pub max_level: usize,
-}
+        if *entry_point == Some(slot) {
+            *entry_point = None;
+        }
+
+        // The last node moves into the freed slot; point its links there
+        let last = nodes.len() - 1;
+        nodes.swap_remove(slot);
+        self.slot_epoch.fetch_add(1, AtomicOrdering::Relaxed);
+        if slot != last {
+            for other in 0..nodes.len() {
+                for level in 0..nodes.layers(other) {
+                    for neighbor in nodes.links_mut(other, level).iter_mut().filter(|neighbor| **neighbor == last) {
+                        *neighbor = slot;
+                    }
+                }
+            }
+            if *entry_point == Some(last) {
+                *entry_point = Some(slot);
+            }
+        }
+        self.maintain_entry_point(nodes, entry_point);
+        Ok(())
+    }
+
+    // Elects a new entry point unless the current one is a node on the top
+    // layer, e.g. after its node was removed or the top layer emptied.
+    fn maintain_entry_point(&self, nodes: &NodeSlab, entry_point: &mut Option<usize>) {
+        let checked = checked_entry_point(nodes, *entry_point);
+        if checked != *entry_point {
+            *entry_point = checked;
+            self.audit(|| GraphEvent::EntryPoint { id: checked.map(|slot| nodes[slot].id) });
+        }
+    }
+
+    // Counts `inserted` unpublished inserts and publishes once enough pile up.
+    fn note_inserts(&self, inserted: usize) {
+        if let Some(snapshots) = &self.snapshot_reads {
+            let pending = snapshots.pending.fetch_add(inserted, AtomicOrdering::Relaxed) + inserted;
+            if pending >= snapshots.refresh_every {
+                self.publish();
+            }
+        }
+    }
+
+    // Runs `f` on the graph searches should see: the published snapshot in
+    // snapshot mode (no lock held while `f` runs), the live graph otherwise.
+    fn with_search_graph<R>(&self, f: impl FnOnce(&NodeSlab, Option<usize>) -> R) -> R {
+        match &self.snapshot_reads {
+            Some(snapshots) => {
+                let graph = Arc::clone(&snapshots.published.read().unwrap());
+                f(&graph.nodes, graph.entry_point)
+            }
+            None => {
+                let nodes = self.nodes.read().unwrap();
+                let entry_point = *self.entry_point.read().unwrap();
+                f(&nodes, entry_point)
+            }
+        }
+    }
+
+    /// Searches coarse-to-fine on prefixes of the vectors at the given
+    /// dimensionalities: the upper layers are traversed with the smallest
+    /// prefix, layer 0 with the largest, and the candidates are reranked
+    /// with the full vector. Intended for Matryoshka embeddings. Ignored
+    /// under a quantization that drops the full vectors.
+    pub fn with_resolutions(mut self, mut dims: Vec<usize>) -> Self {
+        dims.retain(|&dim| dim > 0);
+        dims.sort_unstable();
+        dims.dedup();
+        self.resolutions = dims;
+        self
+    }
+
+    pub fn resolutions(&self) -> &[usize] {
+        &self.resolutions
+    }
+
+    /// Applies `transform` to every inserted vector and every query, e.g.
+    /// `VectorTransform::truncate(256)` to index 1536-d embeddings at 256-d.
+    pub fn with_transform(mut self, transform: VectorTransform) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    pub fn transform(&self) -> Option<&VectorTransform> {
+        self.transform.as_ref()
+    }
+
+    pub fn with_distance_calculator(
+        mut self,
+        distance_calculator: Box<dyn DistanceCalculator + Send + Sync>,
+    ) -> Self {
+        self.distance_calculator = distance_calculator;
+        self
+    }
+
+    /// Inserts `vector` under a freshly allocated id and returns it. Ids are
+    /// allocated above every id inserted so far, including explicit ones, so
+    /// they never collide.
+    pub fn add_auto(&self, vector: Vec<f64>) -> Result<usize, String> {
+        let id = self.next_id.fetch_add(1, AtomicOrdering::Relaxed);
+        self.add(VectorItem { id, vector })?;
+        Ok(id)
+    }
+
+    pub fn add(&self, item: VectorItem) -> Result<(), String> {
+        self.insert(item, None, None)
+    }
+
+    /// Inserts `item` with a payload that is returned by
+    /// `search_with_payloads` and `payload`. Payloads are kept by `save`, but
+    /// not by `save_hnswlib` or `load_mmap`.
+    pub fn add_with_payload(&self, item: VectorItem, payload: serde_json::Value) -> Result<(), String> {
+        self.insert(item, Some(payload), None)
+    }
+
+    /// Inserts `item` so that searches stop returning it at `expires_at`.
+    /// Until `purge_expired` reclaims it, the expired item still counts
+    /// toward `len` and is returned by `get`. Adding the id again replaces
+    /// the expiry. Expiries are kept by `save`, but not by `save_hnswlib`.
+    pub fn add_with_expiry(&self, item: VectorItem, expires_at: SystemTime) -> Result<(), String> {
+        self.insert(item, None, Some(expires_at))
+    }
+
+    /// The expiry set by `add_with_expiry` for `id`, if any.
+    pub fn expiry(&self, id: usize) -> Option<SystemTime> {
+        let nodes = self.nodes.read().unwrap();
+        nodes.slot_of(id).and_then(|slot| nodes[slot].expires_at)
+    }
+
+    /// Marks `id` deleted, like hnswlib's `mark_deleted`, and returns whether
+    /// it is in the index. Searches skip marked items but still traverse
+    /// their links, so unlike `remove` this leaves the graph untouched and
+    /// is undone by `unmark`. Marked items still count toward `len`, and
+    /// adding the id again clears the mark.
+    pub fn mark_deleted(&self, id: usize) -> bool {
+        self.set_deleted(id, true)
+    }
+
+    /// Clears a mark set by `mark_deleted`; returns whether `id` is in the
+    /// index.
+    pub fn unmark(&self, id: usize) -> bool {
+        self.set_deleted(id, false)
+    }
+
+    pub fn is_marked_deleted(&self, id: usize) -> bool {
+        let nodes = self.nodes.read().unwrap();
+        nodes.slot_of(id).is_some_and(|slot| nodes[slot].deleted)
+    }
+
+    fn set_deleted(&self, id: usize, deleted: bool) -> bool {
+        {
+            let mut nodes = self.nodes.write().unwrap();
+            let Some(slot) = nodes.slot_of(id) else {
+                return false;
+            };
+            nodes.get_mut(slot).unwrap().deleted = deleted;
+        }
+        self.publish();
+        true
+    }
+
+    /// Removes every expired item, as `remove` would, and returns how many
+    /// there were. Nothing runs this automatically; call it periodically,
+    /// from a background thread if need be.
+    pub fn purge_expired(&self) -> Result<usize, String> {
+        let now = metrics::wall_clock();
+        let purged = {
+            let mut nodes = self.nodes.write().unwrap();
+            let mut entry_point = self.entry_point.write().unwrap();
+            let expired: Vec<usize> = nodes.iter().filter(|node| node.is_expired(now)).map(|node| node.id).collect();
+            for &id in &expired {
+                let slot = nodes.slot_of(id).unwrap();
+                self.audited(|| format!("remove {}", id), || self.unlink_node(&mut nodes, &mut entry_point, slot))?;
+            }
+            expired.len()
+        };
+        if purged > 0 {
+            self.publish();
+        }
+        Ok(purged)
+    }
+
+    /// The number of vectors in the index.
+    pub fn len(&self) -> usize {
+        self.nodes.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.nodes.read().unwrap().contains_id(id)
+    }
+
+    /// The id of the node searches start from, a node on the top layer. A
+    /// new one is elected whenever removals take that node away or empty its
+    /// layer.
+    pub fn entry_point(&self) -> Option<usize> {
+        let nodes = self.nodes.read().unwrap();
+        let entry_point = self.entry_point.read().unwrap();
+        entry_point.map(|slot| nodes[slot].id)
+    }
+
+    /// The vector stored under `id`, after any `VectorTransform`.
+    pub fn get(&self, id: usize) -> Option<VectorItem> {
+        let nodes = self.nodes.read().unwrap();
+        nodes.slot_of(id).map(|slot| self.vector_of(&nodes[slot]).into_owned())
+    }
+
+    /// `get` for many ids under one lock acquisition; `None` for ids that are
+    /// not in the index.
+    pub fn get_many(&self, ids: &[usize]) -> Vec<Option<VectorItem>> {
+        let nodes = self.nodes.read().unwrap();
+        ids.iter()
+            .map(|&id| nodes.slot_of(id).map(|slot| self.vector_of(&nodes[slot]).into_owned()))
+            .collect()
+    }
+
+    /// `get_many`, paired with each item's payload.
+    pub fn get_many_with_payloads(&self, ids: &[usize]) -> Vec<Option<(VectorItem, Option<serde_json::Value>)>> {
+        let nodes = self.nodes.read().unwrap();
+        ids.iter()
+            .map(|&id| {
+                let node = &nodes[nodes.slot_of(id)?];
+                Some((self.vector_of(node).into_owned(), node.payload.clone()))
+            })
+            .collect()
+    }
+
+    /// The stored items, in insertion order, as of the call. Vectors are
+    /// shared with the index rather than copied, so this is cheap even for
+    /// large indexes, and later inserts do not affect the iteration.
+    pub fn iter(&self) -> impl Iterator<Item = std::sync::Arc<VectorItem>> {
+        let nodes = self.nodes.read().unwrap();
+        nodes
+            .iter()
+            .map(|node| match self.vector_of(node) {
+                Cow::Borrowed(_) => node.item.clone(),
+                Cow::Owned(item) => std::sync::Arc::new(item),
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// The stored ids, in insertion order, as of the call.
+    pub fn ids(&self) -> impl Iterator<Item = usize> {
+        let nodes = self.nodes.read().unwrap();
+        nodes.iter().map(|node| node.id).collect::<Vec<_>>().into_iter()
+    }
+
+    /// The payload stored with `id`, if any.
+    pub fn payload(&self, id: usize) -> Option<serde_json::Value> {
+        self.with_search_graph(|nodes, _| nodes.slot_of(id).and_then(|slot| nodes[slot].payload.clone()))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "hnsw.add", level = "debug", skip_all, fields(id = item.id), err)
+    )]
+    fn insert(
+        &self,
+        item: VectorItem,
+        payload: Option<serde_json::Value>,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), String> {
+        let stopwatch = Stopwatch::start();
+        let item = self.prepare_item(item)?;
+        let id = item.id;
+        // An overwrite keeps the old node's level (see `resolve_duplicate`)
+        let level_for = |nodes: &NodeSlab| match nodes.slot_of(id) {
+            Some(slot) => nodes[slot].layer,
+            None => self.random_level(nodes.len()),
+        };
+
+        // The layer searches only need shared access, so they run alongside
+        // searches and other inserts; the write locks are held just for linking.
+        // A deterministic build does everything under the write locks instead,
+        // so levels are drawn in the order nodes are linked.
+        let searched = match self.deterministic {
+            true => None,
+            false => {
+                let nodes = self.nodes.read().unwrap();
+                let entry_point = *self.entry_point.read().unwrap();
+                let node_level = level_for(&nodes);
+                let epoch = self.slot_epoch.load(AtomicOrdering::Relaxed);
+                match entry_point {
+                    Some(ep) => Some((node_level, Some(self.insertion_candidates(&nodes, ep, &item, node_level)?), epoch)),
+                    None => Some((node_level, None, epoch)),
+                }
+            }
+        };
+
+        let mut nodes = self.nodes.write().unwrap();
+        let mut entry_point = self.entry_point.write().unwrap();
+        let (node_level, candidates) = match searched {
+            // Candidates name slots, which a removal in the meantime may have moved
+            Some((node_level, candidates, epoch)) if epoch == self.slot_epoch.load(AtomicOrdering::Relaxed) => {
+                (node_level, candidates)
+            }
+            Some((node_level, _, _)) => (node_level, None),
+            None => (level_for(&nodes), None),
+        };
+        // The id was added or removed while the candidates were searched
+        let (node_level, candidates) = match nodes.slot_of(id).map(|slot| nodes[slot].layer) {
+            Some(layer) if layer != node_level => (layer, None),
+            _ => (node_level, candidates),
+        };
+        // Checked before an overwrite unlinks the old node, so a rejected
+        // item leaves the graph as it was
+        self.check_dimension(&nodes, &item, "Vector")?;
+        let is_new = !nodes.contains_id(item.id);
+        self.check_vector_quota(&nodes, usize::from(is_new))?;
+        let inserted = self.audited(|| format!("insert {} level {}", id, node_level), || {
+            if !self.resolve_duplicate(&mut nodes, &mut entry_point, item.id)? {
+                return Ok(false);
+            }
+            let candidates = match (candidates, *entry_point) {
+                (Some(candidates), _) => candidates,
+                // Another insert created the first node in the meantime, or
+                // the graph was reshaped
+                (None, Some(ep)) => self.insertion_candidates(&nodes, ep, &item, node_level)?,
+                (None, None) => Vec::new(),
+            };
+            self.link_node(&mut nodes, &mut entry_point, item, node_level, candidates)?;
+            Ok::<_, String>(true)
+        })?;
+        if !inserted {
+            return Ok(());
+        }
+        if payload.is_some() || expires_at.is_some() {
+            let slot = nodes.slot_of(id).unwrap();
+            let node = nodes.get_mut(slot).unwrap();
+            node.payload = payload;
+            node.expires_at = expires_at;
+        }
+        drop((nodes, entry_point));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(level = node_level, new = is_new, "inserted");
+        self.record_inserts(usize::from(is_new), usize::from(!is_new), stopwatch.elapsed());
+        self.note_inserts(1);
+        Ok(())
+    }
+
+    // Runs the insert hooks and the transform, and records the id for `add_auto`.
+    fn prepare_item(&self, mut item: VectorItem) -> Result<VectorItem, String> {
+        for hook in &self.insert_hooks {
+            hook.before_insert(item.id, &mut item.vector)?;
+        }
+        self.next_id.fetch_max(item.id.saturating_add(1), AtomicOrdering::Relaxed);
+        match &self.transform {
+            Some(transform) => Ok(VectorItem {
+                id: item.id,
+                vector: transform.apply(&item.vector)?,
+            }),
+            None => Ok(item),
+        }
+    }
+
+    // Neighbor candidates for a new node at each of its levels (index = level).
+    // Only reads the graph, so several inserts can compute these concurrently.
+    fn insertion_candidates(
+        &self,
+        nodes: &NodeSlab,
+        entry_point: usize,
+        item: &VectorItem,
+        node_level: usize,
+    ) -> Result<Vec<Vec<Neighbor>>, String> {
+        // Neighbor selection compares candidates with exact distances between
+        // stored vectors, so the candidates need exact distances too whenever
+        // the full vectors are there
+        let quantization = self.quantization.as_ref().filter(|q| !q.keeps_full_vectors());
+        let prepared = quantization.map(|q| q.prepare_query(self.distance_calculator.as_ref(), &item.vector));
+        let metric = NodeDistance {
+            calculator: self.distance_calculator.as_ref(),
+            prefix: None,
+            quantized: quantization.zip(prepared.as_ref()),
+            query_norm: query_norm(self.distance_calculator.as_ref(), item),
+            trace: None,
+        };
+        (0..=node_level)
+            .map(|level| {
+                self.search_at_layer(nodes, entry_point, item, level,
+                    if level == 0 { self.ef_construction } else { self.m }, metric, None)
+            })
+            .collect()
+    }
+
+    // Applies the duplicate policy to `id`. Returns whether to go ahead with
+    // the insert; on overwrite, the old node is first unlinked from its
+    // neighbors. Nodes it did not link back to keep their links to its slot,
+    // which then lead to the new node; it is inserted on the same level, so
+    // those links stay on layers it is on.
+    fn resolve_duplicate(
+        &self,
+        nodes: &mut NodeSlab,
+        entry_point: &mut Option<usize>,
+        id: usize,
+    ) -> Result<bool, String> {
+        let slot = match nodes.slot_of(id) {
+            Some(slot) => slot,
+            None => return Ok(true),
+        };
+        match self.duplicate_policy {
+            DuplicatePolicy::Reject => Err(format!("Id {} is already in the index", id)),
+            DuplicatePolicy::Ignore => Ok(false),
+            DuplicatePolicy::Overwrite => {
+                // The node's own links go with it when `link_node` replaces it
+                for (level, layer) in nodes.all_links(slot).enumerate() {
+                    for &neighbor in layer {
+                        self.audit(|| GraphEvent::EdgeRemoved { from: id, to: nodes[neighbor].id, level });
+                    }
+                }
+                for level in 0..nodes.layers(slot) {
+                    for other in nodes.links(slot, level).to_vec() {
+                        if nodes.links(other, level).contains(&slot) {
+                            nodes.retain_links(other, level, |neighbor| neighbor != slot);
+                            self.audit(|| GraphEvent::EdgeRemoved { from: nodes[other].id, to: id, level });
+                        }
+                    }
+                }
+                if *entry_point == Some(slot) {
+                    *entry_point = elect_entry_point(nodes, Some(slot));
+                    self.audit(|| GraphEvent::EntryPoint { id: entry_point.map(|slot| nodes[slot].id) });
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    // Connects a new node to the selected candidates and inserts it.
+    fn link_node(
+        &self,
+        nodes: &mut NodeSlab,
+        entry_point: &mut Option<usize>,
+        item: VectorItem,
+        node_level: usize,
+        candidates: Vec<Vec<Neighbor>>,
+    ) -> Result<(), String> {
+        self.check_dimension(nodes, &item, "Vector")?;
+        let node_id = item.id;
+        let slot = nodes.next_slot(node_id);
+        let mut connections = self.empty_links(node_level);
+
+        // Handle first node case
+        let entry = match *entry_point {
+            Some(entry) => entry,
+            None => {
+                let new_node = Node {
+                    id: node_id,
+                    codes: self.encode(&item),
+                    norm: self.cached_norm(&item),
+                    item: self.store_item(item),
+                    layer: node_level,
+                    payload: None,
+                    expires_at: None,
+                    deleted: false,
+                };
+                *entry_point = Some(nodes.insert(new_node, connections));
+                self.audit(|| GraphEvent::EntryPoint { id: Some(node_id) });
+                return Ok(());
+            }
+        };
+
+        // Select the new node's neighbors at each layer
+        for (level, neighbors) in candidates.into_iter().enumerate() {
+            // An overwritten node may still show up among its own candidates
+            let neighbors: Vec<_> = neighbors.into_iter().filter(|n| n.id != slot).collect();
+            if level < connections.len() {
+                connections[level].extend(self.select_neighbors(nodes, &item, &neighbors, level)?);
+            }
+        }
+
+        // Insert the new node before the reverse links, so pruning can
+        // measure distances to it
+        let selected_links = connections.clone();
+        let new_node = Node {
+            id: node_id,
+            codes: self.encode(&item),
+            norm: self.cached_norm(&item),
+            item: self.store_item(item),
+            layer: node_level,
+            payload: None,
+            expires_at: None,
+            deleted: false,
+        };
+        nodes.insert(new_node, connections);
+
+        // Update reverse connections: append the new edge and, if that takes
+        // the neighbor over its degree limit, prune its list back down
+        for (level, selected) in selected_links.iter().enumerate() {
+            for &neighbor_id in selected {
+                self.audit(|| GraphEvent::EdgeAdded { from: node_id, to: nodes[neighbor_id].id, level });
+                self.add_reverse_edge(nodes, neighbor_id, slot, level)?;
+            }
+        }
+
+        // Update entry point if necessary
+        if node_level > nodes[entry].layer {
+            *entry_point = Some(slot);
+            self.audit(|| GraphEvent::EntryPoint { id: Some(node_id) });
+        }
+
+        Ok(())
+    }
+
+    // Runs one graph mutation as a unit in the audit log, if there is one.
+    fn audited<R>(&self, operation: impl FnOnce() -> String, mutation: impl FnOnce() -> R) -> R {
+        let Some(audit) = &self.audit else {
+            return mutation();
+        };
+        audit.begin(operation);
+        let result = mutation();
+        audit.finish();
+        result
+    }
+
+    fn audit(&self, event: impl FnOnce() -> GraphEvent) {
+        if let Some(audit) = &self.audit {
+            audit.record(event);
+        }
+    }
+
+    fn store_item(&self, item: VectorItem) -> std::sync::Arc<VectorItem> {
+        match (&self.vector_pool, &self.quantization) {
+            // Only the codes are kept
+            (_, Some(quantization)) if !quantization.keeps_full_vectors() => {
+                std::sync::Arc::new(VectorItem { id: item.id, vector: Vec::new() })
+            }
+            (Some(pool), _) => pool.intern(item),
+            (None, _) => std::sync::Arc::new(item),
+        }
+    }
+
+    fn encode(&self, item: &VectorItem) -> Vec<u8> {
+        match &self.quantization {
+            Some(quantization) => quantization.encode(&item.vector),
+            None => Vec::new(),
+        }
+    }
+
+    // The norm cache for a new node, filled now if the metric uses norms.
+    fn cached_norm(&self, item: &VectorItem) -> OnceLock<f64> {
+        match self.distance_calculator.uses_norms() {
+            true => OnceLock::from(vector::norm(&item.vector)),
+            false => OnceLock::new(),
+        }
+    }
+
+    // The distance between two stored nodes, from their cached norms when
+    // the metric uses norms and the full vectors are kept.
+    fn node_distance(&self, a: &Node, b: &Node) -> f64 {
+        let full_vectors = self.quantization.as_ref().is_none_or(Quantization::keeps_full_vectors);
+        match self.distance_calculator.uses_norms() && full_vectors {
+            true => self.distance_calculator.distance_with_norms(&a.item.vector, a.norm(), &b.item.vector, b.norm()),
+            false => self.calculate_distances(&self.vector_of(a), &self.vector_of(b)),
+        }
+    }
+
+    fn add_reverse_edge(&self, nodes: &mut NodeSlab, from: usize, to: usize, level: usize) -> Result<(), String> {
+        if level >= nodes.layers(from) || nodes.links(from, level).contains(&to) {
+            return Ok(());
+        }
+        nodes.push_link(from, level, to);
+        self.audit(|| GraphEvent::EdgeAdded { from: nodes[from].id, to: nodes[to].id, level });
+        if nodes.links(from, level).len() > self.max_connections(level) {
+            self.shrink_connections(nodes, from, level)?;
+        }
+        Ok(())
+    }
+
+    // Cuts the links of `slot` at `level` back down to the layer's degree
+    // limit, keeping the ones the neighbor selection prefers.
+    fn shrink_connections(&self, nodes: &mut NodeSlab, slot: usize, level: usize) -> Result<(), String> {
+        let node = &nodes[slot];
+        let candidates: Vec<_> = nodes
+            .links(slot, level)
+            .iter()
+            .map(|&id| Neighbor { id, distance: self.node_distance(node, &nodes[id]) })
+            .collect();
+        let kept = self.select_neighbors(nodes, &self.vector_of(node), &candidates, level)?;
+        for dropped in nodes.links(slot, level).iter().filter(|neighbor| !kept.contains(neighbor)) {
+            self.audit(|| GraphEvent::EdgeRemoved { from: node.id, to: nodes[*dropped].id, level });
+        }
+        // Refills the list in place, within its preallocated room
+        nodes.set_links(slot, level, &kept);
+        Ok(())
+    }
+
+    // Picks the neighbors of `query` among `candidates` at `level`, using the
+    // index's `NeighborSelection` (Algorithms 3 and 4 of the HNSW paper).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "hnsw.select_neighbors", level = "trace", skip_all, fields(level, candidates = candidates.len()))
+    )]
+    fn select_neighbors(
+        &self,
+        nodes: &NodeSlab,
+        query: &VectorItem,
+        candidates: &[Neighbor],
+        level: usize,
+    ) -> Result<Vec<usize>, String> {
+        let max_connections = self.max_connections(level);
+        let mut working: Vec<_> = candidates.to_vec();
+
+        let (extend_candidates, keep_pruned) = match self.neighbor_selection {
+            NeighborSelection::Simple => {
+                working.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+                return Ok(working.iter().take(max_connections).map(|n| n.id).collect());
+            }
+            NeighborSelection::Heuristic { extend_candidates, keep_pruned } => (extend_candidates, keep_pruned),
+        };
+
+        if extend_candidates {
+            let mut seen: HashSet<usize> = candidates.iter().map(|n| n.id).collect();
+            for candidate in candidates {
+                for &adj in nodes.links(candidate.id, level) {
+                    if seen.insert(adj) {
+                        let distance = self.calculate_distances(query, &self.vector_of(&nodes[adj]));
+                        working.push(Neighbor { id: adj, distance });
+                    }
+                }
+            }
+        }
+        working.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+
+        // Keep a candidate only if it is closer to the query than to every
+        // neighbor kept so far, which spreads the links in all directions
+        let mut selected: Vec<usize> = Vec::with_capacity(max_connections);
+        let mut pruned = Vec::new();
+        for candidate in working {
+            if selected.len() >= max_connections {
+                break;
+            }
+            let diverse = selected.iter().all(|&existing| {
+                self.node_distance(&nodes[candidate.id], &nodes[existing]) >= candidate.distance
+            });
+            if diverse {
+                selected.push(candidate.id);
+            } else {
+                pruned.push(candidate.id);
+            }
+        }
+
+        if keep_pruned {
+            let room = max_connections - selected.len();
+            selected.extend(pruned.into_iter().take(room));
+        }
+        Ok(selected)
+    }
+
+    // The degree limit for links on `level`: 2M on the base layer, M above.
+    fn max_connections(&self, level: usize) -> usize {
+        if level == 0 { 2 * self.m } else { self.m }
+    }
+
+    // Adjacency lists for a node on `node_level`, each preallocated to hold
+    // one link over its limit (reverse edges are pushed before pruning).
+    fn empty_links(&self, node_level: usize) -> Vec<Vec<usize>> {
+        (0..=node_level).map(|level| Vec::with_capacity(self.max_connections(level) + 1)).collect()
+    }
+
+    // A level for a node joining an index of `size` nodes.
+    fn random_level(&self, size: usize) -> usize {
+        let cap = self.max_level.min(level_cap(size, self.m));
+        match &self.rng {
+            Some(rng) => self.draw_level(&mut *rng.lock().unwrap(), cap),
+            None => self.draw_level(&mut rand::thread_rng(), cap),
+        }
+    }
+
+    fn draw_level(&self, rng: &mut impl Rng, cap: usize) -> usize {
+        let mut level = 0;
+        while rng.gen::<f64>() < self.level_lambda && level < cap {
+            level += 1;
+        }
+        level
+    }
+
+    pub(crate) fn calculate_distances(&self, item1: &VectorItem, item2: &VectorItem) -> f64 {
+        self.distance_calculator.distance(&item1.vector, &item2.vector)
+    }
+
+    // Beam search of one layer. With `accept`, nodes failing it are still
+    // traversed but never returned, so the beam keeps looking until it has
+    // `ef` accepted nodes or runs out of graph.
+    #[allow(clippy::too_many_arguments)]
+    fn search_at_layer(
+        &self,
+        nodes: &NodeSlab,
+        entry_point: usize,
+        query: &VectorItem,
+        level: usize,
+        ef: usize,
+        metric: NodeDistance,
+        accept: Option<&dyn Fn(usize) -> bool>,
+    ) -> Result<Vec<Neighbor>, String> {
+        let accepted = |slot: usize| accept.is_none_or(|accept| accept(slot));
+        let entry_node = nodes.get(entry_point)
+            .ok_or_else(|| format!("Entry point {} not found", entry_point))?;
+    
+        if level >= nodes.layers(entry_point) {
+            return Ok(Vec::new());
+        }
+    
+        let mut visited = HashSet::new();
+        // `Neighbor` orders by reversed distance: `candidates` pops the closest,
+        // while `results` (wrapped in `Reverse`) keeps the furthest on top
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+    
+        let initial_dist = metric.to(query, entry_node);
+        let initial = Neighbor {
+            id: entry_point,
+            distance: initial_dist,
+        };
+    
+        candidates.push(initial.clone());
+        if accepted(entry_point) {
+            results.push(Reverse(initial));
+        }
+        visited.insert(entry_point);
+        let (mut fresh, mut distances) = (Vec::new(), Vec::new());
+    
+        while let Some(current) = candidates.pop() {
+            // Get worst distance in results
+            let furthest_dist = match results.len() < ef {
+                true => f64::INFINITY,
+                false => results.peek().map_or(f64::INFINITY, |n| n.0.distance),
+            };
+    
+            if current.distance > furthest_dist {
+                break;
+            }
+    
+            // Measure all unvisited neighbors in one batch
+            fresh.clear();
+            fresh.extend(
+                nodes
+                    .links(current.id, level)
+                    .iter()
+                    .copied()
+                    .filter(|&neighbor_id| neighbor_id < nodes.len() && visited.insert(neighbor_id)),
+            );
+            distances.clear();
+            metric.batch(query, nodes, &fresh, &mut distances);
+            for (&neighbor_id, &distance) in fresh.iter().zip(&distances) {
+                let neighbor = Neighbor {
+                    id: neighbor_id,
+                    distance,
+                };
+                let furthest_dist = results.peek().map_or(f64::INFINITY, |n| n.0.distance);
+
+                if results.len() < ef || distance < furthest_dist {
+                    candidates.push(neighbor.clone());
+                    if accepted(neighbor_id) {
+                        results.push(Reverse(neighbor));
+                    }
+
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+            if let Some(trace) = metric.trace {
+                trace.borrow_mut().hop(visited.len());
+            }
+        }
+    
+        let mut results: Vec<Neighbor> = results.into_iter().map(|n| n.0).collect();
+        results.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+        Ok(results)
+    }
+
+    /// The `k` stored items nearest to `query`, nearest first. Every search
+    /// method returns an empty list for `k == 0` or an empty index, and at
+    /// most `len()` items otherwise; a query of the wrong dimension is still
+    /// an error in both cases once the dimension is known.
+    pub fn search(&self, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        self.search_excluding(query, k, &[])
+    }
+
+    /// Like `search`, returning `(id, distance)` pairs instead of copies of
+    /// the vectors, which dominate the cost of large-`k` searches over wide
+    /// vectors; `get_many` fetches the vectors of the ids that turn out to be
+    /// needed. Distances are the ones results were ranked by, so they are
+    /// approximate under a `Quantization` that drops the full vectors. Search
+    /// hooks work on vectors, so with any installed this copies them anyway.
+    pub fn search_ids(&self, query: &VectorItem, k: usize) -> Result<Vec<(usize, f64)>, String> {
+        self.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        let (results, items) = self.with_search_graph(|nodes, entry_point| {
+            let neighbors = self.search_neighbors(nodes, entry_point, &prepared, &self.request(k))?;
+            let results: Vec<(usize, f64)> = neighbors.iter().map(|n| (nodes[n.id].id, n.distance)).collect();
+            let items = (!self.search_hooks.is_empty()).then(|| self.items_of(nodes, &neighbors));
+            Ok::<_, String>((results, items))
+        })?;
+        let Some(items) = items else {
+            return Ok(results);
+        };
+        // Hooks may drop, reorder or rewrite results; follow what they return
+        let distances: HashMap<usize, f64> = results.into_iter().collect();
+        Ok(self
+            .run_search_hooks(query, items)?
+            .iter()
+            .map(|item| (item.id, distances.get(&item.id).copied().unwrap_or_else(|| self.calculate_distances(&prepared, item))))
+            .collect())
+    }
+
+    /// Like `search`, also returning what the search did: where it entered
+    /// the graph, and the hops, distance computations and visited nodes on
+    /// each layer. Meant for working out why a query has poor recall.
+    pub fn search_traced(&self, query: &VectorItem, k: usize) -> Result<(Vec<VectorItem>, SearchTrace), String> {
+        self.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        let trace = RefCell::new(SearchTrace::default());
+        let results = self.with_search_graph(|nodes, entry_point| {
+            self.search_nodes(nodes, entry_point, &prepared, &SearchRequest { trace: Some(&trace), ..self.request(k) })
+        })?;
+        Ok((self.run_search_hooks(query, results)?, trace.into_inner()))
+    }
+
+    /// Like `search`, paired with each result's payload.
+    pub fn search_with_payloads(
+        &self,
+        query: &VectorItem,
+        k: usize,
+    ) -> Result<Vec<(VectorItem, Option<serde_json::Value>)>, String> {
+        let results = self.search(query, k)?;
+        Ok(self.with_search_graph(|nodes, _| {
+            results
+                .into_iter()
+                .map(|item| {
+                    let payload = nodes.slot_of(item.id).and_then(|slot| nodes[slot].payload.clone());
+                    (item, payload)
+                })
+                .collect()
+        }))
+    }
+
+    /// Like `search`, but never returns the ids in `exclude` (e.g. items the
+    /// user has already seen); up to `k` other items are still returned.
+    pub fn search_excluding(&self, query: &VectorItem, k: usize, exclude: &[usize]) -> Result<Vec<VectorItem>, String> {
+        self.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        let results = self.with_search_graph(|nodes, entry_point| {
+            self.search_nodes(nodes, entry_point, &prepared, &SearchRequest { exclude, ..self.request(k) })
+        })?;
+        self.run_search_hooks(query, results)
+    }
+
+    /// Every stored item within `radius` of `query`, nearest first. The search
+    /// is repeated with a doubling `k` until the furthest result lies outside
+    /// the radius, so like `search` it is approximate: an item the graph walk
+    /// misses is missing here too.
+    pub fn search_radius(&self, query: &VectorItem, radius: f64) -> Result<Vec<VectorItem>, String> {
+        self.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        let within = |item: &VectorItem| self.calculate_distances(&prepared, item) <= radius;
+        let mut results = self.with_search_graph(|nodes, entry_point| {
+            let mut k = RADIUS_START_K;
+            loop {
+                let results = self.search_nodes(nodes, entry_point, &prepared, &self.request(k))?;
+                if results.len() < k || results.last().is_none_or(|item| !within(item)) {
+                    return Ok::<_, String>(results);
+                }
+                k *= 2;
+            }
+        })?;
+        results.retain(within);
+        self.run_search_hooks(query, results)
+    }
+
+    /// The true `k` nearest stored items to `query`, found by comparing it
+    /// with every item in parallel rather than walking the graph. Ties are
+    /// broken by id. Useful as ground truth, and for indexes small enough
+    /// that a scan is as fast as a graph search. Rerankers are not applied.
+    pub fn search_exact(&self, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        self.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        let results = self.with_search_graph(|nodes, _| {
+            self.check_dimension(nodes, &prepared, "Query")?;
+            let now = metrics::wall_clock();
+            let mut scored: Vec<(f64, usize)> = (0..nodes.len())
+                .into_par_iter()
+                .filter(|&slot| nodes[slot].is_live(now))
+                .map(|slot| (self.calculate_distances(&prepared, &self.vector_of(&nodes[slot])), slot))
+                .collect();
+            let by_distance = |a: &(f64, usize), b: &(f64, usize)| a.0.total_cmp(&b.0).then(nodes[a.1].id.cmp(&nodes[b.1].id));
+            if k < scored.len() {
+                scored.select_nth_unstable_by(k, by_distance);
+                scored.truncate(k);
+            }
+            scored.sort_by(by_distance);
+            Ok::<_, String>(scored.into_iter().map(|(_, slot)| self.vector_of(&nodes[slot]).into_owned()).collect())
+        })?;
+        self.run_search_hooks(query, results)
+    }
+
+    /// Searches only among ids `filter` allows: a closure, an id set or a
+    /// bitmap. The graph is explored past rejected nodes, so a selective
+    /// filter still yields `k` results when that many reachable ids pass it,
+    /// at the cost of visiting more nodes.
+    pub fn search_filtered(
+        &self,
+        query: &VectorItem,
+        k: usize,
+        filter: &impl IdFilter,
+    ) -> Result<Vec<VectorItem>, String> {
+        self.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        let results = self.with_search_graph(|nodes, entry_point| {
+            self.search_nodes(nodes, entry_point, &prepared, &SearchRequest { filter: Some(filter), ..self.request(k) })
+        })?;
+        self.run_search_hooks(query, results)
+    }
+
+    /// The `k` nearest neighbors of the stored item `id`, excluding the item
+    /// itself. The stored vector is already transformed, so it is used as the
+    /// query without transforming it again.
+    pub fn neighbors_of(&self, id: usize, k: usize) -> Result<Vec<VectorItem>, String> {
+        self.check_query_quota(1)?;
+        let (query, results) = self.with_search_graph(|nodes, entry_point| {
+            let slot = nodes.slot_of(id).ok_or_else(|| format!("Id {} is not in the index", id))?;
+            let query = self.vector_of(&nodes[slot]).into_owned();
+            let results = self.search_nodes(nodes, entry_point, &query, &SearchRequest { exclude: &[id], ..self.request(k) })?;
+            Ok::<_, String>((query, results))
+        })?;
+        self.run_search_hooks(&query, results)
+    }
+
+    /// Searches using only the listed dimensions of the (transformed) vectors.
+    /// Both sides are projected onto `dims` before the index metric is applied,
+    /// so the ranking is exactly the metric restricted to that subspace. The
+    /// graph itself was built on full vectors, and prefix routing is skipped.
+    pub fn search_masked(&self, query: &VectorItem, k: usize, dims: &[usize]) -> Result<Vec<VectorItem>, String> {
+        self.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+
+        if dims.is_empty() {
+            return Err("Dimension mask must not be empty".to_string());
+        }
+        if let Some(&dim) = dims.iter().find(|&&dim| dim >= prepared.vector.len()) {
+            return Err(format!(
+                "Mask dimension {} is out of range for a {}-dimensional query",
+                dim,
+                prepared.vector.len()
+            ));
+        }
+
+        let masked = MaskedDistance::new(self.distance_calculator.as_ref(), dims);
+        let results = self.with_search_graph(|nodes, entry_point| {
+            self.search_nodes(nodes, entry_point, &prepared, &SearchRequest { calculator: &masked, resolutions: &[], ..self.request(k) })
+        })?;
+        self.run_search_hooks(query, results)
+    }
+
+    /// Runs `search` for every query in parallel and returns the results in
+    /// query order. The whole batch sees the same graph.
+    pub fn batch_search(&self, queries: &[VectorItem], k: usize) -> Result<Vec<Vec<VectorItem>>, String> {
+        self.batch_search_with(queries, k, None)
+    }
+
+    /// `search_filtered` for a batch of queries sharing one filter.
+    pub fn batch_search_filtered(
+        &self,
+        queries: &[VectorItem],
+        k: usize,
+        filter: &impl IdFilter,
+    ) -> Result<Vec<Vec<VectorItem>>, String> {
+        self.batch_search_with(queries, k, Some(filter))
+    }
+
+    fn batch_search_with(
+        &self,
+        queries: &[VectorItem],
+        k: usize,
+        filter: Option<&dyn IdFilter>,
+    ) -> Result<Vec<Vec<VectorItem>>, String> {
+        self.check_query_quota(queries.len())?;
+        let strategy = OnceLock::new();
+        self.with_search_graph(|nodes, entry_point| {
+            queries
+                .par_iter()
+                .map(|query| {
+                    let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+                    let request = SearchRequest { filter, strategy: Some(&strategy), ..self.request(k) };
+                    let results = self.search_nodes(nodes, entry_point, &prepared, &request)?;
+                    self.run_search_hooks(query, results)
+                })
+                .collect()
+        })
+    }
+
+    // A request with the index's metric and resolutions and no exclusions.
+    fn request(&self, k: usize) -> SearchRequest<'_> {
+        SearchRequest {
+            k,
+            calculator: self.distance_calculator.as_ref(),
+            resolutions: &self.resolutions,
+            exclude: &[],
+            filter: None,
+            trace: None,
+            strategy: None,
+        }
+    }
+
+    fn check_vector_quota(&self, nodes: &NodeSlab, additional: usize) -> Result<(), String> {
+        match &self.quota {
+            Some(quota) => quota.check_vectors(nodes.len(), additional).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    fn check_query_quota(&self, queries: usize) -> Result<(), String> {
+        match &self.quota {
+            Some(quota) => quota.acquire_queries(queries).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    fn run_search_hooks(&self, query: &VectorItem, mut results: Vec<VectorItem>) -> Result<Vec<VectorItem>, String> {
+        for hook in &self.search_hooks {
+            hook.after_search(query, &mut results)?;
+        }
+        Ok(results)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "hnsw.search", level = "debug", skip_all, fields(k = request.k), err)
+    )]
+    fn search_nodes(
+        &self,
+        nodes: &NodeSlab,
+        entry_point: Option<usize>,
+        query: &VectorItem,
+        request: &SearchRequest,
+    ) -> Result<Vec<VectorItem>, String> {
+        let neighbors = self.search_neighbors(nodes, entry_point, query, request)?;
+        Ok(self.items_of(nodes, &neighbors))
+    }
+
+    // Copies out the vectors of search results.
+    fn items_of(&self, nodes: &NodeSlab, neighbors: &[Neighbor]) -> Vec<VectorItem> {
+        neighbors.iter().map(|n| self.vector_of(&nodes[n.id]).into_owned()).collect()
+    }
+
+    // The results of a search as slots and distances, nearest first.
+    fn search_neighbors(
+        &self,
+        nodes: &NodeSlab,
+        entry_point: Option<usize>,
+        query: &VectorItem,
+        request: &SearchRequest,
+    ) -> Result<Vec<Neighbor>, String> {
+        let stopwatch = Stopwatch::start();
+        let results = match self.rerankers.is_empty() {
+            true => self.walk_graph(nodes, entry_point, query, request)?,
+            false => self.rerank(nodes, entry_point, query, request)?,
+        };
+        let elapsed = stopwatch.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(results = results.len(), elapsed_us = elapsed.as_micros() as u64, "searched");
+        self.metrics.record_search(results.len(), elapsed);
+        for sink in &self.metrics_sinks {
+            sink.on_search(results.len(), elapsed);
+        }
+        Ok(results)
+    }
+
+    // Fetches a candidate pool from the graph and lets the rerankers pick the
+    // top `k` from it.
+    fn rerank(
+        &self,
+        nodes: &NodeSlab,
+        entry_point: Option<usize>,
+        query: &VectorItem,
+        request: &SearchRequest,
+    ) -> Result<Vec<Neighbor>, String> {
+        if request.k == 0 {
+            return Ok(Vec::new());
+        }
+        let pool = SearchRequest { k: request.k.max(self.ef_search), ..*request };
+        let pool = self.walk_graph(nodes, entry_point, query, &pool)?;
+        let mut candidates: Vec<_> = self
+            .items_of(nodes, &pool)
+            .into_iter()
+            .map(|item| {
+                let distance = request.calculator.distance(&query.vector, &item.vector);
+                let payload = nodes.slot_of(item.id).and_then(|slot| nodes[slot].payload.clone());
+                Candidate { item, payload, distance, score: -distance }
+            })
+            .collect();
+        for reranker in &self.rerankers {
+            candidates = reranker.rerank(query, candidates)?;
+        }
+        Ok(candidates
+            .into_iter()
+            .take(request.k)
+            .filter_map(|candidate| Some(Neighbor { id: nodes.slot_of(candidate.item.id)?, distance: candidate.distance }))
+            .collect())
+    }
+
+    fn walk_graph(
+        &self,
+        nodes: &NodeSlab,
+        entry_point: Option<usize>,
+        query: &VectorItem,
+        request: &SearchRequest,
+    ) -> Result<Vec<Neighbor>, String> {
+        let SearchRequest { k, calculator, resolutions, exclude, filter, trace, strategy } = *request;
+        self.check_dimension(nodes, query, "Query")?;
+        let ep = match search_entry_point(nodes, entry_point) {
+            Some(ep) if k > 0 => ep,
+            _ => return Ok(Vec::new()),
+        };
+        // Widened so that excluded ids cannot crowd out the k results
+        let ef = self.ef_search.max(k + exclude.len());
+        if let Some(filter) = filter {
+            let choose = || self.strategy(nodes, entry_point, ef, filter);
+            let strategy = match strategy {
+                Some(shared) => *shared.get_or_init(choose),
+                None => choose(),
+            };
+            if strategy == SearchStrategy::BruteForce {
+                return Ok(self.scan(nodes, query, request, filter));
+            }
+        }
+        // With multiple resolutions, route on the smallest prefix and run the
+        // layer-0 beam on the largest; otherwise use the full vector throughout.
+        // Without full vectors there are no prefixes, so the codes are used.
+        let quantization = self.quantization.as_ref();
+        let resolutions = match quantization {
+            Some(quantization) if !quantization.keeps_full_vectors() => &[],
+            _ => resolutions,
+        };
+        let prepared = quantization.map(|q| q.prepare_query(calculator, &query.vector));
+        let quantized = quantization.zip(prepared.as_ref());
+        let full = NodeDistance { calculator, prefix: None, quantized, query_norm: query_norm(calculator, query), trace };
+        let at = |dims: Option<&usize>| match dims {
+            Some(&dims) => NodeDistance { prefix: Some(dims), query_norm: None, ..full },
+            None => full,
+        };
+        let (route, beam) = (at(resolutions.first()), at(resolutions.last()));
+
+        let mut curr_ep = ep;
+        let ep_level = nodes[ep].layer;
+        if let Some(trace) = trace {
+            let mut trace = trace.borrow_mut();
+            trace.entry_point = Some(nodes[ep].id);
+            trace.enter_layer(ep_level);
+        }
+        let mut curr_dist = route.to(query, &nodes[curr_ep]);
+    
+        // First traverse down to find a good entering point
+        for level in (1..=ep_level).rev() {
+            if level < ep_level {
+                if let Some(trace) = trace {
+                    trace.borrow_mut().enter_layer(level);
+                }
+            }
+            let mut visited = 1;
+            loop {
+                let mut best_dist = curr_dist;
+                let mut best_ep = curr_ep;
+                
+                // Check all neighbors at this level
+                if level < nodes.layers(curr_ep) {
+                    visited += nodes.links(curr_ep, level).len();
+                    if let Some(trace) = trace {
+                        trace.borrow_mut().hop(visited);
+                    }
+                    for &neighbor_id in nodes.links(curr_ep, level) {
+                        let dist = route.to(query, &nodes[neighbor_id]);
+                        if dist < best_dist {
+                            best_dist = dist;
+                            best_ep = neighbor_id;
+                        }
+                    }
+                }
+                
+                if best_ep == curr_ep {
+                    break;  // No better neighbor found
+                }
+                curr_ep = best_ep;
+                curr_dist = best_dist;
+            }
+        }
+    
+        // Perform final search at layer 0 with larger ef
+        if let Some(trace) = trace.filter(|_| ep_level > 0) {
+            trace.borrow_mut().enter_layer(0);
+        }
+        // Expired and marked nodes are traversed like filtered-out ones
+        let now = metrics::wall_clock();
+        let accept = |slot: usize| {
+            let node = &nodes[slot];
+            node.is_live(now) && filter.is_none_or(|filter| filter.allows(node.id))
+        };
+        let mut neighbors = self.search_at_layer(nodes, curr_ep, query, 0, ef, beam, Some(&accept))?;
+        if !exclude.is_empty() {
+            let excluded: HashSet<usize> = exclude.iter().filter_map(|&id| nodes.slot_of(id)).collect();
+            neighbors.retain(|n| !excluded.contains(&n.id));
+        }
+
+        // Rerank prefix-based or quantized candidates with the full vectors
+        if beam.prefix.is_some() || quantization.is_some_and(Quantization::keeps_full_vectors) {
+            for neighbor in &mut neighbors {
+                neighbor.distance = calculator.distance(&query.vector, &self.vector_of(&nodes[neighbor.id]).vector);
+            }
+        }
+        
+        // Sort by distance before returning
+        neighbors.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+        neighbors.truncate(k);
+        Ok(neighbors)
+    }
+    
+    // Exact search over the live items `filter` accepts.
+    fn scan(&self, nodes: &NodeSlab, query: &VectorItem, request: &SearchRequest, filter: &dyn IdFilter) -> Vec<Neighbor> {
+        let now = metrics::wall_clock();
+        let mut scored: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| filter.allows(node.id) && !request.exclude.contains(&node.id) && node.is_live(now))
+            .map(|(slot, node)| Neighbor { id: slot, distance: request.calculator.distance(&query.vector, &self.vector_of(node).vector) })
+            .collect();
+        scored.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        scored.truncate(request.k);
+        scored
+    }
+
+    // The plan sample for `nodes`, drawn once per graph size and slot epoch.
+    // The seed is fixed, so the same graph always gets the same sample.
+    fn plan_sample(&self, nodes: &NodeSlab) -> Arc<PlanSample> {
+        let epoch = self.slot_epoch.load(AtomicOrdering::Relaxed);
+        let mut cached = self.plan_sample.lock().unwrap();
+        if let Some(sample) = cached.as_ref().filter(|sample| sample.len == nodes.len() && sample.epoch == epoch) {
+            return Arc::clone(sample);
+        }
+        let slots = rand::seq::index::sample(&mut StdRng::seed_from_u64(0), nodes.len(), PLAN_SAMPLE.min(nodes.len())).into_vec();
+        let links: usize = slots.iter().map(|&slot| nodes.links(slot, 0).len()).sum();
+        let average_degree = if slots.is_empty() { 0.0 } else { links as f64 / slots.len() as f64 };
+        let sample = Arc::new(PlanSample { len: nodes.len(), epoch, slots, average_degree });
+        *cached = Some(Arc::clone(&sample));
+        sample
+    }
+
+    // The graph's side of a plan, as if the filter accepted the whole sample.
+    fn plan_input(&self, nodes: &NodeSlab, entry_point: Option<usize>, ef: usize, sample: &PlanSample) -> PlanInput {
+        PlanInput {
+            filtered: false,
+            len: nodes.len(),
+            sampled: sample.slots.len(),
+            accepted: sample.slots.len(),
+            top_level: search_entry_point(nodes, entry_point).map_or(0, |ep| nodes[ep].layer),
+            upper_degree: self.m,
+            average_degree: sample.average_degree,
+            ef,
+        }
+    }
+
+    // Costs a search from the graph's shape and, with a filter, the share of
+    // the plan sample it accepts.
+    fn plan(&self, nodes: &NodeSlab, entry_point: Option<usize>, ef: usize, filter: Option<&dyn IdFilter>) -> QueryPlan {
+        let sample = self.plan_sample(nodes);
+        let input = self.plan_input(nodes, entry_point, ef, &sample);
+        match filter {
+            Some(filter) => {
+                let accepted = sample.slots.iter().filter(|&&slot| filter.allows(nodes[slot].id)).count();
+                QueryPlan::choose(&PlanInput { filtered: true, accepted, ..input })
+            }
+            None => QueryPlan::choose(&input),
+        }
+    }
+
+    // The strategy `plan` picks for `filter`, evaluating the filter only until
+    // the rest of the sample is unlikely to change it. The sample is in random
+    // order, so each prefix is a sample too; accepting more ids only favours
+    // the graph, so the choice is settled once the prefix's share, three
+    // standard errors either way, lands on one side.
+    fn strategy(&self, nodes: &NodeSlab, entry_point: Option<usize>, ef: usize, filter: &dyn IdFilter) -> SearchStrategy {
+        let sample = self.plan_sample(nodes);
+        let input = PlanInput { filtered: true, ..self.plan_input(nodes, entry_point, ef, &sample) };
+        let total = sample.slots.len();
+        let (mut seen, mut accepted) = (0, 0);
+        for chunk in sample.slots.chunks(PLAN_CHUNK) {
+            seen += chunk.len();
+            accepted += chunk.iter().filter(|&&slot| filter.allows(nodes[slot].id)).count();
+            let share = accepted as f64 / seen as f64;
+            // Never zero, so a prefix passing all or nothing is not taken as exact
+            let margin = 3.0 * (share * (1.0 - share)).max(1.0 / seen as f64).sqrt() / (seen as f64).sqrt();
+            let fewest = (((share - margin) * total as f64).floor().max(0.0) as usize).max(accepted);
+            let most = (((share + margin) * total as f64).ceil() as usize).min(accepted + total - seen);
+            if QueryPlan::choose(&PlanInput { accepted: fewest, ..input }).strategy == SearchStrategy::Graph {
+                return SearchStrategy::Graph;
+            }
+            if QueryPlan::choose(&PlanInput { accepted: most, ..input }).strategy == SearchStrategy::BruteForce {
+                return SearchStrategy::BruteForce;
+            }
+        }
+        QueryPlan::choose(&input).strategy
+    }
+
+    /// How `search_filtered` (or `search`, without a filter) would run
+    /// `query` with this `k`: graph traversal or an exact scan of the items
+    /// the filter accepts, with estimates of the work involved. Filtered
+    /// searches pick whichever strategy is estimated to be cheaper.
+    pub fn explain_plan(&self, query: &VectorItem, k: usize, filter: Option<&dyn IdFilter>) -> Result<QueryPlan, String> {
+        let prepared = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        self.with_search_graph(|nodes, entry_point| {
+            self.check_dimension(nodes, &prepared, "Query")?;
+            Ok(self.plan(nodes, entry_point, self.ef_search.max(k), filter))
+        })
+    }
+
+    pub fn batch_add(&self, items: Vec<VectorItem>) -> Result<(), String> {
+        self.batch_add_with_progress(items, |_, _| true).map(drop)
+    }
+
+    /// Like `batch_add`, calling `progress(done, total)` after each item. If
+    /// it returns `false` the batch stops there, keeping the items added so
+    /// far. Returns how many items were added.
+    pub fn batch_add_with_progress(
+        &self,
+        items: Vec<VectorItem>,
+        mut progress: impl FnMut(usize, usize) -> bool,
+    ) -> Result<usize, String> {
+        let total = items.len();
+        self.reserve(total);
+        let mut done = 0;
+        for item in items {
+            self.add(item)?;
+            done += 1;
+            if !progress(done, total) {
+                break;
+            }
+        }
+        self.publish();
+        Ok(done)
+    }
+
+    /// Builds the batch on all rayon threads. Items are taken in chunks: the
+    /// graph searches for a chunk run in parallel against the graph as it was
+    /// before the chunk, distances to the chunk's earlier items are added as
+    /// extra candidates, and the nodes are then linked one by one in input
+    /// order. Chunks stay small relative to the index so the result is close
+    /// to sequential insertion. Every item is validated before any is inserted.
+    pub fn par_batch_add(&self, items: Vec<VectorItem>) -> Result<(), String> {
+        self.par_batch_add_with_progress(items, |_, _| true).map(drop)
+    }
+
+    /// Like `par_batch_add`, calling `progress(done, total)` after each chunk,
+    /// where `total` leaves out duplicates the policy ignores. If it returns
+    /// `false` the batch stops there, keeping the items added so far. The
+    /// index stays locked while `progress` runs, so it must not use the index.
+    /// Returns how many items were added.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "hnsw.par_batch_add", level = "debug", skip_all, fields(items = items.len()), err)
+    )]
+    pub fn par_batch_add_with_progress(
+        &self,
+        items: Vec<VectorItem>,
+        mut progress: impl FnMut(usize, usize) -> bool,
+    ) -> Result<usize, String> {
+        let stopwatch = Stopwatch::start();
+        let items = items
+            .into_par_iter()
+            .map(|item| self.prepare_item(item))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.par_insert(items, HashMap::new(), &mut progress, stopwatch)
+    }
+
+    // The body of `par_batch_add` for items already through `prepare_item`,
+    // storing `extras[id]` with each item that has them.
+    fn par_insert(
+        &self,
+        items: Vec<VectorItem>,
+        extras: HashMap<usize, NodeExtras>,
+        progress: &mut dyn FnMut(usize, usize) -> bool,
+        stopwatch: Stopwatch,
+    ) -> Result<usize, String> {
+        let mut nodes = self.nodes.write().unwrap();
+        let mut entry_point = self.entry_point.write().unwrap();
+        let (done, inserted) = self.par_link(&mut nodes, &mut entry_point, items, extras, progress)?;
+        drop((nodes, entry_point));
+        self.record_inserts(inserted, done - inserted, stopwatch.elapsed());
+        self.publish();
+        Ok(done)
+    }
+
+    // Links `items` into `nodes` chunk by chunk; returns how many were linked
+    // and how many of those were new.
+    fn par_link(
+        &self,
+        nodes: &mut NodeSlab,
+        entry_point: &mut Option<usize>,
+        items: Vec<VectorItem>,
+        mut extras: HashMap<usize, NodeExtras>,
+        progress: &mut dyn FnMut(usize, usize) -> bool,
+    ) -> Result<(usize, usize), String> {
+        if let Some(first) = items.first() {
+            let expected = self.expected_dimension(nodes).unwrap_or(first.vector.len());
+            if let Some(item) = items.iter().find(|item| item.vector.len() != expected) {
+                return Err(format!("Vector {} has {} dimensions, expected {}", item.id, item.vector.len(), expected));
+            }
+        }
+        let new_ids = items.iter().map(|item| item.id).filter(|&id| !nodes.contains_id(id)).collect::<HashSet<_>>();
+        self.check_vector_quota(nodes, new_ids.len())?;
+        nodes.reserve(new_ids.len());
+
+        let mut seen = HashSet::new();
+        let mut kept = Vec::with_capacity(items.len());
+        for item in items {
+            let duplicate = nodes.contains_id(item.id) || !seen.insert(item.id);
+            match (duplicate, self.duplicate_policy) {
+                (true, DuplicatePolicy::Reject) => return Err(format!("Id {} is already in the index", item.id)),
+                (true, DuplicatePolicy::Ignore) => {}
+                _ => kept.push(item),
+            }
+        }
+        let size = nodes.len();
+        let total = kept.len();
+        let (mut done, mut inserted) = (0, 0);
+        let mut items = kept
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| match nodes.slot_of(item.id) {
+                Some(slot) => (nodes[slot].layer, item),
+                None => (self.random_level(size + i), item),
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .peekable();
+
+        while items.peek().is_some() {
+            let chunk_size = match self.deterministic {
+                true => 1,
+                false => (nodes.len() / PAR_CHUNK_DIVISOR).clamp(1, PAR_CHUNK_MAX),
+            };
+            let chunk: Vec<_> = items.by_ref().take(chunk_size).collect();
+
+            // Slots the chunk's items will get when linked in order
+            let mut pending = HashMap::new();
+            let slots: Vec<usize> = chunk
+                .iter()
+                .map(|(_, item)| match nodes.slot_of(item.id) {
+                    Some(slot) => slot,
+                    None => {
+                        let next = nodes.len() + pending.len();
+                        *pending.entry(item.id).or_insert(next)
+                    }
+                })
+                .collect();
+
+            let candidates = chunk
+                .par_iter()
+                .enumerate()
+                .map(|(i, (level, item))| {
+                    let mut candidates = match *entry_point {
+                        Some(ep) => self.insertion_candidates(nodes, ep, item, *level)?,
+                        None => vec![Vec::new(); level + 1],
+                    };
+                    for ((other_level, other), &other_slot) in chunk[..i].iter().zip(&slots) {
+                        let neighbor = Neighbor {
+                            id: other_slot,
+                            distance: self.calculate_distances(item, other),
+                        };
+                        for layer in candidates.iter_mut().take(other_level + 1) {
+                            layer.push(neighbor.clone());
+                        }
+                    }
+                    Ok(candidates)
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            for ((level, item), mut candidates) in chunk.into_iter().zip(candidates) {
+                let id = item.id;
+                // An id repeated in the batch keeps the level it was first linked at
+                let level = match nodes.slot_of(id).map(|slot| nodes[slot].layer) {
+                    Some(layer) if layer != level => {
+                        candidates = match *entry_point {
+                            Some(ep) => self.insertion_candidates(nodes, ep, &item, layer)?,
+                            None => vec![Vec::new(); layer + 1],
+                        };
+                        layer
+                    }
+                    _ => level,
+                };
+                inserted += usize::from(!nodes.contains_id(id));
+                self.audited(|| format!("insert {} level {}", id, level), || {
+                    self.resolve_duplicate(nodes, entry_point, item.id)?;
+                    self.link_node(nodes, entry_point, item, level, candidates)
+                })?;
+                if let Some(extras) = extras.remove(&id) {
+                    let slot = nodes.slot_of(id).unwrap();
+                    extras.apply(nodes.get_mut(slot).unwrap());
+                }
+                done += 1;
+            }
+            if !progress(done, total) {
+                break;
+            }
+        }
+        Ok((done, inserted))
+    }
+
+    /// Adds every item of `other` to this index with its payload, expiry and
+    /// deletion mark, e.g. to combine shards built in parallel. The items are
+    /// re-inserted as by `par_batch_add`, so the merged graph is as good as
+    /// one built here, and this index's duplicate policy decides ids stored
+    /// in both. `other`'s vectors are already transformed, so both indexes
+    /// must have the same metric and transform; insert hooks are not run
+    /// again.
+    pub fn merge(&self, other: &HnswIndex) -> Result<(), String> {
+        let stopwatch = Stopwatch::start();
+        let (ours, theirs) = (self.distance_calculator.name(), other.distance_calculator.name());
+        if ours != theirs {
+            return Err(format!("Cannot merge a {} index into a {} index", theirs, ours));
+        }
+        if self.transform != other.transform {
+            return Err("Cannot merge indexes with different transforms".to_string());
+        }
+
+        let nodes = other.nodes.read().unwrap();
+        let items: Vec<VectorItem> = nodes.iter().map(|node| other.vector_of(node).into_owned()).collect();
+        let extras: HashMap<_, _> = nodes.iter().filter_map(|node| Some((node.id, NodeExtras::of(node)?))).collect();
+        drop(nodes);
+        self.next_id.fetch_max(other.next_id.load(AtomicOrdering::Relaxed), AtomicOrdering::Relaxed);
+        self.par_insert(items, extras, &mut |_, _| true, stopwatch).map(drop)
+    }
+
+    /// Rebuilds the graph from the live vectors and returns how many items
+    /// were dropped: those marked by `mark_deleted` and those expired. Use it
+    /// to reclaim their space, or to restore recall after heavy update and
+    /// removal churn has worn the graph down. Payloads and expiries are kept.
+    /// The index is locked until the rebuild finishes, though with snapshot
+    /// reads searches keep using the last snapshot.
+    pub fn rebuild(&self) -> Result<usize, String> {
+        let mut nodes = self.nodes.write().unwrap();
+        let mut entry_point = self.entry_point.write().unwrap();
+        let now = metrics::wall_clock();
+        let live: Vec<&Node> = nodes.iter().filter(|node| node.is_live(now)).collect();
+        let dropped = nodes.len() - live.len();
+        let items: Vec<VectorItem> = live.iter().map(|node| self.vector_of(node).into_owned()).collect();
+        let extras = live.iter().filter_map(|node| Some((node.id, NodeExtras::of(node)?))).collect();
+        self.audited(|| "rebuild".to_string(), || {
+            nodes.clear();
+            self.slot_epoch.fetch_add(1, AtomicOrdering::Relaxed);
+            *entry_point = None;
+        });
+        self.par_link(&mut nodes, &mut entry_point, items, extras, &mut |_, _| true)?;
+        drop((nodes, entry_point));
+        self.publish();
+        Ok(dropped)
+    }
+
+    /// `rebuild` with a new `m` and `ef_construction`, as set by `with_m` and
+    /// `with_ef_construction`.
+    pub fn rebuild_with(&mut self, m: usize, ef_construction: usize) -> Result<usize, String> {
+        self.set_m(m);
+        self.ef_construction = ef_construction.max(1);
+        self.rebuild()
+    }
+
+    /// Writes the index to `path` in the versioned binary layout described in
+    /// `persist.rs`, replacing the file atomically. The distance calculator is
+    /// not stored.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let nodes = self.nodes.read().unwrap();
+        let entry_point = self.entry_point.read().unwrap();
+        self.save_graph(&nodes, *entry_point, path.as_ref())
+    }
+
+    fn save_graph(&self, nodes: &NodeSlab, entry_point: Option<usize>, path: &Path) -> io::Result<()> {
+        let meta = persist::IndexMeta {
+            level_lambda: self.level_lambda,
+            max_level: self.max_level,
+            transform: self.transform.as_ref(),
+            resolutions: &self.resolutions,
+            next_id: self.next_id.load(AtomicOrdering::Relaxed),
+            params: self.graph_params(),
+        };
+
+        persist::replace_file(path, |file| persist::write_durable(file, &self.full_precision(nodes), entry_point, &meta))
+    }
+
+    fn graph_params(&self) -> GraphParams {
+        GraphParams { m: self.m, ef_construction: self.ef_construction, ef_search: self.ef_search }
+    }
+
+    /// Loads an index written by `save`. Like the serde representation, the
+    /// loaded index uses `EuclideanDistance` until `with_distance_calculator`
+    /// is called. Files from before M and the beam widths were saved get the
+    /// defaults.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let parts = persist::read_index(&mut reader)?;
+        let params = parts.params.unwrap_or(DEFAULT_PARAMS);
+        Ok(HnswIndex {
+            next_id: AtomicUsize::new(next_id_after(&parts.nodes, parts.next_id)),
+            entry_point: Arc::new(RwLock::new(checked_entry_point(&parts.nodes, parts.entry_point))),
+            nodes: Arc::new(RwLock::new(parts.nodes)),
+            level_lambda: parts.level_lambda,
+            max_level: parts.max_level,
+            m: params.m,
+            ef_construction: params.ef_construction,
+            ef_search: params.ef_search,
+            distance_calculator: Box::new(crate::vector::EuclideanDistance),
+            transform: parts.transform,
+            resolutions: Vec::new(),
+            insert_hooks: Vec::new(),
+            search_hooks: Vec::new(),
+            rerankers: Vec::new(),
+            quota: None,
+            snapshot_reads: None,
+            dimension: None,
+            duplicate_policy: DuplicatePolicy::default(),
+            neighbor_selection: NeighborSelection::default(),
+            rng: None,
+            deterministic: false,
+            vector_pool: None,
+            audit: None,
+            metrics: IndexMetrics::default(),
+            metrics_sinks: Vec::new(),
+            slot_epoch: AtomicUsize::new(0),
+            plan_sample: Mutex::new(None),
+            quantization: None,
+        }
+        .with_resolutions(parts.resolutions))
+    }
+
+    /// Reads an index written by hnswlib's `saveIndex` over a float32 space.
+    /// hnswlib does not record the metric, so set the matching one with
+    /// `with_distance_calculator`. hnswlib's deleted marks become
+    /// `mark_deleted` marks.
+    pub fn load_hnswlib(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let graph = hnswlib::read_hnswlib(&mut BufReader::new(file), len)?;
+        let mut index = HnswIndex::new(Box::new(crate::vector::EuclideanDistance))
+            .with_m(graph.m)
+            .with_ef_construction(graph.ef_construction);
+        index.next_id = AtomicUsize::new(next_id_after(&graph.nodes, 0));
+        index.entry_point = Arc::new(RwLock::new(checked_entry_point(&graph.nodes, graph.entry_point)));
+        index.nodes = Arc::new(RwLock::new(graph.nodes));
+        index.level_lambda = graph.level_lambda;
+        Ok(index)
+    }
+
+    /// Writes the graph in hnswlib's format so it can be loaded with
+    /// `hnswlib.Index.load_index`. Vectors are stored as `f32`; any transform
+    /// must be applied to queries on the hnswlib side. `mark_deleted` marks
+    /// are written as hnswlib's deleted marks. Like `save`, it writes a new
+    /// file and renames it over `path`.
+    pub fn save_hnswlib(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let nodes = self.nodes.read().unwrap();
+        let entry_point = self.entry_point.read().unwrap();
+        persist::replace_file(path.as_ref(), |file| {
+            let mut writer = BufWriter::new(&mut *file);
+            hnswlib::write_hnswlib(&mut writer, &self.full_precision(&nodes), *entry_point, self.m, self.ef_construction, self.level_lambda)?;
+            writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()
+        })
+    }
+
+    /// Opens a file written by `save` as a read-only, memory-mapped index.
+    #[cfg(feature = "mmap")]
+    pub fn load_mmap(path: impl AsRef<Path>) -> io::Result<crate::mmap::MmapIndex> {
+        crate::mmap::MmapIndex::open(path)
+    }
+
+    /// Inserts the rows of a 2-D float32/float64 `.npy` file, keyed by row
+    /// number or by the 1-D integer `.npy` array at `ids`; see `io::read_npy`.
+    pub fn add_npy(&self, vectors: impl AsRef<Path>, ids: Option<&Path>) -> io::Result<()> {
+        let mut items = crate::io::read_npy(BufReader::new(File::open(vectors)?))?;
+        if let Some(ids) = ids {
+            let ids = crate::io::read_npy_ids(BufReader::new(File::open(ids)?))?;
+            items = crate::io::assign_ids(items, &ids)?;
+        }
+        self.par_batch_add(items).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Inserts every row of a Parquet file batch by batch; see
+    /// `parquet::read_parquet`. Returns the number of rows read.
+    #[cfg(feature = "parquet")]
+    pub fn add_parquet(&self, path: impl AsRef<Path>, id_column: &str, vector_column: &str) -> io::Result<usize> {
+        let mut rows = 0;
+        for batch in crate::parquet::read_parquet(path, id_column, vector_column)? {
+            let batch = batch?;
+            rows += batch.len();
+            self.par_batch_add(batch).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+        Ok(rows)
+    }
+
+    /// Inserts the rows of an integer id column and a vector column; see
+    /// `arrow::items_from_arrow`.
+    #[cfg(feature = "arrow")]
+    pub fn add_arrow(&self, ids: &dyn arrow_array::Array, vectors: &dyn arrow_array::Array) -> Result<(), String> {
+        self.par_batch_add(crate::arrow::items_from_arrow(ids, vectors)?)
+    }
+
+    /// `batch_search` with the results as one record batch; see
+    /// `arrow::results_to_record_batch`.
+    #[cfg(feature = "arrow")]
+    pub fn batch_search_arrow(&self, queries: &[VectorItem], k: usize) -> Result<arrow_array::RecordBatch, String> {
+        let results = self.batch_search(queries, k)?;
+        let distances = queries
+            .iter()
+            .zip(&results)
+            .map(|(query, hits)| {
+                let query = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+                Ok(hits.iter().map(|item| self.distance_calculator.distance(&query.vector, &item.vector)).collect())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        crate::arrow::results_to_record_batch(&results, &distances)
+    }
+
+    /// The parameters this index actually runs with.
+    pub fn config(&self) -> IndexConfig {
+        let vector_precision = match &self.quantization {
+            Some(quantization) if !quantization.keeps_full_vectors() => quantization.name(),
+            _ => "f64",
+        };
+        IndexConfig {
+            metric: self.distance_calculator.name().to_string(),
+            dimension: self.dimension(),
+            m: self.m,
+            m_max0: 2 * self.m,
+            ef_construction: self.ef_construction,
+            ef_search: self.ef_search,
+            level_lambda: self.level_lambda,
+            max_level: self.max_level,
+            resolutions: self.resolutions.clone(),
+            duplicate_policy: self.duplicate_policy,
+            neighbor_selection: self.neighbor_selection,
+            deterministic: self.deterministic,
+            vector_precision,
+            quantization: self.quantization.clone(),
+        }
+    }
+
+    pub fn get_stats(&self) -> IndexStats {
+        let nodes = self.nodes.read().unwrap();
+        let mut level_counts = BTreeMap::new();
+        let mut total_connections = 0;
+        let mut layer_degrees: Vec<LayerDegrees> = Vec::new();
+        let mut isolated_nodes = 0;
+
+        for (slot, node) in nodes.iter().enumerate() {
+            *level_counts.entry(node.layer).or_insert(0) += 1;
+            let connections = nodes.all_links(slot).map(<[usize]>::len).sum::<usize>();
+            total_connections += connections;
+            isolated_nodes += usize::from(connections == 0);
+
+            if layer_degrees.len() < nodes.layers(slot) {
+                layer_degrees.resize(nodes.layers(slot), LayerDegrees::default());
+            }
+            for (degrees, links) in layer_degrees.iter_mut().zip(nodes.all_links(slot)) {
+                degrees.nodes += 1;
+                degrees.average += links.len() as f64;
+                degrees.max = degrees.max.max(links.len());
+            }
+        }
+        for degrees in &mut layer_degrees {
+            degrees.average /= degrees.nodes as f64;
+        }
+
+        IndexStats {
+            total_nodes: nodes.len(),
+            level_distribution: level_counts,
+            total_connections,
+            max_level: self.max_level,
+            layer_degrees,
+            isolated_nodes,
+            components: layer0_components(&nodes),
+        }
+    }
+
+    /// Checks the graph's invariants: the entry point is on the top layer,
+    /// every node is reachable from it on layer 0, every link points at a
+    /// stored node on the link's layer, and no node has more links than its
+    /// layer allows. With `check_symmetry`, links without a link back are
+    /// listed as well.
+    pub fn validate(&self, check_symmetry: bool) -> ValidationReport {
+        let nodes = self.nodes.read().unwrap();
+        let entry_point = *self.entry_point.read().unwrap();
+        validate::validate(&nodes, entry_point, |level| self.max_connections(level), check_symmetry)
+    }
+}
+
+// Connected components of layer 0, following links in either direction.
+fn layer0_components(nodes: &NodeSlab) -> usize {
+    fn root(parents: &mut [usize], mut slot: usize) -> usize {
+        while parents[slot] != slot {
+            parents[slot] = parents[parents[slot]];
+            slot = parents[slot];
+        }
+        slot
+    }
+
+    let mut parents: Vec<usize> = (0..nodes.len()).collect();
+    let mut components = nodes.len();
+    for slot in 0..nodes.len() {
+        for &neighbor in nodes.links(slot, 0).iter().filter(|&&n| n < nodes.len()) {
+            let (a, b) = (root(&mut parents, slot), root(&mut parents, neighbor));
+            if a != b {
+                parents[a] = b;
+                components -= 1;
+            }
+        }
+    }
+    components
+}
+
+// The node on the highest layer, lowest slot first on ties, ignoring `skip`.
+fn elect_entry_point(nodes: &NodeSlab, skip: Option<usize>) -> Option<usize> {
+    nodes
+        .iter()
+        .enumerate()
+        .filter(|&(slot, _)| Some(slot) != skip)
+        .max_by_key(|&(slot, node)| (node.layer, Reverse(slot)))
+        .map(|(slot, _)| slot)
+}
+
+// The entry point searches start from. A stale one is a bug elsewhere, but
+// not worth failing over, so it is replaced by an elected one.
+fn search_entry_point(nodes: &NodeSlab, entry_point: Option<usize>) -> Option<usize> {
+    entry_point.filter(|&ep| ep < nodes.len()).or_else(|| elect_entry_point(nodes, None))
+}
+
+// Keeps a loaded entry point if it is on the top layer, else elects one, so a
+// missing or stale entry point never hides part of the graph from search.
+fn checked_entry_point(nodes: &NodeSlab, stored: Option<usize>) -> Option<usize> {
+    let top = nodes.iter().map(|node| node.layer).max();
+    match stored {
+        Some(slot) if nodes.get(slot).is_some_and(|node| Some(node.layer) == top) => Some(slot),
+        _ => elect_entry_point(nodes, None),
+    }
+}
+
+// Restores the auto-id high-water mark, never going below the stored ids.
+fn next_id_after(nodes: &NodeSlab, stored: usize) -> usize {
+    nodes.iter().map(|node| node.id.saturating_add(1)).max().unwrap_or(0).max(stored)
+}
+
+// Deserialized indexes come back with `EuclideanDistance`; use
+// `with_distance_calculator` to restore a different metric.
+#[cfg(feature = "serde")]
+impl serde::Serialize for HnswIndex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let nodes = self.nodes.read().unwrap();
+        let entry_point = self.entry_point.read().unwrap();
+        let nodes = self.full_precision(&nodes);
+        let data = SerializedIndex {
+            nodes: nodes
+                .iter()
+                .enumerate()
+                .map(|(slot, node)| SerializedNode { node, connections: nodes.all_links(slot).collect() })
+                .collect(),
+            entry_point: *entry_point,
+            level_lambda: self.level_lambda,
+            max_level: self.max_level,
+            transform: self.transform.as_ref(),
+            resolutions: &self.resolutions,
+            next_id: self.next_id.load(AtomicOrdering::Relaxed),
+            m: self.m,
+            ef_construction: self.ef_construction,
+            ef_search: self.ef_search,
+        };
+        serde::Serialize::serialize(&data, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for HnswIndex {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data: DeserializedIndex = serde::Deserialize::deserialize(deserializer)?;
+        let nodes = data.nodes.into_iter().map(|node| (node.node, node.connections)).collect();
+        let nodes = NodeSlab::from_nodes(nodes).map_err(serde::de::Error::custom)?;
+        if let Some(ep) = data.entry_point.filter(|&ep| ep >= nodes.len()) {
+            return Err(serde::de::Error::custom(format!("Entry point slot {} out of range", ep)));
+        }
+        if let Some(slot) = (0..nodes.len()).find(|&slot| nodes.all_links(slot).flatten().any(|&target| target >= nodes.len())) {
+            return Err(serde::de::Error::custom(format!("Node {} links to a missing slot", nodes[slot].id)));
+        }
+
+        Ok(HnswIndex {
+            next_id: AtomicUsize::new(next_id_after(&nodes, data.next_id)),
+            entry_point: Arc::new(RwLock::new(checked_entry_point(&nodes, data.entry_point))),
+            nodes: Arc::new(RwLock::new(nodes)),
+            level_lambda: data.level_lambda,
+            max_level: data.max_level,
+            m: data.m.unwrap_or(M).max(2),
+            ef_construction: data.ef_construction.unwrap_or(EF_CONSTRUCTION).max(1),
+            ef_search: data.ef_search.unwrap_or(EF_SEARCH).max(1),
+            distance_calculator: Box::new(crate::vector::EuclideanDistance),
+            transform: data.transform,
+            resolutions: Vec::new(),
+            insert_hooks: Vec::new(),
+            search_hooks: Vec::new(),
+            rerankers: Vec::new(),
+            quota: None,
+            snapshot_reads: None,
+            dimension: None,
+            duplicate_policy: DuplicatePolicy::default(),
+            neighbor_selection: NeighborSelection::default(),
+            rng: None,
+            deterministic: false,
+            vector_pool: None,
+            audit: None,
+            metrics: IndexMetrics::default(),
+            metrics_sinks: Vec::new(),
+            slot_epoch: AtomicUsize::new(0),
+            plan_sample: Mutex::new(None),
+            quantization: None,
+        }
+        .with_resolutions(data.resolutions))
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct SerializedIndex<'a> {
+    // In slot order; links and the entry point are slots
+    nodes: Vec<SerializedNode<'a>>,
+    entry_point: Option<usize>,
+    level_lambda: f64,
+    max_level: usize,
+    transform: Option<&'a VectorTransform>,
+    resolutions: &'a [usize],
+    next_id: usize,
+    m: usize,
+    ef_construction: usize,
+    ef_search: usize,
+}
+
+// A node with its neighbor slots per layer, which the slab stores apart
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct SerializedNode<'a> {
+    #[serde(flatten)]
+    node: &'a Node,
+    connections: Vec<&'a [usize]>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct DeserializedNode {
+    #[serde(flatten)]
+    node: Node,
+    connections: Vec<Vec<usize>>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct DeserializedIndex {
+    nodes: Vec<DeserializedNode>,
+    entry_point: Option<usize>,
+    level_lambda: f64,
+    max_level: usize,
+    #[serde(default)]
+    transform: Option<VectorTransform>,
+    #[serde(default)]
+    resolutions: Vec<usize>,
+    #[serde(default)]
+    next_id: usize,
+    #[serde(default)]
+    m: Option<usize>,
+    #[serde(default)]
+    ef_construction: Option<usize>,
+    #[serde(default)]
+    ef_search: Option<usize>,
+}
+
+/// An immutable view of an index's graph; see `HnswIndex::snapshot`. It
+/// searches with the index's metric, transform, hooks and parameters.
+#[derive(Clone)]
+pub struct IndexSnapshot<'a> {
+    index: &'a HnswIndex,
+    graph: Arc<Graph>,
+}
+
+impl IndexSnapshot<'_> {
+    pub fn len(&self) -> usize {
+        self.graph.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.graph.nodes.is_empty()
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.graph.nodes.contains_id(id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<VectorItem> {
+        let slot = self.graph.nodes.slot_of(id)?;
+        Some(self.index.vector_of(&self.graph.nodes[slot]).into_owned())
+    }
+
+    pub fn payload(&self, id: usize) -> Option<serde_json::Value> {
+        self.graph.nodes.slot_of(id).and_then(|slot| self.graph.nodes[slot].payload.clone())
+    }
+
+    /// Every stored item, in storage order.
+    pub fn iter(&self) -> impl Iterator<Item = VectorItem> + '_ {
+        self.graph.nodes.iter().map(|node| self.index.vector_of(node).into_owned())
+    }
+
+    /// `HnswIndex::search` against the snapshot.
+    pub fn search(&self, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        let index = self.index;
+        index.check_query_quota(1)?;
+        let prepared = VectorTransform::apply_to_item(index.transform.as_ref(), query)?;
+        let results = index.search_nodes(&self.graph.nodes, self.graph.entry_point, &prepared, &index.request(k))?;
+        index.run_search_hooks(query, results)
+    }
+
+    /// Writes the snapshot like `HnswIndex::save`, so the file is consistent
+    /// however many writes the index takes meanwhile.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.index.save_graph(&self.graph.nodes, self.graph.entry_point, path.as_ref())
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexStats {
+    pub total_nodes: usize,
+    /// Node count per layer, ordered by layer.
+    pub level_distribution: BTreeMap<usize, usize>,
+    pub total_connections: usize,
+    pub max_level: usize,
+    /// Link counts per layer, indexed by layer, over the nodes on it.
+    pub layer_degrees: Vec<LayerDegrees>,
+    /// Nodes with no links on any layer.
+    pub isolated_nodes: usize,
+    /// Connected components of layer 0 with links taken as undirected; more
+    /// than one means some nodes cannot be reached by search.
+    pub components: usize,
+}
+
+/// Degree summary of one layer; see `IndexStats::layer_degrees`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LayerDegrees {
+    pub nodes: usize,
+    pub average: f64,
+    pub max: usize,
+}
+
+/// Effective parameters of an index; see `HnswIndex::config`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexConfig {
+    /// `DistanceCalculator::name` of the metric.
+    pub metric: String,
+    /// `None` until the first insert fixes it.
+    pub dimension: Option<usize>,
+    /// Link budget per node on the upper layers.
+    pub m: usize,
+    /// Link budget per node on layer 0.
+    pub m_max0: usize,
+    pub ef_construction: usize,
+    /// Beam width of a search, raised to `k` when `k` is larger.
+    pub ef_search: usize,
+    pub level_lambda: f64,
+    pub max_level: usize,
+    pub resolutions: Vec<usize>,
+    pub duplicate_policy: DuplicatePolicy,
+    pub neighbor_selection: NeighborSelection,
+    pub deterministic: bool,
+    /// How the vectors themselves are stored: `f64`, or the quantization's
+    /// code format when the full vectors are dropped.
+    pub vector_precision: &'static str,
+    pub quantization: Option<Quantization>,
+}
+
+impl fmt::Display for IndexConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dimension = self.dimension.map_or_else(|| "unset".to_string(), |d| d.to_string());
+        writeln!(f, "metric: {}", self.metric)?;
+        writeln!(f, "dimension: {}", dimension)?;
+        writeln!(f, "M: {} (layer 0: {})", self.m, self.m_max0)?;
+        writeln!(f, "ef: construction {}, search {}", self.ef_construction, self.ef_search)?;
+        writeln!(f, "levels: lambda {:.4}, max {}", self.level_lambda, self.max_level)?;
+        writeln!(f, "vectors: {}", self.vector_precision)?;
+        match &self.quantization {
+            Some(q) => write!(f, "quantization: {} (full vectors kept: {})", q.name(), q.keeps_full_vectors()),
+            None => write!(f, "quantization: none"),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::EuclideanDistance;
+    use crate::{train_pq, BinaryQuantizer, EuclideanDistance, ScalarQuantizer};
+
+    use super::*;
+    use rand::distributions::{Distribution, Uniform};
+    use rand::thread_rng;
+
+    fn generate_random_vector(dim: usize) -> Vec<f64> {
+        let mut rng = thread_rng();
+        let dist = Uniform::from(-1.0..1.0);
+        (0..dim).map(|_| dist.sample(&mut rng)).collect()
+    }
+
+    #[test]
+    fn test_basic_insertion() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let item = VectorItem {
+            id: 1,
+            vector: vec![1.0, 0.0],
+        };
+        assert!(index.add(item).is_ok());
+    }
+    
+    #[test]
+    fn test_batch_add() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let mut items = Vec::new();
+
+        for i in 0..100 {
+            items.push(VectorItem {
+                id: i,
+                vector: generate_random_vector(10),
+            });
+        }
+
+        assert!(index.batch_add(items).is_ok());
+        let stats = index.get_stats();
+        assert_eq!(stats.total_nodes, 100);
+        assert_eq!(stats.level_distribution.values().sum::<usize>(), 100);
+        assert_eq!(stats.level_distribution.keys().next(), Some(&0));
+    }
+
+    #[test]
+    fn test_add_auto_allocates_unique_ids() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        assert_eq!(index.add_auto(generate_random_vector(4)).unwrap(), 0);
+        index.add(VectorItem { id: 10, vector: generate_random_vector(4) }).unwrap();
+        assert_eq!(index.add_auto(generate_random_vector(4)).unwrap(), 11);
+
+        let ids: Vec<_> = (0..100)
+            .into_par_iter()
+            .map(|_| index.add_auto(generate_random_vector(4)).unwrap())
+            .collect();
+        let unique: HashSet<_> = ids.iter().collect();
+        assert_eq!(unique.len(), 100);
+        assert!(ids.iter().all(|&id| id > 11));
+        assert_eq!(index.get_stats().total_nodes, 103);
+    }
+
+    #[test]
+    fn test_hooks() {
+        use crate::hooks::{Normalize, RejectNonFinite};
+        use std::sync::atomic::AtomicUsize;
+
+        let searches = Arc::new(AtomicUsize::new(0));
+        let counter = searches.clone();
+        let index = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_insert_hook(RejectNonFinite)
+            .with_insert_hook(Normalize)
+            .with_search_hook(move |_: &VectorItem, results: &mut Vec<VectorItem>| {
+                counter.fetch_add(1, AtomicOrdering::Relaxed);
+                results.truncate(1);
+                Ok(())
+            });
+
+        index.add(VectorItem { id: 1, vector: vec![3.0, 4.0] }).unwrap();
+        index.add(VectorItem { id: 2, vector: vec![0.0, 2.0] }).unwrap();
+        assert!(index.add(VectorItem { id: 3, vector: vec![f64::NAN, 1.0] }).is_err());
+        assert!(index.add(VectorItem { id: 4, vector: vec![0.0, 0.0] }).is_err());
+        assert_eq!(index.get_stats().total_nodes, 2);
+
+        let results = index.search(&VectorItem { id: 0, vector: vec![0.6, 0.8] }, 2).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].vector, vec![0.6, 0.8]);
+        index.batch_search(&[results[0].clone(), results[0].clone()], 2).unwrap();
+        assert_eq!(searches.load(AtomicOrdering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_par_batch_add() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let items: Vec<_> = (0..2000)
+            .map(|i| VectorItem { id: i, vector: generate_random_vector(8) })
+            .collect();
+        index.par_batch_add(items).unwrap();
+
+        let nodes = index.nodes.read().unwrap();
+        assert_eq!(nodes.len(), 2000);
+        for (slot, node) in nodes.iter().enumerate() {
+            assert_eq!(nodes.layers(slot), node.layer + 1);
+            assert!(nodes.all_links(slot).flatten().all(|&target| target < nodes.len()));
+        }
+        drop(nodes);
+
+        let query = VectorItem { id: 5000, vector: generate_random_vector(8) };
+        assert!(!index.search(&query, 10).unwrap().is_empty());
+
+        let bad = vec![VectorItem { id: 1, vector: vec![0.0] }];
+        let strict = HnswIndex::new(Box::new(EuclideanDistance)).with_transform(VectorTransform::truncate(2));
+        assert!(strict.par_batch_add(bad).is_err());
+        assert_eq!(strict.get_stats().total_nodes, 0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let items: Vec<_> = (0..600).map(|i| VectorItem { id: i, vector: generate_random_vector(6) }).collect();
+        let shards: Vec<HnswIndex> = items
+            .chunks(200)
+            .map(|chunk| {
+                let shard = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(chunk[0].id as u64);
+                shard.par_batch_add(chunk.to_vec()).unwrap();
+                shard
+            })
+            .collect();
+        shards[1].add_with_payload(VectorItem { id: 250, vector: items[250].vector.clone() }, serde_json::json!("tagged")).unwrap();
+
+        let merged = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(9);
+        for shard in &shards {
+            merged.merge(shard).unwrap();
+        }
+        assert_eq!(merged.len(), 600);
+        assert!(merged.validate(false).is_valid());
+        assert_eq!(merged.payload(250), Some(serde_json::json!("tagged")));
+        assert_eq!(merged.add_auto(vec![0.0; 6]).unwrap(), 600);
+
+        let mut found = 0;
+        for item in &items {
+            found += usize::from(merged.search(item, 1).unwrap()[0].id == item.id);
+        }
+        assert!(found >= 590, "found {} of 600", found);
+
+        // Ids in both are left to the duplicate policy
+        let strict = HnswIndex::new(Box::new(EuclideanDistance)).with_duplicate_policy(DuplicatePolicy::Reject);
+        strict.merge(&shards[0]).unwrap();
+        assert!(strict.merge(&shards[0]).is_err());
+        let truncated = HnswIndex::new(Box::new(EuclideanDistance)).with_transform(VectorTransform::truncate(2));
+        assert!(merged.merge(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_batch_progress() {
+        let items: Vec<_> = (0..500).map(|i| VectorItem { id: i, vector: generate_random_vector(4) }).collect();
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let mut calls = Vec::new();
+        let added = index.batch_add_with_progress(items.clone(), |done, total| {
+            calls.push((done, total));
+            done < 10
+        });
+        assert_eq!(added, Ok(10));
+        assert_eq!(calls.last(), Some(&(10, 500)));
+        assert_eq!(index.len(), 10);
+
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let mut calls = Vec::new();
+        let added = index.par_batch_add_with_progress(items.clone(), |done, total| {
+            calls.push((done, total));
+            true
+        });
+        assert_eq!(added, Ok(500));
+        assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(calls.last(), Some(&(500, 500)));
+
+        // Stopping after the first chunk keeps only that chunk
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let added = index.par_batch_add_with_progress(items, |_, _| false).unwrap();
+        assert!(added > 0 && added < 500);
+        assert_eq!(index.len(), added);
+        assert_eq!(index.metrics().snapshot().inserts, added as u64);
+    }
+
+    #[test]
+    fn test_concurrent_add_and_search() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        std::thread::scope(|scope| {
+            for t in 0..4 {
+                let index = &index;
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        let item = VectorItem { id: t * 100 + i, vector: generate_random_vector(4) };
+                        index.add(item).unwrap();
+                    }
+                });
+                scope.spawn(move || {
+                    let query = VectorItem { id: 0, vector: generate_random_vector(4) };
+                    for _ in 0..100 {
+                        index.search(&query, 5).unwrap();
+                    }
+                });
+            }
+        });
+
+        let nodes = index.nodes.read().unwrap();
+        assert_eq!(nodes.len(), 400);
+        assert!((0..nodes.len()).all(|slot| nodes.all_links(slot).flatten().all(|&target| target < nodes.len())));
+    }
+
+    #[test]
+    fn test_quota() {
+        let quota = Quota::new().with_max_vectors(3).with_max_qps(2.0);
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_quota(quota);
+        for i in 0..3 {
+            index.add(VectorItem { id: i, vector: vec![i as f64] }).unwrap();
+        }
+        // Re-adding an existing id does not count against the limit
+        index.add(VectorItem { id: 2, vector: vec![2.0] }).unwrap();
+        let err = index.add(VectorItem { id: 3, vector: vec![3.0] }).unwrap_err();
+        assert!(err.contains("limited to 3 vectors"));
+        assert!(index.par_batch_add(vec![VectorItem { id: 4, vector: vec![4.0] }]).is_err());
+
+        let query = VectorItem { id: 0, vector: vec![0.0] };
+        assert!(index.batch_search(&[query.clone(), query.clone()], 1).is_ok());
+        assert!(index.search(&query, 1).unwrap_err().contains("queries per second"));
+    }
+
+    #[test]
+    fn test_snapshot_reads() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_snapshot_reads(10);
+        let query = VectorItem { id: 0, vector: vec![0.0, 0.0] };
+        for i in 0..5 {
+            index.add(VectorItem { id: i, vector: vec![i as f64, 0.0] }).unwrap();
+        }
+        assert!(index.search(&query, 5).unwrap().is_empty());
+
+        for i in 5..10 {
+            index.add(VectorItem { id: i, vector: vec![i as f64, 0.0] }).unwrap();
+        }
+        assert!(!index.search(&query, 5).unwrap().is_empty());
+
+        index.add(VectorItem { id: 10, vector: vec![-1.0, 0.0] }).unwrap();
+        assert!(index.search(&query, 20).unwrap().iter().all(|r| r.id != 10));
+        assert_eq!(index.with_search_graph(|nodes, _| nodes.len()), 10);
+        index.publish();
+        assert_eq!(index.with_search_graph(|nodes, _| nodes.len()), 11);
+
+        // Searches do not block while the live graph is write-locked
+        let _nodes = index.nodes.write().unwrap();
+        assert!(!index.search(&query, 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(2);
+        for i in 0..100 {
+            index.add_with_payload(VectorItem { id: i, vector: vec![i as f64, 0.0] }, serde_json::json!(i)).unwrap();
+        }
+        let snapshot = index.snapshot();
+
+        // Writes on another thread leave the snapshot as it was
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 100..200 {
+                    index.add(VectorItem { id: i, vector: vec![-(i as f64), 0.0] }).unwrap();
+                }
+                index.remove(0).unwrap();
+            });
+        });
+        let query = VectorItem { id: 0, vector: vec![-0.4, 0.0] };
+        assert_eq!(index.search(&query, 1).unwrap()[0].id, 1);
+        let copy = snapshot.clone();
+        assert_eq!(copy.search(&query, 1).unwrap()[0].id, 0);
+        assert_eq!((copy.len(), index.len()), (100, 199));
+        assert!(copy.contains(0) && !copy.contains(150));
+        assert_eq!(copy.get(3).unwrap().vector, [3.0, 0.0]);
+        assert_eq!(copy.payload(3), Some(serde_json::json!(3)));
+        assert_eq!(copy.iter().map(|item| item.id).max(), Some(99));
+
+        let path = std::env::temp_dir().join(format!("hnsw_snapshot_{}.bin", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = HnswIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 100);
+        assert_eq!(loaded.search(&query, 1).unwrap()[0].id, 0);
+    }
+
+    #[test]
+    fn test_dimension_validation() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        assert_eq!(index.dimension(), None);
+        index.add(VectorItem { id: 0, vector: vec![0.0, 1.0] }).unwrap();
+        assert_eq!(index.dimension(), Some(2));
+
+        let err = index.add(VectorItem { id: 1, vector: vec![0.0] }).unwrap_err();
+        assert_eq!(err, "Vector 1 has 1 dimensions, expected 2");
+        assert!(index.par_batch_add(vec![VectorItem { id: 2, vector: vec![0.0; 3] }]).is_err());
+        assert!(index.search(&VectorItem { id: 9, vector: vec![0.0; 3] }, 1).is_err());
+        assert_eq!(index.get_stats().total_nodes, 1);
+
+        let fixed = HnswIndex::new(Box::new(EuclideanDistance)).with_dimension(3);
+        assert!(fixed.add(VectorItem { id: 0, vector: vec![0.0, 1.0] }).is_err());
+        assert!(fixed.search(&VectorItem { id: 9, vector: vec![0.0; 2] }, 1).is_err());
+        fixed.add(VectorItem { id: 0, vector: vec![0.0; 3] }).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_policy() {
+        let item = |id, x| VectorItem { id, vector: vec![x, 0.0] };
+        let build = |policy| {
+            let index = HnswIndex::new(Box::new(EuclideanDistance)).with_duplicate_policy(policy);
+            for i in 0..50 {
+                index.add(item(i, i as f64)).unwrap();
+            }
+            index
+        };
+
+        let reject = build(DuplicatePolicy::Reject);
+        assert_eq!(reject.add(item(3, 9.0)).unwrap_err(), "Id 3 is already in the index");
+        assert!(reject.par_batch_add(vec![item(60, 0.0), item(60, 1.0)]).is_err());
+        assert_eq!(reject.get_stats().total_nodes, 50);
+
+        let ignore = build(DuplicatePolicy::Ignore);
+        ignore.add(item(3, 9.0)).unwrap();
+        ignore.par_batch_add(vec![item(4, 9.0), item(60, 1.0)]).unwrap();
+        let nodes = ignore.nodes.read().unwrap();
+        assert_eq!(nodes.len(), 51);
+        assert_eq!(nodes[nodes.slot_of(3).unwrap()].item.vector, vec![3.0, 0.0]);
+        drop(nodes);
+
+        let overwrite = build(DuplicatePolicy::Overwrite);
+        for i in 0..50 {
+            overwrite.add(item(i, -(i as f64))).unwrap();
+        }
+        let nodes = overwrite.nodes.read().unwrap();
+        assert_eq!(nodes.len(), 50);
+        for (slot, node) in nodes.iter().enumerate() {
+            assert_eq!(node.item.vector[0], -(node.id as f64));
+            assert!(nodes.all_links(slot).flatten().all(|&n| n != slot && n < nodes.len()));
+        }
+        let entry = overwrite.entry_point.read().unwrap().unwrap();
+        assert_eq!(nodes[entry].layer, nodes.iter().map(|n| n.layer).max().unwrap());
+        drop(nodes);
+        overwrite.par_batch_add((0..50).map(|i| item(i % 25, i as f64)).collect()).unwrap();
+        assert!(overwrite.validate(false).is_valid());
+
+        // A rejected overwrite leaves the old node linked
+        assert_eq!(overwrite.add(VectorItem { id: 5, vector: vec![1.0; 3] }).unwrap_err(), "Vector 5 has 3 dimensions, expected 2");
+        assert!(overwrite.validate(false).is_valid());
+        assert_eq!(overwrite.search(&overwrite.get(5).unwrap(), 1).unwrap()[0].id, 5);
+    }
+
+    #[test]
+    fn test_neighbors_of() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_transform(VectorTransform::truncate(2));
+        for i in 0..20 {
+            index.add(VectorItem { id: i, vector: vec![i as f64, 0.0, 100.0] }).unwrap();
+        }
+
+        let results = index.neighbors_of(5, 3).unwrap();
+        assert!(!results.is_empty() && results.len() <= 3);
+        assert!(results.iter().all(|r| r.id != 5 && r.vector.len() == 2));
+        assert!(index.neighbors_of(5, 0).unwrap().is_empty());
+        assert_eq!(index.neighbors_of(99, 3).unwrap_err(), "Id 99 is not in the index");
+    }
+
+    #[test]
+    fn test_search_excluding() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..100 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(4) }).unwrap();
+        }
+        let query = VectorItem { id: 1000, vector: generate_random_vector(4) };
+        let first = index.search(&query, 5).unwrap();
+        let shown: Vec<usize> = first.iter().map(|r| r.id).collect();
+
+        let next = index.search_excluding(&query, 5, &shown).unwrap();
+        let reachable = index.search(&query, 100).unwrap().len();
+        assert_eq!(next.len(), 5.min(reachable - shown.len()));
+        assert!(next.iter().all(|r| !shown.contains(&r.id)));
+        // Unknown ids are ignored
+        let ids = |results: Vec<VectorItem>| results.into_iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids(index.search_excluding(&query, 5, &[12345]).unwrap()), shown);
+    }
+
+    #[test]
+    fn test_search_ids() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..200 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(16) }).unwrap();
+        }
+        let query = VectorItem { id: 1000, vector: generate_random_vector(16) };
+        let results = index.search_ids(&query, 10).unwrap();
+        let items = index.search(&query, 10).unwrap();
+        let ids: Vec<usize> = results.iter().map(|r| r.0).collect();
+        assert_eq!(ids, items.iter().map(|r| r.id).collect::<Vec<_>>());
+        for ((id, distance), item) in results.iter().zip(index.get_many(&ids)) {
+            assert_eq!(*distance, EuclideanDistance.distance(&query.vector, &item.unwrap().vector), "id {}", id);
+        }
+        assert!(index.search_ids(&query, 0).unwrap().is_empty());
+
+        // Search hooks still see, and decide, the results
+        let hooked = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_search_hook(|_: &VectorItem, results: &mut Vec<VectorItem>| {
+                results.reverse();
+                Ok(())
+            });
+        hooked.add(VectorItem { id: 1, vector: vec![1.0] }).unwrap();
+        hooked.add(VectorItem { id: 2, vector: vec![3.0] }).unwrap();
+        assert_eq!(hooked.search_ids(&VectorItem { id: 0, vector: vec![0.0] }, 2).unwrap(), [(2, 3.0), (1, 1.0)]);
+    }
+
+    #[test]
+    fn test_neighbor_selection() {
+        // Two tight clusters on either side of the query at the origin
+        let points = [(0, 1.0), (1, 1.1), (2, 1.2), (3, -2.0), (4, -2.1)];
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for &(id, x) in &points {
+            index.add(VectorItem { id, vector: vec![x, 0.0] }).unwrap();
+        }
+        let query = VectorItem { id: 9, vector: vec![0.0, 0.0] };
+        let nodes = index.nodes.read().unwrap();
+        let candidates: Vec<_> = points
+            .iter()
+            .map(|&(id, x)| Neighbor { id: nodes.slot_of(id).unwrap(), distance: f64::abs(x) })
+            .collect();
+        let select = |selection| {
+            let index = HnswIndex::new(Box::new(EuclideanDistance)).with_neighbor_selection(selection);
+            let slots = index.select_neighbors(&nodes, &query, &candidates, 0).unwrap();
+            slots.into_iter().map(|slot| nodes[slot].id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(select(NeighborSelection::Simple), vec![0, 1, 2, 3, 4]);
+        let strict = NeighborSelection::Heuristic { extend_candidates: false, keep_pruned: false };
+        assert_eq!(select(strict), vec![0, 3]);
+        assert_eq!(select(NeighborSelection::default()), vec![0, 3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn test_reverse_edges() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..300 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(8) }).unwrap();
+        }
+
+        let nodes = index.nodes.read().unwrap();
+        let mut reciprocal = 0;
+        let mut edges = 0;
+        for slot in 0..nodes.len() {
+            for (level, links) in nodes.all_links(slot).enumerate() {
+                assert!(links.len() <= index.max_connections(level));
+                assert!(!links.contains(&slot));
+                for &neighbor in links {
+                    edges += 1;
+                    if nodes.links(neighbor, level).contains(&slot) {
+                        reciprocal += 1;
+                    }
+                }
+            }
+        }
+        // Pruning drops some back links, but most edges should survive both ways
+        assert!(reciprocal * 2 > edges, "{} of {} edges reciprocal", reciprocal, edges);
+        drop(nodes);
+
+        let query = VectorItem { id: 999, vector: generate_random_vector(8) };
+        assert_eq!(index.search(&query, 10).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_degree_cap() {
+        // A hub at the origin surrounded by a tight cluster gets far more
+        // back links than it may keep
+        for selection in [NeighborSelection::Simple, NeighborSelection::default()] {
+            let index = HnswIndex::new(Box::new(EuclideanDistance)).with_neighbor_selection(selection);
+            index.add(VectorItem { id: 0, vector: vec![0.0, 0.0] }).unwrap();
+            for i in 1..200 {
+                let angle = i as f64;
+                let radius = 1.0 + i as f64 * 1e-3;
+                index.add(VectorItem { id: i, vector: vec![radius * angle.cos(), radius * angle.sin()] }).unwrap();
+            }
+
+            let nodes = index.nodes.read().unwrap();
+            for (slot, node) in nodes.iter().enumerate() {
+                for (level, links) in nodes.all_links(slot).enumerate() {
+                    assert!(links.len() <= index.max_connections(level), "{:?}: node {} has {} links on level {}", selection, node.id, links.len(), level);
+                }
+            }
+            assert_eq!(nodes.links(0, 0).len(), 2 * M, "{:?}", selection);
+        }
+    }
+
+    #[test]
+    fn test_seeded_builds_match() {
+        let items: Vec<_> = (0..200).map(|id| VectorItem { id, vector: generate_random_vector(8) }).collect();
+        let build = |seed| {
+            let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(seed);
+            index.batch_add(items.clone()).unwrap();
+            let nodes = index.nodes.read().unwrap();
+            (0..nodes.len()).map(|slot| nodes.all_links(slot).map(<[usize]>::to_vec).collect::<Vec<_>>()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(build(7), build(7));
+        assert_ne!(build(7), build(8));
+    }
+
+    #[test]
+    fn test_deterministic_build() {
+        let items: Vec<_> = (0..300).map(|id| VectorItem { id, vector: generate_random_vector(8) }).collect();
+        let save = |parallel: bool| {
+            let index = HnswIndex::new(Box::new(EuclideanDistance)).with_deterministic_build(3);
+            match parallel {
+                true => index.par_batch_add(items.clone()).unwrap(),
+                false => index.batch_add(items.clone()).unwrap(),
+            }
+            index.add_with_payload(VectorItem { id: 5, vector: vec![0.5; 8] }, "five".into()).unwrap();
+            let path = std::env::temp_dir().join(format!("hnsw_deterministic_{}_{}.bin", parallel, std::process::id()));
+            index.save(&path).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            bytes
+        };
+
+        assert_eq!(save(true), save(true));
+        assert_eq!(save(true), save(false));
+    }
+
+    #[test]
+    fn test_vector_pool() {
+        let pool = std::sync::Arc::new(VectorPool::new());
+        let shared: Vec<_> = (0..20).map(|id| VectorItem { id, vector: generate_random_vector(4) }).collect();
+        let alice = HnswIndex::new(Box::new(EuclideanDistance)).with_vector_pool(pool.clone());
+        let bob = HnswIndex::new(Box::new(EuclideanDistance)).with_vector_pool(pool.clone());
+        alice.batch_add(shared.clone()).unwrap();
+        bob.batch_add(shared[..10].to_vec()).unwrap();
+        bob.add(VectorItem { id: 100, vector: generate_random_vector(4) }).unwrap();
+        assert_eq!(pool.len(), 21);
+
+        let (a, b) = (alice.nodes.read().unwrap(), bob.nodes.read().unwrap());
+        let slot = |nodes: &NodeSlab, id| nodes.slot_of(id).unwrap();
+        assert!(std::sync::Arc::ptr_eq(&a[slot(&a, 3)].item, &b[slot(&b, 3)].item));
+        drop((a, b));
+
+        drop(bob);
+        assert_eq!(pool.purge(), 1);
+        assert_eq!(alice.search(&shared[0], 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_payloads() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..50 {
+            let item = VectorItem { id: i, vector: generate_random_vector(4) };
+            if i % 2 == 0 {
+                index.add_with_payload(item, serde_json::json!({ "file": format!("doc{}.txt", i) })).unwrap();
+            } else {
+                index.add(item).unwrap();
+            }
+        }
+
+        assert_eq!(index.payload(4), Some(serde_json::json!({ "file": "doc4.txt" })));
+        assert_eq!(index.payload(5), None);
+        let query = VectorItem { id: 100, vector: generate_random_vector(4) };
+        for (item, payload) in index.search_with_payloads(&query, 10).unwrap() {
+            assert_eq!(payload.is_some(), item.id % 2 == 0);
+        }
+
+        // Overwriting replaces the payload along with the vector
+        index.add(VectorItem { id: 4, vector: generate_random_vector(4) }).unwrap();
+        assert_eq!(index.payload(4), None);
+    }
+
+    #[test]
+    fn test_mark_deleted() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..100 {
+            index.add(VectorItem { id: i, vector: vec![i as f64, 0.0] }).unwrap();
+        }
+        for id in [29, 30, 31] {
+            assert!(index.mark_deleted(id));
+        }
+        assert!(!index.mark_deleted(1000));
+        assert!(index.is_marked_deleted(30));
+        assert_eq!(index.len(), 100);
+
+        let query = VectorItem { id: 0, vector: vec![30.2, 0.0] };
+        let ids: Vec<usize> = index.search(&query, 3).unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids, [32, 28, 33]);
+        let filter = |id: usize| id.is_multiple_of(2);
+        let ids: Vec<usize> = index.search_filtered(&query, 2, &filter).unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids, [32, 28]);
+
+        // The marks survive both file formats
+        for hnswlib in [false, true] {
+            let path = std::env::temp_dir().join(format!("hnsw_marked_{}_{}.bin", hnswlib, std::process::id()));
+            let loaded = match hnswlib {
+                false => index.save(&path).and_then(|_| HnswIndex::load(&path)),
+                true => index.save_hnswlib(&path).and_then(|_| HnswIndex::load_hnswlib(&path)),
+            }
+            .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert!(loaded.is_marked_deleted(31) && !loaded.is_marked_deleted(32));
+            assert_eq!(loaded.search(&query, 1).unwrap()[0].id, 32);
+        }
+
+        assert!(index.unmark(30));
+        assert!(!index.is_marked_deleted(30));
+        assert_eq!(index.search(&query, 1).unwrap()[0].id, 30);
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let mut index = HnswIndex::new(Box::new(EuclideanDistance));
+        let items: Vec<_> = (0..300).map(|i| VectorItem { id: i, vector: generate_random_vector(4) }).collect();
+        index.par_batch_add(items.clone()).unwrap();
+        for id in (0..300).filter(|id| id % 3 == 0) {
+            index.mark_deleted(id);
+        }
+        index.add_with_payload(items[1].clone(), serde_json::json!("kept")).unwrap();
+        index.add_with_expiry(items[2].clone(), SystemTime::now() - Duration::from_secs(1)).unwrap();
+
+        assert_eq!(index.rebuild().unwrap(), 101);
+        assert_eq!(index.len(), 199);
+        assert!(!index.contains(0) && !index.contains(2));
+        assert!(!index.is_marked_deleted(1));
+        assert_eq!(index.payload(1), Some("kept".into()));
+        assert!(index.validate(false).is_valid());
+        for item in items.iter().filter(|item| index.contains(item.id)).take(20) {
+            assert_eq!(index.search(item, 1).unwrap()[0].id, item.id);
+        }
+
+        assert_eq!(index.rebuild_with(8, 50).unwrap(), 0);
+        assert_eq!((index.config().m, index.config().ef_construction), (8, 50));
+        assert_eq!(index.len(), 199);
+        assert!(index.validate(false).is_valid());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let future = SystemTime::now() + Duration::from_secs(86_400);
+        for i in 0..100 {
+            let item = VectorItem { id: i, vector: vec![i as f64, 0.0] };
+            match i % 3 {
+                0 => index.add_with_expiry(item, past).unwrap(),
+                1 => index.add_with_expiry(item, future).unwrap(),
+                _ => index.add(item).unwrap(),
+            }
+        }
+        assert_eq!(index.expiry(1), Some(future));
+        assert_eq!(index.expiry(2), None);
+
+        // Expired items are skipped but still route the search
+        let query = VectorItem { id: 0, vector: vec![30.2, 0.0] };
+        let ids: Vec<usize> = index.search(&query, 4).unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids, [31, 29, 32, 28]);
+        let exact: Vec<usize> = index.search_exact(&query, 4).unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(exact, ids);
+        assert_eq!(index.len(), 100);
+
+        assert_eq!(index.purge_expired().unwrap(), 34);
+        assert_eq!(index.len(), 66);
+        assert!(!index.contains(30));
+        assert!(index.validate(false).is_valid());
+        assert_eq!(index.purge_expired().unwrap(), 0);
 
-    use super::*;
-    use rand::distributions::{Distribution, Uniform};
-    use rand::thread_rng;
+        // Re-adding clears the expiry; saving keeps it
+        index.add(VectorItem { id: 1, vector: vec![1.0, 0.0] }).unwrap();
+        assert_eq!(index.expiry(1), None);
+        let path = std::env::temp_dir().join(format!("hnsw_expiry_{}.bin", std::process::id()));
+        index.save(&path).unwrap();
+        let loaded = HnswIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let millis = |at: SystemTime| at.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+        assert_eq!(loaded.expiry(4).map(millis), Some(millis(future)));
+        assert_eq!(loaded.expiry(1), None);
+    }
 
-    fn generate_random_vector(dim: usize) -> Vec<f64> {
-        let mut rng = thread_rng();
-        let dist = Uniform::from(-1.0..1.0);
-        (0..dim).map(|_| dist.sample(&mut rng)).collect()
+    #[test]
+    fn test_level_cap() {
+        assert_eq!(level_cap(0, M), 0);
+        assert_eq!(level_cap(1, M), 0);
+        assert_eq!(level_cap(M, M), 1);
+        assert_eq!(level_cap(100, M), 2);
+        assert_eq!(level_cap(M * M + 1, M), 3);
+
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(1);
+        for i in 0..100 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(4) }).unwrap();
+        }
+        let stats = index.get_stats();
+        assert!(stats.level_distribution.keys().all(|&level| level <= 2));
     }
 
     #[test]
-    fn test_basic_insertion() {
+    fn test_entry_point_election() {
         let index = HnswIndex::new(Box::new(EuclideanDistance));
-        let item = VectorItem {
-            id: 1,
-            vector: vec![1.0, 0.0],
+        for i in 0..100 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(4) }).unwrap();
+        }
+        let nodes = index.nodes.read().unwrap().clone();
+        let top = nodes.iter().map(|node| node.layer).max().unwrap();
+        let first_top = nodes.iter().position(|node| node.layer == top).unwrap();
+        assert_eq!(elect_entry_point(&nodes, None), Some(first_top));
+        assert_eq!(checked_entry_point(&nodes, None), Some(first_top));
+        let bottom = nodes.iter().position(|node| node.layer == 0).unwrap();
+        if top > 0 {
+            assert_eq!(checked_entry_point(&nodes, Some(bottom)), Some(first_top));
+        }
+
+        assert!(index.set_entry_point(1000).is_err());
+        index.set_entry_point(nodes[bottom].id).unwrap();
+        assert_eq!(*index.entry_point.read().unwrap(), Some(bottom));
+    }
+
+    #[test]
+    fn test_entry_point_maintenance() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(3);
+        let items: Vec<_> = (0..200).map(|i| VectorItem { id: i, vector: generate_random_vector(4) }).collect();
+        index.par_batch_add(items.clone()).unwrap();
+
+        // Removing the entry point each time soon empties the top layer
+        let entry_layer = |index: &HnswIndex| {
+            let nodes = index.nodes.read().unwrap();
+            let top = nodes.iter().map(|node| node.layer).max().unwrap();
+            (nodes[nodes.slot_of(index.entry_point().unwrap()).unwrap()].layer, top)
         };
-        assert!(index.add(item).is_ok());
+        for _ in 0..10 {
+            index.remove(index.entry_point().unwrap()).unwrap();
+            let (layer, top) = entry_layer(&index);
+            assert_eq!(layer, top);
+            let query = items.iter().find(|item| index.contains(item.id)).unwrap();
+            assert_eq!(index.search(query, 1).unwrap()[0].id, query.id);
+        }
+        assert!(index.validate(false).is_valid());
+
+        // A manual entry point below the top layer is replaced on removal
+        let bottom = index.nodes.read().unwrap().iter().find(|node| node.layer == 0).unwrap().id;
+        index.set_entry_point(bottom).unwrap();
+        let other = index.ids().find(|&id| id != bottom).unwrap();
+        index.remove(other).unwrap();
+        let (layer, top) = entry_layer(&index);
+        assert_eq!(layer, top);
+        index.truncate(0);
+        assert_eq!(index.entry_point(), None);
     }
-    
+
     #[test]
-    fn test_batch_add() {
+    fn test_search_filtered() {
         let index = HnswIndex::new(Box::new(EuclideanDistance));
-        let mut items = Vec::new();
+        for i in 0..500 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(8) }).unwrap();
+        }
+        let query = VectorItem { id: 1000, vector: generate_random_vector(8) };
+
+        // 1 in 50 ids pass: post-filtering a top-k would almost never find 5
+        let results = index.search_filtered(&query, 5, &|id| id % 50 == 0).unwrap();
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|r| r.id % 50 == 0));
+        assert!(index.search_filtered(&query, 5, &|_| false).unwrap().is_empty());
+
+        let all = index.search_filtered(&query, 10, &|_| true).unwrap();
+        let plain = index.search(&query, 10).unwrap();
+        assert_eq!(all.iter().map(|r| r.id).collect::<Vec<_>>(), plain.iter().map(|r| r.id).collect::<Vec<_>>());
+
+        // One tenant's allowlist, shared by a batch of queries
+        let tenant: HashSet<usize> = (0..500).filter(|id| id % 7 == 3).collect();
+        let queries: Vec<_> = (0..4).map(|i| VectorItem { id: 2000 + i, vector: generate_random_vector(8) }).collect();
+        for results in index.batch_search_filtered(&queries, 5, &tenant).unwrap() {
+            assert_eq!(results.len(), 5);
+            assert!(results.iter().all(|r| tenant.contains(&r.id)));
+        }
+    }
+
+    #[test]
+    fn test_container_api() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        assert!(index.is_empty());
+        assert!(index.get(1).is_none());
+        for i in 0..10 {
+            index.add(VectorItem { id: i * 2, vector: vec![i as f64, 0.0] }).unwrap();
+        }
+        index.add(VectorItem { id: 4, vector: vec![9.0, 9.0] }).unwrap();
+
+        assert_eq!(index.len(), 10);
+        assert!(!index.is_empty());
+        assert!(index.contains(18) && !index.contains(3));
+        assert_eq!(index.get(4).map(|item| item.vector), Some(vec![9.0, 9.0]));
+
+        let many = index.get_many(&[18, 3, 4]);
+        assert_eq!(many.iter().map(|item| item.as_ref().map(|item| item.id)).collect::<Vec<_>>(), vec![Some(18), None, Some(4)]);
+        index.add_with_payload(VectorItem { id: 6, vector: vec![3.0, 0.0] }, "six".into()).unwrap();
+        let with_payloads = index.get_many_with_payloads(&[6, 8, 5]);
+        assert_eq!(with_payloads[0].as_ref().unwrap().1, Some("six".into()));
+        assert_eq!(with_payloads[1].as_ref().unwrap().1, None);
+        assert!(with_payloads[2].is_none());
+
+        assert_eq!(index.ids().collect::<Vec<_>>(), (0..10).map(|i| i * 2).collect::<Vec<_>>());
+        let items: Vec<_> = index.iter().collect();
+        index.add(VectorItem { id: 100, vector: vec![0.0, 0.0] }).unwrap();
+        assert_eq!(items.len(), 10);
+        assert_eq!(items[2].id, 4);
+        assert_eq!(items[2].vector, vec![9.0, 9.0]);
+    }
+
+    #[test]
+    fn test_audit_log_replays_graph() {
+        let path = std::env::temp_dir().join(format!("hnsw_audit_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_audit_log(AuditLog::to_file(&path).unwrap());
+        for i in 0..150 {
+            index.add(VectorItem { id: i % 120, vector: generate_random_vector(4) }).unwrap();
+        }
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log.lines().count(), 150);
+        assert!(log.starts_with("insert 0 level 0: entry=0\n"));
+
+        // Replaying the edge events must rebuild the graph exactly
+        let mut edges = HashSet::new();
+        for event in log.lines().flat_map(|line| line.split_once(": ").unwrap().1.split(' ')) {
+            let parse = |edge: &str| {
+                let (from, rest) = edge.split_once("->").unwrap();
+                let (to, level) = rest.split_once('@').unwrap();
+                (from.parse::<usize>().unwrap(), to.parse::<usize>().unwrap(), level.parse::<usize>().unwrap())
+            };
+            match event.as_bytes().first() {
+                Some(b'+') => assert!(edges.insert(parse(&event[1..])), "duplicate edge {}", event),
+                Some(b'-') => assert!(edges.remove(&parse(&event[1..])), "unknown edge {}", event),
+                _ => {}
+            }
+        }
+        let nodes = index.nodes.read().unwrap();
+        let actual: HashSet<_> = (0..nodes.len())
+            .flat_map(|from| {
+                let nodes = &nodes;
+                nodes.all_links(from).enumerate().flat_map(move |(level, links)| {
+                    links.iter().map(move |&slot| (nodes[from].id, slot, level))
+                })
+            })
+            .map(|(from, slot, level)| (from, nodes[slot].id, level))
+            .collect();
+        assert_eq!(edges, actual);
+    }
 
+    #[test]
+    fn test_clear_and_truncate() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_snapshot_reads(1000);
         for i in 0..100 {
-            items.push(VectorItem {
-                id: i,
-                vector: generate_random_vector(10),
-            });
+            index.add(VectorItem { id: i, vector: generate_random_vector(4) }).unwrap();
+        }
+        index.truncate(40);
+        assert_eq!(index.len(), 40);
+        assert!(index.contains(39) && !index.contains(40));
+        {
+            let nodes = index.nodes.read().unwrap();
+            assert!((0..nodes.len()).flat_map(|slot| nodes.all_links(slot).flatten()).all(|&slot| slot < 40));
+            assert!(index.entry_point.read().unwrap().is_some_and(|slot| slot < 40));
         }
+        let query = VectorItem { id: 1000, vector: generate_random_vector(4) };
+        assert!(index.search(&query, 10).unwrap().iter().all(|r| r.id < 40));
 
-        assert!(index.batch_add(items).is_ok());
+        index.clear();
+        assert!(index.is_empty());
+        assert!(index.search(&query, 10).unwrap().is_empty());
+        assert_eq!(index.add_auto(generate_random_vector(4)).unwrap(), 0);
+        index.publish();
+        assert_eq!(index.search(&query, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_search_radius() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(1);
+        for i in 0..200 {
+            index.add(VectorItem { id: i, vector: vec![i as f64, 0.0] }).unwrap();
+        }
+
+        // Wider than RADIUS_START_K, so k has to grow
+        let query = VectorItem { id: 0, vector: vec![100.2, 0.0] };
+        let ids: Vec<usize> = index.search_radius(&query, 30.0).unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids.len(), 60);
+        assert_eq!(ids[..3], [100, 101, 99]);
+        assert!(ids.iter().all(|&id| (71..=130).contains(&id)));
+
+        assert!(index.search_radius(&query, 0.1).unwrap().is_empty());
+        assert_eq!(index.search_radius(&query, 1e9).unwrap().len(), 200);
+        assert!(HnswIndex::new(Box::new(EuclideanDistance)).search_radius(&query, 1.0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_traced() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(6);
+        for i in 0..500 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(8) }).unwrap();
+        }
+        let query = VectorItem { id: 1000, vector: generate_random_vector(8) };
+        let (results, trace) = index.search_traced(&query, 10).unwrap();
+        let ids = |results: &[VectorItem]| results.iter().map(|item| item.id).collect::<Vec<_>>();
+        assert_eq!(ids(&results), ids(&index.search(&query, 10).unwrap()));
+
+        let nodes = index.nodes.read().unwrap();
+        let ep = index.entry_point.read().unwrap().unwrap();
+        assert_eq!(trace.entry_point, Some(nodes[ep].id));
+        let layers: Vec<usize> = trace.layers.iter().map(|layer| layer.layer).collect();
+        assert_eq!(layers, (0..=nodes[ep].layer).rev().collect::<Vec<_>>());
+        assert_eq!(trace.distance_computations, trace.layers.iter().map(|layer| layer.distance_computations).sum::<usize>());
+        let bottom = trace.layers.last().unwrap();
+        assert!(bottom.hops > 0 && bottom.visited >= index.ef_search && bottom.distance_computations >= bottom.visited - 1);
+
+        let (results, trace) = HnswIndex::new(Box::new(EuclideanDistance)).search_traced(&query, 10).unwrap();
+        assert!(results.is_empty() && trace == SearchTrace::default());
+    }
+
+    #[test]
+    fn test_search_exact() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(2);
+        let items: Vec<VectorItem> = (0..300).map(|i| VectorItem { id: i, vector: generate_random_vector(8) }).collect();
+        index.batch_add(items.clone()).unwrap();
+
+        let query = VectorItem { id: 1000, vector: generate_random_vector(8) };
+        let mut expected: Vec<(f64, usize)> = items.iter().map(|item| (EuclideanDistance.distance(&query.vector, &item.vector), item.id)).collect();
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let ids: Vec<usize> = index.search_exact(&query, 10).unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids, expected.iter().take(10).map(|&(_, id)| id).collect::<Vec<_>>());
+
+        assert_eq!(index.search_exact(&query, 1000).unwrap().len(), 300);
+        assert!(index.search_exact(&query, 0).unwrap().is_empty());
+        assert!(index.search_exact(&VectorItem { id: 0, vector: vec![0.0; 3] }, 5).is_err());
+    }
+
+    #[test]
+    fn test_remove() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(3);
+        let vectors: Vec<_> = (0..300).map(|_| generate_random_vector(6)).collect();
+        for (id, vector) in vectors.iter().enumerate() {
+            index.add(VectorItem { id, vector: vector.clone() }).unwrap();
+        }
+        assert!(!index.remove(1000).unwrap());
+
+        // Remove the entry point and every third id
+        let first = index.nodes.read().unwrap()[index.entry_point.read().unwrap().unwrap()].id;
+        let removed: HashSet<_> = (0..300).filter(|id| id % 3 == 0).chain([first]).collect();
+        for &id in &removed {
+            assert!(index.remove(id).unwrap());
+        }
+        assert_eq!(index.len(), 300 - removed.len());
+        assert!(removed.iter().all(|&id| !index.contains(id)));
+        {
+            let nodes = index.nodes.read().unwrap();
+            let ep = index.entry_point.read().unwrap().unwrap();
+            assert_eq!(nodes[ep].layer, nodes.iter().map(|node| node.layer).max().unwrap());
+            for slot in 0..nodes.len() {
+                assert!(nodes.all_links(slot).flatten().all(|&target| target < nodes.len()));
+            }
+        }
+        let report = index.validate(false);
+        assert!(report.is_valid(), "{}", report);
+
+        let mut found = 0;
+        for (id, vector) in vectors.iter().enumerate().filter(|(id, _)| !removed.contains(id)) {
+            let results = index.search(&VectorItem { id, vector: vector.clone() }, 5).unwrap();
+            assert!(results.iter().all(|r| !removed.contains(&r.id)));
+            found += usize::from(results[0].id == id);
+        }
+        assert!(found as f64 >= 0.95 * index.len() as f64, "found {} of {}", found, index.len());
+    }
+
+    #[test]
+    fn test_validate() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(4);
+        assert!(index.validate(false).is_valid());
+        for i in 0..300 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(6) }).unwrap();
+        }
+        let report = index.validate(true);
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(report.nodes, 300);
+        assert!(report.asymmetric_links.is_some());
+        assert!(index.validate(false).asymmetric_links.is_none());
+
+        // Cut one node off and point another at a missing slot
+        let mut nodes = index.nodes.write().unwrap();
+        let orphan = nodes.iter().position(|node| node.layer == 0).unwrap();
+        for slot in 0..nodes.len() {
+            nodes.retain_links(slot, 0, |target| target != orphan);
+        }
+        let id = nodes[orphan].id;
+        nodes.push_link((orphan + 1) % 300, 0, 5000);
+        drop(nodes);
+        let report = index.validate(false);
+        assert!(!report.is_valid());
+        assert_eq!(report.unreachable, [id]);
+        assert_eq!(report.dangling_links.len(), 1);
+    }
+
+    #[test]
+    fn test_degree_stats() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(5);
+        for i in 0..300 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(6) }).unwrap();
+        }
         let stats = index.get_stats();
-        assert_eq!(stats.total_nodes, 100);
+        assert_eq!(stats.layer_degrees.len(), stats.level_distribution.keys().max().unwrap() + 1);
+        assert_eq!(stats.layer_degrees[0].nodes, 300);
+        assert!(stats.layer_degrees[0].max <= index.max_connections(0));
+        assert!(stats.layer_degrees[0].average > 1.0);
+        assert_eq!((stats.isolated_nodes, stats.components), (0, 1));
+
+        // Detach a layer-0 node completely
+        let mut nodes = index.nodes.write().unwrap();
+        let orphan = nodes.iter().position(|node| node.layer == 0).unwrap();
+        for slot in 0..nodes.len() {
+            nodes.retain_links(slot, 0, |target| target != orphan);
+        }
+        nodes.set_links(orphan, 0, &[]);
+        drop(nodes);
+        let stats = index.get_stats();
+        assert_eq!((stats.isolated_nodes, stats.components), (1, 2));
+    }
+
+    #[test]
+    fn test_preallocated_links() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_capacity(300);
+        let items = (0..300).map(|i| VectorItem { id: i, vector: generate_random_vector(4) }).collect();
+        index.batch_add(items).unwrap();
+        index.reserve(100);
+
+        // No adjacency list outgrew its initial allocation
+        let nodes = index.nodes.read().unwrap();
+        for slot in 0..nodes.len() {
+            for level in 0..nodes.layers(slot) {
+                assert_eq!(nodes.link_capacity(slot, level), index.max_connections(level) + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..20 {
+            index.add(VectorItem { id: i % 15, vector: generate_random_vector(4) }).unwrap();
+        }
+        index.par_batch_add((10..30).map(|i| VectorItem { id: i, vector: generate_random_vector(4) }).collect()).unwrap();
+        let queries: Vec<_> = (0..3).map(|i| VectorItem { id: 100 + i, vector: generate_random_vector(4) }).collect();
+        index.batch_search(&queries, 5).unwrap();
+
+        let metrics = index.metrics().reset();
+        assert_eq!((metrics.inserts, metrics.updates), (30, 10));
+        assert_eq!((metrics.searches, metrics.results), (3, 15));
+        assert_eq!(index.metrics().snapshot().searches, 0);
+    }
+
+    #[test]
+    fn test_batched_distances() {
+        use std::sync::atomic::AtomicUsize;
+
+        // Euclidean, counting how many distances arrive in batches
+        struct Batched(std::sync::Arc<AtomicUsize>);
+        impl DistanceCalculator for Batched {
+            fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+                EuclideanDistance.distance(a, b)
+            }
+
+            fn distance_batch(&self, query: &[f64], vectors: &[&[f64]], out: &mut Vec<f64>) {
+                self.0.fetch_add(vectors.len(), AtomicOrdering::Relaxed);
+                out.extend(vectors.iter().map(|vector| EuclideanDistance.distance(query, vector)));
+            }
+        }
+
+        let batched = std::sync::Arc::new(AtomicUsize::new(0));
+        let index = HnswIndex::new(Box::new(Batched(batched.clone()))).with_deterministic_build(5);
+        let plain = HnswIndex::new(Box::new(EuclideanDistance)).with_deterministic_build(5);
+        let items: Vec<_> = (0..300).map(|id| VectorItem { id, vector: generate_random_vector(8) }).collect();
+        index.par_batch_add(items.clone()).unwrap();
+        plain.par_batch_add(items.clone()).unwrap();
+        assert!(batched.load(AtomicOrdering::Relaxed) > 0);
+
+        let before = batched.load(AtomicOrdering::Relaxed);
+        let ids = |results: Vec<VectorItem>| results.iter().map(|item| item.id).collect::<Vec<_>>();
+        assert_eq!(ids(index.search(&items[3], 10).unwrap()), ids(plain.search(&items[3], 10).unwrap()));
+        assert!(batched.load(AtomicOrdering::Relaxed) > before);
+    }
+
+    #[test]
+    fn test_angular_distance() {
+        use crate::vector::AngularDistance;
+
+        assert_eq!(AngularDistance.distance(&[1.0, 0.0], &[0.0, 3.0]), 1.0);
+        assert_eq!(AngularDistance.distance(&[1.0, 0.0], &[-2.0, 0.0]), 2.0);
+        assert_eq!(AngularDistance.distance(&[3.0, 4.0], &[6.0, 8.0]), 0.0);
+        assert_eq!(AngularDistance.distance(&[0.0, 0.0], &[1.0, 0.0]), 1.0);
+        assert_eq!(AngularDistance.distance(&[0.0, 0.0], &[0.0, 0.0]), 0.0);
+
+        // The same metric without the norm cache
+        struct Uncached;
+        impl DistanceCalculator for Uncached {
+            fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+                AngularDistance.distance(a, b)
+            }
+        }
+
+        let index = HnswIndex::new(Box::new(AngularDistance)).with_deterministic_build(7);
+        let uncached = HnswIndex::new(Box::new(Uncached)).with_deterministic_build(7);
+        let items: Vec<_> = (0..300).map(|id| VectorItem { id, vector: generate_random_vector(8) }).collect();
+        index.par_batch_add(items.clone()).unwrap();
+        uncached.par_batch_add(items.clone()).unwrap();
+        assert!(index.nodes.read().unwrap().iter().all(|node| node.norm.get().is_some()));
+        assert!(uncached.nodes.read().unwrap().iter().all(|node| node.norm.get().is_none()));
+        assert_eq!(index.config().metric, "angular");
+
+        let ids = |results: Vec<VectorItem>| results.iter().map(|item| item.id).collect::<Vec<_>>();
+        assert_eq!(ids(index.search(&items[3], 10).unwrap()), ids(uncached.search(&items[3], 10).unwrap()));
+        assert_eq!(index.search(&items[3], 1).unwrap()[0].id, 3);
+    }
+
+    #[test]
+    fn test_metrics_sink() {
+        #[derive(Default)]
+        struct Counts {
+            inserted: AtomicUsize,
+            updated: AtomicUsize,
+            results: AtomicUsize,
+        }
+        impl MetricsSink for Counts {
+            fn on_insert(&self, inserted: usize, updated: usize, _elapsed: Duration) {
+                self.inserted.fetch_add(inserted, AtomicOrdering::Relaxed);
+                self.updated.fetch_add(updated, AtomicOrdering::Relaxed);
+            }
+            fn on_search(&self, results: usize, _elapsed: Duration) {
+                self.results.fetch_add(results, AtomicOrdering::Relaxed);
+            }
+        }
+
+        let counts = std::sync::Arc::new(Counts::default());
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_metrics_sink(counts.clone());
+        index.add(VectorItem { id: 0, vector: generate_random_vector(4) }).unwrap();
+        index.add(VectorItem { id: 0, vector: generate_random_vector(4) }).unwrap();
+        index.par_batch_add((1..10).map(|i| VectorItem { id: i, vector: generate_random_vector(4) }).collect()).unwrap();
+        index.search(&VectorItem { id: 100, vector: generate_random_vector(4) }, 4).unwrap();
+
+        assert_eq!(counts.inserted.load(AtomicOrdering::Relaxed), 10);
+        assert_eq!(counts.updated.load(AtomicOrdering::Relaxed), 1);
+        assert_eq!(counts.results.load(AtomicOrdering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_sq8_quantization() {
+        let items: Vec<_> = (0..300).map(|i| VectorItem { id: i, vector: generate_random_vector(8) }).collect();
+        let sample: Vec<_> = items.iter().map(|item| item.vector.clone()).collect();
+        let quantizer = ScalarQuantizer::train(&sample).unwrap();
+
+        for rerank in [false, true] {
+            let index = HnswIndex::new(Box::new(EuclideanDistance))
+                .with_quantization(Quantization::Sq8 { quantizer: quantizer.clone(), rerank })
+                .unwrap();
+            assert_eq!(index.dimension(), Some(8));
+            index.batch_add(items.clone()).unwrap();
+            assert!(index.nodes.read().unwrap().iter().all(|node| node.codes.len() == 8));
+            assert_eq!(index.nodes.read().unwrap()[0].item.vector.is_empty(), !rerank);
+
+            // Stored vectors come back within half a quantization step
+            let stored = index.get(7).unwrap();
+            assert!(stored.vector.iter().zip(&items[7].vector).all(|(a, b)| (a - b).abs() <= 1.0 / 255.0));
+
+            let found = items.iter().filter(|item| index.search(item, 1).unwrap()[0].id == item.id).count();
+            assert!(found >= 290, "{} of 300 found themselves", found);
+
+            // Prefix routing needs the full vectors; without them the codes route
+            let layered = HnswIndex::new(Box::new(EuclideanDistance))
+                .with_resolutions(vec![2, 4])
+                .with_quantization(Quantization::Sq8 { quantizer: quantizer.clone(), rerank })
+                .unwrap();
+            layered.batch_add(items.clone()).unwrap();
+            let found = items.iter().filter(|item| layered.search(item, 1).unwrap()[0].id == item.id).count();
+            assert!(found >= 290, "{} of 300 found themselves with resolutions", found);
+
+            // Saved files hold full-precision vectors either way
+            let path = std::env::temp_dir().join(format!("hnsw_sq8_{}_{}.bin", rerank, std::process::id()));
+            index.save(&path).unwrap();
+            let loaded = HnswIndex::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.get(7).unwrap().vector, stored.vector);
+        }
+
+        let wrong = ScalarQuantizer::train(&[vec![0.0, 1.0]]).unwrap();
+        assert!(HnswIndex::new(Box::new(EuclideanDistance))
+            .with_dimension(8)
+            .with_quantization(Quantization::Sq8 { quantizer: wrong, rerank: false })
+            .is_err());
+    }
+
+    #[test]
+    fn test_pq_quantization() {
+        let items: Vec<_> = (0..300).map(|i| VectorItem { id: i, vector: generate_random_vector(8) }).collect();
+        let sample: Vec<_> = items.iter().map(|item| item.vector.clone()).collect();
+        let quantizer = train_pq(&sample, 4, 6).unwrap();
+        let index = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_quantization(Quantization::Pq { quantizer, rerank: true })
+            .unwrap();
+        index.batch_add(items.clone()).unwrap();
+        assert!(index.nodes.read().unwrap().iter().all(|node| node.codes.len() == 4));
+
+        let found = items.iter().filter(|item| index.search(item, 1).unwrap()[0].id == item.id).count();
+        assert!(found >= 280, "{} of 300 found themselves", found);
+    }
+
+    #[test]
+    fn test_binary_quantization() {
+        let items: Vec<_> = (0..300).map(|i| VectorItem { id: i, vector: generate_random_vector(32) }).collect();
+        let index = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_quantization(Quantization::Binary(BinaryQuantizer::sign()))
+            .unwrap();
+        index.batch_add(items.clone()).unwrap();
+        assert!(index.nodes.read().unwrap().iter().all(|node| node.codes.len() == 4));
+
+        // Results are reranked with exact distances
+        let query = VectorItem { id: 1000, vector: generate_random_vector(32) };
+        let results = index.search(&query, 10).unwrap();
+        let distances: Vec<_> = results.iter().map(|r| EuclideanDistance.distance(&query.vector, &r.vector)).collect();
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+        let found = items.iter().filter(|item| index.search(item, 1).unwrap()[0].id == item.id).count();
+        assert!(found >= 280, "{} of 300 found themselves", found);
+    }
+
+    #[test]
+    fn test_empty_and_single_item() {
+        let all_searches = |index: &HnswIndex, query: &VectorItem, k: usize| {
+            vec![
+                index.search(query, k).unwrap(),
+                index.search_excluding(query, k, &[9]).unwrap(),
+                index.search_filtered(query, k, &|_: usize| true).unwrap(),
+                index.search_masked(query, k, &[0]).unwrap(),
+                index.batch_search(std::slice::from_ref(query), k).unwrap().remove(0),
+                index.search_with_payloads(query, k).unwrap().into_iter().map(|(item, _)| item).collect(),
+            ]
+        };
+        let query = VectorItem { id: 100, vector: vec![0.5, 0.5] };
+
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        assert!(all_searches(&index, &query, 5).iter().all(Vec::is_empty));
+        assert!(index.batch_search(&[], 5).unwrap().is_empty());
+        assert!(index.neighbors_of(1, 5).is_err());
+        let fixed = HnswIndex::new(Box::new(EuclideanDistance)).with_dimension(3);
+        assert!(fixed.search(&query, 5).is_err());
+
+        index.add(VectorItem { id: 1, vector: vec![1.0, 1.0] }).unwrap();
+        assert!(all_searches(&index, &query, 5).iter().all(|results| results.len() == 1 && results[0].id == 1));
+        assert!(all_searches(&index, &query, 0).iter().all(Vec::is_empty));
+        assert!(index.search_excluding(&query, 5, &[1]).unwrap().is_empty());
+        assert!(index.search_filtered(&query, 5, &|_: usize| false).unwrap().is_empty());
+        assert!(index.neighbors_of(1, 5).unwrap().is_empty());
+        assert!(index.search(&VectorItem { id: 100, vector: vec![0.5] }, 0).is_err());
+    }
+
+    #[test]
+    fn test_explain_plan() {
+        let items: Vec<_> = (0..2000).map(|i| VectorItem { id: i, vector: generate_random_vector(4) }).collect();
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        index.par_batch_add(items.clone()).unwrap();
+        let query = VectorItem { id: 5000, vector: generate_random_vector(4) };
+
+        let open = index.explain_plan(&query, 10, None).unwrap();
+        assert_eq!((open.strategy, open.selectivity), (SearchStrategy::Graph, 1.0));
+
+        // A filter passing 10 of 2000 ids is answered by an exact scan
+        let rare = |id: usize| id.is_multiple_of(200);
+        let plan = index.explain_plan(&query, 3, Some(&rare)).unwrap();
+        assert_eq!(plan.strategy, SearchStrategy::BruteForce);
+        assert!(plan.selectivity < 0.05);
+        let mut expected: Vec<_> = items.iter().filter(|item| rare(item.id)).collect();
+        expected.sort_by(|a, b| EuclideanDistance.distance(&query.vector, &a.vector).total_cmp(&EuclideanDistance.distance(&query.vector, &b.vector)));
+        let results = index.search_filtered(&query, 3, &rare).unwrap();
+        assert_eq!(results.iter().map(|r| r.id).collect::<Vec<_>>(), expected[..3].iter().map(|e| e.id).collect::<Vec<_>>());
+
+        assert!(index.explain_plan(&VectorItem { id: 0, vector: vec![1.0] }, 3, None).is_err());
+
+        // Searches settle on the plan's strategy without sampling the whole
+        // filter, and reuse the sample until the graph changes
+        let calls = AtomicUsize::new(0);
+        let broad = |_: usize| {
+            calls.fetch_add(1, AtomicOrdering::Relaxed);
+            true
+        };
+        let sample = index.with_search_graph(|nodes, entry_point| {
+            for filter in [&broad as &dyn IdFilter, &rare, &|id: usize| id < 40, &|_: usize| false] {
+                let plan = index.plan(nodes, entry_point, 64, Some(filter));
+                assert_eq!(index.strategy(nodes, entry_point, 64, filter), plan.strategy);
+            }
+            calls.store(0, AtomicOrdering::Relaxed);
+            index.strategy(nodes, entry_point, 10, &broad);
+            assert_eq!(calls.load(AtomicOrdering::Relaxed), PLAN_CHUNK);
+            index.plan_sample(nodes)
+        });
+        assert!(Arc::ptr_eq(&sample, &index.with_search_graph(|nodes, _| index.plan_sample(nodes))));
+        index.add(VectorItem { id: 5001, vector: generate_random_vector(4) }).unwrap();
+        let grown = index.with_search_graph(|nodes, _| index.plan_sample(nodes));
+        assert_eq!(grown.len, items.len() + 1);
+
+        // A stale entry point is planned around, not indexed
+        *index.entry_point.write().unwrap() = Some(items.len());
+        assert_eq!(index.explain_plan(&query, 10, None).unwrap().strategy, SearchStrategy::Graph);
+    }
+
+    #[test]
+    fn test_rerankers() {
+        // Ranks candidates by a payload field, then keeps the even ids
+        let by_priority = |_: &VectorItem, mut candidates: Vec<Candidate>| {
+            for candidate in &mut candidates {
+                candidate.score = candidate.payload.as_ref().and_then(|p| p["priority"].as_f64()).unwrap_or(0.0);
+            }
+            candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+            Ok(candidates)
+        };
+        let even = |_: &VectorItem, candidates: Vec<Candidate>| {
+            Ok(candidates.into_iter().filter(|c| c.item.id.is_multiple_of(2)).collect())
+        };
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_reranker(by_priority).with_reranker(even);
+        for i in 0..200 {
+            let payload = serde_json::json!({ "priority": i });
+            index.add_with_payload(VectorItem { id: i, vector: generate_random_vector(4) }, payload).unwrap();
+        }
+
+        let results = index.search(&VectorItem { id: 1000, vector: generate_random_vector(4) }, 5).unwrap();
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|r| r.id % 2 == 0));
+        assert!(results.windows(2).all(|w| w[0].id > w[1].id));
+    }
+
+    #[test]
+    fn test_config() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_dimension(4).with_deterministic_build(1);
+        let config = index.config();
+        assert_eq!(config.metric, "euclidean");
+        assert_eq!((config.dimension, config.m, config.ef_search), (Some(4), M, EF_SEARCH));
+        assert!(config.deterministic);
+        assert_eq!((config.vector_precision, config.quantization), ("f64", None));
+
+        let quantization = Quantization::Sq8 { quantizer: ScalarQuantizer::per_vector(), rerank: false };
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_quantization(quantization).unwrap();
+        let config = index.config();
+        assert_eq!(config.vector_precision, "sq8");
+        assert!(config.to_string().contains("quantization: sq8 (full vectors kept: false)"));
+
+        // Tuned graph parameters bound every adjacency list
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_seed(2).with_m(4).with_ef_construction(20).with_ef_search(8);
+        let config = index.config();
+        assert_eq!((config.m, config.m_max0, config.ef_construction, config.ef_search), (4, 8, 20, 8));
+        assert!((config.level_lambda - 1.0 / 4f64.ln()).abs() < 1e-12);
+        for i in 0..300 {
+            index.add(VectorItem { id: i, vector: vec![(i % 17) as f64, (i / 17) as f64] }).unwrap();
+        }
+        let nodes = index.nodes.read().unwrap();
+        for slot in 0..nodes.len() {
+            assert!(nodes.links(slot, 0).len() <= 8);
+            assert!(nodes.all_links(slot).skip(1).all(|links| links.len() <= 4));
+        }
+        drop(nodes);
+        let query = VectorItem { id: 0, vector: vec![3.0, 3.0] };
+        assert_eq!(index.search(&query, 1).unwrap()[0].id, 54);
+    }
+
+    #[test]
+    fn test_add_npy() {
+        let items: Vec<_> = (0..20).map(|i| VectorItem { id: i, vector: vec![i as f64, 1.0] }).collect();
+        let path = std::env::temp_dir().join(format!("hnsw_add_npy_{}.npy", std::process::id()));
+        crate::io::write_npy(&mut File::create(&path).unwrap(), &items).unwrap();
+
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        index.add_npy(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(index.len(), 20);
+        assert_eq!(index.get(7).unwrap().vector, vec![7.0, 1.0]);
+    }
+
+    #[test]
+    fn test_batch_search_matches_search() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..200 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(8) }).unwrap();
+        }
+
+        let queries: Vec<_> = (0..20)
+            .map(|i| VectorItem { id: 1000 + i, vector: generate_random_vector(8) })
+            .collect();
+        let batched = index.batch_search(&queries, 5).unwrap();
+
+        assert_eq!(batched.len(), queries.len());
+        for (query, results) in queries.iter().zip(&batched) {
+            let single: Vec<_> = index.search(query, 5).unwrap().iter().map(|r| r.id).collect();
+            let ids: Vec<_> = results.iter().map(|r| r.id).collect();
+            assert_eq!(ids, single);
+        }
+    }
+
+    #[test]
+    fn test_transform_applied_on_insert_and_query() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_transform(VectorTransform::truncate(4));
+        for i in 0..20 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(16) }).unwrap();
+        }
+
+        let query = VectorItem { id: 100, vector: generate_random_vector(16) };
+        let results = index.search(&query, 3).unwrap();
+        assert!(!results.is_empty());
+        assert!(results.iter().all(|r| r.vector.len() == 4));
+
+        let short = VectorItem { id: 101, vector: generate_random_vector(2) };
+        assert!(index.add(short.clone()).is_err());
+        assert!(index.search(&short, 3).is_err());
+    }
+
+    #[test]
+    fn test_multi_resolution_search() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_resolutions(vec![8, 2, 4, 0]);
+        assert_eq!(index.resolutions(), &[2, 4, 8]);
+
+        for i in 0..100 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(16) }).unwrap();
+        }
+
+        let query = VectorItem { id: 1000, vector: generate_random_vector(16) };
+        let results = index.search(&query, 10).unwrap();
+        assert!(!results.is_empty());
+        assert!(results.iter().all(|r| r.vector.len() == 16));
+
+        // Results are reranked on the full vectors
+        let distances: Vec<_> = results.iter().map(|r| EuclideanDistance.distance(&query.vector, &r.vector)).collect();
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_search_masked() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..50 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(6) }).unwrap();
+        }
+
+        let query = VectorItem { id: 100, vector: generate_random_vector(6) };
+        let dims = [0, 3, 5];
+        let results = index.search_masked(&query, 5, &dims).unwrap();
+        assert!(!results.is_empty());
+
+        let masked = MaskedDistance::new(&EuclideanDistance, &dims);
+        let distances: Vec<_> = results.iter().map(|r| masked.distance(&query.vector, &r.vector)).collect();
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+
+        assert!(index.search_masked(&query, 5, &[]).is_err());
+        assert!(index.search_masked(&query, 5, &[6]).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("hnsw_save_load_{}.bin", std::process::id()));
+        let projection = VectorTransform::projection(vec![vec![1.0, 0.0, 0.5], vec![0.0, 1.0, 0.5]]).unwrap();
+        let index = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_transform(projection.clone())
+            .with_resolutions(vec![1])
+            .with_m(32)
+            .with_ef_construction(50)
+            .with_ef_search(20);
+        for i in 0..100 {
+            index.add(VectorItem { id: i * 3, vector: generate_random_vector(3) }).unwrap();
+        }
+        index.add_with_payload(VectorItem { id: 3, vector: generate_random_vector(3) }, "three".into()).unwrap();
+
+        index.save(&path).unwrap();
+        let loaded = HnswIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.get_stats().total_nodes, 100);
+        assert_eq!(loaded.get_stats().total_connections, index.get_stats().total_connections);
+        assert_eq!(loaded.transform(), Some(&projection));
+        assert_eq!(loaded.resolutions(), &[1]);
+        assert_eq!(loaded.payload(3), Some("three".into()));
+        assert_eq!(loaded.graph_params(), GraphParams { m: 32, ef_construction: 50, ef_search: 20 });
+        assert_eq!(loaded.level_lambda, index.level_lambda);
+        assert!(loaded.validate(false).is_valid());
+
+        let query = VectorItem { id: 1000, vector: generate_random_vector(3) };
+        let expected: Vec<_> = index.search(&query, 5).unwrap().iter().map(|r| r.id).collect();
+        let actual: Vec<_> = loaded.search(&query, 5).unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(actual, expected);
+        assert_eq!(loaded.add_auto(generate_random_vector(3)).unwrap(), 298);
+    }
+
+    #[test]
+    fn test_hnswlib_roundtrip() {
+        let path = std::env::temp_dir().join(format!("hnsw_hnswlib_{}.bin", std::process::id()));
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_m(12).with_ef_construction(77);
+        for i in 0..100 {
+            index.add(VectorItem { id: i + 10, vector: generate_random_vector(5) }).unwrap();
+        }
+
+        index.save_hnswlib(&path).unwrap();
+        // Header (96 bytes) + 100 level-0 records of (4 + 24*4) + 5*4 + 8 bytes + upper lists
+        assert!(std::fs::metadata(&path).unwrap().len() >= 96 + 100 * 128 + 100 * 4);
+        let loaded = HnswIndex::load_hnswlib(&path).unwrap();
+        assert_eq!((loaded.m, loaded.ef_construction, loaded.level_lambda), (index.m, index.ef_construction, index.level_lambda));
+
+        // A record size that does not fit the file fails before allocating
+        let mut bytes = std::fs::read(&path).unwrap();
+        let huge = (1u64 << 60) + 8;
+        for (field, value) in [(3, huge), (4, huge - 8)] {
+            bytes[field * 8..field * 8 + 8].copy_from_slice(&value.to_le_bytes());
+        }
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(HnswIndex::load_hnswlib(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let original = index.nodes.read().unwrap();
+        let restored = loaded.nodes.read().unwrap();
+        assert_eq!(restored.len(), original.len());
+        assert_eq!(*loaded.entry_point.read().unwrap(), *index.entry_point.read().unwrap());
+        for (slot, (node, other)) in original.iter().zip(restored.iter()).enumerate() {
+            assert_eq!(other.id, node.id);
+            assert!(restored.all_links(slot).eq(original.all_links(slot)));
+            assert_eq!(other.layer, node.layer);
+            for (a, b) in other.item.vector.iter().zip(&node.item.vector) {
+                assert!((a - b).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_rejects_garbage() {
+        let path = std::env::temp_dir().join(format!("hnsw_garbage_{}.bin", std::process::id()));
+        std::fs::write(&path, vec![b'x'; 128]).unwrap();
+        let err = HnswIndex::load(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_transform(VectorTransform::truncate(4))
+            .with_m(6)
+            .with_ef_search(9);
+        for i in 0..50 {
+            index.add(VectorItem { id: i, vector: generate_random_vector(6) }).unwrap();
+        }
+
+        let json = serde_json::to_string(&index).unwrap();
+        let restored: HnswIndex = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_stats().total_nodes, 50);
+        assert_eq!(restored.get_stats().total_connections, index.get_stats().total_connections);
+        assert_eq!(restored.transform(), Some(&VectorTransform::truncate(4)));
+        assert_eq!(restored.graph_params(), index.graph_params());
+
+        let query = VectorItem { id: 100, vector: generate_random_vector(6) };
+        let expected: Vec<_> = index.search(&query, 5).unwrap().iter().map(|r| r.id).collect();
+        let actual: Vec<_> = restored.search(&query, 5).unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(actual, expected);
     }
 }
@@ -0,0 +1,322 @@
+//! Reader and writer for the on-disk format of the C++/Python hnswlib
+//! (`HierarchicalNSW::saveIndex`), assuming a float32 space such as `l2` or
+//! `ip`.
+//!
+//! ```text
+//! header   offsetLevel0, max_elements, cur_element_count,
+//!          size_data_per_element, label_offset, offsetData: size_t
+//!          maxlevel: i32, enterpoint_node: u32
+//!          maxM, maxM0, M: size_t, mult: f64, ef_construction: size_t
+//! level 0  cur_element_count records of size_data_per_element bytes:
+//!          link count (u16 + deleted flag byte + pad), maxM0 u32 links,
+//!          dim f32 components, u64 label
+//! upper    per element: u32 byte length, then one block per level above 0:
+//!          u32 link count, maxM u32 links
+//! ```
+//!
+//! hnswlib does not store the metric, so as with `HnswIndex::load` the
+//! distance calculator must be set by the caller. Vectors are narrowed to
+//! `f32` on export.
+
+use crate::node::Node;
+use crate::persist::invalid_data;
+use crate::slab::NodeSlab;
+use crate::vector::VectorItem;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+const DELETE_MARK: u8 = 0x01;
+
+// Bytes before the level-0 records
+const HEADER_LEN: u64 = 96;
+
+pub(crate) struct HnswlibGraph {
+    pub nodes: NodeSlab,
+    pub entry_point: Option<usize>,
+    pub m: usize,
+    pub ef_construction: usize,
+    pub level_lambda: f64,
+}
+
+struct Layout {
+    max_elements: usize,
+    element_count: usize,
+    size_data_per_element: usize,
+    label_offset: usize,
+    offset_data: usize,
+    max_level: i32,
+    entry_point: u32,
+    max_m: usize,
+    max_m0: usize,
+    m: usize,
+    mult: f64,
+    ef_construction: usize,
+}
+
+impl Layout {
+    fn size_links_per_element(&self) -> usize {
+        self.max_m * 4 + 4
+    }
+}
+
+/// Reads an hnswlib index of `file_len` bytes. Elements carrying hnswlib's
+/// deleted mark stay in the graph, marked as by `HnswIndex::mark_deleted`.
+/// The header's sizes are checked against each other and the file length
+/// before anything is allocated from them.
+pub(crate) fn read_hnswlib<R: Read>(reader: &mut R, file_len: u64) -> io::Result<HnswlibGraph> {
+    let offset_level0 = read_usize(reader)?;
+    let layout = Layout {
+        max_elements: read_usize(reader)?,
+        element_count: read_usize(reader)?,
+        size_data_per_element: read_usize(reader)?,
+        label_offset: read_usize(reader)?,
+        offset_data: read_usize(reader)?,
+        max_level: i32::from_le_bytes(read_array(reader)?),
+        entry_point: u32::from_le_bytes(read_array(reader)?),
+        max_m: read_usize(reader)?,
+        max_m0: read_usize(reader)?,
+        m: read_usize(reader)?,
+        mult: f64::from_le_bytes(read_array(reader)?),
+        ef_construction: read_usize(reader)?,
+    };
+
+    // Link counts are stored in 16 bits, which also keeps the sizes below
+    // from overflowing
+    let max_degree = u16::MAX as usize;
+    if layout.m == 0 || layout.max_m < layout.m || layout.max_m0 < layout.m || layout.max_m.max(layout.max_m0) > max_degree {
+        return Err(invalid_data("Not an hnswlib index file"));
+    }
+    let links0_len = layout.max_m0 * 4 + 4;
+    if offset_level0 != 0
+        || layout.offset_data != links0_len
+        || layout.label_offset <= layout.offset_data
+        || !(layout.label_offset - layout.offset_data).is_multiple_of(4)
+        || layout.size_data_per_element != layout.label_offset.saturating_add(8)
+        || layout.element_count > layout.max_elements
+    {
+        return Err(invalid_data("Not an hnswlib index file"));
+    }
+    let dimension = (layout.label_offset - layout.offset_data) / 4;
+    let records_len = (layout.element_count as u64).checked_mul(layout.size_data_per_element as u64);
+    let mut remaining = match records_len.and_then(|len| file_len.checked_sub(HEADER_LEN)?.checked_sub(len)) {
+        Some(remaining) => remaining,
+        None => {
+            return Err(invalid_data(format!(
+                "hnswlib file of {} bytes is too short for {} elements of dimension {}",
+                file_len, layout.element_count, dimension
+            )))
+        }
+    };
+
+    let mut labels = Vec::with_capacity(layout.element_count.min(1 << 20));
+    let mut deleted = Vec::with_capacity(labels.capacity());
+    let mut vectors = Vec::with_capacity(labels.capacity());
+    let mut level0 = Vec::with_capacity(labels.capacity());
+    let mut record = vec![0u8; layout.size_data_per_element];
+    for _ in 0..layout.element_count {
+        reader.read_exact(&mut record)?;
+        let count = u16::from_le_bytes([record[0], record[1]]) as usize;
+        if count > layout.max_m0 {
+            return Err(invalid_data(format!("Level-0 link count {} exceeds maxM0", count)));
+        }
+        deleted.push(record[2] & DELETE_MARK != 0);
+        level0.push(read_u32s(&record[4..4 + count * 4]));
+        vectors.push(
+            record[layout.offset_data..layout.label_offset]
+                .chunks_exact(4)
+                .map(|c| f32::from_le_bytes(c.try_into().unwrap()) as f64)
+                .collect::<Vec<_>>(),
+        );
+        labels.push(u64::from_le_bytes(record[layout.label_offset..].try_into().unwrap()) as usize);
+    }
+
+    let block_len = layout.size_links_per_element();
+    let mut upper = Vec::with_capacity(labels.len());
+    for i in 0..layout.element_count {
+        let len = u32::from_le_bytes(read_array(reader)?) as usize;
+        remaining = remaining.saturating_sub(4);
+        if !len.is_multiple_of(block_len) || len as u64 > remaining || len / block_len > layout.max_level.max(0) as usize {
+            return Err(invalid_data(format!("Element {} has a malformed link list", i)));
+        }
+        remaining -= len as u64;
+        let mut bytes = vec![0u8; len];
+        reader.read_exact(&mut bytes)?;
+        let mut levels = Vec::with_capacity(len / block_len);
+        for block in bytes.chunks_exact(block_len) {
+            let count = u32::from_le_bytes(block[..4].try_into().unwrap()) as usize & 0xffff;
+            if count > layout.max_m {
+                return Err(invalid_data(format!("Element {} has {} links, maxM is {}", i, count, layout.max_m)));
+            }
+            levels.push(read_u32s(&block[4..4 + count * 4]));
+        }
+        upper.push(levels);
+    }
+
+    // Slots are hnswlib's internal ids
+    let slot_of = |internal: u32| -> io::Result<usize> {
+        match internal as usize {
+            slot if slot < layout.element_count => Ok(slot),
+            _ => Err(invalid_data(format!("Link to unknown element {}", internal))),
+        }
+    };
+
+    let mut nodes = Vec::with_capacity(labels.len());
+    for (i, ((links0, levels), vector)) in level0.into_iter().zip(upper).zip(vectors).enumerate() {
+        let id = labels[i];
+        let connections = std::iter::once(links0)
+            .chain(levels)
+            .map(|links| links.into_iter().map(slot_of).collect::<io::Result<Vec<_>>>())
+            .collect::<io::Result<Vec<_>>>()?;
+        debug_assert_eq!(vector.len(), dimension);
+
+        nodes.push((Node {
+            id,
+            layer: connections.len() - 1,
+            item: Arc::new(VectorItem { id, vector }),
+            codes: Vec::new(),
+            norm: Default::default(),
+            payload: None,
+            expires_at: None,
+            deleted: deleted[i],
+        }, connections));
+    }
+    let nodes = NodeSlab::from_nodes(nodes).map_err(|_| invalid_data("Duplicate label in hnswlib index"))?;
+
+    // hnswlib stores -1 as the entry point of an empty index
+    let entry_point = if layout.element_count == 0 || layout.max_level < 0 {
+        None
+    } else {
+        Some(slot_of(layout.entry_point)?)
+    };
+
+    Ok(HnswlibGraph {
+        nodes,
+        entry_point,
+        m: layout.m,
+        ef_construction: layout.ef_construction,
+        level_lambda: layout.mult,
+    })
+}
+
+pub(crate) fn write_hnswlib<W: Write>(
+    writer: &mut W,
+    nodes: &NodeSlab,
+    entry_point: Option<usize>,
+    m: usize,
+    ef_construction: usize,
+    level_lambda: f64,
+) -> io::Result<()> {
+    if nodes.len() > u32::MAX as usize {
+        return Err(invalid_data("hnswlib indexes are limited to u32::MAX elements"));
+    }
+
+    let dimension = nodes.get(0).map_or(0, |node| node.item.vector.len());
+    if let Some(node) = nodes.iter().find(|node| node.item.vector.len() != dimension) {
+        return Err(invalid_data(format!(
+            "Vector {} has {} dimensions, expected {}",
+            node.id,
+            node.item.vector.len(),
+            dimension
+        )));
+    }
+
+    // hnswlib's slots are fixed-size, so size them for the largest list here
+    let degree = |level: usize| {
+        (0..nodes.len())
+            .filter(|&slot| level < nodes.layers(slot))
+            .map(|slot| nodes.links(slot, level).len())
+            .max()
+            .unwrap_or(0)
+    };
+    let max_level = nodes.iter().map(|node| node.layer).max().unwrap_or(0);
+    let max_m0 = degree(0).max(2 * m);
+    let max_m = (1..=max_level).map(degree).max().unwrap_or(0).max(m);
+    if max_m0 > u16::MAX as usize {
+        return Err(invalid_data("hnswlib link counts are limited to 65535"));
+    }
+
+    let layout = Layout {
+        max_elements: nodes.len(),
+        element_count: nodes.len(),
+        size_data_per_element: max_m0 * 4 + 4 + dimension * 4 + 8,
+        label_offset: max_m0 * 4 + 4 + dimension * 4,
+        offset_data: max_m0 * 4 + 4,
+        max_level: entry_point.map_or(-1, |ep| nodes[ep].layer as i32),
+        entry_point: entry_point.map_or(u32::MAX, |ep| ep as u32),
+        max_m,
+        max_m0,
+        m,
+        mult: level_lambda,
+        ef_construction,
+    };
+
+    write_usize(writer, 0)?;
+    write_usize(writer, layout.max_elements)?;
+    write_usize(writer, layout.element_count)?;
+    write_usize(writer, layout.size_data_per_element)?;
+    write_usize(writer, layout.label_offset)?;
+    write_usize(writer, layout.offset_data)?;
+    writer.write_all(&layout.max_level.to_le_bytes())?;
+    writer.write_all(&layout.entry_point.to_le_bytes())?;
+    write_usize(writer, layout.max_m)?;
+    write_usize(writer, layout.max_m0)?;
+    write_usize(writer, layout.m)?;
+    writer.write_all(&layout.mult.to_le_bytes())?;
+    write_usize(writer, layout.ef_construction)?;
+
+    let links = |links: &[usize], slots: usize| -> io::Result<Vec<u8>> {
+        let mut block = Vec::with_capacity(4 + slots * 4);
+        block.extend_from_slice(&(links.len() as u32).to_le_bytes());
+        for &link in links {
+            if link >= nodes.len() {
+                return Err(invalid_data(format!("Link to missing slot {}", link)));
+            }
+            block.extend_from_slice(&(link as u32).to_le_bytes());
+        }
+        block.resize(4 + slots * 4, 0);
+        Ok(block)
+    };
+
+    for (slot, node) in nodes.iter().enumerate() {
+        let mut level0 = links(nodes.links(slot, 0), max_m0)?;
+        if node.deleted {
+            level0[2] |= DELETE_MARK;
+        }
+        writer.write_all(&level0)?;
+        for component in &node.item.vector {
+            writer.write_all(&(*component as f32).to_le_bytes())?;
+        }
+        write_usize(writer, node.id)?;
+    }
+
+    for slot in 0..nodes.len() {
+        let upper = nodes.layers(slot).saturating_sub(1);
+        writer.write_all(&((upper * layout.size_links_per_element()) as u32).to_le_bytes())?;
+        for level in nodes.all_links(slot).skip(1) {
+            writer.write_all(&links(level, max_m)?)?;
+        }
+    }
+
+    writer.flush()
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> io::Result<usize> {
+    Ok(u64::from_le_bytes(read_array(reader)?) as usize)
+}
+
+fn write_usize<W: Write>(writer: &mut W, value: usize) -> io::Result<()> {
+    writer.write_all(&(value as u64).to_le_bytes())
+}
+
+fn read_u32s(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+        .collect()
+}
@@ -0,0 +1,58 @@
+use crate::vector::VectorItem;
+
+/// Runs before every insert, on the vector as passed by the caller (before
+/// any `VectorTransform`). Hooks may rewrite the vector or reject the insert;
+/// the id is fixed so `add_auto` can still report it.
+pub trait InsertHook: Send + Sync {
+    fn before_insert(&self, id: usize, vector: &mut Vec<f64>) -> Result<(), String>;
+}
+
+/// Runs on the results of every search before they are returned. Hooks may
+/// reorder, filter or annotate the results, or fail the search.
+pub trait SearchHook: Send + Sync {
+    fn after_search(&self, query: &VectorItem, results: &mut Vec<VectorItem>) -> Result<(), String>;
+}
+
+impl<F> InsertHook for F
+where
+    F: Fn(usize, &mut Vec<f64>) -> Result<(), String> + Send + Sync,
+{
+    fn before_insert(&self, id: usize, vector: &mut Vec<f64>) -> Result<(), String> {
+        self(id, vector)
+    }
+}
+
+impl<F> SearchHook for F
+where
+    F: Fn(&VectorItem, &mut Vec<VectorItem>) -> Result<(), String> + Send + Sync,
+{
+    fn after_search(&self, query: &VectorItem, results: &mut Vec<VectorItem>) -> Result<(), String> {
+        self(query, results)
+    }
+}
+
+/// Rejects vectors containing NaN or infinite components.
+pub struct RejectNonFinite;
+
+impl InsertHook for RejectNonFinite {
+    fn before_insert(&self, id: usize, vector: &mut Vec<f64>) -> Result<(), String> {
+        match vector.iter().position(|x| !x.is_finite()) {
+            Some(dim) => Err(format!("Vector {} has a non-finite component at dimension {}", id, dim)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Scales every vector to unit length; zero vectors are rejected.
+pub struct Normalize;
+
+impl InsertHook for Normalize {
+    fn before_insert(&self, id: usize, vector: &mut Vec<f64>) -> Result<(), String> {
+        let norm = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm == 0.0 {
+            return Err(format!("Vector {} has zero length and cannot be normalized", id));
+        }
+        vector.iter_mut().for_each(|x| *x /= norm);
+        Ok(())
+    }
+}
@@ -0,0 +1,582 @@
+//! Readers and writers for the TEXMEX vector formats used by the standard
+//! ANN benchmark datasets (SIFT1M, GIST1M, BIGANN):
+//!
+//! ```text
+//! .fvecs   per vector: dim: i32, then dim f32 components
+//! .bvecs   per vector: dim: i32, then dim u8 components
+//! .ivecs   per vector: dim: i32, then dim i32 components
+//! ```
+//!
+//! All values are little-endian. The files carry no ids: a vector's id is
+//! its row number, and writers ignore `VectorItem::id`. `.ivecs` files hold
+//! ground truth, one row of neighbor ids per query.
+//!
+//! NumPy `.npy` files are read too: 2-D float32/float64 arrays of vectors,
+//! with ids from the row numbers or from a 1-D integer array. `.npz` archives
+//! of such arrays need the `npz` feature.
+//!
+//! Text input is streamed line by line: CSV rows of `id,v0,v1,...` (an
+//! optional header row is skipped) and JSON Lines of
+//! `{"id": .., "vector": [..]}`. Blank lines are ignored. The writers emit
+//! the same layouts, ids included, without a CSV header.
+
+use crate::persist::invalid_data;
+use crate::vector::VectorItem;
+use std::io::{self, BufRead, Read, Write};
+
+#[derive(Clone, Copy, Debug)]
+enum Component {
+    F32,
+    U8,
+}
+
+/// Streams the vectors of an `.fvecs` or `.bvecs` file; see `fvecs` and
+/// `bvecs`. Every row must have the dimension of the first.
+pub struct VecsReader<R> {
+    reader: R,
+    component: Component,
+    next_id: usize,
+    dimension: Option<usize>,
+}
+
+/// Streams an `.fvecs` file. Wrap files in a `BufReader`.
+pub fn fvecs<R: Read>(reader: R) -> VecsReader<R> {
+    VecsReader { reader, component: Component::F32, next_id: 0, dimension: None }
+}
+
+/// Streams a `.bvecs` file. Wrap files in a `BufReader`.
+pub fn bvecs<R: Read>(reader: R) -> VecsReader<R> {
+    VecsReader { reader, component: Component::U8, next_id: 0, dimension: None }
+}
+
+pub fn read_fvecs<R: Read>(reader: R) -> io::Result<Vec<VectorItem>> {
+    fvecs(reader).collect()
+}
+
+pub fn read_bvecs<R: Read>(reader: R) -> io::Result<Vec<VectorItem>> {
+    bvecs(reader).collect()
+}
+
+impl<R: Read> VecsReader<R> {
+    fn read_vector(&mut self) -> io::Result<Option<VectorItem>> {
+        let Some(dimension) = read_dimension(&mut self.reader)? else {
+            return Ok(None);
+        };
+        check_dimension(&mut self.dimension, dimension, self.next_id)?;
+        let vector = match self.component {
+            Component::F32 => {
+                let mut bytes = vec![0u8; dimension * 4];
+                self.reader.read_exact(&mut bytes)?;
+                bytes.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap()) as f64).collect()
+            }
+            Component::U8 => {
+                let mut bytes = vec![0u8; dimension];
+                self.reader.read_exact(&mut bytes)?;
+                bytes.into_iter().map(f64::from).collect()
+            }
+        };
+        let item = VectorItem { id: self.next_id, vector };
+        self.next_id += 1;
+        Ok(Some(item))
+    }
+}
+
+impl<R: Read> Iterator for VecsReader<R> {
+    type Item = io::Result<VectorItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_vector().transpose()
+    }
+}
+
+/// Reads an `.ivecs` file, e.g. ground-truth neighbor ids.
+pub fn read_ivecs<R: Read>(mut reader: R) -> io::Result<Vec<Vec<usize>>> {
+    let mut rows = Vec::new();
+    let mut expected = None;
+    while let Some(dimension) = read_dimension(&mut reader)? {
+        check_dimension(&mut expected, dimension, rows.len())?;
+        let mut bytes = vec![0u8; dimension * 4];
+        reader.read_exact(&mut bytes)?;
+        let row = bytes
+            .chunks_exact(4)
+            .map(|b| {
+                let value = i32::from_le_bytes(b.try_into().unwrap());
+                usize::try_from(value).map_err(|_| invalid_data(format!("Negative id {} in row {}", value, rows.len())))
+            })
+            .collect::<io::Result<_>>()?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+pub fn write_fvecs<W: Write>(writer: &mut W, items: &[VectorItem]) -> io::Result<()> {
+    for item in items {
+        write_dimension(writer, item.vector.len())?;
+        for &x in &item.vector {
+            writer.write_all(&(x as f32).to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+/// Components must be whole numbers from 0 to 255.
+pub fn write_bvecs<W: Write>(writer: &mut W, items: &[VectorItem]) -> io::Result<()> {
+    for item in items {
+        let bytes = item
+            .vector
+            .iter()
+            .map(|&x| match x {
+                x if x.fract() == 0.0 && (0.0..=255.0).contains(&x) => Ok(x as u8),
+                x => Err(invalid_input(format!("Vector {} has component {}, which is not a byte", item.id, x))),
+            })
+            .collect::<io::Result<Vec<u8>>>()?;
+        write_dimension(writer, bytes.len())?;
+        writer.write_all(&bytes)?;
+    }
+    writer.flush()
+}
+
+pub fn write_ivecs<W: Write>(writer: &mut W, rows: &[Vec<usize>]) -> io::Result<()> {
+    for row in rows {
+        write_dimension(writer, row.len())?;
+        for &value in row {
+            let value = i32::try_from(value).map_err(|_| invalid_input(format!("Id {} does not fit in an i32", value)))?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+// The dimension prefix of the next row, or `None` at a clean end of file.
+fn read_dimension<R: Read>(reader: &mut R) -> io::Result<Option<usize>> {
+    let mut bytes = [0u8; 4];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match reader.read(&mut bytes[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let dimension = i32::from_le_bytes(bytes);
+    usize::try_from(dimension).map(Some).map_err(|_| invalid_data(format!("Negative dimension {}", dimension)))
+}
+
+fn check_dimension(expected: &mut Option<usize>, dimension: usize, row: usize) -> io::Result<()> {
+    match *expected.get_or_insert(dimension) {
+        first if first != dimension => {
+            Err(invalid_data(format!("Row {} has {} dimensions, expected {}", row, dimension, first)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn write_dimension<W: Write>(writer: &mut W, dimension: usize) -> io::Result<()> {
+    let dimension = i32::try_from(dimension).map_err(|_| invalid_input("Dimension does not fit in an i32"))?;
+    writer.write_all(&dimension.to_le_bytes())
+}
+
+fn invalid_input(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TextFormat {
+    Csv,
+    Jsonl,
+}
+
+/// Streams the vectors of a CSV or JSON Lines file; see `csv` and `jsonl`.
+/// Every row must have the dimension of the first, and errors name the line.
+pub struct TextReader<R> {
+    lines: io::Lines<R>,
+    format: TextFormat,
+    line: usize,
+    dimension: Option<usize>,
+}
+
+/// Streams `id,v0,v1,...` rows.
+pub fn csv<R: BufRead>(reader: R) -> TextReader<R> {
+    TextReader { lines: reader.lines(), format: TextFormat::Csv, line: 0, dimension: None }
+}
+
+/// Streams `{"id": .., "vector": [..]}` lines.
+pub fn jsonl<R: BufRead>(reader: R) -> TextReader<R> {
+    TextReader { lines: reader.lines(), format: TextFormat::Jsonl, line: 0, dimension: None }
+}
+
+pub fn read_csv<R: BufRead>(reader: R) -> io::Result<Vec<VectorItem>> {
+    csv(reader).collect()
+}
+
+pub fn read_jsonl<R: BufRead>(reader: R) -> io::Result<Vec<VectorItem>> {
+    jsonl(reader).collect()
+}
+
+pub fn write_csv<W: Write>(writer: &mut W, items: &[VectorItem]) -> io::Result<()> {
+    for item in items {
+        write!(writer, "{}", item.id)?;
+        for x in &item.vector {
+            write!(writer, ",{}", x)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+pub fn write_jsonl<W: Write>(writer: &mut W, items: &[VectorItem]) -> io::Result<()> {
+    for item in items {
+        serde_json::to_writer(&mut *writer, &serde_json::json!({ "id": item.id, "vector": item.vector }))?;
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+impl<R: BufRead> TextReader<R> {
+    fn read_vector(&mut self) -> io::Result<Option<VectorItem>> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            self.line += 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = match self.format {
+                TextFormat::Csv => parse_csv(line),
+                TextFormat::Jsonl => parse_jsonl(line),
+            };
+            let item = match parsed {
+                Ok(item) => item,
+                // A first row whose id is not a number is a header
+                Err(_) if matches!(self.format, TextFormat::Csv) && self.dimension.is_none() && !starts_with_id(line) => {
+                    continue;
+                }
+                Err(e) => return Err(invalid_data(format!("Line {}: {}", self.line, e))),
+            };
+            check_dimension(&mut self.dimension, item.vector.len(), self.line)?;
+            return Ok(Some(item));
+        }
+        Ok(None)
+    }
+}
+
+impl<R: BufRead> Iterator for TextReader<R> {
+    type Item = io::Result<VectorItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_vector().transpose()
+    }
+}
+
+fn starts_with_id(line: &str) -> bool {
+    line.split(',').next().is_some_and(|id| id.trim().parse::<usize>().is_ok())
+}
+
+fn parse_csv(line: &str) -> Result<VectorItem, String> {
+    let mut fields = line.split(',').map(str::trim);
+    let id = fields.next().unwrap_or_default();
+    let id = id.parse().map_err(|_| format!("Malformed id {:?}", id))?;
+    let vector = fields
+        .map(|x| x.parse().map_err(|_| format!("Malformed component {:?}", x)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    Ok(VectorItem { id, vector })
+}
+
+fn parse_jsonl(line: &str) -> Result<VectorItem, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let id = value.get("id").and_then(|id| id.as_u64()).ok_or("Missing or malformed id")?;
+    let vector = value
+        .get("vector")
+        .and_then(|vector| vector.as_array())
+        .ok_or("Missing or malformed vector")?
+        .iter()
+        .map(|x| x.as_f64().ok_or_else(|| format!("Malformed component {}", x)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    let id = usize::try_from(id).map_err(|_| format!("Id {} is not a valid id", id))?;
+    Ok(VectorItem { id, vector })
+}
+
+/// Reads a 2-D `<f4` or `<f8` `.npy` array, one vector per row, with the row
+/// numbers as ids. Fortran-order arrays are transposed on the way in.
+pub fn read_npy<R: Read>(mut reader: R) -> io::Result<Vec<VectorItem>> {
+    let header = NpyHeader::read(&mut reader)?;
+    let (rows, columns) = match header.shape[..] {
+        [rows, columns] => (rows, columns),
+        _ => return Err(invalid_data(format!("Expected a 2-D array, got shape {:?}", header.shape))),
+    };
+    let values = match header.descr.as_str() {
+        "<f4" => read_elements(&mut reader, rows * columns, |b: [u8; 4]| f32::from_le_bytes(b) as f64)?,
+        "<f8" => read_elements(&mut reader, rows * columns, f64::from_le_bytes)?,
+        descr => return Err(invalid_data(format!("Expected float32 or float64 vectors, got {}", descr))),
+    };
+    Ok((0..rows)
+        .map(|row| {
+            let vector = match header.fortran_order {
+                false => values[row * columns..(row + 1) * columns].to_vec(),
+                true => (0..columns).map(|column| values[column * rows + row]).collect(),
+            };
+            VectorItem { id: row, vector }
+        })
+        .collect())
+}
+
+/// Reads a 1-D `.npy` array of 32- or 64-bit integers, e.g. the ids that go
+/// with the rows of a `read_npy` matrix.
+pub fn read_npy_ids<R: Read>(mut reader: R) -> io::Result<Vec<usize>> {
+    let header = NpyHeader::read(&mut reader)?;
+    let len = match header.shape[..] {
+        [len] => len,
+        _ => return Err(invalid_data(format!("Expected a 1-D array, got shape {:?}", header.shape))),
+    };
+    let ids = match header.descr.as_str() {
+        "<i4" => read_elements(&mut reader, len, |b| i32::from_le_bytes(b) as i64)?,
+        "<i8" => read_elements(&mut reader, len, i64::from_le_bytes)?,
+        "<u4" => read_elements(&mut reader, len, |b| u32::from_le_bytes(b) as i64)?,
+        "<u8" => read_elements(&mut reader, len, |b| i64::try_from(u64::from_le_bytes(b)).unwrap_or(-1))?,
+        descr => return Err(invalid_data(format!("Expected integer ids, got {}", descr))),
+    };
+    ids.into_iter()
+        .map(|id| usize::try_from(id).map_err(|_| invalid_data(format!("Id {} is not a valid id", id))))
+        .collect()
+}
+
+/// Replaces the row-number ids of `items` with `ids`, position by position.
+pub fn assign_ids(mut items: Vec<VectorItem>, ids: &[usize]) -> io::Result<Vec<VectorItem>> {
+    if ids.len() != items.len() {
+        return Err(invalid_data(format!("{} ids for {} vectors", ids.len(), items.len())));
+    }
+    for (item, &id) in items.iter_mut().zip(ids) {
+        item.id = id;
+    }
+    Ok(items)
+}
+
+/// Reads the `vectors` array of an `.npz` archive, and the ids from the
+/// `ids` array when given. Names may omit the `.npy` suffix.
+#[cfg(feature = "npz")]
+pub fn read_npz<R: Read + io::Seek>(reader: R, vectors: &str, ids: Option<&str>) -> io::Result<Vec<VectorItem>> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| invalid_data(e.to_string()))?;
+    let mut entry = |name: &str| {
+        let name = if name.ends_with(".npy") { name.to_string() } else { format!("{}.npy", name) };
+        let mut file = archive.by_name(&name).map_err(|e| invalid_data(format!("{}: {}", name, e)))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map(|_| bytes)
+    };
+    let items = read_npy(entry(vectors)?.as_slice())?;
+    match ids {
+        Some(ids) => assign_ids(items, &read_npy_ids(entry(ids)?.as_slice())?),
+        None => Ok(items),
+    }
+}
+
+/// Writes `items` as a 2-D `<f8` `.npy` array. The ids are not written; all
+/// vectors must have the same dimension.
+pub fn write_npy<W: Write>(writer: &mut W, items: &[VectorItem]) -> io::Result<()> {
+    let columns = items.first().map_or(0, |item| item.vector.len());
+    if let Some(item) = items.iter().find(|item| item.vector.len() != columns) {
+        return Err(invalid_input(format!("Vector {} has {} dimensions, expected {}", item.id, item.vector.len(), columns)));
+    }
+    let mut header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}", items.len(), columns);
+    // Magic, version and length take 10 bytes; the header ends in a newline
+    // and pads the data to 64-byte alignment
+    let padded = (10 + header.len() + 1).div_ceil(64) * 64 - 10;
+    header.extend(std::iter::repeat_n(' ', padded - header.len() - 1));
+    header.push('\n');
+    let header_len = u16::try_from(header.len()).map_err(|_| invalid_input("Array shape is too large"))?;
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&header_len.to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for item in items {
+        for &x in &item.vector {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+struct NpyHeader {
+    descr: String,
+    fortran_order: bool,
+    shape: Vec<usize>,
+}
+
+impl NpyHeader {
+    fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut preamble = [0u8; 8];
+        reader.read_exact(&mut preamble)?;
+        if &preamble[..6] != b"\x93NUMPY" {
+            return Err(invalid_data("Not a .npy file"));
+        }
+        let len = match preamble[6] {
+            1 => {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len)?;
+                u16::from_le_bytes(len) as usize
+            }
+            2 | 3 => {
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len)?;
+                u32::from_le_bytes(len) as usize
+            }
+            version => return Err(invalid_data(format!("Unsupported .npy version {}", version))),
+        };
+        let mut header = vec![0u8; len];
+        reader.read_exact(&mut header)?;
+        let header = String::from_utf8(header).map_err(|_| invalid_data("Malformed .npy header"))?;
+
+        let descr = header_value(&header, "descr")?.trim_matches(['\'', '"']).to_string();
+        let fortran_order = match header_value(&header, "fortran_order")? {
+            "True" => true,
+            "False" => false,
+            other => return Err(invalid_data(format!("Malformed fortran_order {}", other))),
+        };
+        let shape = header_value(&header, "shape")?
+            .trim_matches(['(', ')'])
+            .split(',')
+            .map(str::trim)
+            .filter(|dim| !dim.is_empty())
+            .map(|dim| dim.parse().map_err(|_| invalid_data(format!("Malformed shape dimension {}", dim))))
+            .collect::<io::Result<_>>()?;
+        Ok(NpyHeader { descr, fortran_order, shape })
+    }
+}
+
+// The literal after `'key':` in a .npy header dict; tuples are kept whole.
+fn header_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let start = header
+        .find(&format!("'{}':", key))
+        .map(|at| at + key.len() + 3)
+        .ok_or_else(|| invalid_data(format!(".npy header has no {}", key)))?;
+    let rest = header[start..].trim_start();
+    let end = match rest.starts_with('(') {
+        true => rest.find(')').map(|at| at + 1),
+        false => rest.find([',', '}']),
+    };
+    end.map(|end| rest[..end].trim()).ok_or_else(|| invalid_data("Malformed .npy header"))
+}
+
+fn read_elements<R: Read, T, const N: usize>(reader: &mut R, count: usize, decode: impl Fn([u8; N]) -> T) -> io::Result<Vec<T>> {
+    let len = count.checked_mul(N).ok_or_else(|| invalid_data("Array is too large"))?;
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(N).map(|b| decode(b.try_into().unwrap())).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_texmex_roundtrip() {
+        let items = vec![
+            VectorItem { id: 0, vector: vec![0.5, -1.0, 3.0] },
+            VectorItem { id: 1, vector: vec![255.0, 0.0, 7.0] },
+        ];
+        let mut fvecs_bytes = Vec::new();
+        write_fvecs(&mut fvecs_bytes, &items).unwrap();
+        assert_eq!(fvecs_bytes.len(), 2 * (4 + 3 * 4));
+        let read = read_fvecs(fvecs_bytes.as_slice()).unwrap();
+        let pairs = |items: &[VectorItem]| items.iter().map(|i| (i.id, i.vector.clone())).collect::<Vec<_>>();
+        assert_eq!(pairs(&read), pairs(&items));
+
+        let mut bvecs_bytes = Vec::new();
+        assert!(write_bvecs(&mut bvecs_bytes, &items).is_err());
+        write_bvecs(&mut bvecs_bytes, &items[1..]).unwrap();
+        assert_eq!(bvecs_bytes, vec![3, 0, 0, 0, 255, 0, 7]);
+        assert_eq!(read_bvecs(bvecs_bytes.as_slice()).unwrap()[0].vector, vec![255.0, 0.0, 7.0]);
+
+        let truth = vec![vec![3, 1, 4], vec![1, 5, 9]];
+        let mut ivecs_bytes = Vec::new();
+        write_ivecs(&mut ivecs_bytes, &truth).unwrap();
+        assert_eq!(read_ivecs(ivecs_bytes.as_slice()).unwrap(), truth);
+
+        // Truncated rows and mixed dimensions are rejected
+        assert!(read_fvecs(&fvecs_bytes[..fvecs_bytes.len() - 1]).is_err());
+        assert!(read_fvecs(&fvecs_bytes[..2]).is_err());
+        let mut mixed = fvecs_bytes.clone();
+        write_fvecs(&mut mixed, &[VectorItem { id: 2, vector: vec![1.0] }]).unwrap();
+        assert!(read_fvecs(mixed.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_text_readers() {
+        let csv_text = "id,v0,v1\n7, 0.5, -1\n\n9,2e0,3\n";
+        let items = read_csv(csv_text.as_bytes()).unwrap();
+        assert_eq!(items.iter().map(|item| item.id).collect::<Vec<_>>(), vec![7, 9]);
+        assert_eq!(items[1].vector, vec![2.0, 3.0]);
+        assert!(read_csv("1,0.5\n2,0.5,1\n".as_bytes()).is_err());
+        let err = read_csv("1,0.5\nid,x\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().starts_with("Line 2"));
+
+        let jsonl_text = "{\"id\": 3, \"vector\": [1, 2.5]}\n{\"id\": 4, \"vector\": [0, 0], \"tag\": \"x\"}\n";
+        let items = read_jsonl(jsonl_text.as_bytes()).unwrap();
+        assert_eq!((items[0].id, &items[0].vector), (3, &vec![1.0, 2.5]));
+        assert!(read_jsonl("{\"id\": -1, \"vector\": []}".as_bytes()).is_err());
+        assert!(read_jsonl("{\"id\": 1, \"vector\": [1]}\n{\"id\": 2, \"vector\": [1, 2]}".as_bytes()).is_err());
+
+        let items = vec![VectorItem { id: 5, vector: vec![0.1, -2.0] }, VectorItem { id: 8, vector: vec![1e-300, 3.5] }];
+        let (mut csv_out, mut jsonl_out) = (Vec::new(), Vec::new());
+        write_csv(&mut csv_out, &items).unwrap();
+        write_jsonl(&mut jsonl_out, &items).unwrap();
+        for roundtrip in [read_csv(&csv_out[..]).unwrap(), read_jsonl(&jsonl_out[..]).unwrap()] {
+            assert_eq!(roundtrip.iter().map(|item| (item.id, &item.vector)).collect::<Vec<_>>(),
+                items.iter().map(|item| (item.id, &item.vector)).collect::<Vec<_>>());
+        }
+    }
+
+    // A version 1 .npy file around `data`
+    fn npy(descr: &str, fortran_order: &str, shape: &str, data: &[u8]) -> Vec<u8> {
+        let header = format!("{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}\n", descr, fortran_order, shape);
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_npy() {
+        let items = vec![VectorItem { id: 0, vector: vec![0.5, -1.0] }, VectorItem { id: 1, vector: vec![2.0, 3.0] }];
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &items).unwrap();
+        assert!((bytes.len() - 4 * 8).is_multiple_of(64));
+        let read = read_npy(bytes.as_slice()).unwrap();
+        assert_eq!((read[1].id, &read[1].vector), (1, &vec![2.0, 3.0]));
+
+        // Column-major float32
+        let data: Vec<u8> = [0.5f32, 2.0, -1.0, 3.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let read = read_npy(npy("<f4", "True", "(2, 2)", &data).as_slice()).unwrap();
+        assert_eq!(read[0].vector, vec![0.5, -1.0]);
+
+        let data: Vec<u8> = [70i64, 12].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let ids = read_npy_ids(npy("<i8", "False", "(2,)", &data).as_slice()).unwrap();
+        let read = assign_ids(read, &ids).unwrap();
+        assert_eq!((read[0].id, read[1].id), (70, 12));
+        assert!(assign_ids(read, &[1]).is_err());
+
+        assert!(read_npy(npy("<i8", "False", "(2,)", &data).as_slice()).is_err());
+        assert!(read_npy(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "npz")]
+    #[test]
+    fn test_npz() {
+        let items = vec![VectorItem { id: 0, vector: vec![1.0, 2.0] }];
+        let mut vectors = Vec::new();
+        write_npy(&mut vectors, &items).unwrap();
+        let ids = npy("<u4", "False", "(1,)", &9u32.to_le_bytes());
+
+        let mut archive = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, bytes) in [("embeddings.npy", &vectors), ("ids.npy", &ids)] {
+            archive.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            archive.write_all(bytes).unwrap();
+        }
+        let archive = archive.finish().unwrap();
+
+        let read = read_npz(archive.clone(), "embeddings", Some("ids.npy")).unwrap();
+        assert_eq!((read[0].id, &read[0].vector), (9, &vec![1.0, 2.0]));
+        assert!(read_npz(archive, "missing", None).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::cluster::{self, KMeans};
+use crate::hnsw::HnswIndex;
+use crate::vector::VectorItem;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Default)]
+struct Lists {
+    lists: Vec<Vec<VectorItem>>,
+    // The list holding each id
+    locations: HashMap<usize, usize>,
+}
+
+/// IVF-HNSW: vectors are split into inverted lists by their nearest k-means
+/// centroid, a small `HnswIndex` over the centroids routes queries, and a
+/// search scans only the `nprobe` lists whose centroids are nearest the
+/// query. Memory is the vectors plus one centroid per list, with no graph
+/// over the vectors themselves; `nprobe` trades latency for recall per
+/// query. Distances are Euclidean, matching the clustering.
+pub struct IvfHnsw {
+    centroids: Vec<Vec<f64>>,
+    quantizer: HnswIndex,
+    lists: RwLock<Lists>,
+    nprobe: usize,
+}
+
+impl IvfHnsw {
+    /// Clusters `training` into `lists` inverted lists with k-means. The
+    /// training vectors are not added; a sample of the data is enough.
+    pub fn train(training: &[Vec<f64>], lists: usize) -> Result<Self, String> {
+        let clustering = KMeans::new(lists).with_seed(0).fit(training)?;
+        if clustering.centroids.is_empty() {
+            return Err("Training needs at least one vector".to_string());
+        }
+        Ok(IvfHnsw {
+            quantizer: cluster::centroid_index(&clustering.centroids)?,
+            lists: RwLock::new(Lists { lists: vec![Vec::new(); clustering.centroids.len()], ..Default::default() }),
+            centroids: clustering.centroids,
+            nprobe: 1,
+        })
+    }
+
+    /// The number of lists `search` scans, clamped to at least 1.
+    pub fn with_nprobe(mut self, nprobe: usize) -> Self {
+        self.nprobe = nprobe.max(1);
+        self
+    }
+
+    pub fn nprobe(&self) -> usize {
+        self.nprobe
+    }
+
+    pub fn centroids(&self) -> &[Vec<f64>] {
+        &self.centroids
+    }
+
+    /// The number of vectors in each list, by list.
+    pub fn list_sizes(&self) -> Vec<usize> {
+        self.lists.read().unwrap().lists.iter().map(Vec::len).collect()
+    }
+
+    // The list `vector` belongs to.
+    fn assign(&self, vector: &[f64]) -> Result<usize, String> {
+        let dimension = self.centroids[0].len();
+        if vector.len() != dimension {
+            return Err(format!("Vector has {} dimensions, expected {}", vector.len(), dimension));
+        }
+        let query = VectorItem { id: 0, vector: vector.to_vec() };
+        let nearest = self.quantizer.search(&query, 1)?;
+        nearest.first().map(|item| item.id).ok_or_else(|| "No centroid found".to_string())
+    }
+
+    /// Adds `item`, replacing any vector stored under its id.
+    pub fn add(&self, item: VectorItem) -> Result<(), String> {
+        let list = self.assign(&item.vector)?;
+        let mut lists = self.lists.write().unwrap();
+        lists.insert(list, item);
+        Ok(())
+    }
+
+    /// Assigns `items` to lists in parallel, then adds them all. Nothing is
+    /// added if any item has the wrong dimension.
+    pub fn par_batch_add(&self, items: Vec<VectorItem>) -> Result<(), String> {
+        let assignments = items.par_iter().map(|item| self.assign(&item.vector)).collect::<Result<Vec<_>, _>>()?;
+        let mut lists = self.lists.write().unwrap();
+        for (item, list) in items.into_iter().zip(assignments) {
+            lists.insert(list, item);
+        }
+        Ok(())
+    }
+
+    /// Removes `id` and returns whether it was stored.
+    pub fn remove(&self, id: usize) -> bool {
+        self.lists.write().unwrap().remove(id)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.lists.read().unwrap().locations.contains_key(&id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<VectorItem> {
+        let lists = self.lists.read().unwrap();
+        let list = &lists.lists[*lists.locations.get(&id)?];
+        list.iter().find(|item| item.id == id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lists.read().unwrap().locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `k` nearest items among the `nprobe` lists nearest `query`,
+    /// nearest first, ties broken by id.
+    pub fn search(&self, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        self.search_probing(query, k, self.nprobe)
+    }
+
+    /// `search` scanning `nprobe` lists instead of the configured number.
+    /// Probing every list makes the search exact.
+    pub fn search_probing(&self, query: &VectorItem, k: usize, nprobe: usize) -> Result<Vec<VectorItem>, String> {
+        let dimension = self.centroids[0].len();
+        if query.vector.len() != dimension {
+            return Err(format!("Query has {} dimensions, expected {}", query.vector.len(), dimension));
+        }
+        let probed = self.quantizer.search(query, nprobe.max(1))?;
+        let lists = self.lists.read().unwrap();
+        let mut scored: Vec<(f64, &VectorItem)> = probed
+            .par_iter()
+            .flat_map_iter(|centroid| &lists.lists[centroid.id])
+            .map(|item| (cluster::squared_distance(&query.vector, &item.vector), item))
+            .collect();
+        let by_distance = |a: &(f64, &VectorItem), b: &(f64, &VectorItem)| a.0.total_cmp(&b.0).then(a.1.id.cmp(&b.1.id));
+        if k < scored.len() {
+            scored.select_nth_unstable_by(k, by_distance);
+            scored.truncate(k);
+        }
+        scored.sort_by(by_distance);
+        Ok(scored.into_iter().map(|(_, item)| item.clone()).collect())
+    }
+
+    /// Runs `search` for every query, in query order.
+    pub fn batch_search(&self, queries: &[VectorItem], k: usize) -> Result<Vec<Vec<VectorItem>>, String> {
+        queries.par_iter().map(|query| self.search(query, k)).collect()
+    }
+}
+
+impl Lists {
+    fn insert(&mut self, list: usize, item: VectorItem) {
+        self.remove(item.id);
+        self.locations.insert(item.id, list);
+        self.lists[list].push(item);
+    }
+
+    fn remove(&mut self, id: usize) -> bool {
+        let Some(list) = self.locations.remove(&id) else {
+            return false;
+        };
+        let list = &mut self.lists[list];
+        if let Some(position) = list.iter().position(|item| item.id == id) {
+            list.swap_remove(position);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Vec<VectorItem> {
+        (0..400).map(|i| VectorItem { id: i, vector: vec![(i % 20) as f64, (i / 20) as f64] }).collect()
+    }
+
+    #[test]
+    fn test_ivf_search() {
+        let items = grid();
+        let training: Vec<_> = items.iter().map(|item| item.vector.clone()).collect();
+        let index = IvfHnsw::train(&training, 16).unwrap().with_nprobe(4);
+        index.par_batch_add(items).unwrap();
+        assert_eq!(index.len(), 400);
+        assert_eq!(index.list_sizes().len(), 16);
+        assert_eq!(index.list_sizes().iter().sum::<usize>(), 400);
+
+        let query = VectorItem { id: 0, vector: vec![5.1, 5.0] };
+        let ids: Vec<usize> = index.search(&query, 3).unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids, [105, 106, 85]);
+        // The nearest list already holds the nearest item; probing all is exact
+        assert_eq!(index.search_probing(&query, 1, 1).unwrap()[0].id, 105);
+        assert_eq!(index.search_probing(&query, 400, 16).unwrap().len(), 400);
+
+        // Updates move an id to its new list
+        index.add(VectorItem { id: 105, vector: vec![50.0, 50.0] }).unwrap();
+        assert_eq!(index.len(), 400);
+        assert_eq!(index.get(105).unwrap().vector, [50.0, 50.0]);
+        assert_eq!(index.search(&query, 1).unwrap()[0].id, 106);
+        assert!(index.remove(105) && !index.remove(105) && !index.contains(105));
+        assert!(index.add(VectorItem { id: 1000, vector: vec![1.0] }).is_err());
+        assert!(index.search(&VectorItem { id: 0, vector: vec![1.0] }, 1).is_err());
+    }
+}
@@ -1,7 +1,76 @@
+#![deny(unsafe_code)]
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "tokio")]
+mod async_index;
+mod audit;
+pub mod cluster;
+mod collections;
+pub mod eval;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filter;
+mod fusion;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod hooks;
 mod hnsw;
+mod hnswlib;
+pub mod io;
+mod ivf;
+#[cfg(test)]
+mod metric_check;
+mod metrics;
+mod multivector;
 mod node;
+mod persist;
+mod plan;
+mod pool;
+mod quantize;
+mod quota;
+pub mod rerank;
+mod sharded;
+mod slab;
+mod sparse;
+mod sync;
+mod trace;
+mod transform;
+mod validate;
 pub mod vector;
+#[cfg(feature = "space")]
+pub mod space_compat;
+#[cfg(feature = "mmap")]
+mod mapped;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-pub use hnsw::HnswIndex;
+#[cfg(feature = "tokio")]
+pub use async_index::AsyncIndex;
+pub use audit::AuditLog;
+pub use collections::CollectionManager;
+pub use filter::IdFilter;
+pub use fusion::Fusion;
+pub use hnsw::{DuplicatePolicy, HnswIndex, IndexConfig, IndexSnapshot, IndexStats, LayerDegrees, NeighborSelection};
+pub use ivf::IvfHnsw;
+pub use metrics::{IndexMetrics, MetricsSink, MetricsSnapshot};
+pub use multivector::{Aggregation, MultiVectorIndex};
+#[cfg(feature = "mmap")]
+pub use mmap::MmapIndex;
 pub use node::Node;
-pub use vector::{DistanceCalculator, EuclideanDistance, VectorItem};
+pub use plan::{QueryPlan, SearchStrategy};
+pub use pool::VectorPool;
+pub use quantize::{train_pq, BinaryQuantizer, ProductQuantizer, Quantization, ScalarQuantizer};
+pub use quota::{Quota, QuotaError};
+pub use sharded::{Partitioning, ShardedHnsw};
+pub use sparse::{JaccardDistance, SparseSet};
+pub use trace::{LayerTrace, SearchTrace};
+pub use transform::VectorTransform;
+pub use validate::ValidationReport;
+pub use vector::{AngularDistance, DistanceCalculator, EuclideanDistance, Mahalanobis, MaskedDistance, VectorItem, WeightedEuclidean};
@@ -0,0 +1,127 @@
+//! The crate's only `unsafe` code outside the C API (`ffi.rs`): mapping an
+//! index file into memory.
+//!
+//! Memory-mapping is unsound if the file changes underneath the mapping.
+//! A `MappedFile` can only be made from a `LockedFile`, which can only be
+//! made by opening a path read-only and taking a shared advisory lock that
+//! lives as long as the handle. The crate's writers never modify an index
+//! file in place: `persist::replace_file` writes a new file and renames it
+//! over the path, so the mapped inode stays unchanged even when the index
+//! is re-saved. Under Miri, which cannot map files, the file is read into
+//! memory instead so everything built on top stays testable:
+//! `MIRIFLAGS=-Zmiri-disable-isolation cargo +nightly miri test --features mmap mmap`
+
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, Range};
+use std::path::Path;
+
+/// A file opened read-only under a shared lock, held until it is dropped.
+pub(crate) struct LockedFile(File);
+
+impl LockedFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        file.try_lock_shared().map_err(|_| {
+            io::Error::new(io::ErrorKind::WouldBlock, "Index file is locked for writing")
+        })?;
+        Ok(LockedFile(file))
+    }
+}
+
+pub(crate) struct MappedFile {
+    #[cfg(not(miri))]
+    bytes: memmap2::Mmap,
+    #[cfg(miri)]
+    bytes: Vec<u8>,
+    // Keeps the shared lock until the mapping is dropped
+    _file: LockedFile,
+}
+
+impl MappedFile {
+    pub fn map(file: LockedFile) -> io::Result<Self> {
+        Ok(MappedFile {
+            bytes: map(&file)?,
+            _file: file,
+        })
+    }
+
+    /// The little-endian `u64` at `offset`, if it lies within the file.
+    pub fn word(&self, offset: usize) -> Option<u64> {
+        let end = offset.checked_add(8)?;
+        let bytes = self.get(offset..end)?;
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// The little-endian `u64`s in `range`, which must lie within the file.
+    pub fn words(&self, range: Range<usize>) -> impl Iterator<Item = u64> + '_ {
+        self[range]
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+    }
+
+    /// Asks the OS to start reading `range` in, so that several scattered
+    /// reads issued back to back wait on their IO together rather than one
+    /// after another. Only a hint; a no-op where unsupported.
+    pub fn prefetch(&self, range: Range<usize>) {
+        #[cfg(all(unix, not(miri)))]
+        {
+            let _ = self.bytes.advise_range(memmap2::Advice::WillNeed, range.start, range.len());
+        }
+        #[cfg(not(all(unix, not(miri))))]
+        let _ = range;
+    }
+}
+
+#[cfg(not(miri))]
+#[allow(unsafe_code)]
+fn map(file: &LockedFile) -> io::Result<memmap2::Mmap> {
+    // SAFETY: a `LockedFile` is open read-only under a shared lock, and the
+    // `MappedFile` owns it for the lifetime of the mapping. No writer in the
+    // crate modifies an index file in place (see module docs).
+    unsafe { memmap2::Mmap::map(&file.0) }
+}
+
+#[cfg(miri)]
+fn map(file: &LockedFile) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    io::Read::read_to_end(&mut &file.0, &mut bytes)?;
+    Ok(bytes)
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words() {
+        let path = std::env::temp_dir().join(format!("hnsw_mapped_{}.bin", std::process::id()));
+        let bytes: Vec<u8> = [7u64, u64::MAX].iter().flat_map(|w| w.to_le_bytes()).chain([1, 2]).collect();
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mapped = MappedFile::map(LockedFile::open(&path).unwrap()).unwrap();
+        assert_eq!(mapped.len(), 18);
+        assert_eq!(mapped.word(0), Some(7));
+        assert_eq!(mapped.word(8), Some(u64::MAX));
+        assert_eq!(mapped.word(11), None);
+        assert_eq!(mapped.word(usize::MAX - 4), None);
+        assert_eq!(mapped.words(0..16).collect::<Vec<_>>(), vec![7, u64::MAX]);
+
+        // Saving over the path leaves the mapped file as it was
+        crate::persist::replace_file(&path, |file| io::Write::write_all(file, &[9; 32])).unwrap();
+        assert_eq!(mapped.len(), 18);
+        assert_eq!(mapped.word(0), Some(7));
+        assert_eq!(std::fs::read(&path).unwrap(), [9; 32]);
+
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
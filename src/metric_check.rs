@@ -0,0 +1,191 @@
+//! Shared test harness for `DistanceCalculator`s.
+//!
+//! `check_metric` compares a calculator against a straightforward reference
+//! implementation over random, extreme and degenerate inputs, and checks the
+//! properties the index relies on (symmetry, non-negativity, zero self
+//! distance). Every new metric or kernel should get a test that calls it.
+
+use crate::vector::DistanceCalculator;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const DIMENSIONS: [usize; 6] = [1, 2, 3, 8, 61, 256];
+const RANDOM_PAIRS: usize = 50;
+
+/// How far a calculator may drift from its reference: a result passes if it
+/// is within `abs` of the reference or within `rel` of its magnitude.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Tolerance {
+    pub abs: f64,
+    pub rel: f64,
+}
+
+impl Tolerance {
+    pub const EXACT: Tolerance = Tolerance { abs: 0.0, rel: 0.0 };
+    pub const F64: Tolerance = Tolerance { abs: 1e-12, rel: 1e-12 };
+
+    pub fn accepts(&self, actual: f64, expected: f64) -> bool {
+        if expected.is_nan() || actual.is_nan() {
+            return expected.is_nan() && actual.is_nan();
+        }
+        if actual == expected {
+            return true;
+        }
+        let error = (actual - expected).abs();
+        error <= self.abs || error <= self.rel * expected.abs().max(actual.abs())
+    }
+}
+
+/// Input pairs for one dimensionality, labelled for failure messages.
+fn cases(dim: usize, rng: &mut StdRng) -> Vec<(String, Vec<f64>, Vec<f64>)> {
+    let random = |rng: &mut StdRng, scale: f64| -> Vec<f64> {
+        (0..dim).map(|_| rng.gen_range(-1.0..1.0) * scale).collect()
+    };
+    let mut cases = vec![
+        ("zeros".to_string(), vec![0.0; dim], vec![0.0; dim]),
+        ("negative zero".to_string(), vec![-0.0; dim], vec![0.0; dim]),
+        ("one hot".to_string(), vec![0.0; dim], {
+            let mut v = vec![0.0; dim];
+            v[dim - 1] = 1.0;
+            v
+        }),
+        ("opposite".to_string(), vec![1.0; dim], vec![-1.0; dim]),
+    ];
+    // Large and tiny magnitudes whose squares still fit in an f64
+    for &scale in &[1e150, 1e-150] {
+        cases.push((format!("scale {:e}", scale), random(rng, scale), random(rng, scale)));
+    }
+    for i in 0..RANDOM_PAIRS {
+        let scale = 10f64.powi(rng.gen_range(-6..=6));
+        cases.push((format!("random #{}", i), random(rng, scale), random(rng, scale)));
+    }
+    cases
+}
+
+/// Checks `calculator` against `reference` (which sees the raw vectors) and
+/// panics with the offending input on the first mismatch.
+pub(crate) fn check_metric(
+    calculator: &dyn DistanceCalculator,
+    reference: impl Fn(&[f64], &[f64]) -> f64,
+    tolerance: Tolerance,
+) {
+    for &dim in &DIMENSIONS {
+        check_metric_at(dim, calculator, &reference, tolerance);
+    }
+}
+
+/// `check_metric` at a single dimensionality, for metrics fixed to one.
+pub(crate) fn check_metric_at(
+    dim: usize,
+    calculator: &dyn DistanceCalculator,
+    reference: impl Fn(&[f64], &[f64]) -> f64,
+    tolerance: Tolerance,
+) {
+    let mut rng = StdRng::seed_from_u64(0x5eed ^ dim as u64);
+    for (label, a, b) in cases(dim, &mut rng) {
+        let actual = calculator.distance(&a, &b);
+        let expected = reference(&a, &b);
+        assert!(
+            tolerance.accepts(actual, expected),
+            "{} (dim {}): got {}, expected {}",
+            label, dim, actual, expected
+        );
+        assert!(actual >= 0.0, "{} (dim {}): negative distance {}", label, dim, actual);
+        assert!(
+            tolerance.accepts(calculator.distance(&b, &a), actual),
+            "{} (dim {}): not symmetric",
+            label, dim
+        );
+        assert_eq!(calculator.distance(&a, &a), 0.0, "{} (dim {}): non-zero self distance", label, dim);
+    }
+
+    // NaN components must not turn into a plausible-looking distance
+    let mut poisoned = vec![1.0; dim];
+    poisoned[0] = f64::NAN;
+    let distance = calculator.distance(&poisoned, &vec![1.0; dim]);
+    assert!(distance.is_nan(), "NaN input (dim {}) gave {}", dim, distance);
+}
+
+/// Euclidean distance accumulated with Kahan summation.
+pub(crate) fn reference_euclidean(a: &[f64], b: &[f64]) -> f64 {
+    let (mut sum, mut compensation) = (0.0f64, 0.0f64);
+    for (x, y) in a.iter().zip(b) {
+        let term = (x - y) * (x - y) - compensation;
+        let next = sum + term;
+        compensation = (next - sum) - term;
+        sum = next;
+    }
+    sum.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::{EuclideanDistance, Mahalanobis, MaskedDistance, VectorItem, WeightedEuclidean};
+
+    #[test]
+    fn test_tolerance() {
+        assert!(Tolerance::EXACT.accepts(1.0, 1.0));
+        assert!(!Tolerance::EXACT.accepts(1.0, 1.0 + f64::EPSILON));
+        assert!(Tolerance::F64.accepts(1e20, 1e20 + 1e7));
+        assert!(Tolerance::F64.accepts(f64::NAN, f64::NAN));
+        assert!(!Tolerance::F64.accepts(0.0, f64::NAN));
+    }
+
+    #[test]
+    fn test_euclidean() {
+        check_metric(&EuclideanDistance, reference_euclidean, Tolerance::F64);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_legacy_calculate() {
+        // Callers of the old method still get `distance`
+        let item = |vector: Vec<f64>| VectorItem { id: 0, vector };
+        assert_eq!(EuclideanDistance.calculate(&item(vec![0.0, 3.0]), &item(vec![4.0, 0.0])), 5.0);
+    }
+
+    #[test]
+    fn test_masked() {
+        let dims = [0, 2, 5];
+        let masked = MaskedDistance::new(&EuclideanDistance, &dims);
+        let project = |v: &[f64]| dims.iter().filter_map(|&d| v.get(d).copied()).collect::<Vec<_>>();
+        check_metric(&masked, |a, b| reference_euclidean(&project(a), &project(b)), Tolerance::F64);
+    }
+
+    #[test]
+    fn test_weighted_euclidean() {
+        for &dim in &DIMENSIONS {
+            let weights: Vec<f64> = (0..dim).map(|i| (i % 4) as f64 * 0.5).collect();
+            let scale = |v: &[f64]| v.iter().zip(&weights).map(|(x, w)| x * w.sqrt()).collect::<Vec<_>>();
+            let weighted = WeightedEuclidean::new(weights.clone()).unwrap();
+            check_metric_at(dim, &weighted, |a, b| reference_euclidean(&scale(a), &scale(b)), Tolerance { abs: 1e-12, rel: 1e-9 });
+        }
+        assert!(WeightedEuclidean::new(vec![1.0, -0.5]).is_err());
+        assert!(WeightedEuclidean::new(vec![f64::NAN]).is_err());
+        assert!(WeightedEuclidean::new(vec![1.0, 1.0]).unwrap().distance(&[0.0, 0.0, 5.0], &[0.0, 0.0, 0.0]).is_nan());
+    }
+
+    #[test]
+    fn test_mahalanobis() {
+        for &dim in &DIMENSIONS {
+            let identity: Vec<Vec<f64>> = (0..dim).map(|i| (0..dim).map(|j| (i == j) as u8 as f64).collect()).collect();
+            check_metric_at(dim, &Mahalanobis::new(identity).unwrap(), reference_euclidean, Tolerance::F64);
+        }
+
+        // A correlated pair of dimensions: moving along the correlation is
+        // cheaper than moving across it
+        let metric = Mahalanobis::new(vec![vec![2.0, -1.0], vec![-1.0, 2.0]]).unwrap();
+        assert_eq!(metric.distance(&[0.0, 0.0], &[1.0, 1.0]), 2f64.sqrt());
+        assert_eq!(metric.distance(&[0.0, 0.0], &[1.0, -1.0]), 6f64.sqrt());
+        assert!(Mahalanobis::new(vec![vec![1.0, 0.5], vec![0.0, 1.0]]).is_err());
+        assert!(Mahalanobis::new(vec![vec![1.0, 0.0]]).is_err());
+        assert!(metric.distance(&[0.0], &[1.0]).is_nan());
+
+        // Semi-definite is fine, indefinite is not
+        assert!(Mahalanobis::new(vec![vec![1.0, 1.0], vec![1.0, 1.0]]).is_ok());
+        assert!(Mahalanobis::new(vec![vec![0.0, 0.0], vec![0.0, 3.0]]).is_ok());
+        assert!(Mahalanobis::new(vec![vec![1.0, 2.0], vec![2.0, 1.0]]).is_err());
+        assert!(Mahalanobis::new(vec![vec![0.0, 1.0], vec![1.0, 0.0]]).is_err());
+        assert!(Mahalanobis::new(vec![vec![-1.0]]).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Operation counters kept by every index; see `HnswIndex::metrics`. All
+/// counters are relaxed atomics, so recording never takes a lock.
+#[derive(Debug, Default)]
+pub struct IndexMetrics {
+    inserts: AtomicU64,
+    updates: AtomicU64,
+    searches: AtomicU64,
+    results: AtomicU64,
+    search_nanos: AtomicU64,
+}
+
+/// The counters at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Vectors added under a new id.
+    pub inserts: u64,
+    /// Vectors that overwrote an existing id.
+    pub updates: u64,
+    /// Queries answered, counting each query of a batch.
+    pub searches: u64,
+    /// Results returned over all searches.
+    pub results: u64,
+    /// Time spent walking the graph over all searches.
+    pub search_time: Duration,
+}
+
+impl IndexMetrics {
+    /// Reads every counter. Counters are read one by one, so operations
+    /// finishing meanwhile may show up in some and not others.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            inserts: self.inserts.load(Ordering::Relaxed),
+            updates: self.updates.load(Ordering::Relaxed),
+            searches: self.searches.load(Ordering::Relaxed),
+            results: self.results.load(Ordering::Relaxed),
+            search_time: Duration::from_nanos(self.search_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Zeroes every counter and returns the values they had, so no operation
+    /// is lost between reporting intervals.
+    pub fn reset(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            inserts: self.inserts.swap(0, Ordering::Relaxed),
+            updates: self.updates.swap(0, Ordering::Relaxed),
+            searches: self.searches.swap(0, Ordering::Relaxed),
+            results: self.results.swap(0, Ordering::Relaxed),
+            search_time: Duration::from_nanos(self.search_nanos.swap(0, Ordering::Relaxed)),
+        }
+    }
+
+    pub(crate) fn record_inserts(&self, inserts: usize, updates: usize) {
+        self.inserts.fetch_add(inserts as u64, Ordering::Relaxed);
+        self.updates.fetch_add(updates as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_search(&self, results: usize, elapsed: Duration) {
+        self.searches.fetch_add(1, Ordering::Relaxed);
+        self.results.fetch_add(results as u64, Ordering::Relaxed);
+        self.search_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Receives every completed insert and search, e.g. to forward them to a
+/// Prometheus or StatsD client; see `HnswIndex::with_metrics_sink`. Called
+/// on the thread that did the work, so implementations should be quick.
+/// Both methods do nothing by default. An `Arc` of a sink is a sink, so the
+/// caller can keep a handle to it.
+pub trait MetricsSink: Send + Sync {
+    /// One `add` or batch finished, adding `_inserted` new ids and
+    /// overwriting `_updated` existing ones.
+    fn on_insert(&self, _inserted: usize, _updated: usize, _elapsed: Duration) {}
+
+    /// One query finished with `_results` results; `_elapsed` covers the
+    /// graph walk, as in `MetricsSnapshot::search_time`.
+    fn on_search(&self, _results: usize, _elapsed: Duration) {}
+}
+
+impl<T: MetricsSink + ?Sized> MetricsSink for std::sync::Arc<T> {
+    fn on_insert(&self, inserted: usize, updated: usize, elapsed: Duration) {
+        (**self).on_insert(inserted, updated, elapsed)
+    }
+
+    fn on_search(&self, results: usize, elapsed: Duration) {
+        (**self).on_search(results, elapsed)
+    }
+}
+
+/// Measures search time. `wasm32-unknown-unknown` has no clock, so there
+/// every search is recorded as taking no time.
+pub(crate) struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
+
+/// The time expiries are checked against. Without a clock on
+/// `wasm32-unknown-unknown` this is the epoch, so nothing expires there.
+pub(crate) fn wall_clock() -> SystemTime {
+    #[cfg(not(target_arch = "wasm32"))]
+    return SystemTime::now();
+    #[cfg(target_arch = "wasm32")]
+    return SystemTime::UNIX_EPOCH;
+}
+
+impl MetricsSnapshot {
+    /// The activity between `earlier` and this snapshot.
+    pub fn since(&self, earlier: &MetricsSnapshot) -> MetricsSnapshot {
+        MetricsSnapshot {
+            inserts: self.inserts.saturating_sub(earlier.inserts),
+            updates: self.updates.saturating_sub(earlier.updates),
+            searches: self.searches.saturating_sub(earlier.searches),
+            results: self.results.saturating_sub(earlier.results),
+            search_time: self.search_time.saturating_sub(earlier.search_time),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_and_reset() {
+        let metrics = IndexMetrics::default();
+        metrics.record_inserts(3, 1);
+        metrics.record_search(10, Duration::from_micros(5));
+        let first = metrics.snapshot();
+        assert_eq!((first.inserts, first.updates, first.searches, first.results), (3, 1, 1, 10));
+
+        metrics.record_search(4, Duration::from_micros(2));
+        let delta = metrics.snapshot().since(&first);
+        assert_eq!((delta.searches, delta.results, delta.search_time), (1, 4, Duration::from_micros(2)));
+
+        assert_eq!(metrics.reset().searches, 2);
+        assert_eq!(metrics.snapshot(), MetricsSnapshot::default());
+    }
+}
@@ -0,0 +1,554 @@
+//! Read-only index served straight from a memory-mapped `HnswIndex::save`
+//! file. Vectors and adjacency lists are decoded on demand during search, so
+//! opening is O(1) in the index size and several processes mapping the same
+//! file share its pages. The trailing checksum is not verified, as that would
+//! read the whole file. Payloads are not available. Items marked deleted or
+//! expired are skipped in results but still traversed, as in `HnswIndex`.
+//!
+//! For data larger than memory, `with_sketches` keeps a compressed copy of
+//! every vector in RAM, DiskANN-style: the graph walk then reads only
+//! adjacency lists from the file, and full vectors are read just to rerank
+//! the final candidates. The walk expands `beam_width` nodes at a time and
+//! prefetches their lists together, so their IO overlaps.
+
+use crate::hnsw::EF_SEARCH;
+use crate::mapped::{LockedFile, MappedFile};
+use crate::metrics;
+use crate::persist::{self, invalid_data, GraphParams, Header, HEADER_LEN, NO_ENTRY_POINT, NO_EXPIRY};
+use crate::quantize::Quantization;
+use crate::transform::VectorTransform;
+use crate::vector::{DistanceCalculator, EuclideanDistance, VectorItem};
+use ordered_float::OrderedFloat;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use rayon::prelude::*;
+
+/// Nodes expanded per step of the layer-0 beam search.
+const BEAM_WIDTH: usize = 4;
+
+pub struct MmapIndex {
+    map: MappedFile,
+    node_count: usize,
+    dimension: usize,
+    entry_point: Option<usize>,
+    transform: Option<VectorTransform>,
+    resolutions: Vec<usize>,
+    ids: Range<usize>,
+    vectors: Range<usize>,
+    offsets: Range<usize>,
+    links: Range<usize>,
+    // Absent in files older than versions 5 and 6
+    expiries: Option<Range<usize>>,
+    deleted: Option<Range<usize>>,
+    distance_calculator: Box<dyn DistanceCalculator + Send + Sync>,
+    sketches: Option<Sketches>,
+    beam_width: usize,
+    ef_search: usize,
+}
+
+// Compressed vectors held in memory, `code_len` bytes per slot
+struct Sketches {
+    quantization: Quantization,
+    codes: Vec<u8>,
+    code_len: usize,
+}
+
+impl Sketches {
+    fn of(&self, slot: usize) -> &[u8] {
+        &self.codes[slot * self.code_len..(slot + 1) * self.code_len]
+    }
+}
+
+impl MmapIndex {
+    /// Maps the file at `path`. Only the header and section boundaries are
+    /// checked here; node data is bounds-checked as it is read.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let map = MappedFile::map(LockedFile::open(path)?)?;
+
+        let header = Header::parse(&map)?;
+        let node_count = header.node_count;
+        let dimension = header.dimension;
+
+        let mut cursor = HEADER_LEN;
+        let transform = section(&map, &mut cursor, None)?;
+        let transform = persist::read_transform(&map.words(transform).collect::<Vec<_>>())?;
+        let resolutions = match header.version {
+            1 => Vec::new(),
+            _ => {
+                let range = section(&map, &mut cursor, None)?;
+                map.words(range).map(|dim| dim as usize).collect()
+            }
+        };
+        let ids = section(&map, &mut cursor, Some(node_count))?;
+        let vector_words = node_count
+            .checked_mul(dimension)
+            .ok_or_else(|| invalid_data("Vector section is too large"))?;
+        let vectors = section(&map, &mut cursor, Some(vector_words))?;
+        let offsets = section(&map, &mut cursor, Some(node_count + 1))?;
+        let links = section(&map, &mut cursor, None)?;
+        if header.version >= 4 {
+            // Payloads are not served from the mapping; just check the extent
+            section(&map, &mut cursor, None)?;
+        }
+        let expiries = match header.version {
+            1..=4 => None,
+            _ => Some(section(&map, &mut cursor, Some(node_count))?),
+        };
+        let deleted = match header.version {
+            1..=5 => None,
+            _ => Some(section(&map, &mut cursor, Some(node_count))?),
+        };
+        let ef_search = match header.version {
+            1..=6 => EF_SEARCH,
+            _ => GraphParams::read(&map.words(section(&map, &mut cursor, None)?).collect::<Vec<_>>())?.ef_search,
+        };
+
+        let entry_point = match header.entry_point {
+            NO_ENTRY_POINT => None,
+            slot if (slot as usize) < node_count => Some(slot as usize),
+            slot => return Err(invalid_data(format!("Entry point slot {} out of range", slot))),
+        };
+
+        Ok(MmapIndex {
+            map,
+            node_count,
+            dimension,
+            entry_point,
+            transform,
+            resolutions,
+            ids,
+            vectors,
+            offsets,
+            links,
+            expiries,
+            deleted,
+            distance_calculator: Box::new(EuclideanDistance),
+            sketches: None,
+            beam_width: BEAM_WIDTH,
+            ef_search,
+        })
+    }
+
+    /// Encodes every vector with `quantization` and keeps the codes in
+    /// memory, so searches walk the graph on the codes and read full vectors
+    /// from the file only to rerank their final candidates. This reads the
+    /// whole vector section once. The quantization's own `rerank` setting is
+    /// ignored: results are always reranked. Train it on vectors in the
+    /// file's (transformed) space, e.g. from `sample_vectors`.
+    pub fn with_sketches(mut self, quantization: Quantization) -> Result<Self, String> {
+        if let Some(trained) = quantization.trained_dimension().filter(|&dim| dim != self.dimension) {
+            return Err(format!("Quantizer expects {} dimensions, the index has {}", trained, self.dimension));
+        }
+        let codes: Vec<Vec<u8>> = (0..self.node_count)
+            .into_par_iter()
+            .map_init(Vec::new, |vector, slot| {
+                self.read_vector(slot, self.dimension, vector);
+                quantization.encode(vector)
+            })
+            .collect();
+        let code_len = codes.first().map_or(0, Vec::len);
+        if codes.iter().any(|code| code.len() != code_len) {
+            return Err("Sketches need a fixed code length per vector".to_string());
+        }
+        self.sketches = Some(Sketches { quantization, codes: codes.concat(), code_len });
+        Ok(self)
+    }
+
+    /// Nodes expanded at once by the layer-0 beam search, whose adjacency
+    /// lists are fetched together. Wider beams overlap more IO at the cost
+    /// of some extra distance computations; clamped to at least 1.
+    pub fn with_beam_width(mut self, beam_width: usize) -> Self {
+        self.beam_width = beam_width.max(1);
+        self
+    }
+
+    /// Bytes of sketch codes held in memory; 0 without `with_sketches`.
+    pub fn sketch_bytes(&self) -> usize {
+        self.sketches.as_ref().map_or(0, |sketches| sketches.codes.len())
+    }
+
+    /// Up to `count` stored vectors spread evenly over the file, for training
+    /// a quantizer for `with_sketches`.
+    pub fn sample_vectors(&self, count: usize) -> Vec<Vec<f64>> {
+        let count = count.min(self.node_count);
+        (0..count)
+            .map(|i| {
+                let mut vector = Vec::new();
+                self.read_vector(i * self.node_count / count, self.dimension, &mut vector);
+                vector
+            })
+            .collect()
+    }
+
+    pub fn with_distance_calculator(
+        mut self,
+        distance_calculator: Box<dyn DistanceCalculator + Send + Sync>,
+    ) -> Self {
+        self.distance_calculator = distance_calculator;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.node_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.node_count == 0
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn search(&self, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        let query = VectorTransform::apply_to_item(self.transform.as_ref(), query)?;
+        if query.vector.len() != self.dimension && self.node_count > 0 {
+            return Err(format!(
+                "Query {} has {} dimensions, expected {}",
+                query.id,
+                query.vector.len(),
+                self.dimension
+            ));
+        }
+        let ep = match self.entry_point {
+            Some(ep) if k > 0 => ep,
+            _ => return Ok(Vec::new()),
+        };
+
+        let prefix = |dims: usize| &query.vector[..dims.min(query.vector.len())];
+        let route_dims = self.resolutions.first().copied().unwrap_or(self.dimension);
+        let beam_dims = self.resolutions.last().copied().unwrap_or(self.dimension);
+        let route_query = prefix(route_dims);
+        let beam_query = prefix(beam_dims);
+
+        // With sketches every traversal distance is taken on the codes, and
+        // prefixes go unused
+        let prepared = self.sketches.as_ref().map(|sketches| sketches.quantization.prepare_query(self.distance_calculator.as_ref(), &query.vector));
+        let mut scratch = Vec::with_capacity(self.dimension);
+        let mut distance = |q: &[f64], slot: usize, dims: usize| match (&self.sketches, &prepared) {
+            (Some(sketches), Some(prepared)) if slot < self.node_count => {
+                let calculator = self.distance_calculator.as_ref();
+                Ok(sketches.quantization.distance(calculator, &query.vector, prepared, sketches.of(slot)))
+            }
+            _ => self.distance(q, slot, dims, &mut scratch),
+        };
+
+        // Greedy descent through the upper layers
+        let mut curr = ep;
+        let mut curr_dist = distance(route_query, curr, route_dims)?;
+        for level in (1..=self.layer(ep)?).rev() {
+            loop {
+                let mut best = (curr_dist, curr);
+                for neighbor in self.neighbors(curr, level)? {
+                    let dist = distance(route_query, neighbor, route_dims)?;
+                    if dist < best.0 {
+                        best = (dist, neighbor);
+                    }
+                }
+                if best.1 == curr {
+                    break;
+                }
+                (curr_dist, curr) = best;
+            }
+        }
+
+        // Beam search at layer 0, expanding up to `beam_width` of the nearest
+        // unexpanded candidates per step
+        let ef = self.ef_search.max(k);
+        let start = distance(beam_query, curr, beam_dims)?;
+        let mut visited = HashSet::from([curr]);
+        let mut candidates = BinaryHeap::from([Reverse((OrderedFloat(start), curr))]);
+        // Marked and expired nodes are traversed but never returned
+        let now = metrics::wall_clock().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+        let mut results = BinaryHeap::new();
+        if self.is_live(curr, now) {
+            results.push((OrderedFloat(start), curr));
+        }
+        let mut expanded = Vec::with_capacity(self.beam_width);
+        let mut fresh = Vec::new();
+
+        loop {
+            expanded.clear();
+            while expanded.len() < self.beam_width {
+                let Some(Reverse((dist, slot))) = candidates.pop() else {
+                    break;
+                };
+                if results.len() >= ef && dist > results.peek().unwrap().0 {
+                    candidates.clear();
+                    break;
+                }
+                expanded.push(slot);
+            }
+            if expanded.is_empty() {
+                break;
+            }
+            for &slot in &expanded {
+                self.prefetch_links(slot)?;
+            }
+            fresh.clear();
+            for &slot in &expanded {
+                fresh.extend(self.neighbors(slot, 0)?.into_iter().filter(|&neighbor| visited.insert(neighbor)));
+            }
+            if self.sketches.is_none() {
+                for &neighbor in &fresh {
+                    self.prefetch_vector(neighbor, beam_dims);
+                }
+            }
+            for &neighbor in &fresh {
+                let dist = OrderedFloat(distance(beam_query, neighbor, beam_dims)?);
+                if results.len() < ef || dist < results.peek().unwrap().0 {
+                    candidates.push(Reverse((dist, neighbor)));
+                    if self.is_live(neighbor, now) {
+                        results.push((dist, neighbor));
+                        if results.len() > ef {
+                            results.pop();
+                        }
+                    }
+                }
+            }
+        }
+
+        // Rerank on the full vectors when routing used prefixes or sketches,
+        // reading them in file order
+        let mut scored = results.into_vec();
+        if beam_dims < self.dimension || self.sketches.is_some() {
+            scored.sort_unstable_by_key(|&(_, slot)| slot);
+            for &(_, slot) in &scored {
+                self.prefetch_vector(slot, self.dimension);
+            }
+            let mut scratch = Vec::with_capacity(self.dimension);
+            for (dist, slot) in &mut scored {
+                *dist = OrderedFloat(self.distance(&query.vector, *slot, self.dimension, &mut scratch)?);
+            }
+        }
+        scored.sort_unstable();
+
+        Ok(scored
+            .into_iter()
+            .take(k)
+            .map(|(_, slot)| {
+                let mut item = VectorItem { id: self.id(slot), vector: Vec::new() };
+                self.read_vector(slot, self.dimension, &mut item.vector);
+                item
+            })
+            .collect())
+    }
+
+    fn prefetch_links(&self, slot: usize) -> Result<(), String> {
+        let block = self.link_block(slot)?;
+        self.map.prefetch(self.links.start + block.start * 8..self.links.start + block.end * 8);
+        Ok(())
+    }
+
+    fn prefetch_vector(&self, slot: usize, dims: usize) {
+        let start = self.vectors.start + slot * self.dimension * 8;
+        self.map.prefetch(start..start + dims.min(self.dimension) * 8);
+    }
+
+    // Whether `slot` is neither marked deleted nor expired at `now`, in
+    // milliseconds since the Unix epoch.
+    fn is_live(&self, slot: usize, now: u64) -> bool {
+        let deleted = self.deleted.as_ref().is_some_and(|range| word_at(&self.map, range.start + slot * 8) != 0);
+        let expired = self.expiries.as_ref().is_some_and(|range| {
+            let expiry = word_at(&self.map, range.start + slot * 8);
+            expiry != NO_EXPIRY && expiry <= now
+        });
+        !deleted && !expired
+    }
+
+    fn id(&self, slot: usize) -> usize {
+        word_at(&self.map, self.ids.start + slot * 8) as usize
+    }
+
+    fn read_vector(&self, slot: usize, dims: usize, out: &mut Vec<f64>) {
+        let start = self.vectors.start + slot * self.dimension * 8;
+        out.clear();
+        out.extend(
+            self.map[start..start + dims.min(self.dimension) * 8]
+                .chunks_exact(8)
+                .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap())),
+        );
+    }
+
+    fn distance(&self, query: &[f64], slot: usize, dims: usize, scratch: &mut Vec<f64>) -> Result<f64, String> {
+        if slot >= self.node_count {
+            return Err(format!("Slot {} out of range", slot));
+        }
+        self.read_vector(slot, dims, scratch);
+        Ok(self.distance_calculator.distance(query, scratch))
+    }
+
+    fn link_block(&self, slot: usize) -> Result<Range<usize>, String> {
+        let start = word_at(&self.map, self.offsets.start + slot * 8) as usize;
+        let end = word_at(&self.map, self.offsets.start + (slot + 1) * 8) as usize;
+        if start >= end || end > self.links.len() / 8 {
+            return Err(format!("Malformed adjacency list for slot {}", slot));
+        }
+        Ok(start..end)
+    }
+
+    fn link(&self, index: usize) -> usize {
+        word_at(&self.map, self.links.start + index * 8) as usize
+    }
+
+    fn layer(&self, slot: usize) -> Result<usize, String> {
+        let block = self.link_block(slot)?;
+        match self.link(block.start) {
+            0 => Err(format!("Slot {} has no layers", slot)),
+            layer_count => Ok(layer_count - 1),
+        }
+    }
+
+    fn neighbors(&self, slot: usize, level: usize) -> Result<Vec<usize>, String> {
+        let corrupt = || format!("Malformed adjacency list for slot {}", slot);
+        let block = self.link_block(slot)?;
+        let layer_count = self.link(block.start);
+
+        let mut pos = block.start + 1;
+        for layer in 0..layer_count.min(level + 1) {
+            if pos >= block.end {
+                return Err(corrupt());
+            }
+            let count = self.link(pos);
+            pos += 1;
+            if pos + count > block.end {
+                return Err(corrupt());
+            }
+            if layer == level {
+                let neighbors: Vec<usize> = (pos..pos + count).map(|i| self.link(i)).collect();
+                if neighbors.iter().any(|&n| n >= self.node_count) {
+                    return Err(corrupt());
+                }
+                return Ok(neighbors);
+            }
+            pos += count;
+        }
+        Ok(Vec::new())
+    }
+}
+
+// Only called on offsets inside sections validated by `open`
+fn word_at(map: &MappedFile, offset: usize) -> u64 {
+    map.word(offset).expect("offset inside a validated section")
+}
+
+/// Returns the byte range of the length-prefixed section at `cursor` and
+/// advances past it.
+fn section(map: &MappedFile, cursor: &mut usize, expected_words: Option<usize>) -> io::Result<Range<usize>> {
+    if *cursor + 8 > map.len() {
+        return Err(invalid_data("Index file is truncated"));
+    }
+    let len = word_at(map, *cursor) as usize;
+    let start = *cursor + 8;
+    if !len.is_multiple_of(8) || expected_words.is_some_and(|words| words.checked_mul(8) != Some(len)) {
+        return Err(invalid_data(format!("Unexpected section length {}", len)));
+    }
+    let end = start
+        .checked_add(len)
+        .filter(|&end| end <= map.len())
+        .ok_or_else(|| invalid_data("Index file is truncated"))?;
+    *cursor = end;
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HnswIndex;
+    use rand::Rng;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("hnsw_mmap_{}_{}.bin", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_mmap_search() {
+        let mut rng = rand::thread_rng();
+        let index = HnswIndex::new(Box::new(EuclideanDistance)).with_resolutions(vec![2]);
+        for i in 0..200 {
+            let vector = (0..6).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            index.add(VectorItem { id: i * 7, vector }).unwrap();
+        }
+        // Nearest the query, but marked deleted or expired, except the last
+        let now = std::time::SystemTime::now();
+        index.add(VectorItem { id: 7000, vector: vec![0.1; 6] }).unwrap();
+        assert!(index.mark_deleted(7000));
+        index.add_with_expiry(VectorItem { id: 7007, vector: vec![0.1; 6] }, now - std::time::Duration::from_secs(1)).unwrap();
+        index.add_with_expiry(VectorItem { id: 7014, vector: vec![0.11; 6] }, now + std::time::Duration::from_secs(3600)).unwrap();
+        let path = temp_path("search");
+        index.save(&path).unwrap();
+
+        let mapped = HnswIndex::load_mmap(&path).unwrap();
+        assert_eq!(mapped.len(), 203);
+        assert_eq!(mapped.dimension(), 6);
+
+        let query = VectorItem { id: 0, vector: vec![0.1; 6] };
+        let results = mapped.search(&query, 10).unwrap();
+        assert!(mapped.search(&query, 0).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(results[0].id, 7014);
+        assert!(results.iter().all(|r| r.id % 7 == 0 && r.vector.len() == 6 && r.id != 7000 && r.id != 7007));
+        let distances: Vec<_> = results.iter().map(|r| EuclideanDistance.distance(&query.vector, &r.vector)).collect();
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_mmap_sketches() {
+        let mut rng = rand::thread_rng();
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        let items: Vec<_> = (0..500)
+            .map(|id| VectorItem { id, vector: (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect() })
+            .collect();
+        index.par_batch_add(items.clone()).unwrap();
+        let path = temp_path("sketches");
+        index.save(&path).unwrap();
+
+        let mapped = MmapIndex::open(&path).unwrap();
+        assert_eq!(mapped.sketch_bytes(), 0);
+        let sample = mapped.sample_vectors(200);
+        assert_eq!(sample.len(), 200);
+        let sq8 = Quantization::Sq8 { quantizer: crate::ScalarQuantizer::train(&sample).unwrap(), rerank: false };
+        let mapped = mapped.with_sketches(sq8).unwrap().with_beam_width(8);
+        assert_eq!(mapped.sketch_bytes(), 500 * 8);
+
+        // Reranking from the file gives exact distances and near-exact recall
+        let mut found = 0;
+        for query in items.iter().take(50) {
+            let results = mapped.search(query, 10).unwrap();
+            let exact = index.search_exact(query, 10).unwrap();
+            assert_eq!(results[0].id, query.id);
+            assert_eq!(results[0].vector, query.vector);
+            found += results.iter().filter(|r| exact.iter().any(|e| e.id == r.id)).count();
+        }
+        assert!(found >= 480, "recall {} / 500", found);
+
+        let pq = Quantization::Pq { quantizer: crate::train_pq(&sample, 4, 4).unwrap(), rerank: true };
+        let mapped = MmapIndex::open(&path).unwrap().with_sketches(pq).unwrap();
+        assert_eq!(mapped.sketch_bytes(), 500 * 4);
+        assert_eq!(mapped.search(&items[7], 1).unwrap()[0].id, 7);
+        let wrong = Quantization::Sq8 { quantizer: crate::ScalarQuantizer::train(&[vec![0.0; 3]]).unwrap(), rerank: false };
+        assert!(MmapIndex::open(&path).unwrap().with_sketches(wrong).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_rejects_truncated_file() {
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        for i in 0..10 {
+            index.add(VectorItem { id: i, vector: vec![i as f64, 0.0] }).unwrap();
+        }
+        let path = temp_path("truncated");
+        index.save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 16]).unwrap();
+
+        let err = MmapIndex::open(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
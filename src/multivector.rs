@@ -0,0 +1,179 @@
+use crate::hnsw::HnswIndex;
+use crate::transform::VectorTransform;
+use crate::vector::VectorItem;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+/// Vectors retrieved per wanted document on the first try; doubled until
+/// enough distinct documents turn up.
+const VECTORS_PER_DOCUMENT: usize = 4;
+
+/// How `MultiVectorIndex::search` turns a document's per-vector distances
+/// into one score.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Aggregation {
+    /// The distance of the document's nearest vector, i.e. its best match.
+    #[default]
+    Max,
+    /// The mean distance over all of the document's vectors.
+    Mean,
+}
+
+#[derive(Default)]
+struct Documents {
+    // Vector ids per document, and the document of each vector id
+    vectors: HashMap<usize, Vec<usize>>,
+    owners: HashMap<usize, usize>,
+}
+
+/// Documents stored as several vectors each, such as the chunks of a long
+/// text or the token embeddings of a late-interaction model, searched as
+/// whole documents. The vectors live in one `HnswIndex` under ids allocated
+/// with `add_auto`, so vectors should only be added through this type.
+pub struct MultiVectorIndex {
+    index: HnswIndex,
+    documents: RwLock<Documents>,
+}
+
+impl MultiVectorIndex {
+    pub fn new(index: HnswIndex) -> Self {
+        MultiVectorIndex { index, documents: RwLock::new(Documents::default()) }
+    }
+
+    /// The index holding the vectors.
+    pub fn index(&self) -> &HnswIndex {
+        &self.index
+    }
+
+    /// Stores `vectors` under `doc_id`, replacing any vectors it had.
+    pub fn add_document(&self, doc_id: usize, vectors: Vec<Vec<f64>>) -> Result<(), String> {
+        if vectors.is_empty() {
+            return Err(format!("Document {} has no vectors", doc_id));
+        }
+        let mut documents = self.documents.write().unwrap();
+        self.remove_vectors(&mut documents, doc_id)?;
+        let mut ids = Vec::with_capacity(vectors.len());
+        for vector in vectors {
+            let id = self.index.add_auto(vector)?;
+            documents.owners.insert(id, doc_id);
+            ids.push(id);
+        }
+        documents.vectors.insert(doc_id, ids);
+        Ok(())
+    }
+
+    /// Removes `doc_id` and its vectors; returns whether it was stored.
+    pub fn remove_document(&self, doc_id: usize) -> Result<bool, String> {
+        let mut documents = self.documents.write().unwrap();
+        self.remove_vectors(&mut documents, doc_id)
+    }
+
+    fn remove_vectors(&self, documents: &mut Documents, doc_id: usize) -> Result<bool, String> {
+        let Some(ids) = documents.vectors.remove(&doc_id) else {
+            return Ok(false);
+        };
+        for id in ids {
+            documents.owners.remove(&id);
+            self.index.remove(id)?;
+        }
+        Ok(true)
+    }
+
+    pub fn contains(&self, doc_id: usize) -> bool {
+        self.documents.read().unwrap().vectors.contains_key(&doc_id)
+    }
+
+    /// The vectors stored under `doc_id`, after any `VectorTransform`.
+    pub fn vectors(&self, doc_id: usize) -> Option<Vec<VectorItem>> {
+        let documents = self.documents.read().unwrap();
+        let ids = documents.vectors.get(&doc_id)?;
+        self.index.get_many(ids).into_iter().collect()
+    }
+
+    /// The number of documents.
+    pub fn len(&self) -> usize {
+        self.documents.read().unwrap().vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `k` documents nearest to `query` as `(doc_id, distance)`, nearest
+    /// first, ties broken by id. The graph search finds the candidate
+    /// documents; each is then scored against all of its vectors, so `Mean`
+    /// is exact for every document returned.
+    pub fn search(&self, query: &VectorItem, k: usize, aggregation: Aggregation) -> Result<Vec<(usize, f64)>, String> {
+        let documents = self.documents.read().unwrap();
+        let vectors = documents.owners.len();
+        let mut wanted = k.saturating_mul(VECTORS_PER_DOCUMENT);
+        let candidates = loop {
+            let results = self.index.search(query, wanted)?;
+            let candidates: HashSet<usize> = results.iter().filter_map(|item| documents.owners.get(&item.id).copied()).collect();
+            if candidates.len() >= k || results.len() < wanted || wanted >= vectors {
+                break candidates;
+            }
+            wanted = wanted.saturating_mul(2);
+        };
+
+        let prepared = VectorTransform::apply_to_item(self.index.transform(), query)?;
+        let mut scored: Vec<(usize, f64)> = candidates
+            .into_iter()
+            .map(|doc_id| {
+                let distances = self
+                    .index
+                    .get_many(&documents.vectors[&doc_id])
+                    .into_iter()
+                    .flatten()
+                    .map(|item| self.index.calculate_distances(&prepared, &item));
+                let score = match aggregation {
+                    Aggregation::Max => distances.fold(f64::INFINITY, f64::min),
+                    Aggregation::Mean => {
+                        let (sum, count) = distances.fold((0.0, 0), |(sum, count), d| (sum + d, count + 1));
+                        sum / count as f64
+                    }
+                };
+                (doc_id, score)
+            })
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        scored.truncate(k);
+        Ok(scored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistance;
+
+    #[test]
+    fn test_multi_vector_search() {
+        let index = MultiVectorIndex::new(HnswIndex::new(Box::new(EuclideanDistance)));
+        // Document 1 has one chunk right at the query and one far away;
+        // document 2 has two chunks fairly close to it
+        index.add_document(1, vec![vec![0.0, 0.0], vec![6.0, 0.0]]).unwrap();
+        index.add_document(2, vec![vec![1.0, 0.0], vec![0.0, 1.0]]).unwrap();
+        for doc_id in 3..50 {
+            let x = doc_id as f64;
+            index.add_document(doc_id, vec![vec![x, x], vec![x + 0.5, x], vec![x, x + 0.5]]).unwrap();
+        }
+        assert_eq!(index.len(), 49);
+        assert_eq!(index.index().len(), 145);
+
+        let query = VectorItem { id: 0, vector: vec![0.0, 0.0] };
+        assert_eq!(index.search(&query, 2, Aggregation::Max).unwrap(), [(1, 0.0), (2, 1.0)]);
+        assert_eq!(index.search(&query, 2, Aggregation::Mean).unwrap(), [(2, 1.0), (1, 3.0)]);
+
+        // Replacing a document drops its old vectors
+        index.add_document(1, vec![vec![20.0, 20.0]]).unwrap();
+        assert_eq!(index.index().len(), 144);
+        assert_eq!(index.vectors(1).unwrap()[0].vector, [20.0, 20.0]);
+        assert_eq!(index.search(&query, 1, Aggregation::Max).unwrap()[0].0, 2);
+
+        assert!(index.remove_document(2).unwrap());
+        assert!(!index.remove_document(2).unwrap() && !index.contains(2));
+        assert_eq!(index.search(&query, 1, Aggregation::Max).unwrap()[0].0, 3);
+        assert!(index.add_document(5, Vec::new()).is_err());
+    }
+}
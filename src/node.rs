@@ -1,8 +1,62 @@
 use crate::vector::VectorItem;
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
 
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Node {
     pub id: usize,
-    pub connections: Vec<Vec<usize>>,
-    pub item: VectorItem,
+    /// Shared with a `VectorPool` when the index has one.
+    pub item: Arc<VectorItem>,
+    /// The top layer the node is linked on; its links, one list per layer
+    /// up to this one, live in the index's `NodeSlab`.
     pub layer: usize,
+    /// Caller data stored with the vector; see `HnswIndex::add_with_payload`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub payload: Option<serde_json::Value>,
+    /// When the node stops being returned by searches; see
+    /// `HnswIndex::add_with_expiry`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub expires_at: Option<SystemTime>,
+    /// Set by `HnswIndex::mark_deleted`: searches skip the node but still
+    /// traverse its links.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub deleted: bool,
+    /// `item` compressed by the index's `Quantization`; empty otherwise.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub codes: Vec<u8>,
+    /// The norm of `item`, for metrics that use norms. Filled on insert
+    /// under such a metric, otherwise on first use by `norm`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub norm: OnceLock<f64>,
+}
+
+impl Node {
+    pub(crate) fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether searches may return the node at `now`.
+    pub(crate) fn is_live(&self, now: SystemTime) -> bool {
+        !self.deleted && !self.is_expired(now)
+    }
+
+    /// The cached norm of `item`.
+    pub(crate) fn norm(&self) -> f64 {
+        *self.norm.get_or_init(|| crate::vector::norm(&self.item.vector))
+    }
+
+    /// The vector compared with `dims` leading dimensions; see `prefix`.
+    pub(crate) fn vector_at(&self, dims: Option<usize>) -> &[f64] {
+        prefix(&self.item.vector, dims)
+    }
+}
+
+/// The first `dims` components of `vector` (all of them if it is shorter),
+/// or the whole vector for `None`. Multi-resolution indexes route on these.
+pub(crate) fn prefix(vector: &[f64], dims: Option<usize>) -> &[f64] {
+    match dims {
+        Some(dims) => &vector[..dims.min(vector.len())],
+        None => vector,
+    }
 }
@@ -0,0 +1,93 @@
+//! Streams vectors out of Parquet files, one record batch at a time, so a
+//! data-lake export can be indexed without materializing it. The file needs
+//! an integer id column and a vector column as described in `arrow`; other
+//! columns are not read.
+
+use crate::persist::invalid_data;
+use crate::vector::VectorItem;
+use ::parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use ::parquet::arrow::ProjectionMask;
+use arrow_array::RecordBatchReader;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Iterates over the batches of a Parquet file as `VectorItem`s; see
+/// `read_parquet`.
+pub struct ParquetVectors {
+    batches: ParquetRecordBatchReader,
+    ids: usize,
+    vectors: usize,
+}
+
+/// Opens `path` and reads only `id_column` and `vector_column` from it.
+pub fn read_parquet(path: impl AsRef<Path>, id_column: &str, vector_column: &str) -> io::Result<ParquetVectors> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?).map_err(|e| invalid_data(e.to_string()))?;
+    let column = |name: &str| {
+        builder.schema().index_of(name).map_err(|_| invalid_data(format!("Parquet file has no column {}", name)))
+    };
+    let mut roots = [column(id_column)?, column(vector_column)?];
+    roots.sort_unstable();
+    let mask = ProjectionMask::roots(builder.parquet_schema(), roots);
+    let batches = builder.with_projection(mask).build().map_err(|e| invalid_data(e.to_string()))?;
+    // Projected columns keep their file order, so look them up by name again
+    let schema = batches.schema();
+    let ids = schema.index_of(id_column).map_err(|e| invalid_data(e.to_string()))?;
+    let vectors = schema.index_of(vector_column).map_err(|e| invalid_data(e.to_string()))?;
+    Ok(ParquetVectors { batches, ids, vectors })
+}
+
+impl Iterator for ParquetVectors {
+    type Item = io::Result<Vec<VectorItem>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.batches.next()?;
+        Some(batch.map_err(|e| invalid_data(e.to_string())).and_then(|batch| {
+            crate::arrow::items_from_arrow(batch.column(self.ids).as_ref(), batch.column(self.vectors).as_ref())
+                .map_err(invalid_data)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EuclideanDistance, HnswIndex};
+    use ::parquet::arrow::ArrowWriter;
+    use arrow_array::{Array, FixedSizeListArray, Float32Array, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_add_parquet() {
+        let item = Arc::new(Field::new("item", DataType::Float32, false));
+        let values = Float32Array::from_iter_values((0..60).map(|x| x as f32));
+        let vectors = FixedSizeListArray::try_new(item, 3, Arc::new(values), None).unwrap();
+        let schema = Schema::new(vec![
+            Field::new("title", DataType::Utf8, false),
+            Field::new("embedding", vectors.data_type().clone(), false),
+            Field::new("doc_id", DataType::UInt64, false),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from_iter_values((0..20).map(|i| format!("doc {}", i)))),
+                Arc::new(vectors),
+                Arc::new(UInt64Array::from_iter_values((0..20).map(|i| 100 + i))),
+            ],
+        )
+        .unwrap();
+
+        let path = std::env::temp_dir().join(format!("hnsw_parquet_{}.parquet", std::process::id()));
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let index = HnswIndex::new(Box::new(EuclideanDistance));
+        assert_eq!(index.add_parquet(&path, "doc_id", "embedding").unwrap(), 20);
+        assert_eq!(index.get(105).unwrap().vector, vec![15.0, 16.0, 17.0]);
+        assert!(read_parquet(&path, "doc_id", "missing").is_err());
+        assert!(read_parquet(&path, "doc_id", "title").unwrap().next().unwrap().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
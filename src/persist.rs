@@ -0,0 +1,683 @@
+//! Binary index file layout used by `HnswIndex::save` / `HnswIndex::load`.
+//!
+//! All integers are little-endian `u64` and all floats little-endian `f64`, so
+//! every section starts 8-byte aligned:
+//!
+//! ```text
+//! header (64 bytes)
+//!   magic "HNSWRS\0\0" | version: u32 | reserved: u32
+//!   node_count | dimension | entry_point (slot, u64::MAX if none)
+//!   max_level | level_lambda: f64 | next_id (auto-id high-water mark, 0 if unknown)
+//! sections, each prefixed by its byte length:
+//!   transform     kind (0 none, 1 truncate, 2 projection) + parameters
+//!   resolutions   prefix dimensionalities (since version 2)
+//!   ids           node_count external ids; position = slot
+//!   vectors       node_count * dimension components
+//!   link offsets  node_count + 1 word offsets into the links section
+//!   links         per node: layer_count, then per layer: count, slots
+//!   payloads      per node: JSON byte length (u64::MAX if none), then the
+//!                 bytes zero-padded to whole words (since version 4)
+//!   expiries      node_count expiry times in milliseconds since the Unix
+//!                 epoch, u64::MAX if none (since version 5)
+//!   deleted       node_count words, 1 if the node is marked deleted and 0
+//!                 otherwise (since version 6)
+//!   parameters    m | ef_construction | ef_search (since version 7)
+//! checksum (since version 3)
+//!   FNV-1a 64 of every preceding byte
+//! ```
+
+use crate::node::Node;
+use crate::slab::NodeSlab;
+use crate::transform::VectorTransform;
+use crate::vector::VectorItem;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+pub(crate) const MAGIC: &[u8; 8] = b"HNSWRS\0\0";
+pub(crate) const FORMAT_VERSION: u32 = 7;
+pub(crate) const HEADER_LEN: usize = 64;
+pub(crate) const NO_ENTRY_POINT: u64 = u64::MAX;
+const NO_PAYLOAD: u64 = u64::MAX;
+pub(crate) const NO_EXPIRY: u64 = u64::MAX;
+
+const TRANSFORM_NONE: u64 = 0;
+const TRANSFORM_TRUNCATE: u64 = 1;
+const TRANSFORM_PROJECTION: u64 = 2;
+
+pub(crate) struct IndexParts {
+    pub nodes: NodeSlab,
+    pub entry_point: Option<usize>,
+    pub level_lambda: f64,
+    pub max_level: usize,
+    pub transform: Option<VectorTransform>,
+    pub resolutions: Vec<usize>,
+    pub next_id: usize,
+    // `None` for files older than version 7
+    pub params: Option<GraphParams>,
+}
+
+/// The graph's build and search parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct GraphParams {
+    pub m: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+}
+
+impl GraphParams {
+    pub(crate) fn read(words: &[u64]) -> io::Result<GraphParams> {
+        match *words {
+            [m, ef_construction, ef_search] if m >= 2 && ef_construction >= 1 && ef_search >= 1 => Ok(GraphParams {
+                m: m as usize,
+                ef_construction: ef_construction as usize,
+                ef_search: ef_search as usize,
+            }),
+            _ => Err(invalid_data("Malformed parameters section")),
+        }
+    }
+}
+
+/// Index-wide settings written alongside the graph.
+pub(crate) struct IndexMeta<'a> {
+    pub level_lambda: f64,
+    pub max_level: usize,
+    pub transform: Option<&'a VectorTransform>,
+    pub resolutions: &'a [usize],
+    pub next_id: usize,
+    pub params: GraphParams,
+}
+
+pub(crate) fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Destination of `write_durable`; `sync` must not return before the
+/// written bytes are on stable storage.
+pub(crate) trait Storage: Write {
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+impl Storage for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// Writes a new file with `write`, which must sync it, and renames it over
+/// `path`. A crash or failed write leaves the previous file intact, and the
+/// old inode is never modified, so a `load_mmap` mapping of it stays valid.
+/// Every writer of index files goes through here. Each call writes its own
+/// temporary file, so concurrent saves to the same path cannot touch each
+/// other's data; the last rename wins.
+pub(crate) fn replace_file(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+    let (tmp_path, mut file) = loop {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(format!(".{}.{}.tmp", std::process::id(), NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+        let tmp_path = path.with_file_name(tmp_name);
+        // Left behind by a crashed process that had the same id
+        match OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            file => break (tmp_path, file?),
+        }
+    };
+    let result = write(&mut file).and_then(|()| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result.and_then(|()| sync_parent(path))
+}
+
+// Makes a rename into `path`'s directory durable.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(parent)?.sync_all()
+}
+
+// Directories cannot be opened, and renames are journaled, elsewhere.
+#[cfg(not(unix))]
+fn sync_parent(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Writes the index to `storage` and syncs it.
+pub(crate) fn write_durable<S: Storage>(
+    storage: &mut S,
+    nodes: &NodeSlab,
+    entry_point: Option<usize>,
+    meta: &IndexMeta,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(&mut *storage);
+    write_index(&mut writer, nodes, entry_point, meta)?;
+    writer.into_inner().map_err(|e| e.into_error())?;
+    storage.sync()
+}
+
+pub(crate) fn write_index<W: Write>(
+    writer: &mut W,
+    nodes: &NodeSlab,
+    entry_point: Option<usize>,
+    meta: &IndexMeta,
+) -> io::Result<()> {
+    let writer = &mut Checksummed::new(writer);
+    let dimension = nodes.get(0).map_or(0, |node| node.item.vector.len());
+    if let Some(node) = nodes.iter().find(|node| node.item.vector.len() != dimension) {
+        return Err(invalid_data(format!(
+            "Vector {} has {} dimensions, expected {}",
+            node.id,
+            node.item.vector.len(),
+            dimension
+        )));
+    }
+
+    let entry_point = match entry_point {
+        Some(slot) if slot < nodes.len() => slot as u64,
+        Some(slot) => return Err(invalid_data(format!("Entry point slot {} out of range", slot))),
+        None => NO_ENTRY_POINT,
+    };
+
+    // Header
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    write_u64(writer, nodes.len() as u64)?;
+    write_u64(writer, dimension as u64)?;
+    write_u64(writer, entry_point)?;
+    write_u64(writer, meta.max_level as u64)?;
+    writer.write_all(&meta.level_lambda.to_le_bytes())?;
+    write_u64(writer, meta.next_id as u64)?;
+
+    // Transform
+    let mut words = Vec::new();
+    match meta.transform {
+        None => words.push(TRANSFORM_NONE),
+        Some(VectorTransform::Truncate { dim }) => {
+            words.extend([TRANSFORM_TRUNCATE, *dim as u64]);
+        }
+        Some(VectorTransform::Projection { matrix }) => {
+            words.extend([TRANSFORM_PROJECTION, matrix.len() as u64, matrix[0].len() as u64]);
+            words.extend(matrix.iter().flatten().map(|w| w.to_bits()));
+        }
+    }
+    write_section(writer, &words)?;
+
+    // Resolutions
+    write_section(writer, &meta.resolutions.iter().map(|&dim| dim as u64).collect::<Vec<_>>())?;
+
+    // Ids
+    write_section(writer, &nodes.iter().map(|node| node.id as u64).collect::<Vec<_>>())?;
+
+    // Vectors
+    write_u64(writer, (nodes.len() * dimension * 8) as u64)?;
+    for node in nodes.iter() {
+        for component in &node.item.vector {
+            writer.write_all(&component.to_le_bytes())?;
+        }
+    }
+
+    // Adjacency lists
+    let mut offsets = Vec::with_capacity(nodes.len() + 1);
+    let mut links = Vec::new();
+    for (slot, node) in nodes.iter().enumerate() {
+        offsets.push(links.len() as u64);
+        links.push(nodes.layers(slot) as u64);
+        for layer in nodes.all_links(slot) {
+            links.push(layer.len() as u64);
+            for &neighbor in layer {
+                if neighbor >= nodes.len() {
+                    return Err(invalid_data(format!("Node {} links to missing slot {}", node.id, neighbor)));
+                }
+                links.push(neighbor as u64);
+            }
+        }
+    }
+    offsets.push(links.len() as u64);
+    write_section(writer, &offsets)?;
+    write_section(writer, &links)?;
+
+    // Payloads
+    let mut words = Vec::new();
+    for node in nodes.iter() {
+        match &node.payload {
+            None => words.push(NO_PAYLOAD),
+            Some(payload) => {
+                let bytes = serde_json::to_vec(payload).map_err(|e| invalid_data(e.to_string()))?;
+                words.push(bytes.len() as u64);
+                words.extend(bytes.chunks(8).map(|chunk| {
+                    let mut word = [0u8; 8];
+                    word[..chunk.len()].copy_from_slice(chunk);
+                    u64::from_le_bytes(word)
+                }));
+            }
+        }
+    }
+    write_section(writer, &words)?;
+
+    // Expiries; times before the epoch are clamped to it
+    let expiries: Vec<u64> = nodes
+        .iter()
+        .map(|node| match node.expires_at {
+            None => NO_EXPIRY,
+            Some(at) => at.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis().min(u128::from(NO_EXPIRY - 1)) as u64),
+        })
+        .collect();
+    write_section(writer, &expiries)?;
+    write_section(writer, &nodes.iter().map(|node| u64::from(node.deleted)).collect::<Vec<_>>())?;
+    let GraphParams { m, ef_construction, ef_search } = meta.params;
+    write_section(writer, &[m as u64, ef_construction as u64, ef_search as u64])?;
+
+    let checksum = writer.hash;
+    write_u64(writer, checksum)?;
+    writer.flush()
+}
+
+pub(crate) fn read_index<R: Read>(reader: &mut R) -> io::Result<IndexParts> {
+    let reader = &mut Checksummed::new(reader);
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let header = Header::parse(&header)?;
+    let node_count = header.node_count;
+    let dimension = header.dimension;
+
+    let transform = read_transform(&read_section(reader, None)?)?;
+    let resolutions = match header.version {
+        1 => Vec::new(),
+        _ => read_section(reader, None)?.into_iter().map(|dim| dim as usize).collect(),
+    };
+    let ids = read_section(reader, Some(node_count))?;
+
+    let expected = node_count
+        .checked_mul(dimension)
+        .ok_or_else(|| invalid_data("Vector section is too large"))?;
+    let vectors = read_section(reader, Some(expected))?;
+    let offsets = read_section(reader, Some(node_count + 1))?;
+    let links = read_section(reader, None)?;
+    let mut payloads = match header.version {
+        1..=3 => vec![None; node_count],
+        _ => read_payloads(&read_section(reader, None)?, node_count)?,
+    }
+    .into_iter();
+    let mut expiries = match header.version {
+        1..=4 => vec![NO_EXPIRY; node_count],
+        _ => read_section(reader, Some(node_count))?,
+    }
+    .into_iter()
+    .map(|ms| (ms != NO_EXPIRY).then(|| UNIX_EPOCH + Duration::from_millis(ms)));
+    let mut deleted = match header.version {
+        1..=5 => vec![0; node_count],
+        _ => read_section(reader, Some(node_count))?,
+    }
+    .into_iter();
+    let params = match header.version {
+        1..=6 => None,
+        _ => Some(GraphParams::read(&read_section(reader, None)?)?),
+    };
+    if header.version >= 3 {
+        let expected = reader.hash;
+        let mut stored = [0u8; 8];
+        reader.read_exact(&mut stored)?;
+        if u64::from_le_bytes(stored) != expected {
+            return Err(invalid_data("Index file checksum mismatch"));
+        }
+    }
+
+    let mut nodes = Vec::with_capacity(node_count);
+    for i in 0..node_count {
+        let id = ids[i] as usize;
+        let vector = vectors[i * dimension..(i + 1) * dimension]
+            .iter()
+            .map(|&bits| f64::from_bits(bits))
+            .collect();
+        let connections = read_links(&links, &offsets, i)?
+            .into_iter()
+            .map(|layer| {
+                layer
+                    .into_iter()
+                    .map(|n| match n as usize {
+                        slot if slot < node_count => Ok(slot),
+                        _ => Err(invalid_data(format!("Node {} links to invalid slot {}", id, n))),
+                    })
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if connections.is_empty() {
+            return Err(invalid_data(format!("Node {} has no layers", id)));
+        }
+
+        nodes.push((Node {
+            id,
+            layer: connections.len() - 1,
+            item: Arc::new(VectorItem { id, vector }),
+            codes: Vec::new(),
+            norm: Default::default(),
+            payload: payloads.next().flatten(),
+            expires_at: expiries.next().flatten(),
+            deleted: deleted.next() == Some(1),
+        }, connections));
+    }
+    let nodes = NodeSlab::from_nodes(nodes).map_err(invalid_data)?;
+
+    let entry_point = match header.entry_point {
+        NO_ENTRY_POINT => None,
+        slot if (slot as usize) < node_count => Some(slot as usize),
+        slot => return Err(invalid_data(format!("Entry point slot {} out of range", slot))),
+    };
+
+    Ok(IndexParts {
+        nodes,
+        entry_point,
+        level_lambda: header.level_lambda,
+        max_level: header.max_level,
+        transform,
+        resolutions,
+        next_id: header.next_id,
+        params,
+    })
+}
+
+pub(crate) struct Header {
+    pub version: u32,
+    pub node_count: usize,
+    pub dimension: usize,
+    pub entry_point: u64,
+    pub max_level: usize,
+    pub level_lambda: f64,
+    pub next_id: usize,
+}
+
+impl Header {
+    pub(crate) fn parse(bytes: &[u8]) -> io::Result<Header> {
+        if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+            return Err(invalid_data("Not an hnsw-rust index file"));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported index format version {} (newest supported is {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let word = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        Ok(Header {
+            version,
+            node_count: word(16) as usize,
+            dimension: word(24) as usize,
+            entry_point: word(32),
+            max_level: word(40) as usize,
+            level_lambda: f64::from_bits(word(48)),
+            next_id: word(56) as usize,
+        })
+    }
+}
+
+pub(crate) fn read_transform(words: &[u64]) -> io::Result<Option<VectorTransform>> {
+    match words {
+        [TRANSFORM_NONE] => Ok(None),
+        [TRANSFORM_TRUNCATE, dim] => Ok(Some(VectorTransform::truncate(*dim as usize))),
+        [TRANSFORM_PROJECTION, rows, cols, weights @ ..]
+            if (*rows as usize).checked_mul(*cols as usize) == Some(weights.len()) =>
+        {
+            let matrix = weights
+                .chunks(*cols as usize)
+                .map(|row| row.iter().map(|&bits| f64::from_bits(bits)).collect())
+                .collect();
+            VectorTransform::projection(matrix).map(Some).map_err(invalid_data)
+        }
+        _ => Err(invalid_data("Malformed transform section")),
+    }
+}
+
+/// Splits node `slot`'s block of the links section into per-layer lists of
+/// slots.
+pub(crate) fn read_links(links: &[u64], offsets: &[u64], slot: usize) -> io::Result<Vec<Vec<u64>>> {
+    let corrupt = || invalid_data(format!("Malformed adjacency list for slot {}", slot));
+    let start = offsets[slot] as usize;
+    let end = offsets[slot + 1] as usize;
+    let block = links.get(start..end).ok_or_else(corrupt)?;
+
+    let (&layer_count, mut rest) = block.split_first().ok_or_else(corrupt)?;
+    let mut layers = Vec::with_capacity(layer_count as usize);
+    for _ in 0..layer_count {
+        let (&count, tail) = rest.split_first().ok_or_else(corrupt)?;
+        let count = count as usize;
+        if tail.len() < count {
+            return Err(corrupt());
+        }
+        layers.push(tail[..count].to_vec());
+        rest = &tail[count..];
+    }
+    if !rest.is_empty() {
+        return Err(corrupt());
+    }
+    Ok(layers)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Reader/writer adapter that hashes every byte passing through it.
+struct Checksummed<T> {
+    inner: T,
+    hash: u64,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        Checksummed { inner, hash: FNV_OFFSET }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash = (self.hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_payloads(words: &[u64], node_count: usize) -> io::Result<Vec<Option<serde_json::Value>>> {
+    let corrupt = || invalid_data("Malformed payload section");
+    let mut payloads = Vec::with_capacity(node_count);
+    let mut rest = words;
+    for _ in 0..node_count {
+        let (&len, tail) = rest.split_first().ok_or_else(corrupt)?;
+        if len == NO_PAYLOAD {
+            payloads.push(None);
+            rest = tail;
+            continue;
+        }
+        let len = len as usize;
+        let word_count = len.div_ceil(8);
+        let block = tail.get(..word_count).ok_or_else(corrupt)?;
+        let bytes: Vec<u8> = block.iter().flat_map(|word| word.to_le_bytes()).take(len).collect();
+        payloads.push(Some(serde_json::from_slice(&bytes).map_err(|e| invalid_data(e.to_string()))?));
+        rest = &tail[word_count..];
+    }
+    if !rest.is_empty() {
+        return Err(corrupt());
+    }
+    Ok(payloads)
+}
+
+fn write_section<W: Write>(writer: &mut W, words: &[u64]) -> io::Result<()> {
+    write_u64(writer, (words.len() * 8) as u64)?;
+    for word in words {
+        write_u64(writer, *word)?;
+    }
+    Ok(())
+}
+
+fn read_section<R: Read>(reader: &mut R, expected_words: Option<usize>) -> io::Result<Vec<u64>> {
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len) as usize;
+    if !len.is_multiple_of(8) || expected_words.is_some_and(|words| words.checked_mul(8) != Some(len)) {
+        return Err(invalid_data(format!("Unexpected section length {}", len)));
+    }
+
+    let mut words = Vec::new();
+    let mut buf = [0u8; 8];
+    for _ in 0..len / 8 {
+        reader.read_exact(&mut buf)?;
+        words.push(u64::from_le_bytes(buf));
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// In-memory storage that fails after `write_limit` bytes, keeping only
+    /// the bytes written up to that point (a torn write), or fails to sync.
+    #[derive(Default)]
+    struct FaultyStorage {
+        data: Vec<u8>,
+        write_limit: Option<usize>,
+        fail_sync: bool,
+    }
+
+    impl Write for FaultyStorage {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let room = self.write_limit.map_or(buf.len(), |limit| limit - self.data.len());
+            if room == 0 {
+                return Err(io::Error::other("injected write failure"));
+            }
+            let len = buf.len().min(room);
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Storage for FaultyStorage {
+        fn sync(&mut self) -> io::Result<()> {
+            match self.fail_sync {
+                true => Err(io::Error::other("injected fsync failure")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn sample_nodes() -> NodeSlab {
+        let nodes = (0..20)
+            .map(|id| (Node {
+                id,
+                item: Arc::new(VectorItem { id, vector: vec![id as f64, 1.0, -2.5] }),
+                layer: 0,
+                codes: Vec::new(),
+                norm: Default::default(),
+                payload: (id % 3 == 0).then(|| serde_json::json!({ "id": id, "tags": ["a", "bc"] })),
+                expires_at: (id % 4 == 0).then(|| UNIX_EPOCH + Duration::from_millis(id as u64 * 1000)),
+                deleted: id % 5 == 0,
+            }, vec![vec![(id + 1) % 20, (id + 19) % 20]]))
+            .collect();
+        NodeSlab::from_nodes(nodes).unwrap()
+    }
+
+    fn write(storage: &mut FaultyStorage, nodes: &NodeSlab) -> io::Result<()> {
+        let meta = IndexMeta {
+            level_lambda: 0.5,
+            max_level: 16,
+            transform: None,
+            resolutions: &[],
+            next_id: 20,
+            params: GraphParams { m: 8, ef_construction: 40, ef_search: 12 },
+        };
+        write_durable(storage, nodes, Some(0), &meta)
+    }
+
+    #[test]
+    fn test_replace_file() {
+        let dir = std::env::temp_dir().join(format!("hnsw_replace_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("index.bin");
+
+        // Concurrent writers never mix their bytes or leave temporary files
+        std::thread::scope(|scope| {
+            for byte in 0..8u8 {
+                let path = &path;
+                scope.spawn(move || replace_file(path, |file| file.write_all(&[byte; 4096])).unwrap());
+            }
+        });
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.len() == 4096 && bytes.iter().all(|&b| b == bytes[0]));
+
+        let failed = replace_file(&path, |file| file.write_all(b"torn").and(Err(io::Error::other("disk full"))));
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_injected_failures_are_reported() {
+        let nodes = sample_nodes();
+        let mut storage = FaultyStorage { fail_sync: true, ..Default::default() };
+        assert!(write(&mut storage, &nodes).is_err());
+
+        let mut storage = FaultyStorage { write_limit: Some(100), ..Default::default() };
+        assert!(write(&mut storage, &nodes).is_err());
+        assert_eq!(storage.data.len(), 100);
+    }
+
+    #[test]
+    fn test_corrupt_files_never_load() {
+        let nodes = sample_nodes();
+        let mut storage = FaultyStorage::default();
+        write(&mut storage, &nodes).unwrap();
+        let full = storage.data;
+        let parts = read_index(&mut full.as_slice()).unwrap();
+        assert_eq!(parts.nodes.len(), 20);
+        assert_eq!(parts.params, Some(GraphParams { m: 8, ef_construction: 40, ef_search: 12 }));
+        for (read, written) in parts.nodes.iter().zip(nodes.iter()) {
+            assert_eq!(read.payload, written.payload);
+            assert_eq!(read.expires_at, written.expires_at);
+            assert_eq!(read.deleted, written.deleted);
+        }
+
+        // Torn writes: every proper prefix of the file
+        for len in 0..full.len() {
+            let mut storage = FaultyStorage { write_limit: Some(len), ..Default::default() };
+            assert!(write(&mut storage, &nodes).is_err());
+            assert!(read_index(&mut storage.data.as_slice()).is_err(), "prefix of {} bytes loaded", len);
+        }
+
+        // Bit rot: a flipped bit anywhere after the magic
+        for pos in MAGIC.len()..full.len() {
+            let mut bytes = full.clone();
+            bytes[pos] ^= 0x10;
+            assert!(read_index(&mut bytes.as_slice()).is_err(), "flip at byte {} loaded", pos);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+/// How a filtered search finds its results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchStrategy {
+    /// Beam search over the graph, skipping results the filter rejects.
+    Graph,
+    /// Exact scan of every item the filter accepts. Chosen when so few
+    /// items pass that the beam would have to wander most of the graph.
+    BruteForce,
+}
+
+/// What a search will do and roughly what it will cost; see
+/// `HnswIndex::explain_plan`. Estimates come from sampling the filter, so
+/// they vary with how representative the sample is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryPlan {
+    pub strategy: SearchStrategy,
+    /// Estimated fraction of stored items the filter accepts.
+    pub selectivity: f64,
+    pub estimated_matches: usize,
+    /// Items the chosen strategy looks at.
+    pub estimated_candidates: usize,
+    pub estimated_distance_evaluations: usize,
+    pub graph_distance_evaluations: usize,
+    pub brute_force_distance_evaluations: usize,
+}
+
+// Graph shape and filter sample a plan is costed from
+pub(crate) struct PlanInput {
+    pub(crate) filtered: bool,
+    pub(crate) len: usize,
+    pub(crate) sampled: usize,
+    pub(crate) accepted: usize,
+    pub(crate) top_level: usize,
+    pub(crate) upper_degree: usize,
+    pub(crate) average_degree: f64,
+    pub(crate) ef: usize,
+}
+
+impl QueryPlan {
+    /// Picks the strategy with fewer expected distance evaluations; searches
+    /// without a filter always use the graph. The beam needs `ef` accepted
+    /// nodes, so it expands about `ef / selectivity` nodes on layer 0, each
+    /// costing one evaluation per link; a scan costs one evaluation per
+    /// accepted item. Neither can evaluate more than every item once.
+    pub(crate) fn choose(input: &PlanInput) -> Self {
+        let PlanInput { filtered, len, sampled, accepted, top_level, upper_degree, average_degree, ef } = *input;
+        let selectivity = if sampled == 0 { 1.0 } else { accepted as f64 / sampled as f64 };
+        let estimated_matches = (selectivity * len as f64).round() as usize;
+
+        // A filter passing nothing makes the beam search the whole graph
+        let expanded = if selectivity > 0.0 { (ef as f64 / selectivity).min(len as f64) } else { len as f64 };
+        let graph = (top_level * upper_degree + (expanded * average_degree).ceil() as usize).min(len);
+        let brute_force = estimated_matches;
+
+        let (strategy, estimated_candidates, estimated_distance_evaluations) = if filtered && brute_force < graph {
+            (SearchStrategy::BruteForce, len, brute_force)
+        } else {
+            (SearchStrategy::Graph, expanded.ceil() as usize, graph)
+        };
+        QueryPlan {
+            strategy,
+            selectivity,
+            estimated_matches,
+            estimated_candidates,
+            estimated_distance_evaluations,
+            graph_distance_evaluations: graph,
+            brute_force_distance_evaluations: brute_force,
+        }
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let strategy = match self.strategy {
+            SearchStrategy::Graph => "Graph traversal",
+            SearchStrategy::BruteForce => "Filtered brute force",
+        };
+        writeln!(f, "{}", strategy)?;
+        writeln!(f, "  selectivity: {:.4} (~{} matching items)", self.selectivity, self.estimated_matches)?;
+        writeln!(f, "  candidates: ~{}", self.estimated_candidates)?;
+        writeln!(f, "  distance evaluations: ~{}", self.estimated_distance_evaluations)?;
+        write!(
+            f,
+            "  cost: graph ~{}, brute force ~{}",
+            self.graph_distance_evaluations, self.brute_force_distance_evaluations
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(len: usize, accepted: usize) -> PlanInput {
+        PlanInput { filtered: true, len, sampled: 1000, accepted, top_level: 3, upper_degree: 16, average_degree: 20.0, ef: 64 }
+    }
+
+    #[test]
+    fn test_choose() {
+        let open = QueryPlan::choose(&input(100_000, 1000));
+        assert_eq!(open.strategy, SearchStrategy::Graph);
+        assert_eq!(open.selectivity, 1.0);
+        assert_eq!(open.estimated_distance_evaluations, 3 * 16 + 64 * 20);
+
+        let selective = QueryPlan::choose(&input(100_000, 5));
+        assert_eq!(selective.strategy, SearchStrategy::BruteForce);
+        assert_eq!(selective.estimated_matches, 500);
+        assert_eq!(selective.estimated_distance_evaluations, 500);
+
+        let nothing = QueryPlan::choose(&input(100_000, 0));
+        assert_eq!((nothing.strategy, nothing.estimated_distance_evaluations), (SearchStrategy::BruteForce, 0));
+        assert!(nothing.to_string().starts_with("Filtered brute force\n"));
+
+        let unfiltered = QueryPlan::choose(&PlanInput { filtered: false, sampled: 0, accepted: 0, ..input(100, 0) });
+        assert_eq!((unfiltered.strategy, unfiltered.estimated_distance_evaluations), (SearchStrategy::Graph, 100));
+    }
+}
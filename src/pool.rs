@@ -0,0 +1,72 @@
+use crate::sync::Mutex;
+use crate::vector::VectorItem;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Vector storage shared by several indexes, e.g. one small index per user
+/// over a common set of items. An index built `with_vector_pool` stores the
+/// pool's copy of each item it links, so an item added to many indexes is
+/// held in memory once.
+///
+/// Items are matched by id and vector; an item whose id is pooled with a
+/// different vector is kept by its index alone.
+#[derive(Debug, Default)]
+pub struct VectorPool {
+    items: Mutex<HashMap<usize, Arc<VectorItem>>>,
+}
+
+impl VectorPool {
+    pub fn new() -> Self {
+        VectorPool::default()
+    }
+
+    /// The pooled copy of `item`, adding it if its id is not pooled yet.
+    pub fn intern(&self, item: VectorItem) -> Arc<VectorItem> {
+        let mut items = self.items.lock().unwrap();
+        match items.get(&item.id) {
+            Some(pooled) if pooled.vector == item.vector => Arc::clone(pooled),
+            Some(_) => Arc::new(item),
+            None => {
+                let pooled = Arc::new(item);
+                items.insert(pooled.id, Arc::clone(&pooled));
+                pooled
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops items no index references any more and returns how many.
+    pub fn purge(&self) -> usize {
+        let mut items = self.items.lock().unwrap();
+        let before = items.len();
+        items.retain(|_, item| Arc::strong_count(item) > 1);
+        before - items.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let pool = VectorPool::new();
+        let a = pool.intern(VectorItem { id: 1, vector: vec![1.0, 2.0] });
+        let b = pool.intern(VectorItem { id: 1, vector: vec![1.0, 2.0] });
+        let other = pool.intern(VectorItem { id: 1, vector: vec![3.0, 4.0] });
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &other));
+        assert_eq!(pool.len(), 1);
+
+        drop((a, b));
+        assert_eq!(pool.purge(), 1);
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! Python bindings, built with `maturin build --features python`. The
+//! `Index` class follows the hnswlib Python API, so most scripts only need
+//! `import hnsw_rust as hnswlib`. Only the `l2` space exists, and distances
+//! are squared like hnswlib's. Replacing deleted items is not supported.
+
+use crate::hnsw::EF_SEARCH;
+use crate::{EuclideanDistance, HnswIndex, VectorItem};
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::{PyNotImplementedError, PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+
+// `(labels, distances)`, as hnswlib's knn_query returns them
+type KnnResult<'py> = (Bound<'py, PyArray2<u64>>, Bound<'py, PyArray2<f32>>);
+
+#[pyclass(name = "Index", module = "hnsw_rust")]
+struct PyIndex {
+    #[pyo3(get)]
+    space: String,
+    #[pyo3(get)]
+    dim: usize,
+    #[pyo3(get)]
+    ef: usize,
+    index: Option<HnswIndex>,
+}
+
+#[pymethods]
+impl PyIndex {
+    #[new]
+    fn new(space: &str, dim: usize) -> PyResult<Self> {
+        if space != "l2" {
+            return Err(PyValueError::new_err(format!("Unsupported space {:?}; only \"l2\" is available", space)));
+        }
+        Ok(PyIndex { space: space.to_string(), dim, ef: EF_SEARCH, index: None })
+    }
+
+    #[pyo3(signature = (max_elements, M = 16, ef_construction = 200, random_seed = 100, allow_replace_deleted = false))]
+    #[allow(non_snake_case)]
+    fn init_index(&mut self, max_elements: usize, M: usize, ef_construction: usize, random_seed: u64, allow_replace_deleted: bool) -> PyResult<()> {
+        if allow_replace_deleted {
+            return Err(PyNotImplementedError::new_err("allow_replace_deleted is not supported"));
+        }
+        let index = HnswIndex::new(Box::new(EuclideanDistance))
+            .with_dimension(self.dim)
+            .with_seed(random_seed)
+            .with_m(M)
+            .with_ef_construction(ef_construction)
+            .with_ef_search(self.ef)
+            .with_capacity(max_elements);
+        self.index = Some(index);
+        Ok(())
+    }
+
+    fn load_index(&mut self, path: &str) -> PyResult<()> {
+        let index = HnswIndex::load(path).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        if let Some(dim) = index.iter().next().map(|item| item.vector.len()) {
+            if dim != self.dim {
+                return Err(PyValueError::new_err(format!("Index has {} dimensions, expected {}", dim, self.dim)));
+            }
+        }
+        self.ef = index.config().ef_search;
+        self.index = Some(index);
+        Ok(())
+    }
+
+    fn save_index(&self, path: &str) -> PyResult<()> {
+        self.index()?.save(path).map_err(|e| PyRuntimeError::new_err(e.to_string()))
+    }
+
+    fn set_ef(&mut self, ef: usize) {
+        self.ef = ef;
+        self.index = self.index.take().map(|index| index.with_ef_search(ef));
+    }
+
+    /// `ids` defaults to consecutive ids after the current count.
+    #[pyo3(signature = (data, ids = None, num_threads = -1, replace_deleted = false))]
+    fn add_items(&self, py: Python<'_>, data: &Bound<'_, PyAny>, ids: Option<&Bound<'_, PyAny>>, num_threads: i32, replace_deleted: bool) -> PyResult<()> {
+        if replace_deleted {
+            return Err(PyNotImplementedError::new_err("replace_deleted is not supported"));
+        }
+        let index = self.index()?;
+        let vectors = self.rows(data)?;
+        let ids = match ids {
+            Some(ids) => ids.extract::<Vec<usize>>().or_else(|_| ids.extract::<usize>().map(|id| vec![id]))?,
+            None => (index.len()..index.len() + vectors.len()).collect(),
+        };
+        if ids.len() != vectors.len() {
+            return Err(PyValueError::new_err(format!("{} ids for {} vectors", ids.len(), vectors.len())));
+        }
+        let items = ids.into_iter().zip(vectors).map(|(id, vector)| VectorItem { id, vector }).collect();
+        py.detach(|| on_threads(num_threads, || index.par_batch_add(items)))?.map_err(PyRuntimeError::new_err)
+    }
+
+    /// Returns `(labels, distances)` arrays of shape `(len(data), k)`.
+    #[pyo3(signature = (data, k = 1, num_threads = -1, filter = None))]
+    fn knn_query<'py>(
+        &self,
+        py: Python<'py>,
+        data: &Bound<'py, PyAny>,
+        k: usize,
+        num_threads: i32,
+        filter: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<KnnResult<'py>> {
+        if filter.is_some() {
+            return Err(PyTypeError::new_err("filter is not supported"));
+        }
+        let index = self.index()?;
+        let queries: Vec<_> = self.rows(data)?.into_iter().map(|vector| VectorItem { id: 0, vector }).collect();
+        let search = || queries.par_iter().map(|query| index.search_ids(query, k)).collect::<Result<Vec<_>, _>>();
+        let results = py.detach(|| on_threads(num_threads, search))?.map_err(PyRuntimeError::new_err)?;
+
+        let mut labels = Vec::with_capacity(queries.len() * k);
+        let mut distances = Vec::with_capacity(queries.len() * k);
+        for hits in &results {
+            if hits.len() < k {
+                return Err(PyRuntimeError::new_err(
+                    "Cannot return the results in a contiguous 2D array. Probably ef or M is too small",
+                ));
+            }
+            for &(id, distance) in hits {
+                labels.push(id as u64);
+                distances.push(distance.powi(2) as f32);
+            }
+        }
+        let shape = (queries.len(), k);
+        let labels = Array2::from_shape_vec(shape, labels).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        let distances = Array2::from_shape_vec(shape, distances).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok((labels.into_pyarray(py), distances.into_pyarray(py)))
+    }
+
+    fn get_items<'py>(&self, py: Python<'py>, ids: Vec<usize>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let index = self.index()?;
+        let mut values = Vec::with_capacity(ids.len() * self.dim);
+        for (id, item) in ids.iter().zip(index.get_many(&ids)) {
+            let item = item.ok_or_else(|| PyRuntimeError::new_err(format!("Label {} not found", id)))?;
+            values.extend(item.vector.iter().map(|&x| x as f32));
+        }
+        let values = Array2::from_shape_vec((ids.len(), self.dim), values).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(values.into_pyarray(py))
+    }
+
+    fn get_ids_list(&self) -> PyResult<Vec<usize>> {
+        Ok(self.index()?.ids().collect())
+    }
+
+    fn get_current_count(&self) -> usize {
+        self.index.as_ref().map_or(0, HnswIndex::len)
+    }
+
+    #[getter]
+    fn element_count(&self) -> usize {
+        self.get_current_count()
+    }
+
+    fn __len__(&self) -> usize {
+        self.get_current_count()
+    }
+}
+
+impl PyIndex {
+    fn index(&self) -> PyResult<&HnswIndex> {
+        self.index.as_ref().ok_or_else(|| PyRuntimeError::new_err("Index is not initialized; call init_index or load_index"))
+    }
+
+    // One vector per row of a 2-D float32/float64 array or nested list, or a
+    // single 1-D vector
+    fn rows(&self, data: &Bound<'_, PyAny>) -> PyResult<Vec<Vec<f64>>> {
+        let rows: Vec<Vec<f64>> = if let Ok(array) = data.extract::<PyReadonlyArray2<f32>>() {
+            array.as_array().rows().into_iter().map(|row| row.iter().map(|&x| x as f64).collect()).collect()
+        } else if let Ok(array) = data.extract::<PyReadonlyArray2<f64>>() {
+            array.as_array().rows().into_iter().map(|row| row.to_vec()).collect()
+        } else if let Ok(array) = data.extract::<PyReadonlyArray1<f32>>() {
+            vec![array.as_array().iter().map(|&x| x as f64).collect()]
+        } else if let Ok(array) = data.extract::<PyReadonlyArray1<f64>>() {
+            vec![array.as_array().to_vec()]
+        } else if let Ok(rows) = data.extract::<Vec<Vec<f64>>>() {
+            rows
+        } else {
+            return Err(PyTypeError::new_err("Expected a float32 or float64 numpy array"));
+        };
+        match rows.iter().find(|row| row.len() != self.dim) {
+            Some(row) => Err(PyValueError::new_err(format!("Wrong dimensionality of the vectors: {}, expected {}", row.len(), self.dim))),
+            None => Ok(rows),
+        }
+    }
+}
+
+// Runs `f` on a pool of `num_threads` threads, or on rayon's global pool if
+// it is not positive, as hnswlib's -1 asks for every core.
+fn on_threads<T: Send>(num_threads: i32, f: impl FnOnce() -> T + Send) -> PyResult<T> {
+    match usize::try_from(num_threads) {
+        Ok(threads) if threads > 0 => {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+            Ok(pool.install(f))
+        }
+        _ => Ok(f()),
+    }
+}
+
+#[pymodule]
+fn hnsw_rust(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyIndex>()
+}
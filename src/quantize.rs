@@ -0,0 +1,545 @@
+use crate::vector::DistanceCalculator;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Compressed vector storage; see `HnswIndex::with_quantization`. Graph
+/// traversal compares queries against the compressed codes instead of the
+/// full vectors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Quantization {
+    /// One byte per component. With `rerank`, the full vectors are kept and
+    /// used to rescore the final candidates; without it they are dropped,
+    /// cutting vector memory 8x, and every distance is approximate.
+    Sq8 { quantizer: ScalarQuantizer, rerank: bool },
+    /// One byte per subspace; see `train_pq`. Vectors shrink to `m` bytes
+    /// without `rerank`, at a larger loss of accuracy than SQ8.
+    Pq { quantizer: ProductQuantizer, rerank: bool },
+    /// One bit per component, compared by Hamming distance during traversal.
+    /// The full vectors are always kept and the candidates reranked with
+    /// them, so results are exact up to what the traversal missed.
+    Binary(BinaryQuantizer),
+    /// Half-precision floats, two bytes per component, widened back for
+    /// every distance. The full vectors are dropped; for normalized
+    /// embeddings the loss of accuracy is negligible.
+    #[cfg(feature = "f16")]
+    F16,
+}
+
+impl Quantization {
+    /// Whether the full vectors are stored next to the codes.
+    pub fn keeps_full_vectors(&self) -> bool {
+        match self {
+            Quantization::Sq8 { rerank, .. } => *rerank,
+            Quantization::Pq { rerank, .. } => *rerank,
+            Quantization::Binary(_) => true,
+            #[cfg(feature = "f16")]
+            Quantization::F16 => false,
+        }
+    }
+
+    /// Short name of the code format: `sq8`, `pq`, `binary` or `f16`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantization::Sq8 { .. } => "sq8",
+            Quantization::Pq { .. } => "pq",
+            Quantization::Binary(_) => "binary",
+            #[cfg(feature = "f16")]
+            Quantization::F16 => "f16",
+        }
+    }
+
+    pub(crate) fn encode(&self, vector: &[f64]) -> Vec<u8> {
+        match self {
+            Quantization::Sq8 { quantizer, .. } => quantizer.encode(vector),
+            Quantization::Pq { quantizer, .. } => quantizer.encode(vector),
+            Quantization::Binary(quantizer) => quantizer.encode(vector),
+            #[cfg(feature = "f16")]
+            Quantization::F16 => vector.iter().flat_map(|&x| half::f16::from_f64(x).to_le_bytes()).collect(),
+        }
+    }
+
+    pub(crate) fn decode(&self, codes: &[u8]) -> Vec<f64> {
+        match self {
+            Quantization::Sq8 { quantizer, .. } => quantizer.decode(codes),
+            Quantization::Pq { quantizer, .. } => quantizer.decode(codes),
+            Quantization::Binary(quantizer) => quantizer.decode(codes),
+            #[cfg(feature = "f16")]
+            Quantization::F16 => codes.chunks_exact(2).map(|b| half::f16::from_le_bytes([b[0], b[1]]).to_f64()).collect(),
+        }
+    }
+
+    /// The vector dimension the codes are fixed to, if any.
+    pub fn trained_dimension(&self) -> Option<usize> {
+        match self {
+            Quantization::Sq8 { quantizer, .. } => quantizer.trained_dimension(),
+            Quantization::Pq { quantizer, .. } => Some(quantizer.dimension),
+            Quantization::Binary(quantizer) => quantizer.trained_dimension(),
+            #[cfg(feature = "f16")]
+            Quantization::F16 => None,
+        }
+    }
+
+    /// The dimension of the vector behind `codes`, rounded up to whole bytes
+    /// for binary codes.
+    pub(crate) fn dimension(&self, codes: &[u8]) -> usize {
+        match self {
+            Quantization::Sq8 { quantizer, .. } => quantizer.dimension(codes),
+            Quantization::Pq { quantizer, .. } => quantizer.dimension,
+            Quantization::Binary(_) => codes.len() * 8,
+            #[cfg(feature = "f16")]
+            Quantization::F16 => codes.len() / 2,
+        }
+    }
+
+    /// What `query` brings to `distance` under `calculator`, computed once
+    /// per search.
+    pub(crate) fn prepare_query(&self, calculator: &dyn DistanceCalculator, query: &[f64]) -> PreparedQuery {
+        match self {
+            Quantization::Binary(quantizer) => PreparedQuery::Bits(quantizer.encode(query)),
+            _ if !calculator.is_euclidean() => PreparedQuery::Decode,
+            Quantization::Sq8 { quantizer, .. } => PreparedQuery::Residuals(quantizer.residuals(query)),
+            Quantization::Pq { quantizer, .. } => quantizer.table(query),
+            #[cfg(feature = "f16")]
+            Quantization::F16 => PreparedQuery::Direct,
+        }
+    }
+
+    /// The traversal distance from `query` to the stored `codes`. Under a
+    /// Euclidean metric it is computed from the codes without decoding them.
+    pub(crate) fn distance(&self, calculator: &dyn DistanceCalculator, query: &[f64], prepared: &PreparedQuery, codes: &[u8]) -> f64 {
+        match (self, prepared) {
+            (_, PreparedQuery::Bits(bits)) => hamming(bits, codes) as f64,
+            (Quantization::Sq8 { quantizer, .. }, PreparedQuery::Residuals(residuals)) => {
+                quantizer.squared_distance(query, residuals, codes).sqrt()
+            }
+            (Quantization::Pq { .. }, PreparedQuery::Table { distances, stride }) => codes
+                .iter()
+                .enumerate()
+                .map(|(subspace, &code)| distances[subspace * stride + code as usize])
+                .sum::<f64>()
+                .sqrt(),
+            #[cfg(feature = "f16")]
+            (Quantization::F16, PreparedQuery::Direct) => query
+                .iter()
+                .zip(codes.chunks_exact(2))
+                .map(|(x, b)| (x - half::f16::from_le_bytes([b[0], b[1]]).to_f64()).powi(2))
+                .sum::<f64>()
+                .sqrt(),
+            _ => calculator.distance(query, &self.decode(codes)),
+        }
+    }
+}
+
+/// What a query brings to `Quantization::distance`.
+#[derive(Clone, Debug)]
+pub(crate) enum PreparedQuery {
+    /// The query's own binary codes, compared by Hamming distance.
+    Bits(Vec<u8>),
+    /// SQ8 under a Euclidean metric: the query minus each trained
+    /// dimension's offset, or empty when the offsets are per vector.
+    Residuals(Vec<f64>),
+    /// PQ under a Euclidean metric: the squared distance from each query
+    /// subvector to every centroid of its subspace, at
+    /// `subspace * stride + code` (asymmetric distance computation).
+    Table { distances: Vec<f64>, stride: usize },
+    /// F16 under a Euclidean metric: codes are widened as they are read.
+    #[cfg(feature = "f16")]
+    Direct,
+    /// Any other metric: codes are decoded and handed to it.
+    Decode,
+}
+
+/// Maps each component linearly onto 0..=255. Per-dimension ranges are
+/// learned from a sample with `train`, and components outside them are
+/// clamped. `per_vector` instead stores each vector's own range with its
+/// codes (16 bytes more per vector), which needs no training.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScalarQuantizer {
+    // (offset, step) per dimension; empty for per-vector ranges
+    ranges: Vec<(f64, f64)>,
+}
+
+// Size of the per-vector range stored in front of the codes
+const RANGE_BYTES: usize = 16;
+
+impl ScalarQuantizer {
+    /// Learns each dimension's range from `sample`.
+    pub fn train(sample: &[Vec<f64>]) -> Result<Self, String> {
+        let dimension = match sample.first() {
+            Some(first) if !first.is_empty() => first.len(),
+            _ => return Err("Cannot train a quantizer on an empty sample".to_string()),
+        };
+        if let Some(vector) = sample.iter().find(|vector| vector.len() != dimension) {
+            return Err(format!("Sample vector has {} dimensions, expected {}", vector.len(), dimension));
+        }
+        let ranges = (0..dimension)
+            .map(|dim| {
+                let (min, max) = sample
+                    .iter()
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v[dim]), max.max(v[dim])));
+                (min, (max - min) / 255.0)
+            })
+            .collect();
+        Ok(ScalarQuantizer { ranges })
+    }
+
+    /// Quantizes every vector against its own minimum and maximum.
+    pub fn per_vector() -> Self {
+        ScalarQuantizer { ranges: Vec::new() }
+    }
+
+    fn encode(&self, vector: &[f64]) -> Vec<u8> {
+        if self.ranges.is_empty() {
+            let (min, max) = vector
+                .iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &x| (min.min(x), max.max(x)));
+            let (offset, step) = if vector.is_empty() { (0.0, 0.0) } else { (min, (max - min) / 255.0) };
+            let mut codes = Vec::with_capacity(RANGE_BYTES + vector.len());
+            codes.extend_from_slice(&offset.to_le_bytes());
+            codes.extend_from_slice(&step.to_le_bytes());
+            codes.extend(vector.iter().map(|&x| quantize(x, offset, step)));
+            codes
+        } else {
+            vector.iter().zip(&self.ranges).map(|(&x, &(offset, step))| quantize(x, offset, step)).collect()
+        }
+    }
+
+    fn decode(&self, codes: &[u8]) -> Vec<f64> {
+        if self.ranges.is_empty() {
+            let offset = f64::from_le_bytes(codes[..8].try_into().unwrap());
+            let step = f64::from_le_bytes(codes[8..RANGE_BYTES].try_into().unwrap());
+            codes[RANGE_BYTES..].iter().map(|&code| offset + code as f64 * step).collect()
+        } else {
+            codes.iter().zip(&self.ranges).map(|(&code, &(offset, step))| offset + code as f64 * step).collect()
+        }
+    }
+
+    fn residuals(&self, query: &[f64]) -> Vec<f64> {
+        query.iter().zip(&self.ranges).map(|(x, &(offset, _))| x - offset).collect()
+    }
+
+    // The squared Euclidean distance from `query` to the vector behind
+    // `codes`, given its `residuals`.
+    fn squared_distance(&self, query: &[f64], residuals: &[f64], codes: &[u8]) -> f64 {
+        if self.ranges.is_empty() {
+            let offset = f64::from_le_bytes(codes[..8].try_into().unwrap());
+            let step = f64::from_le_bytes(codes[8..RANGE_BYTES].try_into().unwrap());
+            query.iter().zip(&codes[RANGE_BYTES..]).map(|(x, &code)| (x - offset - code as f64 * step).powi(2)).sum()
+        } else {
+            residuals
+                .iter()
+                .zip(codes)
+                .zip(&self.ranges)
+                .map(|((r, &code), &(_, step))| (r - code as f64 * step).powi(2))
+                .sum()
+        }
+    }
+
+    fn dimension(&self, codes: &[u8]) -> usize {
+        if self.ranges.is_empty() {
+            codes.len() - RANGE_BYTES
+        } else {
+            codes.len()
+        }
+    }
+
+    /// The number of dimensions it was trained on, or `None` for per-vector
+    /// ranges.
+    pub fn trained_dimension(&self) -> Option<usize> {
+        (!self.ranges.is_empty()).then_some(self.ranges.len())
+    }
+}
+
+fn quantize(x: f64, offset: f64, step: f64) -> u8 {
+    if step > 0.0 {
+        ((x - offset) / step).round().clamp(0.0, 255.0) as u8
+    } else {
+        0
+    }
+}
+
+/// Splits vectors into `m` equal subspaces and stores each as the index of
+/// its nearest centroid in that subspace's codebook. Distances are
+/// asymmetric: queries stay at full precision and are compared against the
+/// centroids a stored vector is made of.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProductQuantizer {
+    dimension: usize,
+    // [subspace][centroid] -> components of that subspace
+    codebooks: Vec<Vec<Vec<f64>>>,
+}
+
+// Lloyd iterations per subspace codebook
+const KMEANS_ITERATIONS: usize = 25;
+
+/// Trains a product quantizer with `m` subspaces of `2^nbits` centroids each
+/// (fewer if the sample has fewer distinct points) by k-means over `sample`. The dimension
+/// must be a multiple of `m`, and `nbits` at most 8. Training is seeded, so
+/// the same sample gives the same codebooks.
+pub fn train_pq(sample: &[Vec<f64>], m: usize, nbits: u32) -> Result<ProductQuantizer, String> {
+    let dimension = match sample.first() {
+        Some(first) if !first.is_empty() => first.len(),
+        _ => return Err("Cannot train a quantizer on an empty sample".to_string()),
+    };
+    if let Some(vector) = sample.iter().find(|vector| vector.len() != dimension) {
+        return Err(format!("Sample vector has {} dimensions, expected {}", vector.len(), dimension));
+    }
+    if m == 0 || dimension % m != 0 {
+        return Err(format!("Cannot split {} dimensions into {} subspaces", dimension, m));
+    }
+    if !(1..=8).contains(&nbits) {
+        return Err(format!("nbits must be between 1 and 8, got {}", nbits));
+    }
+
+    let width = dimension / m;
+    let centroids = (1usize << nbits).min(sample.len());
+    let mut rng = StdRng::seed_from_u64(0);
+    let codebooks = (0..m)
+        .map(|subspace| {
+            let points: Vec<&[f64]> = sample.iter().map(|v| &v[subspace * width..(subspace + 1) * width]).collect();
+            kmeans(&points, centroids, &mut rng)
+        })
+        .collect();
+    Ok(ProductQuantizer { dimension, codebooks })
+}
+
+fn kmeans(points: &[&[f64]], k: usize, rng: &mut StdRng) -> Vec<Vec<f64>> {
+    // k-means++ seeding: each further centroid is drawn with probability
+    // proportional to its squared distance from the nearest one so far
+    let mut centroids: Vec<Vec<f64>> = points.choose(rng).map(|p| p.to_vec()).into_iter().collect();
+    while centroids.len() < k {
+        let weights: Vec<f64> = points.iter().map(|p| squared_distance(&centroids[nearest(&centroids, p)], p)).collect();
+        let next = match WeightedIndex::new(&weights) {
+            Ok(weights) => points[weights.sample(rng)],
+            // Every point coincides with a centroid already
+            Err(_) => break,
+        };
+        centroids.push(next.to_vec());
+    }
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![vec![0.0; points[0].len()]; centroids.len()];
+        let mut counts = vec![0usize; centroids.len()];
+        for point in points {
+            let nearest = nearest(&centroids, point);
+            counts[nearest] += 1;
+            sums[nearest].iter_mut().zip(point.iter()).for_each(|(sum, x)| *sum += x);
+        }
+        // An empty cluster keeps its previous centroid
+        for ((centroid, sum), &count) in centroids.iter_mut().zip(sums).zip(&counts) {
+            if count > 0 {
+                *centroid = sum.into_iter().map(|x| x / count as f64).collect();
+            }
+        }
+    }
+    centroids
+}
+
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn nearest(centroids: &[Vec<f64>], point: &[f64]) -> usize {
+    (0..centroids.len())
+        .min_by(|&a, &b| squared_distance(&centroids[a], point).total_cmp(&squared_distance(&centroids[b], point)))
+        .unwrap_or(0)
+}
+
+impl ProductQuantizer {
+    /// The number of subspaces, which is also the code size in bytes.
+    pub fn subspaces(&self) -> usize {
+        self.codebooks.len()
+    }
+
+    fn encode(&self, vector: &[f64]) -> Vec<u8> {
+        let width = self.dimension / self.codebooks.len();
+        self.codebooks
+            .iter()
+            .zip(vector.chunks(width))
+            .map(|(codebook, part)| nearest(codebook, part) as u8)
+            .collect()
+    }
+
+    // The distance table for asymmetric distance computation.
+    fn table(&self, query: &[f64]) -> PreparedQuery {
+        let width = self.dimension / self.codebooks.len();
+        let stride = self.codebooks.iter().map(Vec::len).max().unwrap_or(0);
+        let mut distances = vec![f64::INFINITY; self.codebooks.len() * stride];
+        for (subspace, (codebook, part)) in self.codebooks.iter().zip(query.chunks(width)).enumerate() {
+            for (code, centroid) in codebook.iter().enumerate() {
+                distances[subspace * stride + code] = squared_distance(centroid, part);
+            }
+        }
+        PreparedQuery::Table { distances, stride }
+    }
+
+    fn decode(&self, codes: &[u8]) -> Vec<f64> {
+        codes
+            .iter()
+            .zip(&self.codebooks)
+            .flat_map(|(&code, codebook)| codebook[code as usize].iter().copied())
+            .collect()
+    }
+}
+
+/// Keeps one bit per component: whether it is above that dimension's
+/// threshold. `sign` uses zero, which suits centred embeddings; `train`
+/// uses each dimension's mean over a sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BinaryQuantizer {
+    // Empty for `sign`
+    thresholds: Vec<f64>,
+}
+
+impl BinaryQuantizer {
+    /// Thresholds every component at zero.
+    pub fn sign() -> Self {
+        BinaryQuantizer { thresholds: Vec::new() }
+    }
+
+    /// Thresholds each dimension at its mean over `sample`.
+    pub fn train(sample: &[Vec<f64>]) -> Result<Self, String> {
+        let dimension = match sample.first() {
+            Some(first) if !first.is_empty() => first.len(),
+            _ => return Err("Cannot train a quantizer on an empty sample".to_string()),
+        };
+        if let Some(vector) = sample.iter().find(|vector| vector.len() != dimension) {
+            return Err(format!("Sample vector has {} dimensions, expected {}", vector.len(), dimension));
+        }
+        let thresholds = (0..dimension)
+            .map(|dim| sample.iter().map(|v| v[dim]).sum::<f64>() / sample.len() as f64)
+            .collect();
+        Ok(BinaryQuantizer { thresholds })
+    }
+
+    /// The number of dimensions it was trained on, or `None` for `sign`.
+    pub fn trained_dimension(&self) -> Option<usize> {
+        (!self.thresholds.is_empty()).then_some(self.thresholds.len())
+    }
+
+    fn encode(&self, vector: &[f64]) -> Vec<u8> {
+        let threshold = |dim: usize| self.thresholds.get(dim).copied().unwrap_or(0.0);
+        vector
+            .chunks(8)
+            .enumerate()
+            .map(|(byte, bits)| {
+                bits.iter()
+                    .enumerate()
+                    .filter(|&(bit, &x)| x > threshold(byte * 8 + bit))
+                    .fold(0u8, |code, (bit, _)| code | 1 << bit)
+            })
+            .collect()
+    }
+
+    // The sketch as 0/1 components, padded to whole bytes
+    fn decode(&self, codes: &[u8]) -> Vec<f64> {
+        codes.iter().flat_map(|&code| (0..8).map(move |bit| (code >> bit & 1) as f64)).collect()
+    }
+}
+
+fn hamming(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_error(quantizer: &ScalarQuantizer, vector: &[f64]) -> f64 {
+        let decoded = quantizer.decode(&quantizer.encode(vector));
+        vector.iter().zip(&decoded).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_scalar_quantizer() {
+        let sample = vec![vec![0.0, -1.0, 5.0], vec![1.0, 1.0, 5.0], vec![0.5, 0.0, 5.0]];
+        let trained = ScalarQuantizer::train(&sample).unwrap();
+        assert_eq!(trained.trained_dimension(), Some(3));
+        assert_eq!(trained.encode(&[1.0, -1.0, 5.0]), vec![255, 0, 0]);
+        assert!(max_error(&trained, &[0.3, 0.7, 5.0]) <= 1.0 / 255.0);
+        // Out-of-range components are clamped
+        assert_eq!(trained.decode(&trained.encode(&[2.0, 0.0, 5.0]))[0], 1.0);
+
+        let per_vector = ScalarQuantizer::per_vector();
+        let codes = per_vector.encode(&[10.0, 20.0, 15.0]);
+        assert_eq!(per_vector.dimension(&codes), 3);
+        assert!(max_error(&per_vector, &[10.0, 20.0, 15.0]) <= 10.0 / 255.0);
+
+        assert!(ScalarQuantizer::train(&[]).is_err());
+        assert!(ScalarQuantizer::train(&[vec![1.0], vec![1.0, 2.0]]).is_err());
+    }
+
+    #[test]
+    fn test_product_quantizer() {
+        // Four well separated clusters per subspace
+        let corners = [[0.0, 0.0], [0.0, 10.0], [10.0, 0.0], [10.0, 10.0]];
+        let sample: Vec<Vec<f64>> = (0..64)
+            .map(|i| {
+                let (a, b) = (corners[i % 4], corners[i / 4 % 4]);
+                let jitter = (i % 3) as f64 * 0.01;
+                vec![a[0] + jitter, a[1], b[0], b[1] - jitter]
+            })
+            .collect();
+        let pq = train_pq(&sample, 2, 2).unwrap();
+        assert_eq!(pq.subspaces(), 2);
+        for vector in &sample {
+            let codes = pq.encode(vector);
+            assert_eq!(codes.len(), 2);
+            let decoded = pq.decode(&codes);
+            assert!(vector.iter().zip(&decoded).all(|(a, b)| (a - b).abs() < 0.05));
+        }
+        assert_eq!(train_pq(&sample, 2, 2), Ok(pq));
+
+        assert!(train_pq(&sample, 3, 2).is_err());
+        assert!(train_pq(&sample, 2, 9).is_err());
+        assert!(train_pq(&[], 2, 2).is_err());
+    }
+
+    #[test]
+    fn test_distance_from_codes() {
+        use crate::vector::{AngularDistance, EuclideanDistance};
+
+        let sample: Vec<Vec<f64>> = (0..40).map(|i| (0..4).map(|d| ((i * 7 + d * 3) % 11) as f64).collect()).collect();
+        let query = [1.5, 9.0, 4.2, 0.3];
+        let quantizations = [
+            Quantization::Sq8 { quantizer: ScalarQuantizer::train(&sample).unwrap(), rerank: false },
+            Quantization::Sq8 { quantizer: ScalarQuantizer::per_vector(), rerank: false },
+            Quantization::Pq { quantizer: train_pq(&sample, 2, 3).unwrap(), rerank: false },
+        ];
+        for quantization in &quantizations {
+            let prepared = quantization.prepare_query(&EuclideanDistance, &query);
+            assert!(!matches!(prepared, PreparedQuery::Decode));
+            for vector in &sample {
+                let codes = quantization.encode(vector);
+                let decoded = EuclideanDistance.distance(&query, &quantization.decode(&codes));
+                let direct = quantization.distance(&EuclideanDistance, &query, &prepared, &codes);
+                assert!((direct - decoded).abs() < 1e-9, "{}: {} vs {}", quantization.name(), direct, decoded);
+            }
+            // Other metrics still see the decoded vectors
+            assert!(matches!(quantization.prepare_query(&AngularDistance, &query), PreparedQuery::Decode));
+        }
+    }
+
+    #[test]
+    fn test_binary_quantizer() {
+        let sign = BinaryQuantizer::sign();
+        let codes = sign.encode(&[0.5, -1.0, 2.0, 0.0, 1.0, 1.0, 1.0, 1.0, -3.0, 4.0]);
+        assert_eq!(codes, vec![0b1111_0101, 0b10]);
+        assert_eq!(hamming(&codes, &sign.encode(&[-0.5; 10])), 7);
+
+        let trained = BinaryQuantizer::train(&[vec![10.0, 0.0], vec![20.0, 2.0]]).unwrap();
+        assert_eq!(trained.trained_dimension(), Some(2));
+        assert_eq!(trained.encode(&[16.0, 0.5]), vec![0b01]);
+    }
+
+    #[cfg(feature = "f16")]
+    #[test]
+    fn test_f16() {
+        let vector = [0.5, -1.25, 0.1, 65504.0];
+        let codes = Quantization::F16.encode(&vector);
+        assert_eq!((codes.len(), Quantization::F16.dimension(&codes)), (8, 4));
+        let decoded = Quantization::F16.decode(&codes);
+        assert_eq!(decoded[..2], [0.5, -1.25]);
+        assert!((decoded[2] - 0.1).abs() < 1e-4);
+        assert_eq!(decoded[3], 65504.0);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::sync::Mutex;
+use std::fmt;
+use std::time::Instant;
+
+/// Soft limits for one index. Until the index has namespaces, each tenant is
+/// expected to get its own index (and quota).
+#[derive(Debug)]
+pub struct Quota {
+    max_vectors: Option<usize>,
+    max_qps: Option<f64>,
+    bucket: Mutex<TokenBucket>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuotaError {
+    VectorLimit { limit: usize },
+    RateLimited { max_qps: f64 },
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaError::VectorLimit { limit } => write!(f, "Quota exceeded: index is limited to {} vectors", limit),
+            QuotaError::RateLimited { max_qps } => write!(f, "Quota exceeded: index is limited to {} queries per second", max_qps),
+        }
+    }
+}
+
+impl std::error::Error for QuotaError {}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota::new()
+    }
+}
+
+impl Quota {
+    /// A quota without limits.
+    pub fn new() -> Self {
+        Quota {
+            max_vectors: None,
+            max_qps: None,
+            bucket: Mutex::new(TokenBucket { tokens: 0.0, refilled: Instant::now() }),
+        }
+    }
+
+    pub fn with_max_vectors(mut self, max_vectors: usize) -> Self {
+        self.max_vectors = Some(max_vectors);
+        self
+    }
+
+    /// Limits queries with a token bucket refilled at `max_qps` per second,
+    /// allowing bursts of up to one second's worth of queries.
+    pub fn with_max_qps(mut self, max_qps: f64) -> Self {
+        self.max_qps = Some(max_qps);
+        self.bucket.get_mut().unwrap().tokens = max_qps.max(1.0);
+        self
+    }
+
+    pub fn max_vectors(&self) -> Option<usize> {
+        self.max_vectors
+    }
+
+    pub fn max_qps(&self) -> Option<f64> {
+        self.max_qps
+    }
+
+    /// Checks that `additional` more vectors fit next to `current` ones.
+    pub fn check_vectors(&self, current: usize, additional: usize) -> Result<(), QuotaError> {
+        match self.max_vectors {
+            Some(limit) if current.saturating_add(additional) > limit => Err(QuotaError::VectorLimit { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes `queries` tokens from the bucket, or fails without taking any.
+    pub fn acquire_queries(&self, queries: usize) -> Result<(), QuotaError> {
+        let max_qps = match self.max_qps {
+            Some(max_qps) => max_qps,
+            None => return Ok(()),
+        };
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * max_qps).min(max_qps.max(1.0));
+        bucket.refilled = now;
+
+        if bucket.tokens < queries as f64 {
+            return Err(QuotaError::RateLimited { max_qps });
+        }
+        bucket.tokens -= queries as f64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector_limit() {
+        let quota = Quota::new().with_max_vectors(10);
+        assert!(quota.check_vectors(9, 1).is_ok());
+        assert_eq!(quota.check_vectors(10, 1), Err(QuotaError::VectorLimit { limit: 10 }));
+        assert!(Quota::new().check_vectors(usize::MAX, 1).is_ok());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let quota = Quota::new().with_max_qps(5.0);
+        assert!(quota.acquire_queries(5).is_ok());
+        assert_eq!(quota.acquire_queries(5), Err(QuotaError::RateLimited { max_qps: 5.0 }));
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        assert!(quota.acquire_queries(1).is_ok());
+    }
+}
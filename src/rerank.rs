@@ -0,0 +1,173 @@
+use crate::vector::{DistanceCalculator, VectorItem};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A search result on its way through the rerankers.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub item: VectorItem,
+    pub payload: Option<serde_json::Value>,
+    /// Distance to the query under the search's metric.
+    pub distance: f64,
+    /// Higher is better. Starts as `-distance`; rerankers that rescore
+    /// candidates overwrite it.
+    pub score: f64,
+}
+
+/// The last stage of every search: reorders (and may drop) the candidates
+/// from the graph, which number at least `k`. The index returns the first
+/// `k` of the final order. Rerankers run in registration order.
+pub trait Reranker: Send + Sync {
+    fn rerank(&self, query: &VectorItem, candidates: Vec<Candidate>) -> Result<Vec<Candidate>, String>;
+}
+
+impl<F> Reranker for F
+where
+    F: Fn(&VectorItem, Vec<Candidate>) -> Result<Vec<Candidate>, String> + Send + Sync,
+{
+    fn rerank(&self, query: &VectorItem, candidates: Vec<Candidate>) -> Result<Vec<Candidate>, String> {
+        self(query, candidates)
+    }
+}
+
+fn sort_by_score(candidates: &mut [Candidate]) {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+}
+
+/// Rescores the candidates with another metric, e.g. a more expensive one
+/// than the index uses, and sorts them by it.
+pub struct ExactDistance {
+    calculator: Box<dyn DistanceCalculator + Send + Sync>,
+}
+
+impl ExactDistance {
+    pub fn new(calculator: Box<dyn DistanceCalculator + Send + Sync>) -> Self {
+        ExactDistance { calculator }
+    }
+}
+
+impl Reranker for ExactDistance {
+    fn rerank(&self, query: &VectorItem, mut candidates: Vec<Candidate>) -> Result<Vec<Candidate>, String> {
+        for candidate in &mut candidates {
+            candidate.distance = self.calculator.distance(&query.vector, &candidate.item.vector);
+            candidate.score = -candidate.distance;
+        }
+        sort_by_score(&mut candidates);
+        Ok(candidates)
+    }
+}
+
+/// Maximal marginal relevance: picks candidates one at a time, trading
+/// closeness to the query against distance from those already picked, so
+/// near-duplicates do not crowd the top results. `lambda = 1` keeps the
+/// distance order; lower values favor diversity.
+pub struct Mmr {
+    calculator: Box<dyn DistanceCalculator + Send + Sync>,
+    lambda: f64,
+}
+
+impl Mmr {
+    pub fn new(calculator: Box<dyn DistanceCalculator + Send + Sync>, lambda: f64) -> Self {
+        Mmr { calculator, lambda: lambda.clamp(0.0, 1.0) }
+    }
+}
+
+impl Reranker for Mmr {
+    fn rerank(&self, _query: &VectorItem, mut remaining: Vec<Candidate>) -> Result<Vec<Candidate>, String> {
+        let mut picked: Vec<Candidate> = Vec::with_capacity(remaining.len());
+        // Distance from each remaining candidate to its nearest picked one
+        let mut nearest_picked = vec![f64::INFINITY; remaining.len()];
+        while !remaining.is_empty() {
+            let first = picked.is_empty();
+            let score = |i: usize| mmr_score(self.lambda, remaining[i].distance, nearest_picked[i], first);
+            let best = (0..remaining.len()).max_by(|&a, &b| score(a).total_cmp(&score(b))).unwrap();
+            let best_score = score(best);
+            let mut candidate = remaining.swap_remove(best);
+            nearest_picked.swap_remove(best);
+            candidate.score = best_score;
+            for (other, nearest) in remaining.iter().zip(&mut nearest_picked) {
+                *nearest = nearest.min(self.calculator.distance(&candidate.item.vector, &other.item.vector));
+            }
+            picked.push(candidate);
+        }
+        Ok(picked)
+    }
+}
+
+// lambda * sim(query, d) - (1 - lambda) * max sim(d, picked), with similarity
+// taken as negative distance
+fn mmr_score(lambda: f64, distance: f64, nearest_picked: f64, first: bool) -> f64 {
+    let redundancy = if first { 0.0 } else { -nearest_picked };
+    -lambda * distance - (1.0 - lambda) * redundancy
+}
+
+/// Boosts recent items: the score is `1 / (1 + distance)` halved for every
+/// `half_life` of age. Ages come from a payload field holding a Unix
+/// timestamp in seconds; candidates without one sort last.
+pub struct Recency {
+    field: String,
+    half_life: Duration,
+}
+
+impl Recency {
+    pub fn new(field: impl Into<String>, half_life: Duration) -> Self {
+        Recency { field: field.into(), half_life }
+    }
+}
+
+impl Reranker for Recency {
+    fn rerank(&self, _query: &VectorItem, mut candidates: Vec<Candidate>) -> Result<Vec<Candidate>, String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.as_secs_f64();
+        for candidate in &mut candidates {
+            let timestamp = candidate.payload.as_ref().and_then(|payload| payload.get(&self.field)?.as_f64());
+            candidate.score = match timestamp {
+                Some(timestamp) => {
+                    let age = (now - timestamp).max(0.0);
+                    0.5f64.powf(age / self.half_life.as_secs_f64()) / (1.0 + candidate.distance)
+                }
+                None => f64::NEG_INFINITY,
+            };
+        }
+        sort_by_score(&mut candidates);
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::EuclideanDistance;
+
+    fn candidate(id: usize, x: f64, payload: Option<serde_json::Value>) -> Candidate {
+        Candidate { item: VectorItem { id, vector: vec![x] }, payload, distance: x.abs(), score: -x.abs() }
+    }
+
+    fn ids(candidates: &[Candidate]) -> Vec<usize> {
+        candidates.iter().map(|c| c.item.id).collect()
+    }
+
+    #[test]
+    fn test_builtin_rerankers() {
+        let query = VectorItem { id: 99, vector: vec![0.0] };
+        let candidates = vec![candidate(0, 1.0, None), candidate(1, 1.01, None), candidate(2, -1.5, None)];
+
+        // Id 1 duplicates id 0, so MMR moves the far side of the query up
+        let mmr = Mmr::new(Box::new(EuclideanDistance), 0.5).rerank(&query, candidates.clone()).unwrap();
+        assert_eq!(ids(&mmr), vec![0, 2, 1]);
+        let plain = Mmr::new(Box::new(EuclideanDistance), 1.0).rerank(&query, candidates.clone()).unwrap();
+        assert_eq!(ids(&plain), vec![0, 1, 2]);
+
+        let shifted = VectorItem { id: 99, vector: vec![-2.0] };
+        let exact = ExactDistance::new(Box::new(EuclideanDistance)).rerank(&shifted, candidates).unwrap();
+        assert_eq!(ids(&exact), vec![2, 0, 1]);
+        assert_eq!(exact[0].distance, 0.5);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64();
+        let dated = vec![
+            candidate(0, 1.0, Some(serde_json::json!({ "at": now - 7200.0 }))),
+            candidate(1, 2.0, Some(serde_json::json!({ "at": now }))),
+            candidate(2, 0.0, None),
+        ];
+        let recent = Recency::new("at", Duration::from_secs(600)).rerank(&query, dated).unwrap();
+        assert_eq!(ids(&recent), vec![1, 0, 2]);
+    }
+}
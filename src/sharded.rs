@@ -0,0 +1,173 @@
+use crate::hnsw::HnswIndex;
+use crate::transform::VectorTransform;
+use crate::vector::VectorItem;
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How `ShardedHnsw` picks the shard for a new id.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Partitioning {
+    /// A hash of the id, so an id always lands on the same shard.
+    #[default]
+    Hash,
+    /// Shards in turn, which keeps them the same size whatever the ids.
+    RoundRobin,
+}
+
+/// Several independent indexes searched as one. Inserts go to one shard
+/// each, so inserts into different shards never contend for a lock, and
+/// every search runs on all shards in parallel and merges their top `k`.
+/// Each shard is a full `HnswIndex`, so recall per shard is as usual; the
+/// shards together search more nodes than one graph would.
+pub struct ShardedHnsw {
+    shards: Vec<HnswIndex>,
+    partitioning: Partitioning,
+    next_shard: AtomicUsize,
+}
+
+impl ShardedHnsw {
+    /// `count` shards, shard `i` built by `make_shard(i)`. All shards should
+    /// share a metric and transform, since their distances are compared.
+    pub fn new(count: usize, make_shard: impl Fn(usize) -> HnswIndex) -> Result<Self, String> {
+        if count == 0 {
+            return Err("A sharded index needs at least one shard".to_string());
+        }
+        Ok(ShardedHnsw {
+            shards: (0..count).map(make_shard).collect(),
+            partitioning: Partitioning::default(),
+            next_shard: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn with_partitioning(mut self, partitioning: Partitioning) -> Self {
+        self.partitioning = partitioning;
+        self
+    }
+
+    pub fn partitioning(&self) -> Partitioning {
+        self.partitioning
+    }
+
+    pub fn shards(&self) -> &[HnswIndex] {
+        &self.shards
+    }
+
+    // The shard an id goes to. With round robin, an id already stored stays
+    // on its shard so updates replace it; two concurrent first inserts of
+    // the same id may still land on different shards.
+    fn shard_for(&self, id: usize) -> usize {
+        match self.partitioning {
+            Partitioning::Hash => {
+                let hash = (id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                ((hash >> 32) % self.shards.len() as u64) as usize
+            }
+            Partitioning::RoundRobin => match self.shards.iter().position(|shard| shard.contains(id)) {
+                Some(shard) => shard,
+                None => self.next_shard.fetch_add(1, Ordering::Relaxed) % self.shards.len(),
+            },
+        }
+    }
+
+    pub fn add(&self, item: VectorItem) -> Result<(), String> {
+        self.shards[self.shard_for(item.id)].add(item)
+    }
+
+    pub fn add_with_payload(&self, item: VectorItem, payload: serde_json::Value) -> Result<(), String> {
+        self.shards[self.shard_for(item.id)].add_with_payload(item, payload)
+    }
+
+    /// Splits `items` by shard and adds each part with `par_batch_add`, all
+    /// shards at once. A failing shard does not undo the others.
+    pub fn par_batch_add(&self, items: Vec<VectorItem>) -> Result<(), String> {
+        let mut parts = vec![Vec::new(); self.shards.len()];
+        for item in items {
+            parts[self.shard_for(item.id)].push(item);
+        }
+        self.shards
+            .par_iter()
+            .zip(parts)
+            .try_for_each(|(shard, part)| shard.par_batch_add(part))
+    }
+
+    pub fn remove(&self, id: usize) -> Result<bool, String> {
+        for shard in &self.shards {
+            if shard.remove(id)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.shards.iter().any(|shard| shard.contains(id))
+    }
+
+    pub fn get(&self, id: usize) -> Option<VectorItem> {
+        self.shards.iter().find_map(|shard| shard.get(id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(HnswIndex::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(HnswIndex::is_empty)
+    }
+
+    /// The `k` nearest items over all shards, nearest first, ties broken by
+    /// id.
+    pub fn search(&self, query: &VectorItem, k: usize) -> Result<Vec<VectorItem>, String> {
+        let per_shard = self
+            .shards
+            .par_iter()
+            .map(|shard| {
+                let prepared = VectorTransform::apply_to_item(shard.transform(), query)?;
+                let results = shard.search(query, k)?;
+                Ok(results.into_iter().map(|item| (shard.calculate_distances(&prepared, &item), item)).collect())
+            })
+            .collect::<Result<Vec<Vec<_>>, String>>()?;
+
+        let mut merged: Vec<(f64, VectorItem)> = per_shard.into_iter().flatten().collect();
+        merged.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.id.cmp(&b.1.id)));
+        Ok(merged.into_iter().take(k).map(|(_, item)| item).collect())
+    }
+
+    /// Runs `search` for every query, in query order.
+    pub fn batch_search(&self, queries: &[VectorItem], k: usize) -> Result<Vec<Vec<VectorItem>>, String> {
+        queries.par_iter().map(|query| self.search(query, k)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistance;
+
+    fn grid() -> Vec<VectorItem> {
+        (0..400).map(|i| VectorItem { id: i, vector: vec![(i % 20) as f64, (i / 20) as f64] }).collect()
+    }
+
+    #[test]
+    fn test_sharded_search() {
+        for partitioning in [Partitioning::Hash, Partitioning::RoundRobin] {
+            let sharded = ShardedHnsw::new(4, |i| HnswIndex::new(Box::new(EuclideanDistance)).with_seed(i as u64))
+                .unwrap()
+                .with_partitioning(partitioning);
+            sharded.par_batch_add(grid()).unwrap();
+            assert_eq!(sharded.len(), 400);
+            assert!(sharded.shards().iter().all(|shard| shard.len() > 50));
+
+            let query = VectorItem { id: 0, vector: vec![5.1, 5.0] };
+            let ids: Vec<usize> = sharded.search(&query, 3).unwrap().iter().map(|item| item.id).collect();
+            assert_eq!(ids, [105, 106, 85]);
+
+            // Updates stay on the shard holding the id
+            sharded.add(VectorItem { id: 105, vector: vec![50.0, 50.0] }).unwrap();
+            assert_eq!(sharded.len(), 400);
+            assert_eq!(sharded.get(105).unwrap().vector, [50.0, 50.0]);
+            assert!(sharded.remove(105).unwrap() && !sharded.contains(105));
+            assert_eq!(sharded.batch_search(&[query], 1).unwrap()[0][0].id, 106);
+        }
+        assert!(ShardedHnsw::new(0, |_| HnswIndex::new(Box::new(EuclideanDistance))).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use crate::node::Node;
+use std::collections::HashMap;
+use std::ops::Index;
+
+/// Nodes stored contiguously by dense slot (insertion order), with their
+/// adjacency lists in flat arrays (CSR-style) rather than a `Vec` per list.
+/// Links hold slots, so a search hop is an array index rather than a hash
+/// lookup or a pointer chase; external ids are only mapped at the API
+/// boundary.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NodeSlab {
+    nodes: Vec<Node>,
+    slots: HashMap<usize, usize>,
+    links: Links,
+}
+
+// One adjacency list: `len` slots at `targets[start..]`, with room for
+// `capacity`.
+#[derive(Clone, Copy, Debug, Default)]
+struct List {
+    start: usize,
+    len: usize,
+    capacity: usize,
+}
+
+// The adjacency lists of every slot. A node's lists, one per layer, are
+// consecutive in `lists` from `nodes[slot].0`; each list owns a range of
+// `targets`. A list that outgrows its range moves to the end of `targets`,
+// and a replaced or removed node's lists are abandoned. `compact` squeezes
+// out the gaps once they make up half the arrays.
+#[derive(Clone, Debug, Default)]
+struct Links {
+    // Per slot: (first list, layer count)
+    nodes: Vec<(usize, usize)>,
+    lists: Vec<List>,
+    targets: Vec<usize>,
+    dead_lists: usize,
+    dead_targets: usize,
+}
+
+impl Links {
+    // Stores `links` as new lists at the end, each with room for its
+    // `Vec`'s capacity, and returns where they start.
+    fn append(&mut self, links: &[Vec<usize>]) -> (usize, usize) {
+        let first = self.lists.len();
+        for list in links {
+            let start = self.targets.len();
+            self.targets.extend_from_slice(list);
+            self.targets.resize(start + list.capacity().max(list.len()), 0);
+            self.lists.push(List { start, len: list.len(), capacity: list.capacity().max(list.len()) });
+        }
+        (first, links.len())
+    }
+
+    fn abandon(&mut self, (first, layers): (usize, usize)) {
+        self.dead_lists += layers;
+        self.dead_targets += self.lists[first..first + layers].iter().map(|list| list.capacity).sum::<usize>();
+    }
+
+    fn list(&self, slot: usize, level: usize) -> Option<usize> {
+        let (first, layers) = *self.nodes.get(slot)?;
+        (level < layers).then_some(first + level)
+    }
+
+    fn get(&self, slot: usize, level: usize) -> &[usize] {
+        match self.list(slot, level) {
+            Some(list) => {
+                let List { start, len, .. } = self.lists[list];
+                &self.targets[start..start + len]
+            }
+            None => &[],
+        }
+    }
+
+    // Makes room for `len` targets in `list`, moving it to the end of
+    // `targets` (with at least double the room) if it has less.
+    fn reserve(&mut self, list: usize, len: usize) {
+        let List { start, len: used, capacity } = self.lists[list];
+        if len <= capacity {
+            return;
+        }
+        let moved = self.targets.len();
+        let grown = len.max(2 * capacity);
+        self.targets.extend_from_within(start..start + used);
+        self.targets.resize(moved + grown, 0);
+        self.lists[list] = List { start: moved, len: used, capacity: grown };
+        self.dead_targets += capacity;
+    }
+
+    // Rewrites the arrays in slot order without the abandoned space, once
+    // it makes up half of them.
+    fn compact(&mut self) {
+        if 2 * self.dead_targets <= self.targets.len() && 2 * self.dead_lists <= self.lists.len() {
+            return;
+        }
+        let mut lists = Vec::with_capacity(self.lists.len() - self.dead_lists);
+        let mut targets = Vec::with_capacity(self.targets.len() - self.dead_targets);
+        for (first, layers) in &mut self.nodes {
+            let old = *first;
+            *first = lists.len();
+            for list in &self.lists[old..old + *layers] {
+                let start = targets.len();
+                targets.extend_from_slice(&self.targets[list.start..list.start + list.len]);
+                targets.resize(start + list.capacity, 0);
+                lists.push(List { start, ..*list });
+            }
+        }
+        self.lists = lists;
+        self.targets = targets;
+        self.dead_lists = 0;
+        self.dead_targets = 0;
+    }
+}
+
+impl NodeSlab {
+    pub fn new() -> Self {
+        NodeSlab::default()
+    }
+
+    /// Builds a slab from nodes and their adjacency lists, one per layer;
+    /// slots are the positions in `nodes`.
+    pub fn from_nodes(nodes: Vec<(Node, Vec<Vec<usize>>)>) -> Result<Self, String> {
+        let mut slab = NodeSlab::new();
+        slab.reserve(nodes.len());
+        for (node, links) in nodes {
+            if slab.contains_id(node.id) {
+                return Err(format!("Duplicate node id {}", node.id));
+            }
+            slab.insert(node, links);
+        }
+        Ok(slab)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Node> {
+        self.nodes.get(slot)
+    }
+
+    pub fn get_mut(&mut self, slot: usize) -> Option<&mut Node> {
+        self.nodes.get_mut(slot)
+    }
+
+    pub fn slot_of(&self, id: usize) -> Option<usize> {
+        self.slots.get(&id).copied()
+    }
+
+    pub fn contains_id(&self, id: usize) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    /// The slot `insert` will use for a node with this id.
+    pub fn next_slot(&self, id: usize) -> usize {
+        self.slot_of(id).unwrap_or(self.nodes.len())
+    }
+
+    /// Stores `node` with its adjacency lists, one per layer, replacing any
+    /// node with the same id in place, and returns its slot. Each list keeps
+    /// room for its `Vec`'s capacity, so links can be added up to it without
+    /// moving the list.
+    pub fn insert(&mut self, node: Node, links: Vec<Vec<usize>>) -> usize {
+        let lists = self.links.append(&links);
+        let slot = match self.slot_of(node.id) {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                let old = std::mem::replace(&mut self.links.nodes[slot], lists);
+                self.links.abandon(old);
+                slot
+            }
+            None => {
+                let slot = self.nodes.len();
+                self.slots.insert(node.id, slot);
+                self.nodes.push(node);
+                self.links.nodes.push(lists);
+                slot
+            }
+        };
+        self.links.compact();
+        slot
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+        self.slots.reserve(additional);
+        self.links.nodes.reserve(additional);
+    }
+
+    /// The number of adjacency lists (layers) the node in `slot` has.
+    pub fn layers(&self, slot: usize) -> usize {
+        self.links.nodes.get(slot).map_or(0, |&(_, layers)| layers)
+    }
+
+    /// The links of `slot` on `level`; empty if the node has no such layer.
+    pub fn links(&self, slot: usize, level: usize) -> &[usize] {
+        self.links.get(slot, level)
+    }
+
+    /// The links of `slot` on each of its layers, bottom up.
+    pub fn all_links(&self, slot: usize) -> impl Iterator<Item = &[usize]> + '_ {
+        (0..self.layers(slot)).map(move |level| self.links(slot, level))
+    }
+
+    /// The links of `slot` on `level`, to modify in place.
+    pub fn links_mut(&mut self, slot: usize, level: usize) -> &mut [usize] {
+        match self.links.list(slot, level) {
+            Some(list) => {
+                let List { start, len, .. } = self.links.lists[list];
+                &mut self.links.targets[start..start + len]
+            }
+            None => &mut [],
+        }
+    }
+
+    /// Appends `target` to the links of `slot` on `level`, if it has that
+    /// layer.
+    pub fn push_link(&mut self, slot: usize, level: usize, target: usize) {
+        let Some(list) = self.links.list(slot, level) else {
+            return;
+        };
+        let len = self.links.lists[list].len;
+        self.links.reserve(list, len + 1);
+        let list = &mut self.links.lists[list];
+        self.links.targets[list.start + len] = target;
+        list.len += 1;
+        self.links.compact();
+    }
+
+    /// Replaces the links of `slot` on `level`, if it has that layer.
+    pub fn set_links(&mut self, slot: usize, level: usize, targets: &[usize]) {
+        let Some(list) = self.links.list(slot, level) else {
+            return;
+        };
+        self.links.reserve(list, targets.len());
+        let list = &mut self.links.lists[list];
+        self.links.targets[list.start..list.start + targets.len()].copy_from_slice(targets);
+        list.len = targets.len();
+        self.links.compact();
+    }
+
+    /// Keeps the links of `slot` on `level` that `keep` accepts, in order.
+    pub fn retain_links(&mut self, slot: usize, level: usize, mut keep: impl FnMut(usize) -> bool) {
+        let Some(list) = self.links.list(slot, level) else {
+            return;
+        };
+        let list = &mut self.links.lists[list];
+        let targets = &mut self.links.targets[list.start..list.start + list.len];
+        let mut kept = 0;
+        for i in 0..targets.len() {
+            if keep(targets[i]) {
+                targets[kept] = targets[i];
+                kept += 1;
+            }
+        }
+        list.len = kept;
+    }
+
+    /// Room the links of `slot` on `level` have before they must move.
+    #[cfg(test)]
+    pub fn link_capacity(&self, slot: usize, level: usize) -> usize {
+        self.links.list(slot, level).map_or(0, |list| self.links.lists[list].capacity)
+    }
+
+    /// Drops every node from slot `len` on. Links to the dropped slots are
+    /// left for the caller to remove.
+    pub fn truncate(&mut self, len: usize) {
+        for node in self.nodes.iter().skip(len) {
+            self.slots.remove(&node.id);
+        }
+        self.nodes.truncate(len);
+        for lists in self.links.nodes.split_off(len.min(self.links.nodes.len())) {
+            self.links.abandon(lists);
+        }
+        self.links.compact();
+    }
+
+    /// Removes the node in `slot` and moves the last node into it. Links to
+    /// either slot are left for the caller to fix.
+    pub fn swap_remove(&mut self, slot: usize) -> Node {
+        let node = self.nodes.swap_remove(slot);
+        self.slots.remove(&node.id);
+        if let Some(moved) = self.nodes.get(slot) {
+            self.slots.insert(moved.id, slot);
+        }
+        let lists = self.links.nodes.swap_remove(slot);
+        self.links.abandon(lists);
+        self.links.compact();
+        node
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.slots.clear();
+        self.links = Links::default();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Node> {
+        self.nodes.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Node> {
+        self.nodes.iter_mut()
+    }
+}
+
+impl Index<usize> for NodeSlab {
+    type Output = Node;
+
+    fn index(&self, slot: usize) -> &Node {
+        &self.nodes[slot]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::VectorItem;
+    use std::sync::Arc;
+
+    fn node(id: usize, x: f64) -> Node {
+        Node {
+            id,
+            item: Arc::new(VectorItem { id, vector: vec![x] }),
+            layer: 0,
+            codes: Vec::new(),
+            norm: Default::default(),
+            payload: None,
+            expires_at: None,
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_slots() {
+        let mut slab = NodeSlab::new();
+        assert_eq!(slab.next_slot(42), 0);
+        assert_eq!(slab.insert(node(42, 1.0), vec![vec![]]), 0);
+        assert_eq!(slab.insert(node(7, 2.0), vec![vec![]]), 1);
+        assert_eq!(slab.next_slot(42), 0);
+        assert_eq!(slab.insert(node(42, 3.0), vec![vec![]]), 0);
+
+        assert_eq!(slab.len(), 2);
+        assert_eq!(slab[0].item.vector, vec![3.0]);
+        assert_eq!(slab.slot_of(7), Some(1));
+        assert!(!slab.contains_id(8));
+        assert!(NodeSlab::from_nodes(vec![(node(1, 0.0), vec![]), (node(1, 0.0), vec![])]).is_err());
+
+        slab.truncate(1);
+        assert_eq!(slab.len(), 1);
+        assert!(!slab.contains_id(7));
+        assert_eq!(slab.next_slot(7), 1);
+
+        slab.insert(node(7, 2.0), vec![vec![]]);
+        slab.insert(node(9, 4.0), vec![vec![]]);
+        assert_eq!(slab.swap_remove(0).id, 42);
+        assert_eq!((slab.slot_of(9), slab.slot_of(7), slab.contains_id(42)), (Some(0), Some(1), false));
+        assert_eq!(slab.swap_remove(1).id, 7);
+        assert_eq!(slab.len(), 1);
+    }
+
+    #[test]
+    fn test_links() {
+        let mut slab = NodeSlab::new();
+        slab.insert(node(0, 0.0), vec![Vec::with_capacity(2), vec![]]);
+        slab.insert(node(1, 1.0), vec![vec![0]]);
+        assert_eq!((slab.layers(0), slab.layers(1), slab.layers(2)), (2, 1, 0));
+        assert_eq!(slab.link_capacity(0, 0), 2);
+
+        slab.push_link(0, 0, 1);
+        slab.push_link(0, 0, 1);
+        assert_eq!(slab.link_capacity(0, 0), 2);
+        // Outgrowing the list moves it without touching its neighbors
+        slab.push_link(0, 0, 0);
+        assert_eq!(slab.links(0, 0), [1, 1, 0]);
+        assert!(slab.link_capacity(0, 0) >= 3);
+        assert_eq!(slab.links(1, 0), [0]);
+        assert!(slab.links(0, 1).is_empty());
+        assert!(slab.links(1, 1).is_empty());
+        slab.push_link(1, 1, 0);
+        assert_eq!(slab.layers(1), 1);
+
+        slab.retain_links(0, 0, |target| target != 1);
+        assert_eq!(slab.links(0, 0), [0]);
+        slab.set_links(0, 1, &[1, 1, 1, 1, 1]);
+        slab.links_mut(0, 1)[2] = 0;
+        assert_eq!(slab.all_links(0).collect::<Vec<_>>(), [&[0][..], &[1, 1, 0, 1, 1]]);
+
+        // Churn leaves abandoned lists behind; compaction keeps them bounded
+        for round in 0..100 {
+            slab.insert(node(1, 1.0), vec![vec![round % 2; 3]]);
+            slab.set_links(0, 0, &vec![1; round % 7]);
+        }
+        assert_eq!(slab.links(1, 0), [1, 1, 1]);
+        assert_eq!(slab.links(0, 0), [1; 99 % 7]);
+        assert_eq!(slab.links(0, 1), [1, 1, 0, 1, 1]);
+        assert!(slab.links.targets.len() < 64);
+
+        slab.insert(node(2, 2.0), vec![vec![0, 1]]);
+        slab.swap_remove(0);
+        assert_eq!(slab.links(0, 0), [0, 1]);
+        assert_eq!(slab.links(1, 0), [1, 1, 1]);
+        slab.truncate(1);
+        assert_eq!((slab.layers(0), slab.layers(1)), (1, 0));
+    }
+}
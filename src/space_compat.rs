@@ -0,0 +1,95 @@
+//! Interop with the `space` crate's metric abstractions.
+//!
+//! `EuclideanDistance` implements `space::Metric` so it can be handed to other
+//! rust-cv tooling, and `SpaceMetric` wraps any `space::Metric` over
+//! `VectorItem`s so it can be used as the index's `DistanceCalculator`.
+//! `HnswIndex` itself does not implement `space::Knn`: its nodes live behind a
+//! lock, so it cannot hand out borrowed views of stored points.
+
+use crate::vector::{DistanceCalculator, EuclideanDistance, VectorItem};
+use num_traits::ToPrimitive;
+use pgat::ReferenceProxy;
+
+pub type VectorProxy = ReferenceProxy<VectorItem>;
+
+impl space::Metric<VectorProxy> for EuclideanDistance {
+    // Non-negative floats order the same way as their bit patterns.
+    type Unit = u64;
+
+    fn distance(&self, a: &VectorItem, b: &VectorItem) -> u64 {
+        let distance = DistanceCalculator::distance(self, &a.vector, &b.vector);
+        debug_assert!(!distance.is_nan());
+        distance.to_bits()
+    }
+}
+
+/// Adapts a `space::Metric` so it can drive an `HnswIndex`.
+///
+/// The index only ever compares distances, so the metric's unit just has to
+/// convert to `f64` without changing the ordering.
+///
+/// `space` metrics compare `VectorItem`s, so each comparison copies both
+/// vectors into items.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpaceMetric<M>(pub M);
+
+impl<M> DistanceCalculator for SpaceMetric<M>
+where
+    M: space::Metric<VectorProxy>,
+    M::Unit: ToPrimitive,
+{
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let item = |vector: &[f64]| VectorItem { id: 0, vector: vector.to_vec() };
+        self.0
+            .distance(&item(a), &item(b))
+            .to_f64()
+            .unwrap_or(f64::INFINITY)
+    }
+
+    fn name(&self) -> &str {
+        std::any::type_name::<M>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HnswIndex;
+    use space::Metric;
+
+    #[derive(Clone, Copy)]
+    struct Manhattan;
+
+    impl Metric<VectorProxy> for Manhattan {
+        type Unit = u32;
+
+        fn distance(&self, a: &VectorItem, b: &VectorItem) -> u32 {
+            a.vector.iter().zip(&b.vector).map(|(x, y)| (x - y).abs() as u32).sum()
+        }
+    }
+
+    #[test]
+    fn test_euclidean_metric_preserves_order() {
+        let origin = VectorItem { id: 0, vector: vec![0.0, 0.0] };
+        let near = VectorItem { id: 1, vector: vec![1.0, 0.0] };
+        let far = VectorItem { id: 2, vector: vec![3.0, 4.0] };
+
+        let metric = EuclideanDistance;
+        assert_eq!(Metric::distance(&metric, &origin, &origin), 0);
+        assert!(Metric::distance(&metric, &origin, &near) < Metric::distance(&metric, &origin, &far));
+        assert_eq!(f64::from_bits(Metric::distance(&metric, &origin, &far)), 5.0);
+    }
+
+    #[test]
+    fn test_space_metric_drives_index() {
+        let index = HnswIndex::new(Box::new(SpaceMetric(Manhattan)));
+        for i in 0..20 {
+            index.add(VectorItem { id: i, vector: vec![i as f64 * 10.0, 0.0] }).unwrap();
+        }
+
+        let query = VectorItem { id: 100, vector: vec![52.0, 0.0] };
+        let calculator = SpaceMetric(Manhattan);
+        assert_eq!(calculator.distance(&query.vector, &[50.0, 0.0]), 2.0);
+        assert!(!index.search(&query, 3).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+//! Sets of ids, such as document shingles or tags, stored as fixed-width
+//! `VectorItem`s so they can be indexed under `JaccardDistance`.
+//!
+//! A set becomes the sorted element ids as components, padded with -1 up
+//! to the index dimension. Create the index with
+//! `with_dimension(width)` for the largest set it will hold. Element ids
+//! must be below 2^53 so they survive the trip through `f64`.
+
+use crate::vector::{DistanceCalculator, VectorItem};
+
+// Fills the components of a set item past its last element
+const PADDING: f64 = -1.0;
+
+// Element ids must be below this to be exact in an `f64`
+const MAX_ELEMENT: u64 = 1 << 53;
+
+/// A set of element ids, kept sorted and free of duplicates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SparseSet {
+    elements: Vec<u64>,
+}
+
+impl SparseSet {
+    pub fn new(elements: impl IntoIterator<Item = u64>) -> Self {
+        let mut elements: Vec<u64> = elements.into_iter().collect();
+        elements.sort_unstable();
+        elements.dedup();
+        SparseSet { elements }
+    }
+
+    pub fn elements(&self) -> &[u64] {
+        &self.elements
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// The set as an item with `width` components. Fails if the set has more
+    /// than `width` elements or an element too large for an `f64`.
+    pub fn to_item(&self, id: usize, width: usize) -> Result<VectorItem, String> {
+        if self.elements.len() > width {
+            return Err(format!("Set {} has {} elements, the width is {}", id, self.elements.len(), width));
+        }
+        if let Some(&element) = self.elements.iter().find(|&&element| element >= MAX_ELEMENT) {
+            return Err(format!("Element {} of set {} is not below 2^53", element, id));
+        }
+        let mut vector: Vec<f64> = self.elements.iter().map(|&element| element as f64).collect();
+        vector.resize(width, PADDING);
+        Ok(VectorItem { id, vector })
+    }
+
+    /// The set an item from `to_item` holds.
+    pub fn from_item(item: &VectorItem) -> Self {
+        SparseSet { elements: elements(&item.vector).map(|x| x as u64).collect() }
+    }
+}
+
+// The elements of an encoded set, in order.
+fn elements(vector: &[f64]) -> impl Iterator<Item = f64> + '_ {
+    vector.iter().copied().take_while(|&x| x != PADDING)
+}
+
+/// Jaccard distance `1 - |A ∩ B| / |A ∪ B|` between sets encoded by
+/// `SparseSet::to_item`, found by merging the two sorted element lists.
+/// Two empty sets are at distance 0.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JaccardDistance;
+
+impl DistanceCalculator for JaccardDistance {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let (mut a, mut b) = (elements(a).peekable(), elements(b).peekable());
+        let (mut shared, mut union) = (0usize, 0usize);
+        loop {
+            match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if x == y => {
+                    shared += 1;
+                    a.next();
+                    b.next();
+                }
+                (Some(x), Some(y)) if x < y => {
+                    a.next();
+                }
+                (Some(_), Some(_)) => {
+                    b.next();
+                }
+                (Some(_), None) => {
+                    a.next();
+                }
+                (None, Some(_)) => {
+                    b.next();
+                }
+                (None, None) => break,
+            }
+            union += 1;
+        }
+        if union == 0 {
+            return 0.0;
+        }
+        1.0 - shared as f64 / union as f64
+    }
+
+    fn name(&self) -> &str {
+        "jaccard"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HnswIndex;
+
+    #[test]
+    fn test_jaccard_search() {
+        let set = |ids: &[u64]| SparseSet::new(ids.iter().copied());
+        assert_eq!(set(&[3, 1, 3, 2]).elements(), [1, 2, 3]);
+        let item = set(&[5, 9]).to_item(7, 4).unwrap();
+        assert_eq!(item.vector, [5.0, 9.0, PADDING, PADDING]);
+        assert_eq!(SparseSet::from_item(&item), set(&[5, 9]));
+        assert!(set(&[1, 2, 3]).to_item(0, 2).is_err());
+        assert!(set(&[1 << 60]).to_item(0, 2).is_err());
+
+        let distance = |a: &[u64], b: &[u64]| {
+            JaccardDistance.distance(&set(a).to_item(0, 8).unwrap().vector, &set(b).to_item(1, 8).unwrap().vector)
+        };
+        assert_eq!(distance(&[1, 2, 3], &[2, 3, 4]), 0.5);
+        assert_eq!(distance(&[1, 2], &[1, 2]), 0.0);
+        assert_eq!(distance(&[1], &[2]), 1.0);
+        assert_eq!(distance(&[], &[]), 0.0);
+        assert_eq!(distance(&[], &[1]), 1.0);
+
+        // Tag sets drawn from overlapping windows; set 10 plus one extra tag
+        // is nearest set 10 (1/7) ahead of sets 9 and 11 (3/8)
+        let index = HnswIndex::new(Box::new(JaccardDistance)).with_dimension(8);
+        for id in 0..100u64 {
+            index.add(set(&(id..id + 6).collect::<Vec<_>>()).to_item(id as usize, 8).unwrap()).unwrap();
+        }
+        let query = set(&[10, 11, 12, 13, 14, 15, 99]).to_item(0, 8).unwrap();
+        let results = index.search(&query, 3).unwrap();
+        assert_eq!(results[0].id, 10);
+        assert_eq!(SparseSet::from_item(&results[0]).elements(), [10, 11, 12, 13, 14, 15]);
+    }
+}
@@ -0,0 +1,7 @@
+//! Synchronization primitives used by the index: `std::sync`, or loom's
+//! model-checked versions when built with `RUSTFLAGS="--cfg loom"`.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{atomic, Arc, Mutex, RwLock};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{atomic, Arc, Mutex, RwLock};
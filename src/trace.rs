@@ -0,0 +1,58 @@
+use std::fmt;
+
+/// What one search did, layer by layer; see `HnswIndex::search_traced`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchTrace {
+    /// Id of the node the walk started from; `None` if there was no walk.
+    pub entry_point: Option<usize>,
+    /// The layers walked, top layer first and layer 0 last.
+    pub layers: Vec<LayerTrace>,
+    /// Distances computed over the whole search.
+    pub distance_computations: usize,
+}
+
+/// The walk through one layer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayerTrace {
+    pub layer: usize,
+    /// Nodes whose links were expanded.
+    pub hops: usize,
+    pub distance_computations: usize,
+    /// Nodes evaluated on the layer; on layer 0, the beam's visited set.
+    pub visited: usize,
+}
+
+impl SearchTrace {
+    pub(crate) fn enter_layer(&mut self, layer: usize) {
+        self.layers.push(LayerTrace { layer, ..Default::default() });
+    }
+
+    pub(crate) fn count_distance(&mut self) {
+        self.distance_computations += 1;
+        if let Some(layer) = self.layers.last_mut() {
+            layer.distance_computations += 1;
+        }
+    }
+
+    pub(crate) fn hop(&mut self, visited: usize) {
+        if let Some(layer) = self.layers.last_mut() {
+            layer.hops += 1;
+            layer.visited = visited;
+        }
+    }
+}
+
+impl fmt::Display for SearchTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entry_point = self.entry_point.map_or_else(|| "none".to_string(), |id| id.to_string());
+        write!(f, "entry point: {}, distances: {}", entry_point, self.distance_computations)?;
+        for layer in &self.layers {
+            write!(
+                f,
+                "\nlayer {}: {} hops, {} distances, {} visited",
+                layer.layer, layer.hops, layer.distance_computations, layer.visited
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::vector::VectorItem;
+use std::borrow::Cow;
+
+/// A dimension-reducing transform applied to every vector on insert and to
+/// every query before search. It is stored with the index so a reloaded index
+/// keeps reducing queries the same way.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VectorTransform {
+    /// Keep the first `dim` components (Matryoshka-style embeddings).
+    Truncate { dim: usize },
+    /// Multiply by a trained `output_dim x input_dim` matrix.
+    Projection { matrix: Vec<Vec<f64>> },
+}
+
+impl VectorTransform {
+    pub fn truncate(dim: usize) -> Self {
+        VectorTransform::Truncate { dim }
+    }
+
+    pub fn projection(matrix: Vec<Vec<f64>>) -> Result<Self, String> {
+        let input_dim = matrix.first().map_or(0, |row| row.len());
+        if input_dim == 0 {
+            return Err("Projection matrix must be non-empty".to_string());
+        }
+        if let Some(row) = matrix.iter().position(|row| row.len() != input_dim) {
+            return Err(format!(
+                "Projection matrix row {} has {} columns, expected {}",
+                row,
+                matrix[row].len(),
+                input_dim
+            ));
+        }
+        Ok(VectorTransform::Projection { matrix })
+    }
+
+    pub fn output_dim(&self) -> usize {
+        match self {
+            VectorTransform::Truncate { dim } => *dim,
+            VectorTransform::Projection { matrix } => matrix.len(),
+        }
+    }
+
+    pub fn apply(&self, vector: &[f64]) -> Result<Vec<f64>, String> {
+        match self {
+            VectorTransform::Truncate { dim } => {
+                if vector.len() < *dim {
+                    return Err(format!(
+                        "Cannot truncate a {}-dimensional vector to {} dimensions",
+                        vector.len(),
+                        dim
+                    ));
+                }
+                Ok(vector[..*dim].to_vec())
+            }
+            VectorTransform::Projection { matrix } => {
+                let input_dim = matrix[0].len();
+                if vector.len() != input_dim {
+                    return Err(format!(
+                        "Projection expects {}-dimensional vectors, got {}",
+                        input_dim,
+                        vector.len()
+                    ));
+                }
+                Ok(matrix
+                    .iter()
+                    .map(|row| row.iter().zip(vector).map(|(w, x)| w * x).sum())
+                    .collect())
+            }
+        }
+    }
+
+    pub(crate) fn apply_to_item<'a>(
+        transform: Option<&VectorTransform>,
+        item: &'a VectorItem,
+    ) -> Result<Cow<'a, VectorItem>, String> {
+        match transform {
+            Some(transform) => Ok(Cow::Owned(VectorItem {
+                id: item.id,
+                vector: transform.apply(&item.vector)?,
+            })),
+            None => Ok(Cow::Borrowed(item)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        let transform = VectorTransform::truncate(2);
+        assert_eq!(transform.apply(&[1.0, 2.0, 3.0]).unwrap(), vec![1.0, 2.0]);
+        assert!(transform.apply(&[1.0]).is_err());
+    }
+
+    #[test]
+    fn test_projection() {
+        let transform = VectorTransform::projection(vec![vec![1.0, 0.0, 1.0], vec![0.0, 2.0, 0.0]]).unwrap();
+        assert_eq!(transform.output_dim(), 2);
+        assert_eq!(transform.apply(&[1.0, 2.0, 3.0]).unwrap(), vec![4.0, 4.0]);
+        assert!(transform.apply(&[1.0, 2.0]).is_err());
+        assert!(VectorTransform::projection(vec![vec![1.0], vec![1.0, 2.0]]).is_err());
+    }
+}
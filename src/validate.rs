@@ -0,0 +1,117 @@
+use crate::slab::NodeSlab;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Broken graph invariants found by `HnswIndex::validate`. Nodes are named
+/// by id; a link to a slot holding no node is named by that slot.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub nodes: usize,
+    /// Set when the entry point is missing, out of range or not on the top
+    /// layer.
+    pub entry_point_error: Option<String>,
+    /// Nodes a layer-0 walk from the entry point never reaches.
+    pub unreachable: Vec<usize>,
+    /// `(id, layer, slot)` links to slots that hold no node.
+    pub dangling_links: Vec<(usize, usize, usize)>,
+    /// Nodes without exactly one link list per layer up to their own, or
+    /// linking on a layer to a node that is not on it.
+    pub layer_mismatches: Vec<usize>,
+    /// `(id, layer)` link lists longer than that layer allows.
+    pub over_degree: Vec<(usize, usize)>,
+    /// `(from, to, layer)` links with no link back, when asked for. Pruning
+    /// drops reverse links, so some of these are normal and `is_valid`
+    /// ignores them.
+    pub asymmetric_links: Option<Vec<(usize, usize, usize)>>,
+}
+
+impl ValidationReport {
+    /// True if no invariant other than symmetry is broken.
+    pub fn is_valid(&self) -> bool {
+        self.entry_point_error.is_none()
+            && self.unreachable.is_empty()
+            && self.dangling_links.is_empty()
+            && self.layer_mismatches.is_empty()
+            && self.over_degree.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nodes: {}", self.nodes)?;
+        writeln!(f, "entry point: {}", self.entry_point_error.as_deref().unwrap_or("ok"))?;
+        writeln!(f, "unreachable nodes: {}", self.unreachable.len())?;
+        writeln!(f, "dangling links: {}", self.dangling_links.len())?;
+        writeln!(f, "layer mismatches: {}", self.layer_mismatches.len())?;
+        write!(f, "over-degree link lists: {}", self.over_degree.len())?;
+        if let Some(asymmetric) = &self.asymmetric_links {
+            write!(f, "\nasymmetric links: {}", asymmetric.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `nodes` against `entry_point` and the per-layer link budget.
+pub(crate) fn validate(
+    nodes: &NodeSlab,
+    entry_point: Option<usize>,
+    max_connections: impl Fn(usize) -> usize,
+    check_symmetry: bool,
+) -> ValidationReport {
+    let mut report = ValidationReport { nodes: nodes.len(), ..Default::default() };
+    let top = nodes.iter().map(|node| node.layer).max();
+    report.entry_point_error = match (entry_point, top) {
+        (None, None) => None,
+        (None, Some(_)) => Some("missing on a non-empty index".to_string()),
+        (Some(slot), _) if slot >= nodes.len() => Some(format!("slot {} is out of range", slot)),
+        (Some(slot), Some(top)) if nodes[slot].layer != top => {
+            Some(format!("node {} is on layer {}, below the top layer {}", nodes[slot].id, nodes[slot].layer, top))
+        }
+        (Some(_), _) => None,
+    };
+
+    let mut asymmetric = Vec::new();
+    for (own_slot, node) in nodes.iter().enumerate() {
+        let mut mismatched = nodes.layers(own_slot) != node.layer + 1;
+        for (layer, links) in nodes.all_links(own_slot).enumerate() {
+            if links.len() > max_connections(layer) {
+                report.over_degree.push((node.id, layer));
+            }
+            for &slot in links {
+                let Some(target) = nodes.get(slot) else {
+                    report.dangling_links.push((node.id, layer, slot));
+                    continue;
+                };
+                if layer >= nodes.layers(slot) {
+                    mismatched = true;
+                } else if check_symmetry && !nodes.links(slot, layer).contains(&own_slot) {
+                    asymmetric.push((node.id, target.id, layer));
+                }
+            }
+        }
+        if mismatched {
+            report.layer_mismatches.push(node.id);
+        }
+    }
+    if check_symmetry {
+        report.asymmetric_links = Some(asymmetric);
+    }
+
+    if let Some(start) = entry_point.filter(|&slot| slot < nodes.len()) {
+        let mut seen = vec![false; nodes.len()];
+        let mut queue = VecDeque::from([start]);
+        seen[start] = true;
+        while let Some(slot) = queue.pop_front() {
+            for &next in nodes.links(slot, 0) {
+                if next < nodes.len() && !seen[next] {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        report.unreachable = nodes.iter().zip(&seen).filter(|(_, &seen)| !seen).map(|(node, _)| node.id).collect();
+    } else {
+        report.unreachable = nodes.iter().map(|node| node.id).collect();
+    }
+    report
+}
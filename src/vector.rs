@@ -1,20 +1,268 @@
+use std::sync::Arc;
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VectorItem {
     pub id: usize,
     pub vector: Vec<f64>,
 }
 
+/// A metric over vectors. Implementations provide `distance`; the older
+/// `calculate` is kept for callers and forwards to it.
 pub trait DistanceCalculator {
-    fn calculate(&self, item1: &VectorItem, item2: &VectorItem) -> f64;
+    /// The distance between two vectors of the same dimension.
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64;
+
+    /// `distance` between the vectors of two items.
+    #[deprecated(note = "implement and call `distance`, which takes slices")]
+    fn calculate(&self, item1: &VectorItem, item2: &VectorItem) -> f64 {
+        self.distance(&item1.vector, &item2.vector)
+    }
+
+    /// Appends the distance from `query` to each of `vectors` to `out`, in
+    /// order. Searches and inserts hand over all the unvisited neighbors of
+    /// a node in one call, so an implementation can compute them together,
+    /// e.g. with SIMD. The default calls `distance` for each.
+    fn distance_batch(&self, query: &[f64], vectors: &[&[f64]], out: &mut Vec<f64>) {
+        out.extend(vectors.iter().map(|vector| self.distance(query, vector)));
+    }
+
+    /// `distance_batch` to vectors stored in an index, each with its slot.
+    /// A calculator that keeps its own copy of the stored vectors, such as
+    /// `GpuDistance`, can look them up by slot instead; the item at a slot
+    /// changes when the index moves or replaces nodes, so the copy must be
+    /// checked against it. The default calls `distance_batch`.
+    fn distance_batch_stored(&self, query: &[f64], stored: &[(usize, &Arc<VectorItem>)], out: &mut Vec<f64>) {
+        let vectors: Vec<&[f64]> = stored.iter().map(|(_, item)| item.vector.as_slice()).collect();
+        self.distance_batch(query, &vectors, out);
+    }
+
+    /// Whether `distance` is the Euclidean distance. Searches over SQ8 or
+    /// PQ codes then compute it from the codes and per-query tables rather
+    /// than decoding every stored vector.
+    fn is_euclidean(&self) -> bool {
+        false
+    }
+
+    /// Whether the metric wants each vector's Euclidean norm. The index then
+    /// caches the norm of every stored vector, computes the query's once per
+    /// search, and calls `distance_with_norms` instead of `distance`.
+    fn uses_norms(&self) -> bool {
+        false
+    }
+
+    /// `distance` given the norms of both vectors. The default ignores them.
+    fn distance_with_norms(&self, a: &[f64], _norm_a: f64, b: &[f64], _norm_b: f64) -> f64 {
+        self.distance(a, b)
+    }
+
+    /// Shown by `HnswIndex::config`.
+    fn name(&self) -> &str {
+        "custom"
+    }
+}
+
+/// The Euclidean length of `vector`.
+pub(crate) fn norm(vector: &[f64]) -> f64 {
+    vector.iter().map(|x| x * x).sum::<f64>().sqrt()
 }
 
+#[derive(Clone, Copy, Debug, Default)]
 pub struct EuclideanDistance;
 
 impl DistanceCalculator for EuclideanDistance {
-    fn calculate(&self, item1: &VectorItem, item2: &VectorItem) -> f64 {
-        item1.vector.iter().zip(item2.vector.iter())
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b)
             .map(|(x, y)| (x - y).powi(2))
             .sum::<f64>()
             .sqrt()
     }
+
+    fn is_euclidean(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        "euclidean"
+    }
+}
+
+/// Euclidean distance with a non-negative weight per dimension,
+/// `sqrt(Σ wᵢ (xᵢ - yᵢ)²)`, for data whose dimensions matter unequally.
+/// Vectors of another dimension than the weights are at distance NaN.
+#[derive(Clone, Debug)]
+pub struct WeightedEuclidean {
+    weights: Vec<f64>,
+}
+
+impl WeightedEuclidean {
+    /// Fails if a weight is negative or not finite.
+    pub fn new(weights: Vec<f64>) -> Result<Self, String> {
+        if let Some(dim) = weights.iter().position(|w| !(w.is_finite() && *w >= 0.0)) {
+            return Err(format!("Weight {} of dimension {} is not a non-negative number", weights[dim], dim));
+        }
+        Ok(WeightedEuclidean { weights })
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+}
+
+impl DistanceCalculator for WeightedEuclidean {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        if a.len() != self.weights.len() || b.len() != self.weights.len() {
+            return f64::NAN;
+        }
+        a.iter().zip(b).zip(&self.weights)
+            .map(|((x, y), w)| w * (x - y).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    fn name(&self) -> &str {
+        "weighted_euclidean"
+    }
+}
+
+/// Mahalanobis distance `sqrt(dᵀ M d)` for `d = x - y` under a symmetric
+/// positive semi-definite matrix `M`, such as an inverse covariance or a
+/// learned metric. Costs O(dim²) per comparison; for a diagonal `M` use
+/// `WeightedEuclidean`. Vectors of another dimension than `M` are at
+/// distance NaN.
+#[derive(Clone, Debug)]
+pub struct Mahalanobis {
+    matrix: Vec<Vec<f64>>,
+}
+
+impl Mahalanobis {
+    /// Fails unless `matrix` is square, finite, symmetric and positive
+    /// semi-definite.
+    pub fn new(matrix: Vec<Vec<f64>>) -> Result<Self, String> {
+        let dim = matrix.len();
+        if let Some(row) = matrix.iter().position(|row| row.len() != dim) {
+            return Err(format!("Row {} has {} columns, expected {}", row, matrix[row].len(), dim));
+        }
+        for (i, row) in matrix.iter().enumerate() {
+            if let Some(j) = (0..dim).find(|&j| !row[j].is_finite()) {
+                return Err(format!("Matrix entry ({}, {}) is {}", i, j, row[j]));
+            }
+            if let Some(j) = (0..i).find(|&j| row[j] != matrix[j][i]) {
+                return Err(format!("Matrix is not symmetric at ({}, {})", i, j));
+            }
+        }
+        if let Some(pivot) = indefinite_pivot(&matrix) {
+            return Err(format!("Matrix is not positive semi-definite (pivot {})", pivot));
+        }
+        Ok(Mahalanobis { matrix })
+    }
+
+    pub fn matrix(&self) -> &[Vec<f64>] {
+        &self.matrix
+    }
+}
+
+// Runs an LDLᵀ factorization of the symmetric `matrix` and returns the
+// first pivot that is negative, or zero with a non-zero column below it,
+// beyond rounding error. `None` means the matrix is positive semi-definite.
+fn indefinite_pivot(matrix: &[Vec<f64>]) -> Option<usize> {
+    let dim = matrix.len();
+    let scale = matrix.iter().flatten().fold(0.0f64, |max, x| max.max(x.abs()));
+    let tolerance = scale * dim as f64 * f64::EPSILON * 16.0;
+    let mut lower = vec![vec![0.0; dim]; dim];
+    let mut pivots = vec![0.0; dim];
+    for j in 0..dim {
+        let pivot = matrix[j][j] - (0..j).map(|k| lower[j][k] * lower[j][k] * pivots[k]).sum::<f64>();
+        if pivot < -tolerance {
+            return Some(j);
+        }
+        for i in j + 1..dim {
+            let entry = matrix[i][j] - (0..j).map(|k| lower[i][k] * lower[j][k] * pivots[k]).sum::<f64>();
+            if pivot > tolerance {
+                lower[i][j] = entry / pivot;
+            } else if entry.abs() > tolerance {
+                return Some(j);
+            }
+        }
+        pivots[j] = pivot.max(0.0);
+    }
+    None
+}
+
+impl DistanceCalculator for Mahalanobis {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let dim = self.matrix.len();
+        if a.len() != dim || b.len() != dim {
+            return f64::NAN;
+        }
+        let d: Vec<f64> = a.iter().zip(b).map(|(x, y)| x - y).collect();
+        let form: f64 = self.matrix.iter().zip(&d)
+            .map(|(row, di)| di * row.iter().zip(&d).map(|(m, dj)| m * dj).sum::<f64>())
+            .sum();
+        // `new` rejects indefinite matrices, but rounding can still leave a
+        // tiny negative form for near-equal vectors; clamp rather than max so
+        // NaN still comes through
+        form.clamp(0.0, f64::INFINITY).sqrt()
+    }
+
+    fn name(&self) -> &str {
+        "mahalanobis"
+    }
+}
+
+/// Cosine distance, `1 - cos θ`, from 0 for vectors pointing the same way to
+/// 2 for opposite ones. Inside an index the norms come from a per-vector
+/// cache, so a comparison costs one dot product. A zero vector is at
+/// distance 1 from everything but another zero vector.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AngularDistance;
+
+impl DistanceCalculator for AngularDistance {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        self.distance_with_norms(a, norm(a), b, norm(b))
+    }
+
+    fn uses_norms(&self) -> bool {
+        true
+    }
+
+    fn distance_with_norms(&self, a: &[f64], norm_a: f64, b: &[f64], norm_b: f64) -> f64 {
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return if norm_a == norm_b { 0.0 } else { 1.0 };
+        }
+        let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        // Rounding can push the cosine just past ±1; clamp keeps NaN
+        (1.0 - dot / (norm_a * norm_b)).clamp(0.0, 2.0)
+    }
+
+    fn name(&self) -> &str {
+        "angular"
+    }
+}
+
+/// Restricts another metric to a subset of dimensions by projecting both
+/// vectors onto `dims` before delegating to it.
+pub struct MaskedDistance<'a> {
+    inner: &'a dyn DistanceCalculator,
+    dims: &'a [usize],
+}
+
+impl<'a> MaskedDistance<'a> {
+    pub fn new(inner: &'a dyn DistanceCalculator, dims: &'a [usize]) -> Self {
+        MaskedDistance { inner, dims }
+    }
+
+    fn project(&self, vector: &[f64]) -> Vec<f64> {
+        self.dims.iter().filter_map(|&dim| vector.get(dim).copied()).collect()
+    }
+}
+
+impl DistanceCalculator for MaskedDistance<'_> {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        self.inner.distance(&self.project(a), &self.project(b))
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
 }
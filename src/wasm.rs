@@ -0,0 +1,96 @@
+//! JavaScript bindings, built with `wasm-pack build --features wasm` for
+//! `wasm32-unknown-unknown`. The target has no threads or file system:
+//! batch inserts and searches run on the calling thread, and indexes are
+//! built in memory. Search times in `HnswIndex::metrics` read as zero, and
+//! a `Quota` with a query rate needs a clock, so it is not available here.
+
+use crate::{DistanceCalculator, EuclideanDistance, HnswIndex, VectorItem};
+use wasm_bindgen::prelude::*;
+
+/// A Euclidean index over vectors of a fixed dimension.
+#[wasm_bindgen(js_name = Index)]
+pub struct WasmIndex {
+    index: HnswIndex,
+    dimension: usize,
+}
+
+/// The ids and distances of one search, nearest first.
+#[wasm_bindgen(js_name = SearchResults)]
+pub struct WasmSearchResults {
+    ids: Vec<u32>,
+    distances: Vec<f64>,
+}
+
+#[wasm_bindgen(js_class = Index)]
+impl WasmIndex {
+    /// `seed` makes builds reproducible; see `HnswIndex::with_seed`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(dimension: usize, seed: Option<u32>) -> WasmIndex {
+        let mut index = HnswIndex::new(Box::new(EuclideanDistance)).with_dimension(dimension);
+        if let Some(seed) = seed {
+            index = index.with_seed(seed.into());
+        }
+        WasmIndex { index, dimension }
+    }
+
+    pub fn add(&self, id: u32, vector: &[f64]) -> Result<(), JsError> {
+        let item = VectorItem { id: id as usize, vector: vector.to_vec() };
+        self.index.add(item).map_err(|e| JsError::new(&e))
+    }
+
+    /// Inserts `ids.length` vectors packed row after row into `vectors`.
+    #[wasm_bindgen(js_name = addBatch)]
+    pub fn add_batch(&self, ids: &[u32], vectors: &[f64]) -> Result<(), JsError> {
+        if vectors.len() != ids.len() * self.dimension {
+            return Err(JsError::new(&format!(
+                "Expected {} values for {} vectors of {} dimensions, got {}",
+                ids.len() * self.dimension,
+                ids.len(),
+                self.dimension,
+                vectors.len()
+            )));
+        }
+        let items = ids
+            .iter()
+            .zip(vectors.chunks_exact(self.dimension.max(1)))
+            .map(|(&id, vector)| VectorItem { id: id as usize, vector: vector.to_vec() })
+            .collect();
+        self.index.par_batch_add(items).map_err(|e| JsError::new(&e))
+    }
+
+    pub fn search(&self, query: &[f64], k: usize) -> Result<WasmSearchResults, JsError> {
+        let query = VectorItem { id: 0, vector: query.to_vec() };
+        let hits = self.index.search(&query, k).map_err(|e| JsError::new(&e))?;
+        Ok(WasmSearchResults {
+            ids: hits.iter().map(|item| item.id as u32).collect(),
+            distances: hits.iter().map(|item| EuclideanDistance.distance(&query.vector, &item.vector)).collect(),
+        })
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.index.contains(id as usize)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.index.len()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+}
+
+#[wasm_bindgen(js_class = SearchResults)]
+impl WasmSearchResults {
+    #[wasm_bindgen(getter)]
+    pub fn ids(&self) -> Vec<u32> {
+        self.ids.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn distances(&self) -> Vec<f64> {
+        self.distances.clone()
+    }
+}
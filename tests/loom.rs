@@ -0,0 +1,82 @@
+//! Model-checked interleavings of inserts and searches. Run with
+//! `RUSTFLAGS="--cfg loom" cargo test --test loom --release`.
+#![cfg(loom)]
+
+use hnsw_rust::{EuclideanDistance, HnswIndex, VectorItem};
+use loom::sync::Arc;
+use loom::thread;
+
+fn item(id: usize, x: f64) -> VectorItem {
+    VectorItem { id, vector: vec![x, 0.0] }
+}
+
+fn seeded_index() -> HnswIndex {
+    let index = HnswIndex::new(Box::new(EuclideanDistance));
+    index.add(item(0, 0.0)).unwrap();
+    index.add(item(1, 1.0)).unwrap();
+    index
+}
+
+fn model(f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+    builder.check(f);
+}
+
+// A search racing an insert sees the graph either before or after the new
+// node is spliced in, never a dangling link.
+#[test]
+fn search_during_insert() {
+    model(|| {
+        let index = Arc::new(seeded_index());
+        let writer = {
+            let index = Arc::clone(&index);
+            thread::spawn(move || index.add(item(2, 2.0)).unwrap())
+        };
+
+        let results = index.search(&item(99, 2.0), 3).unwrap();
+        assert!(!results.is_empty() && results.len() <= 3);
+        assert!(results.iter().all(|r| r.id <= 2));
+
+        writer.join().unwrap();
+        assert_eq!(index.get_stats().total_nodes, 3);
+    });
+}
+
+// Two inserts into an empty index race for the entry point; both must end
+// up in the graph and searchable.
+#[test]
+fn concurrent_first_inserts() {
+    model(|| {
+        let index = Arc::new(HnswIndex::new(Box::new(EuclideanDistance)));
+        let handles: Vec<_> = (0..2)
+            .map(|id| {
+                let index = Arc::clone(&index);
+                thread::spawn(move || index.add(item(id, id as f64)).unwrap())
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(index.get_stats().total_nodes, 2);
+        assert!(!index.search(&item(99, 0.5), 2).unwrap().is_empty());
+    });
+}
+
+// Concurrent inserts with auto ids never hand out the same id twice.
+#[test]
+fn concurrent_add_auto() {
+    model(|| {
+        let index = Arc::new(seeded_index());
+        let other = {
+            let index = Arc::clone(&index);
+            thread::spawn(move || index.add_auto(vec![5.0, 5.0]).unwrap())
+        };
+        let mine = index.add_auto(vec![6.0, 6.0]).unwrap();
+        let theirs = other.join().unwrap();
+
+        assert_ne!(mine, theirs);
+        assert_eq!(index.get_stats().total_nodes, 4);
+    });
+}